@@ -0,0 +1,93 @@
+// RustTokioChatServer - 接続元の許可・拒否モジュール
+// MIT License
+//
+// クレート説明:
+// - std: 標準ライブラリ（IPアドレス）
+//
+// access.rs: 設定のAllow・Deny行（アドレスまたはCIDR表記の範囲）で接続元を絞り込む
+// Denyに一致すれば拒否し、Allowが1行でもあればいずれかに一致する接続元だけを受け付ける
+// 判定は接続ごとに現在の設定を読むので、SIGHUP等での再読込が次の接続から反映される
+use crate::init; // 設定管理モジュール
+use std::net::IpAddr; // std: IPアドレス
+
+// アドレスの範囲（192.168.0.0/16・2001:db8::/32、単独のアドレスは/32・/128）
+#[derive(Clone, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr, // 範囲の先頭（ホスト部は0に揃える）
+    prefix: u8,      // ネットワーク部のビット数
+}
+
+impl Cidr {
+    // 「アドレス」または「アドレス/ビット数」を解釈する（IPv4射影アドレスはIPv4として扱う）
+    pub fn parse(text: &str) -> Option<Cidr> {
+        let (address, prefix) = match text.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix.parse::<u8>().ok()?)),
+            None => (text, None),
+        };
+        match address.parse::<IpAddr>().ok()? {
+            IpAddr::V4(v4) => Cidr::new(IpAddr::V4(v4), prefix.unwrap_or(32), 32),
+            IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+                // ::ffff:a.b.c.d/ビット数 はIPv4のビット数に直す
+                Some(v4) => Cidr::new(
+                    IpAddr::V4(v4),
+                    prefix.map_or(Some(32), |p| p.checked_sub(96))?,
+                    32,
+                ),
+                None => Cidr::new(IpAddr::V6(v6), prefix.unwrap_or(128), 128),
+            },
+        }
+    }
+
+    // ホスト部を0に揃えて作る（ビット数が大きすぎればNone）
+    fn new(network: IpAddr, prefix: u8, max: u8) -> Option<Cidr> {
+        if prefix > max {
+            return None;
+        }
+        let network = match network {
+            IpAddr::V4(v4) => IpAddr::V4((u32::from(v4) & mask32(prefix)).into()),
+            IpAddr::V6(v6) => IpAddr::V6((u128::from(v6) & mask128(prefix)).into()),
+        };
+        Some(Cidr { network, prefix })
+    }
+
+    // 接続元が範囲に含まれるか
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                u32::from(ip) & mask32(self.prefix) == u32::from(network)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                u128::from(ip) & mask128(self.prefix) == u128::from(network)
+            }
+            _ => false, // IPv4とIPv6は比べない
+        }
+    }
+}
+
+// 設定の差分・ログでは設定ファイルと同じ書き方で表示する
+impl std::fmt::Debug for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+// 上位prefixビットが1のマスク
+fn mask32(prefix: u8) -> u32 {
+    u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0)
+}
+
+fn mask128(prefix: u8) -> u128 {
+    u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0)
+}
+
+// 接続元を受け付けてよいか（拒否する場合はログ用に理由を返す）
+pub fn check(ip: &IpAddr) -> Result<(), String> {
+    let config = init::CONFIG.read().unwrap();
+    if let Some(rule) = config.deny.iter().find(|rule| rule.contains(ip)) {
+        return Err(format!("Deny {:?}", rule)); // 拒否する範囲に含まれる
+    }
+    if !config.allow.is_empty() && !config.allow.iter().any(|rule| rule.contains(ip)) {
+        return Err("Allowのどれにも一致しない".to_string());
+    }
+    Ok(())
+}
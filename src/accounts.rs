@@ -0,0 +1,376 @@
+// RustTokioChatServer - ハンドルネーム登録モジュール
+// MIT License
+//
+// クレート説明:
+// - argon2: パスワードのハッシュ化と照合
+// - rusqlite: 拡張子が.db・.sqlite・.sqlite3の登録ファイルの読み書き
+// - tokio: ハッシュ計算・照合をブロッキング用スレッドで行う
+// - chrono: 登録日時
+// - std: 標準ライブラリ（ファイル入出力・同期）
+// - lazy_static: グローバル静的変数
+//
+// accounts.rs: /registerで登録したハンドルネームとパスワードのハッシュをAccountFileに保存する
+// ファイルは1行1件（ハンドルネーム<TAB>ハッシュ<TAB>登録日時のUNIX秒<TAB>メールアドレス）で、変更のたびに一時ファイル経由で置き換える
+// 拡張子が.db・.sqlite・.sqlite3ならSQLiteのaccountsテーブルに保存し、変更のたびにその1件だけを書き換える
+// ハンドルネームは登録簿（names.rs）と同じく大文字・小文字を区別しない
+use argon2::password_hash::rand_core::OsRng; // argon2: ソルト生成用の乱数
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString}; // argon2: ハッシュ文字列
+use argon2::Argon2; // argon2: パスワードハッシュ
+use lazy_static::lazy_static; // lazy_static: グローバル静的変数
+use rusqlite::Connection; // rusqlite: SQLite接続
+use std::collections::HashMap; // std: 登録一覧
+use std::sync::Mutex; // std: 登録一覧の排他
+
+// パスワードの最低文字数
+pub const MIN_PASSWORD_CHARS: usize = 6;
+
+// 登録済みのハンドルネーム1件
+#[derive(Debug, Clone)]
+struct Account {
+    name: String,          // 登録時の表記
+    hash: String,          // パスワードのハッシュ（PHC文字列形式）
+    registered_at: i64,    // 登録日時（UNIX秒）
+    email: Option<String>, // 連絡先メールアドレス（/email set、未設定ならNone）
+}
+
+// 本人向けに表示する登録内容（/info）
+pub struct AccountInfo {
+    pub name: String,          // 登録時の表記
+    pub registered_at: i64,    // 登録日時（UNIX秒）
+    pub email: Option<String>, // 連絡先メールアドレス
+}
+
+// SQLiteの登録ファイルに作る表（比較用の名前で引く）
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS accounts (
+    key TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    hash TEXT NOT NULL,
+    registered_at INTEGER NOT NULL,
+    email TEXT
+);";
+
+// 読み込み済みの登録一覧（ファイルのパスと、比較用の名前→登録内容）
+struct Store {
+    path: String,
+    db: Option<Connection>, // SQLiteの登録ファイルなら開いた接続（タブ区切りのファイルならNone）
+    accounts: HashMap<String, Account>,
+}
+
+lazy_static! {
+    static ref STORES: Mutex<HashMap<String, Store>> = Mutex::new(HashMap::new()); // ファイルのパスで引く（最初に使うときに読み込む、テナントごとに別のファイルも可）
+}
+
+// 登録できなかった理由
+#[derive(Debug)]
+pub enum RegisterError {
+    Taken,              // 既に登録されている
+    Io(std::io::Error), // ファイルに書き込めない
+}
+
+// 比較用の名前（大文字・小文字を区別しない）
+fn fold(name: &str) -> String {
+    name.to_lowercase()
+}
+
+// SQLiteに保存する登録ファイルか（拡張子で判断する）
+fn is_database(path: &str) -> bool {
+    std::path::Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| matches!(ext, "db" | "sqlite" | "sqlite3"))
+}
+
+// SQLiteの登録ファイルを開いて登録一覧を読み込む（ファイルがなければ作る）
+fn open_database(path: &str) -> rusqlite::Result<(Connection, HashMap<String, Account>)> {
+    let conn = Connection::open(path)?;
+    conn.execute_batch(SCHEMA)?;
+    let mut accounts = HashMap::new();
+    {
+        let mut stmt =
+            conn.prepare("SELECT key, name, hash, registered_at, email FROM accounts")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                Account {
+                    name: row.get(1)?,
+                    hash: row.get(2)?,
+                    registered_at: row.get(3)?,
+                    email: row.get(4)?,
+                },
+            ))
+        })?;
+        for row in rows {
+            let (key, account) = row?;
+            accounts.insert(key, account);
+        }
+    }
+    Ok((conn, accounts))
+}
+
+// 登録一覧を読み込む（ファイルがなければ空）
+fn load(path: &str) -> HashMap<String, Account> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(), // まだ誰も登録していない
+        Err(e) => {
+            crate::printdaytimeln!("登録ファイル読み込み失敗: {} ({})", path, e); // ログ
+            String::new()
+        }
+    };
+    let mut accounts = HashMap::new();
+    for line in text.lines() {
+        let mut fields = line.split('\t');
+        let (Some(name), Some(hash)) = (fields.next(), fields.next()) else {
+            continue; // 壊れた行は飛ばす
+        };
+        let registered_at = fields.next().and_then(|s| s.parse().ok()).unwrap_or(0);
+        let email = fields.next().filter(|s| !s.is_empty()).map(str::to_string); // 以前の形式（3列）の行は未設定
+        accounts.insert(
+            fold(name),
+            Account {
+                name: name.to_string(),
+                hash: hash.to_string(),
+                registered_at,
+                email,
+            },
+        );
+    }
+    accounts
+}
+
+// 変更した1件を保存する（SQLiteならその行だけ、タブ区切りのファイルなら全体を書き直す）
+fn save(store: &Store, key: &str) -> std::io::Result<()> {
+    let Some(conn) = &store.db else {
+        if is_database(&store.path) {
+            // 開けなかったデータベースをタブ区切りのファイルで上書きしない
+            return Err(std::io::Error::other("登録データベースを開けません"));
+        }
+        return save_file(store);
+    };
+    let result = match store.accounts.get(key) {
+        Some(account) => conn.execute(
+            "INSERT OR REPLACE INTO accounts (key, name, hash, registered_at, email) VALUES (?1, ?2, ?3, ?4, ?5)",
+            rusqlite::params![
+                key,
+                account.name,
+                account.hash,
+                account.registered_at,
+                account.email
+            ],
+        ),
+        None => conn.execute("DELETE FROM accounts WHERE key = ?1", [key]), // 削除した
+    };
+    result.map(|_| ()).map_err(std::io::Error::other)
+}
+
+// 登録一覧をファイルへ書き出す（一時ファイルに書いてから置き換える）
+fn save_file(store: &Store) -> std::io::Result<()> {
+    let mut accounts: Vec<&Account> = store.accounts.values().collect();
+    accounts.sort_by_key(|account| account.registered_at); // 登録順
+    let mut text = String::new();
+    for account in accounts {
+        text.push_str(&format!(
+            "{}\t{}\t{}\t{}\n",
+            account.name,
+            account.hash,
+            account.registered_at,
+            account.email.as_deref().unwrap_or("")
+        ));
+    }
+    let tmp = format!("{}.tmp", store.path);
+    std::fs::write(&tmp, text)?;
+    std::fs::rename(&tmp, &store.path)
+}
+
+// 設定のパスの登録一覧を使う（そのパスを初めて使うときに読み込む）
+fn with_store<T>(path: &str, f: impl FnOnce(&mut Store) -> T) -> T {
+    let mut stores = STORES.lock().unwrap();
+    let store = stores.entry(path.to_string()).or_insert_with(|| {
+        if is_database(path) {
+            match open_database(path) {
+                Ok((conn, accounts)) => {
+                    return Store {
+                        path: path.to_string(),
+                        db: Some(conn),
+                        accounts,
+                    };
+                }
+                Err(e) => {
+                    crate::printdaytimeln!("登録データベース読み込み失敗: {} ({})", path, e); // ログ
+                    return Store {
+                        path: path.to_string(),
+                        db: None,
+                        accounts: HashMap::new(), // 登録も照合もできない
+                    };
+                }
+            }
+        }
+        Store {
+            path: path.to_string(),
+            db: None,
+            accounts: load(path),
+        }
+    });
+    f(store)
+}
+
+// ハンドルネームが登録されているか
+pub fn is_registered(path: &str, name: &str) -> bool {
+    with_store(path, |store| store.accounts.contains_key(&fold(name)))
+}
+
+// ハンドルネームをパスワード付きで登録する
+pub async fn register(path: &str, name: &str, password: &str) -> Result<(), RegisterError> {
+    let hash = hash(password).await.map_err(RegisterError::Io)?;
+    with_store(path, |store| {
+        let key = fold(name);
+        if store.accounts.contains_key(&key) {
+            return Err(RegisterError::Taken); // ハッシュ計算中に他の接続が登録した場合も含む
+        }
+        store.accounts.insert(
+            key.clone(),
+            Account {
+                name: name.to_string(),
+                hash,
+                registered_at: chrono::Utc::now().timestamp(),
+                email: None,
+            },
+        );
+        save(store, &key).map_err(|e| {
+            store.accounts.remove(&key); // 保存できなければ登録しなかったことにする
+            RegisterError::Io(e)
+        })
+    })
+}
+
+// 登録内容を変更して保存する（未登録ならNotFound、保存できなければ変更前に戻す）
+fn update(path: &str, name: &str, f: impl FnOnce(&mut Account)) -> std::io::Result<()> {
+    with_store(path, |store| {
+        let key = fold(name);
+        let Some(account) = store.accounts.get_mut(&key) else {
+            return Err(std::io::Error::from(std::io::ErrorKind::NotFound));
+        };
+        let before = account.clone();
+        f(account);
+        save(store, &key).inspect_err(|_| {
+            store.accounts.insert(key, before); // 保存できなければ変更しなかったことにする
+        })
+    })
+}
+
+// パスワードを変更する（照合は呼び出し側で済ませておく）
+pub async fn set_password(path: &str, name: &str, password: &str) -> std::io::Result<()> {
+    let hash = hash(password).await?;
+    update(path, name, |account| account.hash = hash)
+}
+
+// 連絡先メールアドレスを設定する
+pub fn set_email(path: &str, name: &str, email: &str) -> std::io::Result<()> {
+    update(path, name, |account| {
+        account.email = Some(email.to_string())
+    })
+}
+
+// 登録を削除する（照合は呼び出し側で済ませておく）
+pub fn remove(path: &str, name: &str) -> std::io::Result<()> {
+    with_store(path, |store| {
+        let key = fold(name);
+        let Some(before) = store.accounts.remove(&key) else {
+            return Err(std::io::Error::from(std::io::ErrorKind::NotFound));
+        };
+        save(store, &key).inspect_err(|_| {
+            store.accounts.insert(key, before); // 保存できなければ削除しなかったことにする
+        })
+    })
+}
+
+// 登録内容（未登録ならNone）
+pub fn info(path: &str, name: &str) -> Option<AccountInfo> {
+    with_store(path, |store| {
+        store.accounts.get(&fold(name)).map(|account| AccountInfo {
+            name: account.name.clone(),
+            registered_at: account.registered_at,
+            email: account.email.clone(),
+        })
+    })
+}
+
+// パスワードをハッシュ化する（計算が重いのでブロッキング用スレッドで行う）
+async fn hash(password: &str) -> std::io::Result<String> {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| std::io::Error::other(e.to_string()))
+    })
+    .await
+    .unwrap_or_else(|e| Err(std::io::Error::other(e.to_string())))
+}
+
+// パスワードを照合する（未登録ならfalse）
+pub async fn verify(path: &str, name: &str, password: &str) -> bool {
+    let Some(hash) = with_store(path, |store| {
+        store
+            .accounts
+            .get(&fold(name))
+            .map(|account| account.hash.clone())
+    }) else {
+        return false;
+    };
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || {
+        PasswordHash::new(&hash).is_ok_and(|parsed| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &parsed)
+                .is_ok()
+        })
+    })
+    .await
+    .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 登録・照合・再読み込みをひととおり確かめる（pathはテストごとに別のファイル）
+    async fn register_and_verify(path: &str) {
+        let _ = std::fs::remove_file(path);
+        assert!(!is_registered(path, "alice"));
+        register(path, "Alice", "secret1").await.unwrap();
+        assert!(is_registered(path, "ALICE")); // 大文字小文字は区別しない
+        assert!(matches!(
+            register(path, "alice", "other12").await,
+            Err(RegisterError::Taken)
+        ));
+        assert!(verify(path, "alice", "secret1").await);
+        assert!(!verify(path, "alice", "secret2").await);
+        assert!(!verify(path, "bob", "secret1").await); // 未登録
+
+        // 読み込み直しても（再起動しても）登録は残っている
+        STORES.lock().unwrap().remove(path);
+        assert!(is_registered(path, "alice"));
+        assert_eq!(info(path, "alice").unwrap().name, "Alice");
+        assert!(verify(path, "alice", "secret1").await);
+        remove(path, "alice").unwrap();
+        STORES.lock().unwrap().remove(path);
+        assert!(!is_registered(path, "alice"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // タブ区切りのファイルに保存する
+    #[tokio::test]
+    async fn register_and_verify_with_file() {
+        let path = std::env::temp_dir().join(format!("rtcs-accounts-{}.txt", std::process::id()));
+        register_and_verify(path.to_str().unwrap()).await;
+    }
+
+    // 拡張子が.dbならSQLiteに保存する
+    #[tokio::test]
+    async fn register_and_verify_with_database() {
+        let path = std::env::temp_dir().join(format!("rtcs-accounts-{}.db", std::process::id()));
+        register_and_verify(path.to_str().unwrap()).await;
+    }
+}
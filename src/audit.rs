@@ -0,0 +1,39 @@
+// RustTokioChatServer - 監査ログモジュール
+// MIT License
+//
+// クレート説明:
+// - chrono, chrono-tz: 記録時刻（JST）
+// - std: ファイルへの追記
+// - lazy_static: グローバル静的変数
+//
+// audit.rs: 権限の付与・失効などの監査対象の出来事を、通常のログに加えてAuditLogのファイルへ追記する
+// 1件1行（"[日時] 内容"）で、ファイルが設定されていなければ通常のログにだけ出す
+use crate::init; // 設定管理モジュール
+use chrono_tz::Asia::Tokyo; // chrono-tz: JSTタイムゾーン
+use lazy_static::lazy_static; // lazy_static: グローバル静的変数
+use std::fs::OpenOptions; // std: 追記モードで開く
+use std::io::Write; // std: 書き込み
+use std::sync::Mutex; // std: 同時追記の排他
+
+lazy_static! {
+    static ref WRITE_LOCK: Mutex<()> = Mutex::new(()); // 複数タスクからの追記で行が混ざらないようにする
+}
+
+// 監査対象の出来事を記録する
+pub fn record(event: &str) {
+    crate::printdaytimeln!("監査: {}", event); // 通常のログ
+    let Some(path) = init::CONFIG.read().unwrap().audit_log.clone() else {
+        return; // ファイルは未設定
+    };
+    let now = chrono::Local::now().with_timezone(&Tokyo); // 現在時刻をJSTで取得
+    let line = format!("[{}] {}\n", now.format("%Y/%m/%d %H:%M:%S"), event);
+    let _guard = WRITE_LOCK.lock().unwrap();
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()));
+    if let Err(e) = result {
+        crate::printdaytimeln!("監査ログ書き込み失敗: {} ({})", path, e); // 書けなくても処理は続ける
+    }
+}
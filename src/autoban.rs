@@ -0,0 +1,163 @@
+// RustTokioChatServer - 自動締め出しモジュール
+// MIT License
+//
+// クレート説明:
+// - tokio: 締め出しの期限の時刻
+// - std: 標準ライブラリ（コレクション・同期）
+// - lazy_static: グローバル静的変数
+//
+// autoban.rs: 接続元ごとに違反（連続投稿・スパム・ハンドルネーム入力の失敗による切断、長すぎる行）を数え、
+// AUTOBAN_WINDOW内にAutoBanStrikes回に達したら一時的に接続を断る（fail2banと同じ考え方）
+// FilterRegexのbanに一致した場合は回数を待たずに締め出す
+// 締め出すたびに期間を倍にし（AutoBanSecondsから最大AUTOBAN_MAXまで）、AUTOBAN_FORGETの間違反がなければ元に戻す
+// 締め出しはメモリ上だけで、再起動すると消える
+use crate::init; // 設定管理モジュール
+use lazy_static::lazy_static; // lazy_static: グローバル静的変数
+use std::collections::HashMap; // std: 接続元ごとの違反
+use std::sync::Mutex; // std: スレッド安全なミューテックス
+use tokio::time::{Duration, Instant}; // Tokio: 締め出しの期限
+
+// この期間内の違反だけを数える
+const AUTOBAN_WINDOW: Duration = Duration::from_secs(600);
+// 締め出し期間の上限
+const AUTOBAN_MAX: Duration = Duration::from_secs(86400);
+// 最後の違反からこの期間が過ぎたら締め出し期間を最初に戻す
+const AUTOBAN_FORGET: Duration = Duration::from_secs(86400);
+
+// 接続元1つ分の違反の記録
+struct Offender {
+    strikes: Vec<Instant>,         // AUTOBAN_WINDOW内の違反の時刻
+    bans: u32,                     // これまでに締め出した回数（期間の倍数）
+    banned_until: Option<Instant>, // 締め出しの期限（締め出し中でなければNone）
+    last_reason: &'static str,     // 最後の違反の理由コード
+    last_offense: Instant,         // 最後の違反の時刻
+}
+
+lazy_static! {
+    static ref OFFENDERS: Mutex<HashMap<String, Offender>> = Mutex::new(HashMap::new()); // 接続元（IPアドレス）→違反の記録
+}
+
+// 違反を記録し、締め出しの回数に達したら締め出す（AutoBanStrikesが0なら何もしない）
+pub fn offend(owner: &str, reason: &'static str) {
+    let (strikes, base) = {
+        let config = init::CONFIG.read().unwrap();
+        (config.auto_ban_strikes, config.auto_ban_seconds)
+    };
+    if strikes == 0 {
+        return; // 自動締め出しは無効
+    }
+    let now = Instant::now();
+    let mut offenders = OFFENDERS.lock().unwrap();
+    prune(&mut offenders, now);
+    let offender = offenders.entry(owner.to_string()).or_insert(Offender {
+        strikes: Vec::new(),
+        bans: 0,
+        banned_until: None,
+        last_reason: reason,
+        last_offense: now,
+    });
+    if now.duration_since(offender.last_offense) >= AUTOBAN_FORGET {
+        offender.bans = 0; // しばらくおとなしかったので最初の期間に戻す
+    }
+    offender
+        .strikes
+        .retain(|at| now.duration_since(*at) < AUTOBAN_WINDOW);
+    offender.strikes.push(now);
+    offender.last_reason = reason;
+    offender.last_offense = now;
+    if offender.strikes.len() < strikes {
+        return;
+    }
+    impose(owner, offender, base, now);
+}
+
+// 回数を待たずにすぐ締め出す（FilterRegexのbanなど、AutoBanStrikesが0でも締め出す）
+pub fn ban(owner: &str, reason: &'static str) {
+    let base = init::CONFIG.read().unwrap().auto_ban_seconds;
+    let now = Instant::now();
+    let mut offenders = OFFENDERS.lock().unwrap();
+    prune(&mut offenders, now);
+    let offender = offenders.entry(owner.to_string()).or_insert(Offender {
+        strikes: Vec::new(),
+        bans: 0,
+        banned_until: None,
+        last_reason: reason,
+        last_offense: now,
+    });
+    if now.duration_since(offender.last_offense) >= AUTOBAN_FORGET {
+        offender.bans = 0; // しばらくおとなしかったので最初の期間に戻す
+    }
+    offender.last_reason = reason;
+    offender.last_offense = now;
+    impose(owner, offender, base, now);
+}
+
+// 締め出す（締め出すたびに期間を倍にする）
+fn impose(owner: &str, offender: &mut Offender, base: u64, now: Instant) {
+    let duration = Duration::from_secs(base)
+        .saturating_mul(2u32.saturating_pow(offender.bans))
+        .min(AUTOBAN_MAX); // 締め出すたびに倍にする
+    offender.strikes.clear();
+    offender.bans += 1;
+    offender.banned_until = Some(now + duration);
+    crate::printdaytimeln!(
+        "自動締め出し: {} {}秒 ({}回目、最後の違反 {})",
+        owner,
+        duration.as_secs(),
+        offender.bans,
+        offender.last_reason
+    ); // ログ
+}
+
+// 忘れてよい記録（締め出し中でなく、最後の違反からAUTOBAN_FORGETが過ぎたもの）を消す
+fn prune(offenders: &mut HashMap<String, Offender>, now: Instant) {
+    offenders.retain(|_, offender| {
+        now.duration_since(offender.last_offense) < AUTOBAN_FORGET
+            || offender.banned_until.is_some_and(|until| until > now)
+    });
+}
+
+// 締め出し中なら残り時間を返す
+pub fn banned_for(owner: &str) -> Option<Duration> {
+    let offenders = OFFENDERS.lock().unwrap();
+    let until = offenders.get(owner)?.banned_until?;
+    let left = until.saturating_duration_since(Instant::now());
+    (!left.is_zero()).then_some(left)
+}
+
+// 締め出しを解く（解いたらtrue）
+pub fn lift(owner: &str) -> bool {
+    let mut offenders = OFFENDERS.lock().unwrap();
+    match offenders.get_mut(owner) {
+        Some(offender) if offender.banned_until.is_some() => {
+            offender.banned_until = None;
+            offender.strikes.clear();
+            true
+        }
+        _ => false,
+    }
+}
+
+// 締め出し中の接続元の一覧（1行1件、管理用ソケットのbans用）
+pub fn report() -> String {
+    let mut offenders = OFFENDERS.lock().unwrap();
+    let now = Instant::now();
+    prune(&mut offenders, now);
+    let mut lines = offenders
+        .iter()
+        .filter_map(|(owner, offender)| {
+            let left = offender.banned_until?.saturating_duration_since(now);
+            (!left.is_zero()).then(|| {
+                format!(
+                    "{} 残り{}秒 ({}回目、最後の違反 {})\n",
+                    owner,
+                    left.as_secs(),
+                    offender.bans,
+                    offender.last_reason
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    lines.sort(); // 接続元の順
+    lines.concat()
+}
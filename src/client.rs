@@ -2,48 +2,472 @@
 // MIT License
 //
 // クレート説明:
-// - tokio: 非同期TCP通信・I/O・ブロードキャスト
+// - tokio: 非同期I/O・ブロードキャスト・mpscキュー
 // - chrono-tz: JSTタイムゾーン処理
-// - std: 標準ライブラリ（コレクション・同期）
-// - lazy_static: グローバル静的変数
+// - std: 標準ライブラリ（同期）
+// - tracing: 接続ごとのログのスパン（接続元・ハンドルネーム）
 //
 // client.rs: クライアントとの通信処理を分離
+// 通信路はTransportトレイトで抽象化しているため、TCP以外（インメモリ等）でも同じ処理を使える
+// 時刻はtokio::timeを使う（テストではtokio::time::pause()で決定的に進められる）
 // 必要なクレートをインポート
-use crate::init; // 設定管理モジュール
+use crate::accounts::{self, RegisterError}; // ハンドルネーム登録
+use crate::audit; // 監査ログ
+use crate::autoban; // 自動締め出し
+use crate::commands; // スラッシュコマンド定義
+use crate::disconnect::{DisconnectReason, ReconnectAdvice, ShutdownReason}; // 切断理由・停止理由・再接続の案内
+use crate::dispatch::{self, ChatEvent, ChatKind, RoomMessage}; // メッセージ配送モジュール
+use crate::features::{self, Feature}; // 機能の切り替え
+use crate::fingerprint; // 接続の指紋
+use crate::history; // チャット履歴保存
+use crate::init::{self, FilterAction, FilterPolicy, RepeatPolicy}; // 設定管理モジュール・禁止語・繰り返しを検出した発言の扱い
+use crate::lists; // MOTD・禁止語の補助リスト
+use crate::names::{self, ClaimError}; // ハンドルネーム管理
+use crate::outbound::{self, Outbound}; // 送信キューの書き込みタスク
+use crate::resume; // 接続再開
+use crate::roles::{self, Role}; // 役割
+use crate::rooms::{self, JoinError}; // 部屋管理
+use crate::scoring::{self, MessageInfo}; // スパム判定
+use crate::session::{self, Control, RateWindow, Session, Violation}; // セッション管理モジュール
+use crate::stats; // サーバー統計
+use crate::telnet::Telnet; // telnetのネゴシエーション
+use crate::template; // メッセージテンプレート
+use crate::transport::{QualityProbe, Transport, TransportKind}; // 通信路の抽象化
 use chrono_tz::Asia::Tokyo; // chrono-tz: JSTタイムゾーン
-use lazy_static::lazy_static;
-use std::collections::HashSet; // std: ハンドルネーム一覧用コレクション
-use std::sync::Mutex; // std: スレッド安全なミューテックス
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-    sync::broadcast,
-}; // Tokio: TCPストリーム・非同期I/O・ブロードキャスト // lazy_static: グローバル静的変数
-
-// グローバルなハンドルネーム一覧
-lazy_static! {
-    static ref HANDLE_NAMES: Mutex<HashSet<String>> = Mutex::new(HashSet::new()); // ハンドルネームを保持
-}
-
-// クライアントとの通信処理（1接続あたり1スレッド）
-pub async fn handle_client(
-    mut stream: TcpStream,                    // クライアントとのTCPストリーム
-    mut shutdown_rx: broadcast::Receiver<()>, // サーバーからのシャットダウン通知受信用
-    msg_tx: broadcast::Sender<String>,        // メッセージ送信用
-) {
-    let mut msg_rx = msg_tx.subscribe(); // メッセージ受信用Receiver
-    let mut buf = [0u8; 1024]; // 受信バッファ
-    let mut handle_name = String::new(); // ハンドルネーム
-    let peer_addr = match stream.peer_addr() {
-        // クライアントアドレス取得
-        Ok(addr) => addr.to_string(),    // アドレス取得成功
-        Err(_) => "unknown".to_string(), // 失敗時はunknown
+use std::collections::VecDeque; // std: 繰り返しの検出用の直近の発言
+use std::sync::atomic::Ordering; // std: アトミック操作の順序指定
+use std::sync::Arc; // std: スレッド安全な参照カウント
+use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadHalf}; // Tokio: 非同期I/O・通信路の読み込み側
+use tokio::sync::{broadcast, OwnedSemaphorePermit}; // Tokio: ブロードキャストチャネル・接続準備枠
+use tokio::time::{Duration, Instant}; // Tokio: 帯域計測用の時刻
+use tracing::Instrument; // tracing: 接続ごとのスパン
+
+// 帯域計測ウィンドウの長さ（1秒）
+const BANDWIDTH_WINDOW: Duration = Duration::from_secs(1);
+// 切断時に送信キューの残りと切断理由を書き出す時間の上限
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(3);
+// TCP接続の状態を取得する間隔
+const QUALITY_SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+// スパム判定を待つ上限（超えたら判定なしとして通す）
+const SCORE_TIMEOUT: Duration = Duration::from_secs(2);
+// この期間内にスパム判定で破棄された回数がSPAM_STRIKESに達したら切断する
+const SPAM_WINDOW: Duration = Duration::from_secs(600);
+const SPAM_STRIKES: usize = 3;
+// /identifyでパスワードを間違えられる回数（超えたらハンドルネームを未定義に戻す）
+const IDENTIFY_ATTEMPTS: usize = 3;
+// /drop <パスワード> の後、/drop confirm で削除を確定できる時間
+const DROP_CONFIRM_WINDOW: Duration = Duration::from_secs(60);
+// 1接続で/ignoreできるハンドルネームの数
+const MAX_IGNORED: usize = 100;
+// /muteで指定できる最大の分数（1日）
+const MAX_MUTE_MINUTES: u64 = 1440;
+// /slowで指定できる最大の間隔（秒）
+const MAX_SLOW_SECONDS: u64 = 3600;
+// 繰り返しの検出のために覚えておく発言の数
+const MAX_RECENT_LINES: usize = 50;
+
+// 発言数を制限するトークンバケット（1秒あたりrate個補充、最大burst個まで溜まる）
+struct TokenBucket {
+    tokens: f64,          // 残りのトークン
+    last_refill: Instant, // 最後に補充した時刻
+    strikes: usize,       // 警告後に破棄した発言数（満タンに戻ったら0）
+    warned: bool,         // 警告済みか（満タンに戻ったら解除）
+}
+
+impl TokenBucket {
+    fn new() -> Self {
+        TokenBucket {
+            tokens: f64::MAX, // 最初の補充で容量に切り詰める
+            last_refill: Instant::now(),
+            strikes: 0,
+            warned: false,
+        }
+    }
+
+    // 1発言分のトークンを取る（rate=0は無制限）
+    fn take(&mut self, rate: usize, burst: usize) -> bool {
+        if rate == 0 {
+            return true; // 無制限
+        }
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed * rate as f64).min(burst as f64); // 経過時間分を補充
+        if self.tokens >= burst as f64 {
+            self.strikes = 0; // 落ち着いたら警告をリセット
+            self.warned = false;
+        }
+        if self.tokens < 1.0 {
+            return false; // 空
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    // 今すぐ送れる発言数（取らずに補充後の値だけ計算する、rate=0は無制限でNone）
+    fn remaining(&self, rate: usize, burst: usize) -> Option<usize> {
+        if rate == 0 {
+            return None; // 無制限
+        }
+        let elapsed = self.last_refill.elapsed().as_secs_f64();
+        Some(((self.tokens + elapsed * rate as f64).min(burst as f64)) as usize)
+    }
+}
+
+// 接続ごとの送受信バイト数と帯域制限の状態
+// 累計バイト数は/whoisから参照できるようSession側に記録する
+struct Traffic {
+    window_start: Instant, // 現在の計測ウィンドウの開始時刻
+    window_bytes: usize,   // 現在のウィンドウ内で受理した受信バイト数
+    window_lines: usize,   // 現在のウィンドウ内で受理した行数
+    notified: bool,        // 現在のウィンドウで帯域超過を通知済みか
+    burst_notified: bool,  // 現在のウィンドウで行数超過を通知済みか
+    bytes_warned: bool,    // 現在のウィンドウで受信量の上限接近を警告済みか
+    lines_warned: bool,    // 現在のウィンドウで行数の上限接近を警告済みか
+}
+
+impl Traffic {
+    fn new() -> Self {
+        // 計測開始
+        Traffic {
+            window_start: Instant::now(),
+            window_bytes: 0,
+            window_lines: 0,
+            notified: false,
+            burst_notified: false,
+            bytes_warned: false,
+            lines_warned: false,
+        }
+    }
+
+    // ウィンドウが経過していればカウンタをリセット
+    fn roll_window(&mut self) {
+        if self.window_start.elapsed() >= BANDWIDTH_WINDOW {
+            self.window_start = Instant::now(); // 新しいウィンドウ開始
+            self.window_bytes = 0; // ウィンドウ内カウンタをリセット
+            self.window_lines = 0;
+            self.notified = false; // 通知フラグもリセット
+            self.burst_notified = false;
+            self.bytes_warned = false;
+            self.lines_warned = false;
+        }
+    }
+
+    // 現在の計測ウィンドウの状態
+    fn window(&self) -> RateWindow {
+        RateWindow {
+            start: self.window_start,
+            bytes: self.window_bytes,
+            lines: self.window_lines,
+        }
+    }
+
+    // 1行を受理できるか判定する（limit=0は無制限）
+    fn admit_line(&mut self, limit: usize) -> bool {
+        if limit != 0 && self.window_lines >= limit {
+            return false; // このウィンドウでは行数の上限に達している
+        }
+        self.window_lines += 1; // ウィンドウ内行数を加算
+        true
+    }
+
+    // 受信したnバイトのうち受理できるバイト数を返す（limit=0は無制限）
+    fn admit(&mut self, n: usize, limit: usize, policy: init::BandwidthPolicy) -> usize {
+        self.roll_window(); // ウィンドウ更新
+        let accepted = if limit == 0 || policy == init::BandwidthPolicy::Queue {
+            n // 無制限、またはqueueポリシーでは全量受理（読み込み側で待たせる）
+        } else {
+            n.min(limit.saturating_sub(self.window_bytes)) // dropポリシーでは残り枠まで
+        };
+        self.window_bytes += accepted; // ウィンドウ内受信量を加算
+        accepted
+    }
+
+    // queueポリシーで読み込みを一時停止すべきならウィンドウ終了時刻を返す
+    fn throttled_until(&self, limit: usize, policy: init::BandwidthPolicy) -> Option<Instant> {
+        if limit != 0 && policy == init::BandwidthPolicy::Queue && self.window_bytes >= limit {
+            Some(self.window_start + BANDWIDTH_WINDOW) // 次のウィンドウまで読み込み停止
+        } else {
+            None
+        }
+    }
+}
+
+// /traceのログに出す受信内容（接続パスワードの入力待ちの行と、パスワード・トークンを引数に取るコマンドの引数は伏せる）
+// pendingは前回までに届いた改行前の部分（行の途中で分かれて届いたコマンドも判定する）
+fn redact_trace(pending: &[u8], data: &[u8], password_phase: bool) -> String {
+    let mut line = String::from_utf8_lossy(pending).into_owned(); // 判定中の行（届いた分まで）
+    let mut shown = String::new();
+    for segment in String::from_utf8_lossy(data).split_inclusive(['\n', '\r']) {
+        let body = segment.trim_end_matches(['\n', '\r']);
+        let keep = if password_phase {
+            0 // 接続パスワードの入力待ち
+        } else {
+            let before = line.len();
+            line.push_str(body);
+            secret_offset(&line).map_or(body.len(), |offset| {
+                offset.saturating_sub(before).min(body.len())
+            })
+        };
+        shown.push_str(&body[..keep]);
+        if keep < body.len() {
+            shown.push_str("***"); // 伏せた部分
+        }
+        shown.push_str(&segment[body.len()..]);
+        if body.len() < segment.len() {
+            line.clear(); // 改行で次の行へ
+        }
+    }
+    shown
+}
+
+// 行のうち伏せる引数の始まりの位置（伏せるコマンドでなければNone）
+fn secret_offset(line: &str) -> Option<usize> {
+    let start = line.len() - line.trim_start().len();
+    let command = line[start..].strip_prefix('/')?;
+    let name_end = command.find(' ')?; // コマンド名の後の空白がまだ届いていなければ引数もない
+    commands::has_secret_args(&command[..name_end]).then_some(start + 1 + name_end + 1)
+}
+
+// 名前の変更（/nick・/rename）を同じテナントに反映する（無視リストの付け替えと、部屋を問わない通知、本人には送らない）
+// sessionは名前を変えた本人のセッション（名前は変更済み）
+fn publish_rename(msg_tx: &broadcast::Sender<RoomMessage>, session: &Session, old: &str) {
+    let new = session.handle_name();
+    session::rename_ignored(&session.tenant, old, &new); // 名前を変えても無視され続ける
+    let _ = msg_tx.send(RoomMessage {
+        tenant: session.tenant.clone(),
+        room: session.room(),
+        event: ChatEvent::Nick {
+            id: session.id,
+            old: old.to_string(),
+            new,
+        },
+    });
+}
+
+// /resendで再送できないIDの範囲（afterより後、再送できる最初のIDfirstより前、欠けがなければNone）
+fn unsaved_range(after: u64, first: u64) -> Option<(u64, u64)> {
+    (first > after.saturating_add(1)).then(|| (after + 1, first - 1))
+}
+
+// 繰り返しの比較用に発言を正規化する（大文字小文字・空白・記号・数字の違いは無視し、文字がなければそのまま）
+fn repeat_key(msg: &str) -> String {
+    let key: String = msg
+        .chars()
+        .filter(|c| c.is_alphabetic())
+        .flat_map(char::to_lowercase)
+        .collect();
+    if key.is_empty() {
+        msg.trim().to_string()
+    } else {
+        key
+    }
+}
+
+// 発言の禁止語とFilterRegex（mask）に一致した部分を伏字（*）にする
+fn mask_message(msg: &str, config: &init::Config) -> String {
+    let mut text = msg.to_string();
+    for rule in config
+        .filter_rules
+        .iter()
+        .filter(|rule| rule.action == FilterAction::Mask)
+    {
+        text = rule
+            .pattern
+            .replace_all(&text, |caps: &regex::Captures| {
+                "*".repeat(caps[0].chars().count())
+            })
+            .into_owned(); // 一致部分を同じ文字数の伏字に
+    }
+    lists::mask_filtered(&text)
+}
+
+// 受信制限の現在の使用量と直近の違反履歴（オペレーター向け/whois用）
+fn rate_report(target: &Session, config: &init::Config) -> String {
+    let rate = target.rate();
+    let (bytes, lines) = if rate.start.elapsed() < BANDWIDTH_WINDOW {
+        (rate.bytes, rate.lines) // 計測中のウィンドウ
+    } else {
+        (0, 0) // ウィンドウ経過後は入力がないので0
+    };
+    let limit = |n: usize| {
+        if n == 0 {
+            "無制限".to_string()
+        } else {
+            n.to_string()
+        }
+    };
+    let violations = target
+        .violations()
+        .iter()
+        .rev()
+        .map(|(at, kind)| format!("{}({}秒前)", kind.label(), at.elapsed().as_secs()))
+        .collect::<Vec<_>>(); // 新しい順
+    format!(
+        "SYSTEM>   受信制限: 帯域 {}/{}バイト毎秒 / 行数 {}/{}行毎秒 / 警告{}回\nSYSTEM>   違反履歴: {}\n",
+        bytes,
+        limit(config.max_bytes_per_second),
+        lines,
+        limit(config.max_burst_lines),
+        target.warnings.load(Ordering::Relaxed),
+        if violations.is_empty() {
+            "なし".to_string()
+        } else {
+            violations.join(", ")
+        }
+    )
+}
+
+// 設定ファイルを検査し、現在の設定との差分と影響を報告する（適用はしない）
+// 差分はテナントごとの上書きをしていないグローバル設定と比べ、切断される接続の名前は実行したテナントの分だけ見せる
+fn dry_run_report(tenant: &str) -> String {
+    let current = init::CONFIG.read().unwrap().clone(); // 設定ファイルから読んだままの現在の設定
+    let (candidate, warnings) = match init::check_config() {
+        Ok(checked) => checked,
+        Err(e) => return format!("SYSTEM> 設定ファイルを読み込めません: {}\n", e),
     };
-    let mut line_buf = Vec::new(); // 受信データを一時的に溜めるバッファ
-    let mut phase = 0; // 0:ハンドルネーム未定義, 1:通常エコー
-    let config = init::CONFIG.read().unwrap().clone(); // 設定値を取得
-    let welcome_msg = format!(
-        "\
+    let mut report = "SYSTEM> 設定の検査結果（まだ適用されていません）\n".to_string();
+    for warning in &warnings {
+        report.push_str(&format!("SYSTEM>   警告: {}\n", warning)); // 無視される行
+    }
+    let diff = init::config_diff(&current, &candidate);
+    if diff.is_empty() {
+        report.push_str("SYSTEM>   変更なし\n");
+    }
+    for change in &diff {
+        report.push_str(&format!("SYSTEM>   変更: {}\n", change));
+    }
+    if candidate.addresses != current.addresses {
+        // 待受アドレスが変わると再バインドのため全員切断される
+        let sessions = session::snapshot();
+        let names = sessions
+            .iter()
+            .filter(|session| session.tenant == tenant) // 他のテナントは件数だけ
+            .map(|session| {
+                let name = session.handle_name();
+                if name.is_empty() {
+                    format!("(未定義 {})", session.peer_addr)
+                } else {
+                    name
+                }
+            })
+            .collect::<Vec<_>>();
+        let when = if candidate.rebind_grace_seconds > 0 {
+            format!("{}秒後に", candidate.rebind_grace_seconds) // 猶予の後に切断
+        } else {
+            String::new()
+        };
+        let others = sessions.len() - names.len(); // 他のテナントの接続
+        let others = if others > 0 {
+            format!("（他のテナントの{}件を含む）", others)
+        } else {
+            String::new()
+        };
+        report.push_str(&format!(
+            "SYSTEM>   待受アドレスが変わるため、接続中の{}件{}が{}切断されます: {}\n",
+            sessions.len(),
+            others,
+            when,
+            names.join(", ")
+        ));
+    }
+    report
+}
+
+// /who・/whoisに出す不在の表示（不在でなければ空、sepは前の項目との区切り）
+fn away_label(target: &Session, sep: &str) -> String {
+    match target.away() {
+        Some(reason) if reason.is_empty() => format!("{}不在", sep),
+        Some(reason) => format!("{}不在: {}", sep, reason),
+        None => String::new(),
+    }
+}
+
+// 機械向けの通信路に接続直後に送るhello（サーバーの機能と制限値、1行のJSON）
+fn hello(config: &init::Config) -> String {
+    let mut capabilities = vec!["rooms", "pins", "topic", "private-message", "ack", "help"];
+    if config.history_lines > 0 {
+        capabilities.push("replay"); // 入室時に直近の会話を再送する
+    }
+    if config.account_file.is_some() {
+        capabilities.push("accounts"); // /register・/identify
+    }
+    if config.ping_interval_seconds > 0 {
+        capabilities.push("keepalive"); // /keepalive on・PING・/pong
+    }
+    if config.password.is_some() {
+        capabilities.push("password"); // 最初の行で接続パスワードを求める
+    }
+    if config.resume_seconds > 0 {
+        capabilities.push("resume"); // 再接続用トークン・/resume
+    }
+    format!(
+        "{{\"type\":\"hello\",\"server\":\"RustTokioChatServer\",\"version\":\"{}\",\"capabilities\":[{}],\"limits\":{{\"max_handle_name\":{},\"max_message_length\":{},\"max_bytes_per_second\":{},\"max_burst_lines\":{}}}}}\n",
+        env!("CARGO_PKG_VERSION"),
+        capabilities
+            .iter()
+            .map(|c| format!("\"{}\"", c))
+            .collect::<Vec<_>>()
+            .join(","),
+        config.max_handle_name,
+        config.max_message_length,
+        config.max_bytes_per_second,
+        config.max_burst_lines
+    )
+}
+
+// 接続1本分のクライアント状態
+struct Client<S: Transport> {
+    reader: ReadHalf<S>,                    // クライアントとの通信路の読み込み側
+    outbound: Outbound,                     // 送信キューを書き出す専用タスク
+    quality_probe: Option<QualityProbe>,    // TCP接続の状態の取得方法
+    peer_addr: String,                      // クライアントアドレス
+    session: Arc<Session>,                  // セッション情報（送信キュー等）
+    msg_tx: broadcast::Sender<RoomMessage>, // メッセージバス送信用
+    shutdown_tx: broadcast::Sender<ShutdownReason>, // 停止・再読込通知の送信用（/maintenance）
+    handle_name: String,                    // ハンドルネーム
+    phase: u8,                              // 0:名前未定義, 1:通常エコー, 2:パスワード待ち
+    traffic: Traffic,                       // 送受信量の計測
+    bucket: TokenBucket,                    // 発言数の制限
+    line_buf: Vec<u8>,                      // 受信データを一時的に溜めるバッファ
+    telnet: Telnet,                         // telnetのネゴシエーションの状態
+    family: Option<&'static stats::FamilyStats>, // アドレスファミリー別統計（IP以外の通信路はNone）
+    oper_since: Option<Instant>,            // オペレーター認証した時刻（有効期間の判定用）
+    quality_sampled_at: Option<Instant>,    // TCP接続の状態を最後に取得した時刻
+    identify_deadline: Option<Instant>, // 登録済みのハンドルネームを認証しないまま使える期限（認証待ちでなければNone）
+    identify_failures: usize,           // /identifyでパスワードを間違えた回数
+    name_failures: usize,               // ハンドルネームの入力に失敗した回数
+    password_failures: usize,           // 接続パスワードを間違えた回数
+    declared: Option<String>,           // 通信路が申告した機能（最初の受信で指紋にしたらNone）
+    drop_confirm_until: Option<Instant>, // /drop confirm で登録を削除できる期限（確認待ちでなければNone）
+    keepalive: bool,                     // /keepalive on で接続確認を求めたか
+    last_received: Instant,              // 最後に何か受信した時刻（接続確認の起点）
+    ping_sent: Option<Instant>,          // 応答待ちのPINGを送った時刻（応答待ちでなければNone）
+    ping_token: u64,                     // 最後に送ったPINGの番号
+    registered_here: bool, // この接続で/registerした名前か（/identifyで認証し直すまでRole行の役割を付けない）
+    oversize_offended: bool, // 長すぎる行を自動締め出しの違反として数えたか（1接続1回）
+    last_posted: Option<Instant>, // 最後に部屋で発言した時刻（スローモードの判定用）
+    recent_lines: VecDeque<(Instant, String)>, // RepeatWindowSeconds以内の発言（比較用に正規化済み、繰り返しの検出用）
+    resume_token: Option<String>,              // 再接続用トークン（ResumeSecondsが0ならNone）
+}
+
+impl<S: Transport> Client<S> {
+    // クライアントへの送信を送信キューに積む（書き出しと送信バイト数の記録は書き込みタスクが行う）
+    async fn send(&mut self, text: &str) -> std::io::Result<()> {
+        self.session
+            .queue
+            .send(text.to_string())
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe)) // 書き込みタスクが終了済み
+    }
+
+    // 接続直後のウェルカムメッセージ・MOTD・接続中一覧を送信
+    async fn greet(&mut self, config: &init::Config) -> std::io::Result<()> {
+        if self.session.transport.is_machine() {
+            self.send(&hello(config)).await?; // 機械向けの通信路には飾りのない構造化メッセージだけ送る
+            return self.offer_resume(config).await;
+        }
+        let welcome_msg = format!(
+            "\
 ##############################################\n\
 #### Welcome to Rust Simple Chat Server\n\
 #### You must be set HandleName, And Enjoy!\n\
@@ -53,128 +477,3322 @@ pub async fn handle_client(
 #### CTRL-D : Disconnect\n\
 ##############################################\n\
 ",
-        config.max_handle_name, config.max_message_length
-    ); // ウェルカムメッセージ生成
-    if stream.write_all(welcome_msg.as_bytes()).await.is_err() {
-        // クライアントに送信し失敗したら
-        return; // 切断
-    }
-    // ここで現在の他クライアントのハンドルネーム一覧を送信
-    let list_msg = {
-        let names = HANDLE_NAMES.lock().unwrap(); // ハンドルネーム一覧をロック
-        if names.is_empty() {
-            "現在他のクライアントはいません\n".to_string() // 他に誰もいない場合
-        } else {
-            let list = names.iter().cloned().collect::<Vec<_>>().join(", "); // 一覧をカンマ区切りで連結
-            format!("現在接続中の他クライアント: {}\n", list) // 一覧メッセージ生成
-        }
-    }; // MutexGuardはここでドロップされる
-    let _ = stream.write_all(list_msg.as_bytes()).await; // 一覧をクライアントに送信
-    loop {
-        // メインループ
-        if phase == 0 && handle_name.is_empty() {
-            // ハンドルネーム未定義なら入力促し
-            let prompt = "SYSTEM> ハンドルネームを入力してください\n"; // 入力促しメッセージ
-            if stream.write_all(prompt.as_bytes()).await.is_err() {
-                // 送信失敗時は切断
-                return;
+            config.max_handle_name, config.max_message_length
+        ); // ウェルカムメッセージ生成
+        self.send(&welcome_msg).await?; // 送信失敗時は切断
+        let motd = lists::motd(&self.session.tenant); // MOTDを取得
+        if !motd.is_empty() {
+            self.send(&motd).await?; // MOTDを送信
+        }
+        // ここで現在の他クライアントのハンドルネーム一覧を送信
+        let list_msg = {
+            let names = names::list(&self.session.tenant); // ハンドルネーム一覧を取得
+            if names.is_empty() {
+                "現在他のクライアントはいません\n".to_string() // 他に誰もいない場合
+            } else {
+                let list = names.join(", "); // 一覧をカンマ区切りで連結
+                format!("現在接続中の他クライアント: {}\n", list) // 一覧メッセージ生成
             }
+        };
+        self.send(&list_msg).await?;
+        self.offer_resume(config).await
+    }
+
+    // 再接続用トークンを発行して知らせる（ResumeSecondsが0なら何もしない）
+    async fn offer_resume(&mut self, config: &init::Config) -> std::io::Result<()> {
+        if config.resume_seconds == 0 {
+            return Ok(());
         }
-        let config = init::CONFIG.read().unwrap().clone(); // 設定を都度取得
-        tokio::select! {
-                    // クライアントからの入力
-                    Ok(n) = stream.read(&mut buf) => {
-                        if n == 0 {
-                            crate::printdaytimeln!("切断: {} {}", peer_addr, handle_name); // 切断ログ
-                            // 切断時にハンドルネームを一覧から削除
-                            if !handle_name.is_empty() {
-                                HANDLE_NAMES.lock().unwrap().remove(&handle_name); // 削除
-                            }
-                            break;
-                        }
-                        line_buf.extend_from_slice(&buf[..n]); // バッファに追記
-                        while line_buf.len() < config.max_message_length {
-                            if line_buf.contains(&0x03) || line_buf.contains(&0x04) { // CTRL-C/CTRL-D検出
-                                crate::printdaytimeln!("切断: {} {} (CTRL-C/CTRL-D検出)", peer_addr, handle_name); // ログ
-                                if !handle_name.is_empty() {
-                                    HANDLE_NAMES.lock().unwrap().remove(&handle_name); // 削除
-                                }
-                                return;
-                            }
-                            if let Some(pos) = line_buf.iter().position(|&b| b == b'\n' || b == b'\r') { // 改行検出
-                                let line = line_buf.drain(..=pos).collect::<Vec<u8>>(); // 1行分取り出し
-                                let msg = String::from_utf8_lossy(&line).trim().to_string(); // UTF-8変換
-                                if line.contains(&0x03) || line.contains(&0x04) { // CTRL-C/CTRL-D検出
-                                    crate::printdaytimeln!("切断: {} {}", peer_addr, handle_name); // ログ
-                                    if !handle_name.is_empty() {
-                                        HANDLE_NAMES.lock().unwrap().remove(&handle_name); // 削除
-                                    }
-                                    return;
-                                }
-                                if phase == 0 {
-                                    if msg.is_empty() {
-                                        continue; // 空行は無視
-                                    }
-                                    if !msg.chars().all(|c| !c.is_control() && !c.is_whitespace()) {
-                                        let _ = stream.write_all("SYSTEM> ハンドルネームに使えない文字が含まれています\n".as_bytes()).await; // バリデーション
-                                        continue;
-                                    }
-                                    if msg.as_bytes().len() > config.max_handle_name {
-                                        let _ = stream.write_all("SYSTEM> ハンドルネームが長すぎます\n".as_bytes()).await; // 長さ超過
-                                        crate::printdaytimeln!("切断: {} ハンドルネーム長オーバー", peer_addr); // ログ
-                                        return;
-                                    }
-                                    handle_name = msg.clone(); // ハンドルネーム確定
-                                    // ハンドルネームを一覧に追加
-                                    HANDLE_NAMES.lock().unwrap().insert(handle_name.clone());
-                                    phase = 1; // 通常モードへ
-                                    crate::printdaytimeln!("確定: {} {}", peer_addr, handle_name); // ログ
-                                    let welcome = format!("SYSTEM> {}さん、ようこそ\n", handle_name); // ウェルカム
-                                    let _ = stream.write_all(welcome.as_bytes()).await;
-                                    continue;
-                                }
-                                if phase == 1 && line.contains(&0x19) { // CTRL-Yで再定義
-                                    let old = handle_name.clone();
-                                    // 再定義時は古いハンドルネームを削除
-                                    HANDLE_NAMES.lock().unwrap().remove(&old);
-                                    handle_name.clear();
-                                    phase = 0;
-                                    crate::printdaytimeln!("再定義: {} {} -> (未定義)", peer_addr, old); // ログ
-                                    continue;
-                                }
-                                if !msg.is_empty() {
-                                    let now = chrono::Local::now().with_timezone(&Tokyo); // 現在時刻
-                                    let time_str = now.format("%Y/%m/%d %H:%M").to_string(); // タイムスタンプ
-                                    let echo = format!("{}> {} ({})\n", handle_name, msg, time_str); // メッセージ整形
-                                    // 自分のメッセージを全体にブロードキャスト
-                                    let _ = msg_tx.send(format!("{}", echo));
-                                }
-                            } else {
-                                break; // 改行がなければ抜ける
-                            }
-                        }
-                        if line_buf.len() >= config.max_message_length {
-                            let _ = stream.write_all("SYSTEM> 一行が長すぎます\n".as_bytes()).await; // 長さ超過
-                            line_buf.clear(); // バッファクリア
-                        }
-                    }
-                    // 他クライアントからのメッセージを受信して自分に送信
-                    Ok(broadcast_msg) = msg_rx.recv() => {
-                        // 自分の送信分はスキップ
-        //                if !broadcast_msg.starts_with(&handle_name) {
-        //                    let _ = stream.write_all(broadcast_msg.as_bytes()).await;
-        //                }
-                        // フィルタせず全てのメッセージを自分にも送信
-                        let _ = stream.write_all(broadcast_msg.as_bytes()).await;            }
-                    // サーバー再起動通知受信時
-                    _ = shutdown_rx.recv() => {
-                        let _ = stream.write_all("サーバーを再起動するので切断します\n".as_bytes()).await; // 通知
-                        // シャットダウン時もハンドルネームを削除
-                        if !handle_name.is_empty() {
-                            HANDLE_NAMES.lock().unwrap().remove(&handle_name); // 削除
-                        }
-                        break; // ループ終了
+        let token = resume::issue();
+        let text = if self.session.transport.is_machine() {
+            format!(
+                "{{\"type\":\"resume-token\",\"token\":\"{}\",\"valid_for\":{}}}\n",
+                token, config.resume_seconds
+            ) // 機械向けには型付きのイベント
+        } else {
+            format!(
+                "SYSTEM> 再接続用トークン: {}（切断されても{}秒以内なら、接続し直して /resume {} で戻れます）\n",
+                token, config.resume_seconds, token
+            )
+        };
+        self.resume_token = Some(token);
+        self.send(&text).await
+    }
+
+    // 受信したnバイトを処理する（切断すべき場合は理由を返す）
+    async fn on_input(&mut self, data: &[u8], config: &init::Config) -> Option<DisconnectReason> {
+        if self.session.is_tracing() {
+            crate::printdaytimeln!(
+                "TRACE #{} < {:?}",
+                self.session.id,
+                redact_trace(&self.line_buf, data, self.phase == 2)
+            ); // 受信したままの内容（制御文字はエスケープ、パスワードは伏せる）
+        }
+        self.last_received = Instant::now(); // 何か届けば生きている（PINGの応答を待たない）
+        self.ping_sent = None;
+        if let Some(declared) = self.declared.take() {
+            // 最初の受信から接続の指紋を作る
+            let fingerprint =
+                fingerprint::compute(data, self.session.connected_at.elapsed(), &declared);
+            crate::printdaytimeln!(
+                "指紋: {} {} ({})",
+                self.peer_addr,
+                fingerprint.id,
+                fingerprint.detail
+            ); // ログ
+            self.session.set_fingerprint(fingerprint);
+        }
+        let n = data.len();
+        let accepted = self
+            .traffic
+            .admit(n, config.max_bytes_per_second, config.bandwidth_policy); // 帯域制限を適用
+        self.session.set_rate(self.traffic.window()); // /whois用に計測状態を反映
+        self.session
+            .bytes_in
+            .fetch_add(accepted as u64, Ordering::Relaxed); // 累計受信量を加算
+        if let Some(family) = self.family {
+            stats::add(&family.bytes_in, accepted as u64); // ファミリー別の受信量
+        }
+        self.session
+            .bytes_dropped
+            .fetch_add((n - accepted) as u64, Ordering::Relaxed); // 破棄量を加算
+        if accepted < n && !self.traffic.notified {
+            // 帯域超過分を破棄した場合はウィンドウごとに一度だけ通知
+            self.traffic.notified = true;
+            self.session.record_violation(Violation::Bandwidth); // 違反履歴に記録
+            crate::printdaytimeln!(
+                "帯域超過: {} {} ({}バイト破棄)",
+                self.peer_addr,
+                self.handle_name,
+                n - accepted
+            ); // ログ
+            let _ = self
+                .send("SYSTEM> 送信量が多すぎるため一部を破棄しました\n")
+                .await;
+        }
+        if let Some(soft) = config.soft_limit(config.max_bytes_per_second) {
+            if accepted == n && self.traffic.window_bytes >= soft && !self.traffic.bytes_warned {
+                // 上限に達する前にウィンドウごとに一度だけ警告
+                self.traffic.bytes_warned = true;
+                let (used, limit) = (self.traffic.window_bytes, config.max_bytes_per_second);
+                self.warn_near_limit("受信量", used, limit, "バイト/秒")
+                    .await;
+            }
+        }
+        let (text, reply) = self.telnet.filter(&data[..accepted]); // IACシーケンスを取り除く
+        self.outbound.send_raw(reply); // ネゴシエーションへの応答
+        if let Some(window) = self.telnet.window() {
+            self.session.set_window(window); // /whois用に端末の大きさを反映
+        }
+        self.line_buf.extend_from_slice(&text); // バッファに追記
+        while self.line_buf.len() < self.max_message_length(config) {
+            if self.line_buf.contains(&0x03) || self.line_buf.contains(&0x04) {
+                // CTRL-C/CTRL-D検出
+                return Some(DisconnectReason::Quit);
+            }
+            let Some(pos) = self.line_buf.iter().position(|&b| b == b'\n' || b == b'\r') else {
+                break; // 改行がなければ抜ける
+            };
+            let line = self.line_buf.drain(..=pos).collect::<Vec<u8>>(); // 1行分取り出し
+            let chars = String::from_utf8_lossy(&line).trim().chars().count(); // 文字数
+            if chars == 0 {
+                continue; // 改行のみの行は数えない
+            }
+            if !self.traffic.admit_line(config.max_burst_lines) {
+                // 短時間に送られた行数の超過はウィンドウごとに一度だけ通知
+                if !std::mem::replace(&mut self.traffic.burst_notified, true) {
+                    self.session.record_violation(Violation::BurstLines); // 違反履歴に記録
+                    crate::printdaytimeln!("行数超過: {} {}", self.peer_addr, self.handle_name); // ログ
+                    let _ = self
+                        .send("SYSTEM> 短時間に送信された行が多すぎるため一部を破棄しました\n")
+                        .await;
+                }
+                continue;
+            }
+            self.session.set_rate(self.traffic.window()); // /whois用に計測状態を反映
+            if let Some(soft) = config.soft_limit(config.max_burst_lines) {
+                if self.traffic.window_lines >= soft && !self.traffic.lines_warned {
+                    // 上限に達する前にウィンドウごとに一度だけ警告
+                    self.traffic.lines_warned = true;
+                    let (used, limit) = (self.traffic.window_lines, config.max_burst_lines);
+                    self.warn_near_limit("行数", used, limit, "行/秒").await;
+                }
+            }
+            if config.max_message_chars != 0 && chars > config.max_message_chars {
+                self.session.record_violation(Violation::MessageChars); // 違反履歴に記録
+                let notice = format!(
+                    "SYSTEM> 一行の文字数が多すぎます（最大{}文字）\n",
+                    config.max_message_chars
+                ); // 文字数超過
+                let _ = self.send(&notice).await;
+                continue;
+            }
+            if !self
+                .bucket
+                .take(config.max_messages_per_second, config.message_burst)
+            {
+                // 連続投稿は破棄し、最初は警告、警告後もさらにバースト分続けたら切断
+                self.session.record_violation(Violation::Flood); // 違反履歴に記録
+                if !std::mem::replace(&mut self.bucket.warned, true) {
+                    crate::printdaytimeln!("連続投稿警告: {} {}", self.peer_addr, self.handle_name); // ログ
+                    let _ = self
+                        .send("SYSTEM> 連続投稿が多すぎます。続けると切断されます\n")
+                        .await;
+                } else {
+                    self.bucket.strikes += 1;
+                    if self.bucket.strikes >= config.message_burst {
+                        crate::printdaytimeln!(
+                            "連続投稿で切断: {} {}",
+                            self.peer_addr,
+                            self.handle_name
+                        ); // ログ
+                        return Some(DisconnectReason::Flood);
                     }
                 }
+                continue;
+            }
+            if config
+                .soft_limit(config.max_message_chars)
+                .is_some_and(|soft| chars >= soft)
+            {
+                self.warn_near_limit("文字数", chars, config.max_message_chars, "文字")
+                    .await;
+            } else if config
+                .soft_limit(self.max_message_length(config))
+                .is_some_and(|soft| line.len() >= soft)
+            {
+                let limit = self.max_message_length(config);
+                self.warn_near_limit("バイト数", line.len(), limit, "バイト")
+                    .await;
+            }
+            if let Some(reason) = self.on_line(&line, config).await {
+                return Some(reason); // 行処理の結果で切断
+            }
+        }
+        if self.line_buf.len() >= self.max_message_length(config) {
+            self.session.record_violation(Violation::MessageBytes); // 違反履歴に記録
+            if !std::mem::replace(&mut self.oversize_offended, true) {
+                autoban::offend(&self.session.peer_ip(), "oversize"); // 貼り付けの失敗で締め出さないよう1接続1回だけ数える
+            }
+            let notice = format!(
+                "SYSTEM> 一行のバイト数が多すぎます（最大{}バイト）\n",
+                self.max_message_length(config)
+            ); // バイト数超過
+            let _ = self.send(&notice).await;
+            self.line_buf.clear(); // バッファクリア
+        }
+        None
+    }
+
+    // 今いる部屋で許される一行の最大バイト数（部屋に入る前はMaxMessageLength）
+    fn max_message_length(&self, config: &init::Config) -> usize {
+        config.max_message_length_for(&self.session.room())
+    }
+
+    // 入った部屋が独自の一行の最大バイト数を持っていれば知らせる
+    async fn announce_room_limits(&mut self, config: &init::Config) {
+        let limit = self.max_message_length(config);
+        if limit != config.max_message_length {
+            let notice = format!("SYSTEM> この部屋では一行{}バイトまで送信できます\n", limit);
+            let _ = self.send(&notice).await;
+        }
+    }
+
+    // サーバー側からの切断（送信キューの残り・切断理由・再接続の案内を書き出し、書き込みの完了を待ってから送信側を閉じてFINを送る）
+    async fn close(&mut self, reason: DisconnectReason, advice: Option<ReconnectAdvice>) {
+        if let Some(notice) = reason.notice() {
+            let _ = self.send(notice).await; // 切断理由を通知
+        }
+        if let Some(advice) = advice {
+            let text = if self.session.transport.is_machine() {
+                advice.json(reason) // 機械向けには型付きのイベント
+            } else {
+                advice.notice()
+            };
+            let _ = self.send(&text).await;
+        }
+        self.outbound.finish().await; // 書き込みタスクが残りを書き出して送信側を閉じる
+    }
+
+    // 上限に近づいたことを本人に知らせ、オペレーター向けにログに残す（まだ制限はしない）
+    async fn warn_near_limit(&mut self, what: &str, used: usize, limit: usize, unit: &str) {
+        crate::printdaytimeln!(
+            "上限接近: {} {} {} {}/{}{}",
+            self.peer_addr,
+            self.handle_name,
+            what,
+            used,
+            limit,
+            unit
+        ); // ログ
+        let notice = format!(
+            "SYSTEM> {}が上限に近づいています（{}/{}{}）\n",
+            what, used, limit, unit
+        );
+        let _ = self.send(&notice).await;
+    }
+
+    // 入力されたハンドルネームを使えない理由を知らせ、失敗が続いたら接続元を締め出して切断する
+    async fn reject_name(
+        &mut self,
+        notice: &str,
+        config: &init::Config,
+    ) -> Option<DisconnectReason> {
+        let _ = self.send(notice).await;
+        self.name_failures += 1; // この接続での失敗回数
+        if config.name_attempts == 0 || self.name_failures < config.name_attempts {
+            return None; // 入力し直させる
+        }
+        crate::printdaytimeln!(
+            "ハンドルネーム入力失敗: {} ({}回)",
+            self.peer_addr,
+            self.name_failures
+        ); // ログ
+        if config.name_cooldown_seconds > 0 {
+            names::start_cooldown(
+                &self.session.peer_ip(),
+                Duration::from_secs(config.name_cooldown_seconds),
+            ); // しばらく同じ接続元からの接続を断る
+        }
+        Some(DisconnectReason::NameAttempts)
+    }
+
+    // 入力された接続パスワードを確かめ、合っていればハンドルネームの入力へ進め、間違いが続いたら切断する
+    async fn check_password(
+        &mut self,
+        input: &str,
+        password: &str,
+        config: &init::Config,
+    ) -> Option<DisconnectReason> {
+        if input == password {
+            self.phase = 0; // ハンドルネームの入力へ
+            crate::printdaytimeln!("パスワード認証: {}", self.peer_addr); // ログ
+            return None;
+        }
+        let _ = self.send("SYSTEM> パスワードが違います\n").await;
+        self.password_failures += 1; // この接続での失敗回数
+        if config.password_attempts == 0 || self.password_failures < config.password_attempts {
+            return None; // 入力し直させる
+        }
+        crate::printdaytimeln!(
+            "パスワード入力失敗: {} ({}回)",
+            self.peer_addr,
+            self.password_failures
+        ); // ログ
+        Some(DisconnectReason::PasswordAttempts)
+    }
+
+    // /resume <トークン> : 通信断で切断された接続のハンドルネーム・部屋・認証状態を引き継ぎ、切断中の会話を再送する
+    async fn resume(&mut self, token: &str, config: &init::Config) -> Option<DisconnectReason> {
+        let Some(parked) = resume::take(token, &self.session.tenant) else {
+            return self
+                .reject_name("SYSTEM> 再接続用トークンが無効か期限切れです\n", config)
+                .await; // 総当たりさせないようハンドルネームの入力の失敗として数える
+        };
+        if names::claim(
+            &self.session.tenant,
+            &parked.handle_name,
+            &parked.owner,
+            self.session.queue.clone(),
+        )
+        .is_err()
+        {
+            let _ = self
+                .send("SYSTEM> そのハンドルネームは既に使われています\n")
+                .await; // 予約が切れた後に他の接続が使い始めた
+            return None;
+        }
+        self.handle_name = parked.handle_name; // ハンドルネーム確定
+        self.session.set_handle_name(&self.handle_name); // 他タスクから参照できるよう反映
+        self.record_span_name(); // 以降のログにハンドルネームを付ける
+        self.session.touch(); // 離席判定は再開時から数える
+        self.session
+            .identified
+            .store(parked.identified, Ordering::Relaxed); // 認証済みだったならそのまま
+        self.registered_here = parked.registered_here;
+        self.apply_role(config);
+        if !parked.identified {
+            self.require_identify(config).await; // 登録済みなら認証を求める
+        }
+        let room = if parked.room.is_empty() {
+            config.default_room.clone()
+        } else {
+            parked.room
+        };
+        let (room, seen) = match rooms::join(&self.session, &room, &config.default_room) {
+            Ok(()) => (room, parked.seen), // 切断時にいた部屋に戻る
+            Err(_) => {
+                let _ = rooms::join(&self.session, &config.default_room, &config.default_room);
+                (config.default_room.clone(), u64::MAX) // 切断中に招待制になった部屋には戻れない（再送もしない）
+            }
+        };
+        self.phase = 1; // 通常モードへ
+        crate::printdaytimeln!("再開: {} {}", self.peer_addr, self.handle_name); // ログ
+        self.announce_connect(config);
+        let welcome = format!("SYSTEM> {}さん、おかえりなさい\n", self.handle_name);
+        let _ = self.send(&welcome).await;
+        self.announce_room_limits(config).await; // 今いる部屋の一行の上限
+        self.show_topic().await; // 今いる部屋の話題
+        let missed = self.visible(rooms::recent_since(&self.session.tenant, &room, seen));
+        if !missed.is_empty() {
+            let mut replay = format!("SYSTEM> 切断中の会話 ({}行)\n", missed.len());
+            for line in &missed {
+                replay.push_str(&line.text); // 各行は改行付き
+            }
+            replay.push_str("SYSTEM> ここまで\n");
+            let _ = self.send(&replay).await;
+        }
+        None
+    }
+
+    // 改行までの1行を処理する（切断すべき場合は理由を返す）
+    async fn on_line(&mut self, line: &[u8], config: &init::Config) -> Option<DisconnectReason> {
+        let msg = String::from_utf8_lossy(line).trim().to_string(); // UTF-8変換
+        if self.phase == 2 {
+            match &config.password {
+                Some(password) if !msg.is_empty() => {
+                    return self.check_password(&msg, password, config).await;
+                }
+                Some(_) => return None, // 空行は数えない
+                None => self.phase = 0, // 再読込でパスワードが外されたらそのままハンドルネームの入力へ
+            }
+        }
+        if self.phase == 0 {
+            if msg.is_empty() || msg.starts_with("/pong") {
+                return None; // 空行・/reset後に届いたPINGの応答は無視
+            }
+            if let Some(token) = msg.strip_prefix("/resume ") {
+                return self.resume(token.trim(), config).await; // 通信断で切断された接続を引き継ぐ
+            }
+            if !msg.chars().all(|c| !c.is_control() && !c.is_whitespace()) {
+                return self
+                    .reject_name(
+                        "SYSTEM> ハンドルネームに使えない文字が含まれています\n",
+                        config,
+                    )
+                    .await; // バリデーション
+            }
+            if msg.len() > config.max_handle_name {
+                return self
+                    .reject_name("SYSTEM> ハンドルネームが長すぎます\n", config)
+                    .await; // 長さ超過
+            }
+            // ハンドルネームを一覧に追加（使用中・切断した他の接続元の予約中なら使えず、入力し直させる）
+            match names::claim(
+                &self.session.tenant,
+                &msg,
+                &self.session.peer_ip(),
+                self.session.queue.clone(),
+            ) {
+                Ok(()) => {}
+                Err(ClaimError::InUse) => {
+                    return self
+                        .reject_name("SYSTEM> そのハンドルネームは既に使われています\n", config)
+                        .await;
+                }
+                Err(ClaimError::Reserved) => {
+                    return self
+                        .reject_name(
+                            "SYSTEM> そのハンドルネームは一時的に予約されています\n",
+                            config,
+                        )
+                        .await;
+                }
+            }
+            self.handle_name = msg; // ハンドルネーム確定
+            self.session.set_handle_name(&self.handle_name); // 他タスクから参照できるよう反映
+            self.record_span_name(); // 以降のログにハンドルネームを付ける
+            self.session.touch(); // 離席判定はハンドルネーム確定時から数える
+            self.apply_role(config); // 認証前の役割
+            self.require_identify(config).await; // 登録済みなら認証を求める
+            if self.session.room().is_empty() {
+                let _ = rooms::join(&self.session, &config.default_room, &config.default_room);
+                // 既定の部屋に入る（既定の部屋はいつでも入れる）
+            }
+            self.phase = 1; // 通常モードへ
+            crate::printdaytimeln!("確定: {} {}", self.peer_addr, self.handle_name); // ログ
+            self.announce_connect(config);
+            let welcome = match &config.greeting {
+                Some(greeting) => {
+                    let room = self.session.room();
+                    let context = template::Context {
+                        tenant: &self.session.tenant,
+                        user: &self.handle_name,
+                        room: &room,
+                    };
+                    format!("SYSTEM> {}\n", template::render(greeting, &context))
+                    // 設定のあいさつ
+                }
+                None => format!("SYSTEM> {}さん、ようこそ\n", self.handle_name), // 既定のあいさつ
+            }; // ウェルカム
+            let _ = self.send(&welcome).await;
+            self.announce_room_limits(config).await; // 今いる部屋の一行の上限
+            self.show_topic().await; // 今いる部屋の話題
+            self.show_pins(false).await; // 今いる部屋の固定告知
+            self.replay_recent(config).await; // 直近の会話
+            return None;
+        }
+        if !msg.is_empty() && self.session.touch() {
+            // 離席中からの復帰
+            self.announce_presence(config, "戻りました");
+        }
+        if let Some((name, args)) = commands::parse(line, &msg) {
+            // スラッシュで始まる行・CTRL-Yなどの制御キーはコマンドとして処理
+            return self.on_command(name, args, config).await;
+        }
+        if !msg.is_empty() {
+            if self.identify_deadline.is_some() {
+                let notice = if self.awaiting_signup(config) {
+                    "SYSTEM> /signup で登録するまで発言できません\n" // 登録待ち
+                } else {
+                    "SYSTEM> /identify で認証するまで発言できません\n" // 認証待ち
+                };
+                let _ = self.send(notice).await;
+                return None;
+            }
+            return self.post_chat(&msg, ChatKind::Say, config).await;
+        }
+        None
+    }
+
+    // 発言を今いる部屋に流す（スパム判定・禁止語の伏字・履歴保存・メッセージIDの採番、切断するならSome）
+    async fn post_chat(
+        &mut self,
+        msg: &str,
+        kind: ChatKind,
+        config: &init::Config,
+    ) -> Option<DisconnectReason> {
+        let room = self.session.room();
+        if !rooms::may_speak(&self.session, &room) {
+            let _ = self
+                .send("SYSTEM> この部屋は発言制限中です（+m、部屋のオペレーターと発言権のある人だけが発言できます）\n")
+                .await;
+            return None;
+        }
+        let slow = rooms::slow_interval(&self.session.tenant, &room);
+        if slow > 0 && !rooms::is_op(&self.session, &room) {
+            let wait = self.last_posted.map_or(Duration::ZERO, |at| {
+                Duration::from_secs(slow).saturating_sub(at.elapsed())
+            }); // 前の発言から間隔が空くまでの残り
+            if !wait.is_zero() {
+                let notice = format!(
+                    "SYSTEM> スローモード中です（{}秒に1回まで、あと{}秒）\n",
+                    slow,
+                    wait.as_secs().max(1)
+                );
+                let _ = self.send(&notice).await;
+                return None;
+            }
+        }
+        if let Some(reason) = self.check_filter(msg, config).await {
+            return reason; // 禁止語を含むので破棄・切断
+        }
+        if let Some(reason) = self.check_repeat(msg, config).await {
+            return reason; // 同じ発言の繰り返しなので破棄・切断
+        }
+        if let Some(reason) = self.check_spam(msg, config).await {
+            return reason; // 破棄（繰り返しなら切断）
+        }
+        self.last_posted = Some(Instant::now()); // スローモードの間隔はここから数える
+        if self.session.muted_until().is_some() {
+            return None; // ミュート中は受け付けたことにして誰にも届けない
+        }
+        let body = mask_message(msg, config); // 禁止語・FilterRegex（mask）の一致部分を伏字に
+        history::record(
+            &self.session.qualified_room(),
+            &self.handle_name,
+            &body,
+            kind.as_str(),
+        ); // 履歴に保存（テナントの部屋は「テナント名/部屋名」）
+        let tenant = self.session.tenant.clone();
+        let from = self.handle_name.clone();
+        rooms::remember(&tenant, &room, &from, config.history_lines, |id| {
+            let event = ChatEvent::Chat {
+                id, // 部屋ごとのメッセージID
+                kind,
+                from: self.handle_name.clone(),
+                body,
+                ts: chrono::Local::now().with_timezone(&Tokyo), // 現在時刻
+            };
+            let line = event.render(); // 後から入った人・/resendへの再送用
+            let _ = self.msg_tx.send(RoomMessage {
+                tenant: tenant.clone(),
+                room: room.clone(),
+                event,
+            }); // 自分のメッセージを同じ部屋にブロードキャスト
+            line
+        });
+        None
+    }
+}
+
+impl<S: Transport> Client<S> {
+    // 禁止語を含む発言をFilterPolicyに、FilterRegexに一致した発言を規則に従って扱う（伏字なら通す、破棄したらSome、切断するならSome(Some(理由))）
+    async fn check_filter(
+        &mut self,
+        msg: &str,
+        config: &init::Config,
+    ) -> Option<Option<DisconnectReason>> {
+        if config.filter_policy != FilterPolicy::Mask && lists::contains_filtered(msg) {
+            self.session.record_violation(Violation::Filtered); // 違反履歴に記録
+            if config.filter_policy == FilterPolicy::Disconnect {
+                crate::printdaytimeln!("禁止語で切断: {} {}", self.peer_addr, self.handle_name); // ログ
+                return Some(Some(DisconnectReason::Filtered));
+            }
+            crate::printdaytimeln!("禁止語で破棄: {} {}", self.peer_addr, self.handle_name); // ログ
+            let _ = self
+                .send("SYSTEM> 禁止語を含むため発言を破棄しました\n")
+                .await;
+            return Some(None);
+        }
+        let rule = config
+            .filter_rules
+            .iter()
+            .find(|rule| rule.action != FilterAction::Mask && rule.pattern.is_match(msg))?; // 上から順に最初に一致した規則
+        self.session.record_violation(Violation::Filtered); // 違反履歴に記録
+        crate::printdaytimeln!(
+            "禁止規則に一致: {} {} ({:?} {})",
+            self.peer_addr,
+            self.handle_name,
+            rule.action,
+            rule.pattern.as_str()
+        ); // ログ
+        match rule.action {
+            FilterAction::Drop => Some(None), // 本人には知らせない
+            FilterAction::Warn => {
+                let _ = self
+                    .send("SYSTEM> 禁止されている内容を含むため発言を破棄しました\n")
+                    .await;
+                Some(None)
+            }
+            FilterAction::Ban => {
+                autoban::ban(&self.session.peer_ip(), DisconnectReason::Filtered.code()); // 回数を待たずに締め出す
+                Some(Some(DisconnectReason::Filtered))
+            }
+            FilterAction::Mask => None, // 伏字はmask_messageで扱う
+        }
+    }
+
+    // 同じ（ほぼ同じ）発言をRepeatWindowSeconds以内にRepeatLimit回送ったらRepeatPolicyに従って扱う
+    // 連続投稿の制限とは別に、間隔を空けたコピー＆ペーストの繰り返しを止める（破棄したらSome、切断するならSome(Some(理由))）
+    async fn check_repeat(
+        &mut self,
+        msg: &str,
+        config: &init::Config,
+    ) -> Option<Option<DisconnectReason>> {
+        if config.repeat_limit == 0 {
+            return None; // 検出しない設定
+        }
+        let window = Duration::from_secs(config.repeat_window_seconds);
+        self.recent_lines.retain(|(at, _)| at.elapsed() < window); // 期間を過ぎた発言は忘れる
+        let key = repeat_key(msg);
+        let repeats = self
+            .recent_lines
+            .iter()
+            .filter(|(_, line)| *line == key)
+            .count()
+            + 1; // 今回の分を含む
+        self.recent_lines.push_back((Instant::now(), key)); // 破棄した分も数え続ける
+        if self.recent_lines.len() > MAX_RECENT_LINES {
+            self.recent_lines.pop_front();
+        }
+        if repeats < config.repeat_limit {
+            return None;
+        }
+        self.session.record_violation(Violation::Repeat); // 違反履歴に記録
+        if config.repeat_policy == RepeatPolicy::Disconnect {
+            crate::printdaytimeln!("繰り返しで切断: {} {}", self.peer_addr, self.handle_name); // ログ
+            return Some(Some(DisconnectReason::Repeated));
+        }
+        if repeats == config.repeat_limit {
+            crate::printdaytimeln!("繰り返しを破棄: {} {}", self.peer_addr, self.handle_name); // ログ
+            let notice = format!(
+                "SYSTEM> 同じ内容の発言が続いているため破棄しました（{}秒の間に{}回目）\n",
+                config.repeat_window_seconds, repeats
+            ); // 最初の1回だけ知らせる
+            let _ = self.send(&notice).await;
+        }
+        Some(None)
+    }
+
+    // 発言をスパム判定し、しきい値以上なら破棄して違反に数える（破棄したらSome、切断するならSome(Some(理由))）
+    async fn check_spam(
+        &mut self,
+        msg: &str,
+        config: &init::Config,
+    ) -> Option<Option<DisconnectReason>> {
+        if !features::is_enabled(Feature::Spam) {
+            return None; // 管理用ソケットで止めている
+        }
+        let scorer = scoring::current(config.spam_scorer.as_deref())?; // 判定しない設定
+        let violations = self.session.violations();
+        let info = MessageInfo {
+            content: msg.to_string(),
+            handle_name: self.handle_name.clone(),
+            room: self.session.room(),
+            lines_in_window: self.traffic.window_lines,
+            recent_violations: violations
+                .iter()
+                .filter(|(at, _)| at.elapsed() < Duration::from_secs(60))
+                .count(),
+            connected_secs: self.session.connected_at.elapsed().as_secs(),
+        };
+        let score = tokio::time::timeout(SCORE_TIMEOUT, scorer.score(info))
+            .await
+            .unwrap_or(0.0); // 時間切れは通す
+        if score < config.spam_threshold {
+            return None;
+        }
+        self.session.record_violation(Violation::Spam); // 違反履歴に記録
+        crate::printdaytimeln!(
+            "スパム判定: {} {} ({:.2})",
+            self.peer_addr,
+            self.handle_name,
+            score
+        ); // ログ
+        let strikes = violations
+            .iter()
+            .filter(|(at, kind)| *kind == Violation::Spam && at.elapsed() < SPAM_WINDOW)
+            .count()
+            + 1; // 今回の分を含む
+        if strikes >= SPAM_STRIKES {
+            crate::printdaytimeln!("スパムで切断: {} {}", self.peer_addr, self.handle_name); // ログ
+            return Some(Some(DisconnectReason::Spam));
+        }
+        let _ = self
+            .send("SYSTEM> スパムと判定されたため発言を破棄しました。続けると切断されます\n")
+            .await;
+        Some(None)
+    }
+
+    // ハンドルネームを未定義に戻して入力し直させる（CTRL-Y・/reset）
+    fn reset_handle_name(&mut self) {
+        let old = std::mem::take(&mut self.handle_name);
+        self.session.set_handle_name(""); // 未定義に戻す
+        tracing::Span::current().record("handle", ""); // ログのハンドルネームも未定義に戻す
+        names::release(&self.session.tenant, &old, &self.session.queue); // 再定義時は古いハンドルネームを削除
+        self.session.identified.store(false, Ordering::Relaxed); // 認証は名前ごと
+        self.identify_deadline = None;
+        self.identify_failures = 0;
+        self.drop_confirm_until = None;
+        self.phase = 0;
+        crate::printdaytimeln!("再定義: {} {} -> (未定義)", self.peer_addr, old);
+        // ログ
+    }
+
+    // 今のハンドルネームと認証状態から役割を決め直す（/roleで付与した役割もここで外れる）
+    fn apply_role(&self, config: &init::Config) {
+        let identified = self.session.identified.load(Ordering::Relaxed);
+        let role = if identified && self.registered_here {
+            Role::User // 登録したばかりの名前にはRole行の役割を付けない（先に登録した他人の名前かもしれない）
+        } else {
+            config.role_for(&self.session.tenant, &self.handle_name, identified)
+        };
+        self.session.set_role(role);
+    }
+
+    // TCP接続の状態を記録する（前回から一定時間経っている場合のみ）
+    fn sample_quality(&mut self) {
+        if self
+            .quality_sampled_at
+            .is_some_and(|at| at.elapsed() < QUALITY_SAMPLE_INTERVAL)
+        {
+            return;
+        }
+        self.quality_sampled_at = Some(Instant::now());
+        if let Some(quality) = self.quality_probe.as_ref().and_then(|probe| probe()) {
+            self.session.set_tcp_quality(quality);
+        }
+    }
+
+    // 接続のスパン（ログに付く接続元・ハンドルネーム）に現在のハンドルネームを記録する
+    fn record_span_name(&self) {
+        tracing::Span::current().record("handle", self.handle_name.as_str());
+    }
+
+    // オペレーターによるハンドルネームの強制変更を反映する
+    async fn on_forced_rename(&mut self, new_name: String, config: &init::Config) {
+        self.handle_name = new_name; // 登録簿とセッションは変更済み
+        self.record_span_name(); // ログのハンドルネームも変更
+        let notice = format!(
+            "SYSTEM> オペレーターによりハンドルネームが {} に変更されました\n",
+            self.handle_name
+        );
+        let _ = self.send(&notice).await;
+        self.session.identified.store(false, Ordering::Relaxed); // 認証は名前ごと
+        self.apply_role(config); // 役割も名前ごと
+        self.identify_deadline = None;
+        self.identify_failures = 0;
+        self.drop_confirm_until = None;
+        self.require_identify(config).await; // 変更先が登録済みなら認証を求める
+    }
+
+    // 登録済みのハンドルネームなら、猶予の間に/identifyで認証するよう求める（登録済みのみのサーバーで未登録なら/signupを求める）
+    async fn require_identify(&mut self, config: &init::Config) {
+        let Some(path) = &config.account_file else {
+            return; // 登録が無効
+        };
+        if !accounts::is_registered(path, &self.handle_name) {
+            if config.registered_only {
+                self.identify_deadline =
+                    Some(Instant::now() + Duration::from_secs(config.identify_timeout_seconds));
+                let notice = format!(
+                    "SYSTEM> このサーバーでは登録したハンドルネームだけが使えます。{}秒以内に /signup <パスワード> で登録するか、/reset で登録済みの名前にしてください\n",
+                    config.identify_timeout_seconds
+                );
+                let _ = self.send(&notice).await;
+            }
+            return;
+        }
+        self.identify_deadline =
+            Some(Instant::now() + Duration::from_secs(config.identify_timeout_seconds));
+        let notice = format!(
+            "SYSTEM> {} は登録済みのハンドルネームです。{}秒以内に /identify <パスワード> で認証してください\n",
+            self.handle_name, config.identify_timeout_seconds
+        );
+        let _ = self.send(&notice).await;
+    }
+
+    // 登録済みのみのサーバーで、未登録のハンドルネームの登録を待っているか
+    fn awaiting_signup(&self, config: &init::Config) -> bool {
+        config
+            .account_file
+            .as_deref()
+            .is_some_and(|path| !accounts::is_registered(path, &self.handle_name))
+    }
+
+    // 認証されないまま猶予が過ぎたので、ハンドルネームを未定義に戻す
+    async fn expire_identify(&mut self) {
+        crate::printdaytimeln!("認証期限切れ: {} {}", self.peer_addr, self.handle_name); // ログ
+        self.reset_handle_name();
+        let _ = self
+            .send("SYSTEM> 認証されなかったため、別のハンドルネームを入力してください\n")
+            .await;
+    }
+
+    // オペレーター権限の失効時刻（無操作時間・有効期間のうち早い方、どちらも無効ならNone）
+    fn oper_deadline(&self, config: &init::Config) -> Option<Instant> {
+        let since = self.oper_since.filter(|_| self.session.is_oper())?;
+        let idle = (config.oper_idle_minutes > 0).then(|| {
+            self.session.last_active().max(since)
+                + Duration::from_secs(config.oper_idle_minutes * 60)
+        });
+        let max = (config.oper_max_minutes > 0)
+            .then(|| since + Duration::from_secs(config.oper_max_minutes * 60));
+        idle.into_iter().chain(max).min()
+    }
+
+    // オペレーター権限を失効させ、再認証を促す
+    async fn expire_oper(&mut self) {
+        self.session.oper.store(false, Ordering::Relaxed); // オペレーター権限を外す
+        self.oper_since = None;
+        audit::record(&format!(
+            "オペレーター権限失効: {} {}",
+            self.peer_addr, self.handle_name
+        )); // 監査ログ
+        let _ = self
+            .send(
+                "SYSTEM> オペレーター権限の期限が切れました。必要なら /oper で再認証してください\n",
+            )
+            .await;
+    }
+
+    // 離席判定時刻（ハンドルネーム確定済みで離席判定が有効な場合のみ）
+    fn idle_deadline(&self, config: &init::Config) -> Option<Instant> {
+        if self.phase != 1 || config.idle_minutes == 0 || self.session.is_idle() {
+            return None;
+        }
+        Some(self.session.last_active() + Duration::from_secs(config.idle_minutes * 60))
+    }
+
+    // 一定時間入力がなかったので離席中にする
+    fn mark_idle(&mut self, config: &init::Config) {
+        self.session.idle.store(true, Ordering::Relaxed); // 離席中に変更
+        crate::printdaytimeln!("離席: {} {}", self.peer_addr, self.handle_name); // ログ
+        self.announce_presence(config, "離席中になりました");
+    }
+
+    // 入退室を今いる部屋の他の参加者に通知する
+    fn announce_move(&self, event: ChatEvent) {
+        let _ = self.msg_tx.send(RoomMessage {
+            tenant: self.session.tenant.clone(),
+            room: self.session.room(),
+            event,
+        }); // 静音時間・/quiet on は配送時に除外
+    }
+
+    // ハンドルネームの確定を他のクライアントに通知（設定で有効な場合のみ）
+    fn announce_connect(&self, config: &init::Config) {
+        if config.announce_connect {
+            self.announce_move(ChatEvent::Connect {
+                id: self.session.id,
+                who: self.handle_name.clone(),
+            }); // 部屋を問わず同じテナントの全員に
+        }
+    }
+
+    // 離席・復帰を他のクライアントに通知（設定で有効な場合のみ）
+    fn announce_presence(&self, config: &init::Config, state: &str) {
+        if config.announce_idle {
+            let notice = format!("SYSTEM> {} は{}\n", self.handle_name, state);
+            dispatch::broadcast_ambient(&self.session.tenant, &self.session.room(), &notice);
+            // 同じ部屋の /quiet on でないクライアントに通知
+        }
+    }
+}
+
+impl<S: Transport> Client<S> {
+    // コマンドを処理する（定義はcommands.rsの一覧から引き、使える条件を確かめてから各cmd_*へ振り分ける）
+    // 切断する場合は理由を返す
+    async fn on_command(
+        &mut self,
+        name: &str,
+        args: &str,
+        config: &init::Config,
+    ) -> Option<DisconnectReason> {
+        let Some(command) = commands::find(name) else {
+            let notice = format!("SYSTEM> 不明なコマンドです: /{}\n", name); // 未知のコマンド
+            let _ = self.send(&notice).await;
+            return None;
+        };
+        if self.identify_deadline.is_some() && !command.while_identifying {
+            let notice = if self.awaiting_signup(config) {
+                "SYSTEM> /signup で登録するか /reset で別のハンドルネームにしてください\n"
+            } else {
+                "SYSTEM> /identify で認証するか /reset で別のハンドルネームにしてください\n"
+            };
+            let _ = self.send(notice).await; // 認証待ちの間は他のコマンドを使わせない
+            return None;
+        }
+        if self.session.role() < command.role {
+            let _ = self.send(&roles::denied(command.role)).await; // 役割が足りない
+            return None;
+        }
+        match command.name {
+            "pong" => {} // PINGの応答（受信した時点で接続確認は済んでいる）
+            "help" => self.cmd_help(args).await,
+            "oper" => self.cmd_oper(args, config).await,
+            "wall" => self.cmd_wall(args).await,
+            "whois" => self.cmd_whois(args, config).await,
+            "sessions" => self.cmd_sessions().await,
+            "maintenance" => self.cmd_maintenance(args).await,
+            "stats" => self.cmd_stats().await,
+            "trace" => self.cmd_trace(args).await,
+            "pending" => self.cmd_pending(args).await,
+            "rename" => self.cmd_rename(args, config).await,
+            "quiet" => self.cmd_quiet(args).await,
+            "color" => self.cmd_color(args).await,
+            "ack" => self.cmd_ack(args).await,
+            "resend" => self.cmd_resend(args).await,
+            "nick" => self.cmd_nick(args, config).await,
+            "reset" => self.reset_handle_name(), // CTRL-Y・/reset（CTRL-Yを送れないブラウザ等向け）
+            "me" => return self.cmd_me(args, config).await,
+            "msg" => return self.cmd_msg(args, config).await,
+            "join" => self.cmd_join(args, config).await,
+            "part" => self.cmd_part(config).await,
+            "list" => self.cmd_list().await,
+            "who" => self.cmd_who().await,
+            "ignore" => self.cmd_ignore(args, config).await,
+            "unignore" => self.cmd_unignore(args).await,
+            "away" => self.cmd_away(args, config).await,
+            "back" => self.cmd_back(config).await,
+            "pin" => self.cmd_pin(args).await,
+            "unpin" => self.cmd_unpin(args).await,
+            "pins" => self.show_pins(true).await,
+            "topic" => self.cmd_topic(args).await,
+            "mode" => self.cmd_mode(args, config).await,
+            "invite" => self.cmd_invite(args).await,
+            "slow" => self.cmd_slow(args).await,
+            "reload" => self.cmd_reload(args).await,
+            "mute" => self.cmd_mute(args).await,
+            "register" => self.cmd_register(args, config).await,
+            "identify" => self.cmd_identify(args, config).await,
+            "passwd" => self.cmd_passwd(args, config).await,
+            "email" => self.cmd_email(args, config).await,
+            "drop" => self.cmd_drop(args, config).await,
+            "info" => self.cmd_info(config).await,
+            "limits" => self.cmd_limits(config).await,
+            "keepalive" => self.cmd_keepalive(args, config).await,
+            "role" => self.cmd_role(args, config).await,
+            _ => unreachable!(
+                "commands.rsに定義したコマンドの処理がありません: /{}",
+                command.name
+            ),
+        }
+        None
+    }
+
+    // /me <動作> : 動作を発言する（"* ハンドルネーム 動作" の形で部屋に流す）
+    async fn cmd_me(&mut self, args: &str, config: &init::Config) -> Option<DisconnectReason> {
+        if args.is_empty() {
+            let _ = self.send("SYSTEM> 使い方: /me <動作>\n").await;
+            return None;
+        }
+        self.post_chat(args, ChatKind::Action, config).await
+    }
+
+    // /help [コマンド] : 今の役割で使えるコマンドの一覧・指定したコマンドの使い方
+    async fn cmd_help(&mut self, args: &str) {
+        let role = self.session.role();
+        let text = if args.is_empty() {
+            commands::help(role)
+        } else {
+            match commands::find(args.trim_start_matches('/')) {
+                Some(command) if command.role <= role => commands::help_for(command),
+                _ => format!(
+                    "SYSTEM> 不明なコマンドです: /{}\n",
+                    args.trim_start_matches('/')
+                ),
+            }
+        };
+        let _ = self.send(&text).await;
+    }
+
+    // /oper <パスワード> : オペレーター認証
+    async fn cmd_oper(&mut self, args: &str, config: &init::Config) {
+        let Some(password) = &config.oper_password else {
+            let _ = self
+                .send("SYSTEM> このサーバーではオペレーターは無効です\n")
+                .await;
+            return;
+        };
+        if args != password.as_str() {
+            audit::record(&format!(
+                "オペレーター認証失敗: {} {}",
+                self.peer_addr, self.handle_name
+            )); // 監査ログ
+            let _ = self.send("SYSTEM> パスワードが違います\n").await;
+            return;
+        }
+        self.session.oper.store(true, Ordering::Relaxed); // オペレーター権限を付与
+        self.oper_since = Some(Instant::now()); // 有効期間は再認証のたびに数え直す
+        audit::record(&format!(
+            "オペレーター権限付与: {} {}",
+            self.peer_addr, self.handle_name
+        )); // 監査ログ
+        let _ = self
+            .send("SYSTEM> オペレーターとして認証されました\n")
+            .await;
+    }
+
+    // /register <パスワード> : 現在のハンドルネームをパスワード付きで登録
+    async fn cmd_register(&mut self, args: &str, config: &init::Config) {
+        let Some(path) = &config.account_file else {
+            let _ = self
+                .send("SYSTEM> このサーバーではハンドルネーム登録は無効です\n")
+                .await;
+            return;
+        };
+        if args.is_empty() {
+            let _ = self.send("SYSTEM> 使い方: /register <パスワード>\n").await;
+            return;
+        }
+        if args.chars().count() < accounts::MIN_PASSWORD_CHARS {
+            let notice = format!(
+                "SYSTEM> パスワードは{}文字以上にしてください\n",
+                accounts::MIN_PASSWORD_CHARS
+            );
+            let _ = self.send(&notice).await;
+            return;
+        }
+        match accounts::register(path, &self.handle_name, args).await {
+            Ok(()) => {
+                self.session.identified.store(true, Ordering::Relaxed); // 登録した本人は認証済み
+                self.registered_here = true; // Role行の役割は/identifyで認証し直すまで付けない
+                self.apply_role(config);
+                self.identify_deadline = None; // 登録済みのみのサーバーで登録を待っていた場合
+                audit::record(&format!(
+                    "ハンドルネーム登録: {} {}",
+                    self.peer_addr, self.handle_name
+                )); // 監査ログ
+                let _ = self
+                    .send("SYSTEM> 登録しました。次回からは /identify <パスワード> で認証してください\n")
+                    .await;
+            }
+            Err(RegisterError::Taken) => {
+                let _ = self
+                    .send("SYSTEM> このハンドルネームは既に登録されています\n")
+                    .await;
+            }
+            Err(RegisterError::Io(e)) => {
+                crate::printdaytimeln!("登録ファイル書き込み失敗: {} ({})", path, e); // ログ
+                let _ = self.send("SYSTEM> 登録に失敗しました\n").await;
+            }
+        }
+    }
+
+    // /identify <パスワード> : 登録済みのハンドルネームの本人であることを認証
+    async fn cmd_identify(&mut self, args: &str, config: &init::Config) {
+        let Some(path) = &config.account_file else {
+            let _ = self
+                .send("SYSTEM> このサーバーではハンドルネーム登録は無効です\n")
+                .await;
+            return;
+        };
+        if self.session.identified.load(Ordering::Relaxed) {
+            let _ = self.send("SYSTEM> 既に認証済みです\n").await;
+            return;
+        }
+        if !accounts::is_registered(path, &self.handle_name) {
+            let _ = self
+                .send("SYSTEM> このハンドルネームは登録されていません\n")
+                .await;
+            return;
+        }
+        if !accounts::verify(path, &self.handle_name, args).await {
+            self.identify_failures += 1;
+            audit::record(&format!(
+                "ハンドルネーム認証失敗: {} {}",
+                self.peer_addr, self.handle_name
+            )); // 監査ログ
+            if self.identify_failures >= IDENTIFY_ATTEMPTS {
+                self.reset_handle_name(); // 総当たりさせない
+                let _ = self
+                    .send(
+                        "SYSTEM> 認証に続けて失敗したため、別のハンドルネームを入力してください\n",
+                    )
+                    .await;
+                return;
+            }
+            let _ = self.send("SYSTEM> パスワードが違います\n").await;
+            return;
+        }
+        self.session.identified.store(true, Ordering::Relaxed); // 認証済みにする
+        self.registered_here = false; // 既にある登録に対して認証した
+        self.apply_role(config); // 設定ファイルで役割が決まっていれば適用
+        self.identify_deadline = None;
+        self.identify_failures = 0;
+        crate::printdaytimeln!("認証: {} {}", self.peer_addr, self.handle_name); // ログ
+        let _ = self.send("SYSTEM> 認証しました\n").await;
+    }
+
+    // 登録の管理コマンドを使えるか確かめ、登録ファイルのパスを返す（登録が無効・未認証なら理由を通知してNone）
+    async fn account_path(&mut self, config: &init::Config) -> Option<String> {
+        let Some(path) = config.account_file.clone() else {
+            let _ = self
+                .send("SYSTEM> このサーバーではハンドルネーム登録は無効です\n")
+                .await;
+            return None;
+        };
+        if !self.session.identified.load(Ordering::Relaxed) {
+            let _ = self
+                .send("SYSTEM> 登録したハンドルネームで /identify してから使ってください\n")
+                .await;
+            return None;
+        }
+        Some(path)
+    }
+
+    // /passwd <現在のパスワード> <新しいパスワード> : パスワードの変更
+    async fn cmd_passwd(&mut self, args: &str, config: &init::Config) {
+        let Some(path) = self.account_path(config).await else {
+            return;
+        };
+        let Some((current, new)) = args
+            .split_once(' ')
+            .map(|(current, new)| (current, new.trim()))
+            .filter(|(_, new)| !new.is_empty())
+        else {
+            let _ = self
+                .send("SYSTEM> 使い方: /passwd <現在のパスワード> <新しいパスワード>\n")
+                .await;
+            return;
+        };
+        if new.chars().count() < accounts::MIN_PASSWORD_CHARS {
+            let notice = format!(
+                "SYSTEM> パスワードは{}文字以上にしてください\n",
+                accounts::MIN_PASSWORD_CHARS
+            );
+            let _ = self.send(&notice).await;
+            return;
+        }
+        if !accounts::verify(&path, &self.handle_name, current).await {
+            audit::record(&format!(
+                "パスワード変更失敗: {} {}",
+                self.peer_addr, self.handle_name
+            )); // 監査ログ
+            let _ = self.send("SYSTEM> 現在のパスワードが違います\n").await;
+            return;
+        }
+        if let Err(e) = accounts::set_password(&path, &self.handle_name, new).await {
+            crate::printdaytimeln!("登録ファイル書き込み失敗: {} ({})", path, e); // ログ
+            let _ = self
+                .send("SYSTEM> パスワードを変更できませんでした\n")
+                .await;
+            return;
+        }
+        audit::record(&format!(
+            "パスワード変更: {} {}",
+            self.peer_addr, self.handle_name
+        )); // 監査ログ
+        let _ = self.send("SYSTEM> パスワードを変更しました\n").await;
+    }
+
+    // /email set <メールアドレス> : 連絡先メールアドレスの設定
+    async fn cmd_email(&mut self, args: &str, config: &init::Config) {
+        let Some(path) = self.account_path(config).await else {
+            return;
+        };
+        let Some(email) = args.strip_prefix("set ").map(str::trim) else {
+            let _ = self
+                .send("SYSTEM> 使い方: /email set <メールアドレス>\n")
+                .await;
+            return;
+        };
+        let valid = email.len() <= 254
+            && email.chars().all(|c| !c.is_control() && !c.is_whitespace())
+            && email
+                .split_once('@')
+                .is_some_and(|(local, domain)| !local.is_empty() && domain.contains('.'));
+        if !valid {
+            let _ = self.send("SYSTEM> メールアドレスとして使えません\n").await;
+            return;
+        }
+        if let Err(e) = accounts::set_email(&path, &self.handle_name, email) {
+            crate::printdaytimeln!("登録ファイル書き込み失敗: {} ({})", path, e); // ログ
+            let _ = self
+                .send("SYSTEM> メールアドレスを設定できませんでした\n")
+                .await;
+            return;
+        }
+        audit::record(&format!(
+            "メールアドレス設定: {} {}",
+            self.peer_addr, self.handle_name
+        )); // 監査ログ
+        let notice = format!("SYSTEM> メールアドレスを {} に設定しました\n", email);
+        let _ = self.send(&notice).await;
+    }
+
+    // /drop <パスワード> → /drop confirm : 登録の削除（パスワードの確認と再確認の2段階）
+    async fn cmd_drop(&mut self, args: &str, config: &init::Config) {
+        let Some(path) = self.account_path(config).await else {
+            return;
+        };
+        if args == "confirm" {
+            if self
+                .drop_confirm_until
+                .take()
+                .is_none_or(|until| Instant::now() > until)
+            {
+                let _ = self
+                    .send("SYSTEM> 先に /drop <パスワード> で確認してください\n")
+                    .await;
+                return;
+            }
+            if let Err(e) = accounts::remove(&path, &self.handle_name) {
+                crate::printdaytimeln!("登録ファイル書き込み失敗: {} ({})", path, e); // ログ
+                let _ = self.send("SYSTEM> 登録を削除できませんでした\n").await;
+                return;
+            }
+            self.session.identified.store(false, Ordering::Relaxed); // 登録がなくなったので認証も外す
+            self.apply_role(config);
+            audit::record(&format!(
+                "ハンドルネーム登録削除: {} {}",
+                self.peer_addr, self.handle_name
+            )); // 監査ログ
+            let _ = self
+                .send("SYSTEM> 登録を削除しました。ハンドルネームはこのまま使えます\n")
+                .await;
+            return;
+        }
+        if args.is_empty() {
+            let _ = self.send("SYSTEM> 使い方: /drop <パスワード>\n").await;
+            return;
+        }
+        if !accounts::verify(&path, &self.handle_name, args).await {
+            audit::record(&format!(
+                "登録削除の認証失敗: {} {}",
+                self.peer_addr, self.handle_name
+            )); // 監査ログ
+            let _ = self.send("SYSTEM> パスワードが違います\n").await;
+            return;
+        }
+        self.drop_confirm_until = Some(Instant::now() + DROP_CONFIRM_WINDOW);
+        let notice = format!(
+            "SYSTEM> {} の登録を削除します。よろしければ{}秒以内に /drop confirm と入力してください\n",
+            self.handle_name,
+            DROP_CONFIRM_WINDOW.as_secs()
+        );
+        let _ = self.send(&notice).await;
+    }
+
+    // /info : 自分の登録内容の表示
+    async fn cmd_info(&mut self, config: &init::Config) {
+        let Some(path) = self.account_path(config).await else {
+            return;
+        };
+        let Some(info) = accounts::info(&path, &self.handle_name) else {
+            let _ = self
+                .send("SYSTEM> このハンドルネームは登録されていません\n")
+                .await;
+            return;
+        };
+        let registered = chrono::DateTime::from_timestamp(info.registered_at, 0)
+            .map(|at| {
+                at.with_timezone(&Tokyo)
+                    .format("%Y/%m/%d %H:%M")
+                    .to_string()
+            })
+            .unwrap_or_default(); // 登録日時（JST）
+        let notice = format!(
+            "SYSTEM> 登録名 {} / 登録日時 {} / メールアドレス {}\n",
+            info.name,
+            registered,
+            info.email.as_deref().unwrap_or("未設定")
+        );
+        let _ = self.send(&notice).await;
+    }
+
+    // /limits : この接続に今適用されている上限と残り（発言が破棄された理由を本人が確かめられるように）
+    async fn cmd_limits(&mut self, config: &init::Config) {
+        let limit = |n: usize, unit: &str| {
+            if n == 0 {
+                "無制限".to_string()
+            } else {
+                format!("{}{}", n, unit)
+            }
+        };
+        let room = self.session.room();
+        let length = self.max_message_length(config);
+        let length_source = if length != config.max_message_length {
+            "この部屋の指定"
+        } else if self.session.tenant.is_empty() {
+            "サーバーの設定"
+        } else {
+            "テナントの設定"
+        }; // どの設定が効いているか
+        let rate = self.session.rate();
+        let (bytes, lines) = if rate.start.elapsed() < BANDWIDTH_WINDOW {
+            (rate.bytes, rate.lines) // 計測中のウィンドウ
+        } else {
+            (0, 0)
+        };
+        let messages = match self
+            .bucket
+            .remaining(config.max_messages_per_second, config.message_burst)
+        {
+            Some(left) => format!(
+                "残り{}件（最大{}件、1秒に{}件ずつ回復）",
+                left, config.message_burst, config.max_messages_per_second
+            ),
+            None => "無制限".to_string(),
+        };
+        let idle = if config.idle_minutes == 0 {
+            "なし".to_string()
+        } else {
+            format!("{}分入力がないと離席中", config.idle_minutes)
+        };
+        let mut notice = format!(
+            "SYSTEM> この接続に適用されている上限:\n\
+             SYSTEM>   一行: {}（{}）/ {}\n\
+             SYSTEM>   発言数: {}\n\
+             SYSTEM>   受信: 帯域 {}/{} / 行数 {}/{}\n\
+             SYSTEM>   部屋: {}（同時に入れる部屋は1つ、/join で移動）\n\
+             SYSTEM>   離席: {}\n",
+            limit(length, "バイト"),
+            length_source,
+            limit(config.max_message_chars, "文字"),
+            messages,
+            bytes,
+            limit(config.max_bytes_per_second, "バイト毎秒"),
+            lines,
+            limit(config.max_burst_lines, "行毎秒"),
+            room,
+            idle
+        );
+        if let Some(deadline) = self.oper_deadline(config) {
+            notice.push_str(&format!(
+                "SYSTEM>   オペレーター権限: あと{}秒で失効\n",
+                deadline.saturating_duration_since(Instant::now()).as_secs()
+            ));
+        }
+        let _ = self.send(&notice).await;
+    }
+
+    // /keepalive <on|off> : 入力がない間、サーバーからPINGを送って接続が生きているか確かめる
+    async fn cmd_keepalive(&mut self, args: &str, config: &init::Config) {
+        let notice = match args {
+            _ if config.ping_interval_seconds == 0 => {
+                "SYSTEM> このサーバーでは接続確認を使えません\n".to_string()
+            }
+            "on" => {
+                self.keepalive = true;
+                format!(
+                    "SYSTEM> 接続確認を有効にしました（{}秒入力がなければ PING <番号> を送るので、{}秒以内に /pong <番号> で応答してください）\n",
+                    config.ping_interval_seconds, config.ping_timeout_seconds
+                )
+            }
+            "off" => {
+                self.keepalive = false;
+                self.ping_sent = None;
+                "SYSTEM> 接続確認を無効にしました\n".to_string()
+            }
+            _ => "SYSTEM> 使い方: /keepalive <on|off>\n".to_string(),
+        };
+        let _ = self.send(&notice).await;
+    }
+
+    // /role [ハンドルネーム [役割]] : 役割を表示・付与する（管理者専用、付与した役割は本人の名前・認証が変わるまで有効）
+    async fn cmd_role(&mut self, args: &str, config: &init::Config) {
+        let mut parts = args.split_whitespace();
+        let (Some(name), role) = (parts.next(), parts.next()) else {
+            let notice = format!(
+                "SYSTEM> あなたの役割は {} です\n",
+                self.session.role().label()
+            );
+            let _ = self.send(&notice).await;
+            return;
+        };
+        let Some(target) = session::find_by_handle(&self.session.tenant, name) else {
+            let notice = format!("SYSTEM> {} は接続していません\n", name);
+            let _ = self.send(&notice).await;
+            return;
+        };
+        let Some(role) = role else {
+            let configured = config.role_for(
+                &target.tenant,
+                &target.handle_name(),
+                target.identified.load(Ordering::Relaxed),
+            );
+            let notice = format!(
+                "SYSTEM> {} の役割は {} です（設定ファイルでは {}）\n",
+                target.handle_name(),
+                target.role().label(),
+                configured.label()
+            );
+            let _ = self.send(&notice).await;
+            return;
+        };
+        let Some(role) = Role::parse(role) else {
+            let _ = self
+                .send("SYSTEM> 役割は guest|user|operator|admin のいずれかです\n")
+                .await;
+            return;
+        };
+        target.oper.store(false, Ordering::Relaxed); // /operの権限も付与した役割に置き換える
+        target.set_role(role);
+        audit::record(&format!(
+            "役割付与: {} -> {} ({} {})",
+            target.handle_name(),
+            role.as_str(),
+            self.peer_addr,
+            self.handle_name
+        )); // 監査ログ
+        target.deliver(format!(
+            "SYSTEM> 管理者によりあなたの役割が {} になりました\n",
+            role.label()
+        )); // 本人に通知
+        let notice = format!(
+            "SYSTEM> {} の役割を {} にしました\n",
+            target.handle_name(),
+            role.label()
+        );
+        let _ = self.send(&notice).await;
+    }
+
+    // 次に接続確認をする時刻（PINGを送る時刻か、応答待ちの期限）
+    fn keepalive_deadline(&self, config: &init::Config) -> Option<Instant> {
+        if !self.keepalive || config.ping_interval_seconds == 0 {
+            return None;
+        }
+        Some(match self.ping_sent {
+            Some(sent) => sent + Duration::from_secs(config.ping_timeout_seconds),
+            None => self.last_received + Duration::from_secs(config.ping_interval_seconds),
+        })
+    }
+
+    // 接続確認の時刻になった（応答待ちの期限切れなら切断すべきなのでfalse）
+    async fn on_keepalive(&mut self) -> bool {
+        if self.ping_sent.is_some() {
+            crate::printdaytimeln!(
+                "接続確認の応答なし: {} {}",
+                self.peer_addr,
+                self.handle_name
+            ); // ログ
+            return false;
+        }
+        self.ping_token += 1;
+        self.ping_sent = Some(Instant::now());
+        let _ = self.send(&format!("PING {}\n", self.ping_token)).await;
+        true
+    }
+
+    // /wall <本文> : 全クライアントへの強調表示付き通知（オペレーター専用）
+    async fn cmd_wall(&mut self, args: &str) {
+        if args.is_empty() {
+            let _ = self.send("SYSTEM> 使い方: /wall <本文>\n").await;
+            return;
+        }
+        let now = chrono::Local::now().with_timezone(&Tokyo); // 現在時刻
+        let notice = format!(
+            "!!! WALL> {} ({} {}) !!!\n",
+            args,
+            self.handle_name,
+            now.format("%Y/%m/%d %H:%M")
+        ); // 強調表示の全体通知
+        crate::printdaytimeln!("全体通知: {} {}", self.handle_name, args); // ログ
+        dispatch::broadcast_all(Some(&self.session.tenant), &notice); // 同じテナントの全セッションへ直接配送
+    }
+
+    // /whois <ハンドルネーム> : 接続情報の表示（オペレーターには詳細も表示）
+    async fn cmd_whois(&mut self, args: &str, config: &init::Config) {
+        if args.is_empty() {
+            let _ = self.send("SYSTEM> 使い方: /whois <ハンドルネーム>\n").await;
+            return;
+        }
+        let Some(target) = session::find_by_handle(&self.session.tenant, args) else {
+            let notice = format!("SYSTEM> {} は接続していません\n", args);
+            let _ = self.send(&notice).await;
+            return;
+        };
+        let mut info = format!(
+            "SYSTEM> {} : 接続方式 {}{} / 部屋 {} / 接続{}秒 / 最終入力{}秒前{}{}{}{}\n",
+            target.handle_name(),
+            target.transport.label(),
+            target
+                .window()
+                .map_or_else(String::new, |(width, height)| format!(
+                    " (端末{}x{})",
+                    width, height
+                )),
+            target.room(),
+            target.connected_at.elapsed().as_secs(),
+            target.last_active().elapsed().as_secs(),
+            if target.is_idle() { " / 離席中" } else { "" },
+            away_label(&target, " / "),
+            match target.role() {
+                Role::User => String::new(),
+                role => format!(" / {}", role.label()),
+            },
+            if target.identified.load(Ordering::Relaxed) {
+                " / 認証済み"
+            } else {
+                ""
+            }
+        ); // 誰でも見られる情報
+        if self.session.is_oper() {
+            // オペレーターにはアドレスと通信量も表示
+            info.push_str(&format!(
+                "SYSTEM>   セッション#{} {} / 受信{}バイト 送信{}バイト 破棄{}バイト / 接続品質 {}\n",
+                target.id,
+                target.peer_addr,
+                target.bytes_in.load(Ordering::Relaxed),
+                target.bytes_out.load(Ordering::Relaxed),
+                target.bytes_dropped.load(Ordering::Relaxed),
+                target.quality()
+            ));
+            if let Some(fingerprint) = target.fingerprint() {
+                info.push_str(&format!(
+                    "SYSTEM>   指紋 {} ({})\n",
+                    fingerprint.id, fingerprint.detail
+                )); // 接続拒否を逃れるための再接続の手がかり
+            }
+            info.push_str(&rate_report(&target, config)); // 受信制限の状態と違反履歴
+            if let Some(until) = target.muted_until() {
+                info.push_str(&format!(
+                    "SYSTEM>   ミュート中（残り{}秒）\n",
+                    until.saturating_duration_since(Instant::now()).as_secs()
+                ));
+            }
+        }
+        let _ = self.send(&info).await;
+    }
+
+    // /msg <ハンドルネーム> <本文> : 指定したユーザーにだけメッセージを送る
+    async fn cmd_msg(&mut self, args: &str, config: &init::Config) -> Option<DisconnectReason> {
+        let Some((to, text)) = args
+            .split_once(' ')
+            .map(|(to, text)| (to, text.trim()))
+            .filter(|(_, text)| !text.is_empty())
+        else {
+            let _ = self
+                .send("SYSTEM> 使い方: /msg <ハンドルネーム> <本文>\n")
+                .await;
+            return None;
+        };
+        let Some(queue) = names::sender(&self.session.tenant, to) else {
+            let notice = format!("SYSTEM> {} は接続していません\n", to);
+            let _ = self.send(&notice).await;
+            return None;
+        };
+        if let Some(reason) = self.check_filter(text, config).await {
+            return reason; // 禁止語を含むので破棄・切断
+        }
+        if let Some(reason) = self.check_repeat(text, config).await {
+            return reason; // 同じ発言の繰り返しなので破棄・切断
+        }
+        let ignored = session::find_by_handle(&self.session.tenant, to)
+            .is_some_and(|target| target.is_ignoring(&self.handle_name))
+            || self.session.muted_until().is_some(); // 相手に無視されているか、ミュート中なら控えだけ返して届けない
+        let now = chrono::Local::now().with_timezone(&Tokyo); // 現在時刻
+        let time_str = now.format("%Y/%m/%d %H:%M").to_string(); // タイムスタンプ
+        let text = mask_message(text, config); // 禁止語・FilterRegex（mask）の一致部分を伏字に
+        let private = format!("(私信) {}> {} ({})\n", self.handle_name, text, time_str); // 相手に届く形
+        if !ignored && queue.send(private).is_err() {
+            let notice = format!("SYSTEM> {} に届けられませんでした\n", to); // 切断直後
+            let _ = self.send(&notice).await;
+            return None;
+        }
+        let echo = format!(
+            "(私信 -> {}) {}> {} ({})\n",
+            to, self.handle_name, text, time_str
+        ); // 自分への控え
+        let _ = self.send(&echo).await;
+        if let Some(reason) =
+            session::find_by_handle(&self.session.tenant, to).and_then(|target| target.away())
+        {
+            let notice = if reason.is_empty() {
+                format!("SYSTEM> {} は不在です\n", to)
+            } else {
+                format!("SYSTEM> {} は不在です: {}\n", to, reason)
+            }; // 不在の相手からの自動返答
+            let _ = self.send(&notice).await;
+        }
+        None
+    }
+
+    // /ignore [ハンドルネーム] : 指定したユーザーの発言・私信・入退室を受け取らない（引数なしは一覧）
+    async fn cmd_ignore(&mut self, args: &str, config: &init::Config) {
+        if args.is_empty() {
+            let ignored = self.session.ignored();
+            let notice = if ignored.is_empty() {
+                "SYSTEM> 無視しているユーザーはいません\n".to_string()
+            } else {
+                format!("SYSTEM> 無視しているユーザー: {}\n", ignored.join(", "))
+            };
+            let _ = self.send(&notice).await;
+            return;
+        }
+        if args.len() > config.max_handle_name
+            || !args.chars().all(|c| !c.is_control() && !c.is_whitespace())
+        {
+            let _ = self
+                .send("SYSTEM> 使い方: /ignore <ハンドルネーム>\n")
+                .await;
+            return;
+        }
+        if args.to_lowercase() == self.handle_name.to_lowercase() {
+            let _ = self.send("SYSTEM> 自分は無視できません\n").await;
+            return;
+        }
+        if self.session.ignored().len() >= MAX_IGNORED && !self.session.is_ignoring(args) {
+            let notice = format!(
+                "SYSTEM> 無視できるのは{}人までです（/unignore で外してください）\n",
+                MAX_IGNORED
+            );
+            let _ = self.send(&notice).await;
+            return;
+        }
+        let notice = if self.session.ignore(args) {
+            format!("SYSTEM> {} を無視します（/unignore で解除）\n", args)
+        } else {
+            format!("SYSTEM> {} は既に無視しています\n", args)
+        }; // 接続していない名前も登録できる（接続してきたら無視する）
+        let _ = self.send(&notice).await;
+    }
+
+    // /unignore <ハンドルネーム> : 無視を解除する
+    async fn cmd_unignore(&mut self, args: &str) {
+        if args.is_empty() {
+            let _ = self
+                .send("SYSTEM> 使い方: /unignore <ハンドルネーム>\n")
+                .await;
+            return;
+        }
+        let notice = if self.session.unignore(args) {
+            format!("SYSTEM> {} の無視を解除しました\n", args)
+        } else {
+            format!("SYSTEM> {} は無視していません\n", args)
+        };
+        let _ = self.send(&notice).await;
+    }
+
+    // /away [理由] : 不在にする（/who・/whoisに表示し、私信の送り主に自動で知らせる）
+    async fn cmd_away(&mut self, args: &str, config: &init::Config) {
+        let reason = lists::mask_filtered(args); // 他のクライアントにも見えるので禁止語を伏字に
+        let was_away = self.session.set_away(Some(reason.clone())).is_some();
+        let notice = if reason.is_empty() {
+            "SYSTEM> 不在にしました（/back で解除）\n".to_string()
+        } else {
+            format!("SYSTEM> 不在にしました: {}（/back で解除）\n", reason)
+        };
+        let _ = self.send(&notice).await;
+        if !was_away {
+            self.announce_presence(config, "不在になりました"); // 理由の変更だけなら通知しない
+        }
+    }
+
+    // /back : 不在を解除する
+    async fn cmd_back(&mut self, config: &init::Config) {
+        if self.session.set_away(None).is_none() {
+            let _ = self.send("SYSTEM> 不在にしていません\n").await;
+            return;
+        }
+        let _ = self.send("SYSTEM> 不在を解除しました\n").await;
+        self.announce_presence(config, "戻りました");
+    }
+
+    // /join <部屋名> : 部屋を移動する（なければ作成）
+    async fn cmd_join(&mut self, args: &str, config: &init::Config) {
+        if !rooms::is_valid_name(args) {
+            let _ = self.send("SYSTEM> 使い方: /join <部屋名>\n").await;
+            return;
+        }
+        if self.session.room() == args {
+            let notice = format!("SYSTEM> 既に {} にいます\n", args);
+            let _ = self.send(&notice).await;
+            return;
+        }
+        let old = self.session.room();
+        if let Err(JoinError::InviteOnly) = rooms::join(&self.session, args, &config.default_room) {
+            let notice = format!(
+                "SYSTEM> {} は招待制です（部屋のオペレーターに /invite してもらってください）\n",
+                args
+            );
+            let _ = self.send(&notice).await;
+            return;
+        } // 部屋を移動
+        let _ = self.msg_tx.send(RoomMessage {
+            tenant: self.session.tenant.clone(),
+            room: old,
+            event: ChatEvent::Leave {
+                id: self.session.id,
+                who: self.handle_name.clone(),
+            },
+        }); // 元の部屋に退室を通知
+        self.announce_move(ChatEvent::Join {
+            id: self.session.id,
+            who: self.handle_name.clone(),
+        }); // 移動先の部屋に入室を通知
+        crate::printdaytimeln!("入室: {} {} -> {}", self.peer_addr, self.handle_name, args); // ログ
+        let notice = format!("SYSTEM> {} に入りました\n", args);
+        let _ = self.send(&notice).await;
+        self.announce_room_limits(config).await; // 入室した部屋の一行の上限
+        self.show_topic().await; // 入室した部屋の話題
+        self.show_pins(false).await; // 入室した部屋の固定告知
+        self.replay_recent(config).await; // 直近の会話
+    }
+
+    // /part : 今いる部屋を出て既定の部屋に戻る
+    async fn cmd_part(&mut self, config: &init::Config) {
+        if self.session.room() == config.default_room {
+            let _ = self.send("SYSTEM> 既定の部屋からは出られません\n").await;
+            return;
+        }
+        self.announce_move(ChatEvent::Leave {
+            id: self.session.id,
+            who: self.handle_name.clone(),
+        }); // 元の部屋に退室を通知
+        let _ = rooms::join(&self.session, &config.default_room, &config.default_room); // 既定の部屋に戻る（いつでも入れる）
+        self.announce_move(ChatEvent::Join {
+            id: self.session.id,
+            who: self.handle_name.clone(),
+        }); // 既定の部屋に入室を通知
+        crate::printdaytimeln!(
+            "退室: {} {} -> {}",
+            self.peer_addr,
+            self.handle_name,
+            config.default_room
+        ); // ログ
+        let notice = format!("SYSTEM> 部屋を出て {} に戻りました\n", config.default_room);
+        let _ = self.send(&notice).await;
+        self.announce_room_limits(config).await; // 既定の部屋の一行の上限
+        self.show_topic().await; // 既定の部屋の話題
+        self.show_pins(false).await; // 既定の部屋の固定告知
+        self.replay_recent(config).await; // 直近の会話
+    }
+
+    // 今いる部屋の直近のチャット行を再送する（HistoryLinesが0または発言がなければ何もしない）
+    async fn replay_recent(&mut self, config: &init::Config) {
+        let lines = self.visible(rooms::recent(
+            &self.session.tenant,
+            &self.session.room(),
+            config.history_lines,
+        ));
+        if lines.is_empty() {
+            return;
+        }
+        let mut replay = format!("SYSTEM> 直近の会話 ({}行)\n", lines.len());
+        for line in &lines {
+            replay.push_str(&line.text); // 各行は改行付き
+        }
+        replay.push_str("SYSTEM> ここまで\n");
+        let _ = self.send(&replay).await;
+    }
+
+    // 再送するチャット行から /ignore している相手の行を除く
+    fn visible(&self, lines: Vec<rooms::Line>) -> Vec<rooms::Line> {
+        lines
+            .into_iter()
+            .filter(|line| !self.session.is_ignoring(&line.from))
+            .collect()
+    }
+
+    // 今いる部屋の話題を表示する（設定されていなければ何も出さない）
+    async fn show_topic(&mut self) {
+        let room = self.session.room();
+        if let Some(topic) = rooms::topic(&self.session.tenant, &room) {
+            let notice = format!("SYSTEM> {} の話題: {}\n", room, topic);
+            let _ = self.send(&notice).await;
+        }
+    }
+
+    // 今いる部屋の固定告知を表示する（/pins以外では告知がなければ何も出さない）
+    async fn show_pins(&mut self, always: bool) {
+        let room = self.session.room();
+        let pins = rooms::pins(&self.session.tenant, &room);
+        if pins.is_empty() && !always {
+            return;
+        }
+        let mut list = format!("SYSTEM> {} の固定告知 ({}件)\n", room, pins.len());
+        for (i, pin) in pins.iter().enumerate() {
+            list.push_str(&format!("SYSTEM>   [{}] {}\n", i + 1, pin));
+        }
+        let _ = self.send(&list).await;
+    }
+
+    // /pin <本文> : 今いる部屋に告知を固定する（オペレーター専用）
+    async fn cmd_pin(&mut self, args: &str) {
+        if args.is_empty() {
+            let _ = self.send("SYSTEM> 使い方: /pin <本文>\n").await;
+            return;
+        }
+        if args.chars().count() > rooms::MAX_PIN_CHARS {
+            let notice = format!(
+                "SYSTEM> 固定告知が長すぎます（最大{}文字）\n",
+                rooms::MAX_PIN_CHARS
+            );
+            let _ = self.send(&notice).await;
+            return;
+        }
+        let room = self.session.room();
+        let now = chrono::Local::now().with_timezone(&Tokyo); // 現在時刻（JST）
+        let pin = format!(
+            "{} ({} {})",
+            args,
+            self.handle_name,
+            now.format("%Y/%m/%d %H:%M")
+        ); // 固定した人と日時を添える
+        if !rooms::pin(&self.session.tenant, &room, &pin) {
+            return; // 部屋に入っていない
+        }
+        crate::printdaytimeln!("告知固定: {} {} {}", room, self.handle_name, args); // ログ
+        let _ = self.msg_tx.send(RoomMessage {
+            tenant: self.session.tenant.clone(),
+            room,
+            event: ChatEvent::System(format!("告知が固定されました: {}", pin)),
+        }); // 部屋の参加者に通知
+    }
+
+    // /topic [本文] : 今いる部屋の話題を設定する（引数なしは表示）
+    async fn cmd_topic(&mut self, args: &str) {
+        let room = self.session.room();
+        if args.is_empty() {
+            if rooms::topic(&self.session.tenant, &room).is_none() {
+                let notice = format!("SYSTEM> {} の話題は設定されていません\n", room);
+                let _ = self.send(&notice).await;
+            }
+            self.show_topic().await;
+            return;
+        }
+        if args.chars().count() > rooms::MAX_TOPIC_CHARS {
+            let notice = format!(
+                "SYSTEM> 話題が長すぎます（最大{}文字）\n",
+                rooms::MAX_TOPIC_CHARS
+            );
+            let _ = self.send(&notice).await;
+            return;
+        }
+        let now = chrono::Local::now().with_timezone(&Tokyo); // 現在時刻（JST）
+        let topic = format!(
+            "{} ({} {})",
+            lists::mask_filtered(args),
+            self.handle_name,
+            now.format("%Y/%m/%d %H:%M")
+        ); // 設定した人と日時を添える
+        if !rooms::set_topic(&self.session.tenant, &room, &topic) {
+            return; // 部屋に入っていない
+        }
+        crate::printdaytimeln!("話題設定: {} {} {}", room, self.handle_name, args); // ログ
+        let _ = self.msg_tx.send(RoomMessage {
+            tenant: self.session.tenant.clone(),
+            room,
+            event: ChatEvent::System(format!("話題が変更されました: {}", topic)),
+        }); // 部屋の参加者に通知
+    }
+
+    // /slow [<秒>|off] / /slow server [<秒>|off] : 今いる部屋・サーバー全体のスローモードを表示・切り替える（オペレーター専用）
+    // 同じ人の発言の間隔を指定した秒数以上空けさせる（部屋のオペレーターは対象外）。切り替えは参加者に通知する
+    async fn cmd_slow(&mut self, args: &str) {
+        let room = self.session.room();
+        let (server, value) = match args.strip_prefix("server") {
+            Some(rest) => (true, rest.trim()),
+            None => (false, args),
+        };
+        let label = |seconds: u64| {
+            if seconds == 0 {
+                "なし".to_string()
+            } else {
+                format!("{}秒に1回", seconds)
+            }
+        };
+        if value.is_empty() {
+            let notice = format!(
+                "SYSTEM> スローモード: 部屋 {} {} / サーバー全体 {}\n",
+                room,
+                label(rooms::slow_seconds(&self.session.tenant, &room).unwrap_or(0)),
+                label(rooms::server_slow_seconds(&self.session.tenant))
+            );
+            let _ = self.send(&notice).await;
+            return;
+        }
+        let seconds = match value {
+            "off" => Some(0),
+            _ => value
+                .parse::<u64>()
+                .ok()
+                .filter(|seconds| *seconds <= MAX_SLOW_SECONDS),
+        };
+        let Some(seconds) = seconds else {
+            let notice = format!(
+                "SYSTEM> 使い方: /slow [server] <秒|off>（{}秒まで）\n",
+                MAX_SLOW_SECONDS
+            );
+            let _ = self.send(&notice).await;
+            return;
+        };
+        let change = |target: &str| {
+            if seconds == 0 {
+                format!("{}のスローモードを解除しました", target)
+            } else {
+                format!("{}をスローモード（{}秒に1回）にしました", target, seconds)
+            }
+        };
+        if server {
+            rooms::set_server_slow_seconds(&self.session.tenant, seconds); // 同じテナントだけに適用
+            crate::printdaytimeln!(
+                "スローモード: (サーバー全体) {} {}",
+                self.handle_name,
+                seconds
+            ); // ログ
+            let notice = format!("SYSTEM> オペレーターが{}\n", change("サーバー全体"));
+            dispatch::broadcast_all(Some(&self.session.tenant), &notice); // 同じテナントの全セッションへ直接配送
+            return;
+        }
+        if !rooms::set_slow_seconds(&self.session.tenant, &room, seconds) {
+            let _ = self.send("SYSTEM> 部屋に入っていません\n").await;
+            return;
+        }
+        crate::printdaytimeln!("スローモード: {} {} {}", room, self.handle_name, seconds); // ログ
+        let _ = self.msg_tx.send(RoomMessage {
+            tenant: self.session.tenant.clone(),
+            room,
+            event: ChatEvent::System(format!("{} が{}", self.handle_name, change("この部屋"))),
+        }); // 部屋の参加者（自分を含む）に通知
+    }
+
+    // /mode [<+|-><i|m|s> | <+|-><o|v> <ハンドルネーム>] : 今いる部屋のモードを表示・変更する（部屋のオペレーター）
+    async fn cmd_mode(&mut self, args: &str, config: &init::Config) {
+        let room = self.session.room();
+        let tenant = self.session.tenant.clone();
+        let Some(modes) = rooms::modes(&tenant, &room) else {
+            return; // 部屋に入っていない
+        };
+        if args.is_empty() {
+            let flags = modes.flags();
+            let notice = format!(
+                "SYSTEM> {} のモード: {}\n",
+                room,
+                if flags.is_empty() { "なし" } else { &flags }
+            );
+            let _ = self.send(&notice).await;
+            return;
+        }
+        let (change, target) = args.split_once(' ').unwrap_or((args, ""));
+        let target = target.trim();
+        let mut chars = change.chars();
+        let on = match chars.next() {
+            Some('+') => true,
+            Some('-') => false,
+            _ => {
+                let _ = self
+                    .send("SYSTEM> 使い方: /mode [<+|-><i|m|s> | <+|-><o|v> <ハンドルネーム>]\n")
+                    .await;
+                return;
+            }
+        };
+        let flags: Vec<char> = chars.collect();
+        let member_flag = matches!(flags.as_slice(), ['o'] | ['v']);
+        if flags.is_empty()
+            || (!member_flag && !flags.iter().all(|c| matches!(c, 'i' | 'm' | 's')))
+            || member_flag == target.is_empty()
+        {
+            let _ = self
+                .send("SYSTEM> 使い方: /mode [<+|-><i|m|s> | <+|-><o|v> <ハンドルネーム>]\n")
+                .await;
+            return;
+        }
+        if !rooms::is_op(&self.session, &room) {
+            let _ = self
+                .send("SYSTEM> モードを変えられるのは部屋のオペレーターだけです\n")
+                .await;
+            return;
+        }
+        if member_flag {
+            let member = session::find_by_handle(&tenant, target)
+                .filter(|member| rooms::set_member(&tenant, &room, member.id, flags[0] == 'o', on));
+            let Some(member) = member else {
+                let notice = format!("SYSTEM> {} はこの部屋にいません\n", target);
+                let _ = self.send(&notice).await;
+                return;
+            };
+            let change = format!(
+                "{}{} {}",
+                if on { '+' } else { '-' },
+                flags[0],
+                member.handle_name()
+            );
+            self.announce_mode(&room, &change);
+            return;
+        }
+        if on && flags.contains(&'i') && room == config.default_room {
+            let _ = self.send("SYSTEM> 既定の部屋は招待制にできません\n").await;
+            return;
+        }
+        rooms::set_modes(&tenant, &room, |modes| {
+            for flag in &flags {
+                match flag {
+                    'i' => modes.invite_only = on,
+                    'm' => modes.moderated = on,
+                    _ => modes.secret = on,
+                }
+            }
+        });
+        self.announce_mode(&room, change);
+    }
+
+    // モードの変更を部屋の参加者に通知する
+    fn announce_mode(&self, room: &str, change: &str) {
+        crate::printdaytimeln!("モード変更: {} {} {}", room, self.handle_name, change); // ログ
+        let _ = self.msg_tx.send(RoomMessage {
+            tenant: self.session.tenant.clone(),
+            room: room.to_string(),
+            event: ChatEvent::System(format!(
+                "{} がモードを変更しました: {}",
+                self.handle_name, change
+            )),
+        });
+    }
+
+    // /invite <ハンドルネーム> : 今いる部屋に招待する（招待制の部屋でも次の1回だけ入れる、部屋のオペレーター）
+    async fn cmd_invite(&mut self, args: &str) {
+        if args.is_empty() {
+            let _ = self
+                .send("SYSTEM> 使い方: /invite <ハンドルネーム>\n")
+                .await;
+            return;
+        }
+        let room = self.session.room();
+        if !rooms::is_op(&self.session, &room) {
+            let _ = self
+                .send("SYSTEM> 招待できるのは部屋のオペレーターだけです\n")
+                .await;
+            return;
+        }
+        let Some(target) = session::find_by_handle(&self.session.tenant, args) else {
+            let notice = format!("SYSTEM> {} は接続していません\n", args);
+            let _ = self.send(&notice).await;
+            return;
+        };
+        if !rooms::invite(&self.session.tenant, &room, &target.handle_name()) {
+            return; // 部屋に入っていない
+        }
+        target.deliver(format!(
+            "SYSTEM> {} が {} に招待しました（/join {} で入れます）\n",
+            self.handle_name, room, room
+        )); // 招待された本人に知らせる
+        let notice = format!(
+            "SYSTEM> {} を {} に招待しました\n",
+            target.handle_name(),
+            room
+        );
+        let _ = self.send(&notice).await;
+    }
+
+    // /unpin <番号> : 今いる部屋の固定告知を外す（オペレーター専用）
+    async fn cmd_unpin(&mut self, args: &str) {
+        let room = self.session.room();
+        let Some(pin) = args
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| rooms::unpin(&self.session.tenant, &room, index))
+        else {
+            let _ = self
+                .send("SYSTEM> 使い方: /unpin <番号>（番号は /pins で確認）\n")
+                .await;
+            return;
+        };
+        crate::printdaytimeln!("告知解除: {} {} {}", room, self.handle_name, pin); // ログ
+        let notice = format!("SYSTEM> 固定告知を外しました: {}\n", pin);
+        let _ = self.send(&notice).await;
+    }
+
+    // /list : 部屋の一覧
+    async fn cmd_list(&mut self) {
+        let current = self.session.room(); // 今いる部屋
+        let rooms: Vec<_> = rooms::list(&self.session.tenant)
+            .into_iter()
+            .filter(|(name, _, _, modes)| {
+                !modes.secret || *name == current || self.session.is_oper()
+            })
+            .collect(); // 非公開の部屋は参加者とオペレーターにだけ見せる
+        let mut list = format!("SYSTEM> 部屋一覧 ({}件)\n", rooms.len());
+        for (name, members, age, modes) in rooms {
+            list.push_str(&format!(
+                "SYSTEM>   {}{} {}人 (作成{}分前){}\n",
+                if name == current { "*" } else { " " },
+                name,
+                members,
+                age / 60,
+                if modes.flags().is_empty() {
+                    String::new()
+                } else {
+                    format!(" {}", modes.flags())
+                }
+            ));
+        }
+        let _ = self.send(&list).await;
+    }
+
+    // /who : 同じテナントで接続中のハンドルネームの一覧（部屋・接続時間・無操作時間）
+    async fn cmd_who(&mut self) {
+        let mut users: Vec<_> = session::snapshot()
+            .into_iter()
+            .filter(|target| target.tenant == self.session.tenant && !target.is_pending())
+            .collect(); // ハンドルネーム未定義の接続は出さない
+        users.sort_by_key(|target| target.id); // 接続順に並べる
+        let current = self.session.room();
+        let mut list = format!("SYSTEM> 接続中のユーザー ({}人)\n", users.len());
+        for target in users {
+            let mut room = target.room();
+            if room != current
+                && !self.session.is_oper()
+                && rooms::modes(&target.tenant, &room).is_some_and(|modes| modes.secret)
+            {
+                room = "非公開".to_string(); // 非公開の部屋の名前は参加者とオペレーターにだけ見せる
+            }
+            list.push_str(&format!(
+                "SYSTEM>   {} [{}] 接続{}分 無操作{}分{}{}\n",
+                target.handle_name(),
+                room,
+                target.connected_at.elapsed().as_secs() / 60,
+                target.last_active().elapsed().as_secs() / 60,
+                if target.is_idle() { " 離席中" } else { "" },
+                away_label(&target, " ")
+            ));
+        }
+        let _ = self.send(&list).await;
+    }
+
+    // /quiet [on|off] : 離席・復帰などの重要度の低い通知を受け取らない
+    async fn cmd_quiet(&mut self, args: &str) {
+        let quiet = match args {
+            "on" => true,
+            "off" => false,
+            "" => self.session.is_quiet(), // 引数なしは現在の設定を表示
+            _ => {
+                let _ = self.send("SYSTEM> 使い方: /quiet [on|off]\n").await;
+                return;
+            }
+        };
+        self.session.quiet.store(quiet, Ordering::Relaxed); // 設定を反映
+        let notice = if quiet {
+            "SYSTEM> quietモード: on（離席・復帰などの通知を省略します）\n"
+        } else {
+            "SYSTEM> quietモード: off\n"
+        };
+        let _ = self.send(notice).await;
+    }
+
+    // /color [on|off] : 送信する行（SYSTEM>の行・ハンドルネーム・発言時刻）をANSIエスケープで色付けするか切り替える
+    async fn cmd_color(&mut self, args: &str) {
+        let color = match args {
+            "on" => true,
+            "off" => false,
+            "" => self.session.wants_color(), // 引数なしは現在の設定を表示
+            _ => {
+                let _ = self.send("SYSTEM> 使い方: /color [on|off]\n").await;
+                return;
+            }
+        };
+        if color && self.session.transport.is_machine() {
+            let _ = self
+                .send("SYSTEM> この接続方式では色付けできません\n")
+                .await; // 構造化メッセージを壊さない
+            return;
+        }
+        self.session.color.store(color, Ordering::Relaxed); // 設定を反映（この後に送る行から）
+        let notice = if color {
+            "SYSTEM> colorモード: on\n"
+        } else {
+            "SYSTEM> colorモード: off\n"
+        };
+        let _ = self.send(notice).await;
+    }
+
+    // /ack [on|off|<ID>] : チャット行をメッセージID付きで受け取るか切り替える・受け取ったIDを確認する
+    async fn cmd_ack(&mut self, args: &str) {
+        let acks = match args {
+            "on" => true,
+            "off" => false,
+            "" => self.session.wants_ids(), // 引数なしは現在の設定を表示
+            _ => {
+                let Ok(id) = args.parse::<u64>() else {
+                    let _ = self.send("SYSTEM> 使い方: /ack [on|off|<ID>]\n").await;
+                    return;
+                };
+                let last = rooms::last_seq(&self.session.tenant, &self.session.room());
+                self.session
+                    .acked
+                    .fetch_max(id.min(last), Ordering::Relaxed); // 受け取り確認（戻さない、まだ使われていないIDは最後のIDまで）
+                return; // 確認の度に応答しない
+            }
+        };
+        self.session.acks.store(acks, Ordering::Relaxed); // 設定を反映
+        let notice = if acks {
+            format!(
+                "SYSTEM> ackモード: on（チャット行の前に #ID を付けます。最後のID: {}）\n",
+                rooms::last_seq(&self.session.tenant, &self.session.room())
+            )
+        } else {
+            "SYSTEM> ackモード: off\n".to_string()
+        };
+        let _ = self.send(&notice).await;
+    }
+
+    // /resend [ID] : 指定したID（省略時は最後に /ack したID）より後のチャット行を直近の行から再送する
+    async fn cmd_resend(&mut self, args: &str) {
+        let (tenant, room) = (&self.session.tenant, self.session.room());
+        let last = rooms::last_seq(tenant, &room); // 最後に採番したID
+        let after = match args.parse::<u64>() {
+            _ if args.is_empty() => self.session.acked.load(Ordering::Relaxed).min(last),
+            Ok(id) if id <= last => id,
+            _ => {
+                let usage = format!("SYSTEM> 使い方: /resend [ID]（IDは{}まで）\n", last);
+                let _ = self.send(&usage).await;
+                return;
+            }
+        };
+        let lines = rooms::recent_since(tenant, &room, after);
+        let first = lines.first().map_or(last + 1, |line| line.id); // 再送できる最初のID
+        let mut replay = String::new();
+        if let Some((from, to)) = unsaved_range(after, first) {
+            replay.push_str(&format!(
+                "SYSTEM> #{}〜#{} は保存されていないため再送できません\n",
+                from, to
+            )); // 直近の行から捨てられた分
+        }
+        let lines = self.visible(lines); // 欠けの判定は無視している相手の行も含めて行う
+        replay.push_str(&format!("SYSTEM> 再送 ({}行)\n", lines.len()));
+        for line in &lines {
+            replay.push_str(&dispatch::with_id(line.id, &line.text)); // 各行は改行付き
+        }
+        replay.push_str("SYSTEM> ここまで\n");
+        let _ = self.send(&replay).await;
+    }
+
+    // /reload [--dry-run] : 設定を再読込する（--dry-runは検査と差分の表示のみ、管理者専用）
+    async fn cmd_reload(&mut self, args: &str) {
+        match args {
+            "" => {
+                crate::printdaytimeln!("設定再読込: {} {}", self.peer_addr, self.handle_name); // ログ
+                crate::server::reload(&self.shutdown_tx); // SIGHUPと同じ処理
+            }
+            "--dry-run" => {
+                let report = dry_run_report(&self.session.tenant); // 適用せずに検査
+                let _ = self.send(&report).await;
+            }
+            _ => {
+                let _ = self.send("SYSTEM> 使い方: /reload [--dry-run]\n").await;
+            }
+        }
+    }
+
+    // /maintenance <分> : メンテナンス予告を同じテナントの全クライアントへ通知（オペレーター専用）
+    async fn cmd_maintenance(&mut self, args: &str) {
+        let Ok(eta_minutes) = args.parse::<u64>() else {
+            let _ = self.send("SYSTEM> 使い方: /maintenance <分>\n").await;
+            return;
+        };
+        crate::printdaytimeln!("メンテナンス予告: {} {}分後", self.handle_name, eta_minutes); // ログ
+        let _ = self.shutdown_tx.send(ShutdownReason::Maintenance {
+            tenant: self.session.tenant.clone(),
+            eta: eta_minutes,
+        }); // 部屋を問わず同じテナントの全クライアントに通知
+    }
+
+    // /stats : サーバー統計の表示（オペレーター専用）
+    async fn cmd_stats(&mut self) {
+        let _ = self.send(&stats::report()).await;
+    }
+
+    // /trace <on|off> <セッションID> : 指定セッションの送受信内容をログに出す（オペレーター専用）
+    async fn cmd_trace(&mut self, args: &str) {
+        let parsed = match args.split_once(' ') {
+            Some(("on", id)) => id.trim().parse::<u64>().ok().map(|id| (true, id)),
+            Some(("off", id)) => id.trim().parse::<u64>().ok().map(|id| (false, id)),
+            _ => None,
+        };
+        let Some((on, id)) = parsed else {
+            let _ = self
+                .send("SYSTEM> 使い方: /trace <on|off> <セッションID>（IDは /sessions で確認）\n")
+                .await;
+            return;
+        };
+        let Some(target) =
+            session::find_by_id(id).filter(|target| target.tenant == self.session.tenant)
+        else {
+            let notice = format!("SYSTEM> セッション #{} は存在しません\n", id); // 他のテナントの接続は見えない
+            let _ = self.send(&notice).await;
+            return;
+        };
+        target.trace.store(on, Ordering::Relaxed); // 次の送受信から反映
+        crate::printdaytimeln!(
+            "トレース{}: #{} {} ({} {})",
+            if on { "開始" } else { "終了" },
+            id,
+            target.peer_addr,
+            self.peer_addr,
+            self.handle_name
+        ); // ログ
+        let notice = format!(
+            "SYSTEM> セッション #{} のトレースを{}にしました\n",
+            id,
+            if on { "on" } else { "off" }
+        );
+        let _ = self.send(&notice).await;
+    }
+
+    // /sessions : 同じテナントの全セッションの一覧（オペレーター専用）
+    async fn cmd_sessions(&mut self) {
+        let mut sessions: Vec<_> = session::snapshot()
+            .into_iter()
+            .filter(|target| target.tenant == self.session.tenant)
+            .collect(); // 他のテナントの接続は管理用ソケットの list で見る
+        sessions.sort_by_key(|session| session.id); // 接続順に並べる
+        let mut list = format!("SYSTEM> セッション一覧 ({}件)\n", sessions.len());
+        for target in sessions {
+            let name = target.handle_name();
+            list.push_str(&format!(
+                "SYSTEM>   #{} {} {} {} [{}] 接続{}秒 品質{} 指紋{}{}{}\n",
+                target.id,
+                target.transport.label(),
+                target.peer_addr,
+                if name.is_empty() {
+                    "(未定義)"
+                } else {
+                    &name
+                },
+                target.qualified_room(),
+                target.connected_at.elapsed().as_secs(),
+                target.quality(),
+                target.fingerprint_id(),
+                if target.is_idle() { " 離席中" } else { "" },
+                if target.is_tracing() {
+                    " トレース中"
+                } else {
+                    ""
+                }
+            ));
+        }
+        let _ = self.send(&list).await;
+    }
+
+    // /nick <新しい名前> : 自分のハンドルネームを変更する（入力し直さずに付け替え、他のクライアントに通知）
+    async fn cmd_nick(&mut self, args: &str, config: &init::Config) {
+        if args.is_empty() {
+            let _ = self.send("SYSTEM> 使い方: /nick <新しい名前>\n").await;
+            return;
+        }
+        if args.len() > config.max_handle_name
+            || !args.chars().all(|c| !c.is_control() && !c.is_whitespace())
+        {
+            let _ = self
+                .send("SYSTEM> 新しい名前はハンドルネームとして使えません\n")
+                .await;
+            return;
+        }
+        if args == self.handle_name {
+            let _ = self.send("SYSTEM> 今のハンドルネームと同じです\n").await;
+            return;
+        }
+        let old = self.handle_name.clone();
+        if let Err(e) = names::rename(&self.session.tenant, &old, args, &self.session.queue) {
+            let notice = match e {
+                ClaimError::InUse => format!("SYSTEM> {} は使用中です\n", args),
+                ClaimError::Reserved => format!("SYSTEM> {} は予約されています\n", args),
+            };
+            let _ = self.send(&notice).await;
+            return;
+        }
+        self.handle_name = args.to_string(); // 登録簿は付け替え済み
+        self.session.set_handle_name(&self.handle_name); // 他タスクから参照できるよう反映
+        self.record_span_name(); // ログのハンドルネームも変更
+        crate::printdaytimeln!("名前変更: {} {} -> {}", self.peer_addr, old, args); // ログ
+        publish_rename(&self.msg_tx, &self.session, &old); // 無視リストの付け替えと他のクライアントへの通知
+        let notice = format!("SYSTEM> ハンドルネームを {} に変更しました\n", args);
+        let _ = self.send(&notice).await;
+        self.session.identified.store(false, Ordering::Relaxed); // 認証は名前ごと
+        self.apply_role(config); // 役割も名前ごと
+        self.identify_deadline = None;
+        self.identify_failures = 0;
+        self.drop_confirm_until = None;
+        self.require_identify(config).await; // 変更先が登録済みなら認証を求める
+    }
+
+    // /mute <ハンドルネーム> <分> : 指定した時間だけ発言・私信を止める（オペレーター専用、0で解除）
+    // 本人には知らせず、発言は普段どおり受け付けるが誰にも届けない。期限が来たらタイマーのタスクが解除する
+    async fn cmd_mute(&mut self, args: &str) {
+        let Some((name, minutes)) = args
+            .split_once(' ')
+            .and_then(|(name, minutes)| Some((name, minutes.trim().parse::<u64>().ok()?)))
+            .filter(|(_, minutes)| *minutes <= MAX_MUTE_MINUTES)
+        else {
+            let notice = format!(
+                "SYSTEM> 使い方: /mute <ハンドルネーム> <分>（{}分まで、0で解除）\n",
+                MAX_MUTE_MINUTES
+            );
+            let _ = self.send(&notice).await;
+            return;
+        };
+        let Some(target) = session::find_by_handle(&self.session.tenant, name) else {
+            let notice = format!("SYSTEM> {} は接続していません\n", name);
+            let _ = self.send(&notice).await;
+            return;
+        };
+        let name = target.handle_name();
+        if minutes == 0 {
+            let notice = if target.muted_until().is_some() {
+                target.set_muted_until(None);
+                audit::record(&format!(
+                    "ミュート解除: {} ({} {})",
+                    name, self.peer_addr, self.handle_name
+                )); // 監査ログ
+                format!("SYSTEM> {} のミュートを解除しました\n", name)
+            } else {
+                format!("SYSTEM> {} はミュートされていません\n", name)
+            };
+            let _ = self.send(&notice).await;
+            return;
+        }
+        let until = Instant::now() + Duration::from_secs(minutes * 60);
+        target.set_muted_until(Some(until)); // 掛け直した場合は前のタイマーでは解除されない
+        audit::record(&format!(
+            "ミュート: {} {}分 ({} {})",
+            name, minutes, self.peer_addr, self.handle_name
+        )); // 監査ログ
+        tokio::spawn(async move {
+            tokio::time::sleep_until(until).await;
+            if target.expire_mute(until) {
+                crate::printdaytimeln!("ミュート期限切れ: {}", target.handle_name());
+                // ログ
+            }
+        }); // 期限が来たら解除するタイマー
+        let notice = format!("SYSTEM> {} を{}分ミュートしました\n", name, minutes);
+        let _ = self.send(&notice).await;
+    }
+
+    // /rename <ハンドルネーム> <新しい名前> : ハンドルネームを強制的に変更する（オペレーター専用）
+    async fn cmd_rename(&mut self, args: &str, config: &init::Config) {
+        let Some((old, new)) = args
+            .split_once(' ')
+            .map(|(old, new)| (old, new.trim()))
+            .filter(|(_, new)| !new.is_empty())
+        else {
+            let _ = self
+                .send("SYSTEM> 使い方: /rename <ハンドルネーム> <新しい名前>\n")
+                .await;
+            return;
+        };
+        if new.len() > config.max_handle_name
+            || !new.chars().all(|c| !c.is_control() && !c.is_whitespace())
+        {
+            let _ = self
+                .send("SYSTEM> 新しい名前はハンドルネームとして使えません\n")
+                .await;
+            return;
+        }
+        let Some(target) = session::find_by_handle(&self.session.tenant, old) else {
+            let notice = format!("SYSTEM> {} は接続していません\n", old);
+            let _ = self.send(&notice).await;
+            return;
+        };
+        let old = &target.handle_name(); // 登録簿での表記（大文字・小文字）
+        if let Err(e) = names::rename(&target.tenant, old, new, &target.queue) {
+            let notice = match e {
+                ClaimError::InUse => format!("SYSTEM> {} は使用中です\n", new),
+                ClaimError::Reserved => format!("SYSTEM> {} は予約されています\n", new),
+            };
+            let _ = self.send(&notice).await;
+            return;
+        }
+        target.set_handle_name(new); // 他タスクから参照される名前を更新
+        target.request(Control::Rename(new.to_string())); // 本人のタスクに反映させる
+        crate::printdaytimeln!(
+            "強制名前変更: {} -> {} ({} {})",
+            old,
+            new,
+            self.peer_addr,
+            self.handle_name
+        ); // ログ
+        publish_rename(&self.msg_tx, &target, old); // /nickと同じく無視リストの付け替えと通知
+        let notice = format!("SYSTEM> {} を {} に変更しました\n", old, new);
+        let _ = self.send(&notice).await;
+    }
+
+    // /pending [expire [秒]] : ハンドルネーム未定義の接続の一覧・一括切断（オペレーター専用）
+    async fn cmd_pending(&mut self, args: &str) {
+        let mut pending: Vec<_> = session::snapshot()
+            .into_iter()
+            .filter(|session| session.tenant == self.session.tenant && session.is_pending())
+            .collect(); // 同じテナントのハンドルネーム未定義のセッション
+        pending.sort_by_key(|session| session.id); // 接続順に並べる
+        if let Some(rest) = args.strip_prefix("expire") {
+            // 指定秒数以上経過した接続を切断（省略時はすべて）
+            let rest = rest.trim();
+            let min_age = if rest.is_empty() {
+                Some(0) // 省略時は経過時間を問わない
+            } else {
+                rest.parse::<u64>().ok()
+            };
+            let Some(min_age) = min_age else {
+                let _ = self.send("SYSTEM> 使い方: /pending expire [秒]\n").await;
+                return;
+            };
+            let mut expired = 0;
+            for target in pending {
+                if target.connected_at.elapsed().as_secs() >= min_age {
+                    target.request_disconnect(DisconnectReason::Expired); // 切断を要求
+                    expired += 1;
+                }
+            }
+            crate::printdaytimeln!(
+                "未定義接続の一括切断: {}件 ({}秒以上, {} {})",
+                expired,
+                min_age,
+                self.peer_addr,
+                self.handle_name
+            ); // ログ
+            let notice = format!("SYSTEM> 未定義の接続を{}件切断しました\n", expired);
+            let _ = self.send(&notice).await;
+            return;
+        }
+        let mut list = format!("SYSTEM> ハンドルネーム未定義の接続 ({}件)\n", pending.len());
+        for target in pending {
+            list.push_str(&format!(
+                "SYSTEM>   #{} {} {} 接続{}秒 受信{}バイト\n",
+                target.id,
+                target.transport.label(),
+                target.peer_addr,
+                target.connected_at.elapsed().as_secs(),
+                target.bytes_in.load(Ordering::Relaxed)
+            ));
+        }
+        let _ = self.send(&list).await;
+    }
+}
+
+// クライアントとの通信処理（1接続あたり1タスク）
+pub async fn handle_client<S: Transport>(
+    stream: S,                                  // クライアントとの通信路（TCP・インメモリ等）
+    peer_addr: String,                          // クライアントアドレス（ログ・表示用）
+    transport: TransportKind,                   // 接続元の通信路種別
+    tenant: &str,                               // 接続を受け付けたテナント（既定の待受なら空）
+    setup_permit: Option<OwnedSemaphorePermit>, // 接続準備の同時実行枠（準備が終わったら手放す）
+    shutdown_tx: broadcast::Sender<ShutdownReason>, // サーバーからの停止・再読込通知用
+    msg_tx: broadcast::Sender<RoomMessage>,     // メッセージ送信用
+) {
+    let (_active, active) = ActiveClient::start(); // 接続中の数に数える（タスク終了で戻す）
+    let max_clients = init::CONFIG.read().unwrap().max_clients; // 同時接続数の上限
+    if max_clients != 0 && active > max_clients {
+        // 満員なら受け付けた接続に通知して閉じる（セッションは作らない）
+        let mut stream = stream;
+        let reason = DisconnectReason::ServerFull; // 切断理由
+        stats::incr(&stats::CLIENTS_REJECTED_FULL);
+        crate::printdaytimeln!("接続拒否: {} [{}]", peer_addr, reason.code()); // ログ
+        if let Some(notice) = reason.notice() {
+            let _ = tokio::time::timeout(CLOSE_TIMEOUT, async {
+                let _ = stream.write_all(notice.as_bytes()).await; // 拒否理由を通知
+                let _ = stream.shutdown().await;
+            })
+            .await; // 受信しない相手に待たされないよう上限を設ける
+        }
+        return;
+    }
+    // この接続のログすべてに接続元とハンドルネーム（確定後）を付ける
+    let span = tracing::info_span!("conn", peer = %peer_addr, handle = tracing::field::Empty);
+    serve_client(
+        stream,
+        peer_addr,
+        transport,
+        tenant,
+        setup_permit,
+        shutdown_tx,
+        msg_tx,
+    )
+    .instrument(span)
+    .await
+}
+
+// 接続中のクライアント数への登録（作ったら数え、破棄したら戻す）
+struct ActiveClient;
+
+impl ActiveClient {
+    // 登録して、自分を含めた接続中の数を返す
+    fn start() -> (Self, usize) {
+        let active = stats::ACTIVE_CLIENTS.fetch_add(1, Ordering::Relaxed) + 1;
+        (ActiveClient, active)
+    }
+}
+
+impl Drop for ActiveClient {
+    fn drop(&mut self) {
+        stats::ACTIVE_CLIENTS.fetch_sub(1, Ordering::Relaxed); // パニック・中断でも戻す
+    }
+}
+
+// 1接続分の処理本体（handle_clientが接続のスパンの中で呼ぶ）
+async fn serve_client<S: Transport>(
+    stream: S,
+    peer_addr: String,
+    transport: TransportKind,
+    tenant: &str,
+    setup_permit: Option<OwnedSemaphorePermit>,
+    shutdown_tx: broadcast::Sender<ShutdownReason>,
+    msg_tx: broadcast::Sender<RoomMessage>,
+) {
+    let mut shutdown_rx = shutdown_tx.subscribe(); // 停止・再読込通知の受信用
+    let mut buf = [0u8; 1024]; // 受信バッファ
+    let config = init::CONFIG.read().unwrap().for_tenant(tenant); // 設定値を取得（テナントごとの指定を反映）
+    let (session, queue_rx) = session::register(&peer_addr, transport, tenant); // セッション登録と送信キュー作成
+    session
+        .color
+        .store(config.color && transport.is_terminal(), Ordering::Relaxed); // 端末から接続したクライアントだけ既定で色付けする
+    let family = peer_addr
+        .parse::<std::net::SocketAddr>()
+        .ok()
+        .map(|addr| stats::family(&addr.ip())); // アドレスファミリー別統計
+    if let Some(family) = family {
+        stats::incr(&family.connections); // ファミリー別の接続数
+    }
+    let quality_probe = stream.quality_probe(); // 読み書きに分ける前に取得方法を控える
+    let declared = fingerprint::take_declared(&peer_addr); // 通信路が受け付け時に知った機能
+    let (reader, writer) = tokio::io::split(stream); // 書き込みは専用タスクに任せる
+    let outbound = outbound::spawn(writer, queue_rx, Arc::clone(&session), family);
+    let mut client = Client {
+        reader,
+        outbound,
+        quality_probe,
+        peer_addr,
+        session,
+        msg_tx,
+        shutdown_tx,
+        handle_name: String::new(),
+        phase: if config.password.is_some() { 2 } else { 0 }, // パスワードがあればハンドルネームより先に求める
+        traffic: Traffic::new(),
+        bucket: TokenBucket::new(),
+        line_buf: Vec::new(),
+        telnet: Telnet::new(),
+        family,
+        oper_since: None,
+        quality_sampled_at: None,
+        identify_deadline: None,
+        identify_failures: 0,
+        name_failures: 0,
+        password_failures: 0,
+        declared: Some(declared),
+        drop_confirm_until: None,
+        keepalive: false,
+        last_received: Instant::now(),
+        ping_sent: None,
+        ping_token: 0,
+        registered_here: false,
+        oversize_offended: false,
+        last_posted: None,
+        recent_lines: VecDeque::new(),
+        resume_token: None,
+    };
+    let session = Arc::clone(&client.session); // 切断要求の待ち受け用
+    let mut rebind_deadline: Option<Instant> = None; // 待受アドレス変更後、この接続を切断する時刻
+    let mut new_address: Option<String> = None; // 変更後の待受アドレス（再接続の案内用）
+    let cooling_down = names::is_cooling_down(&client.session.peer_ip()); // 入力に失敗し続けた接続元か
+    let greeted = if cooling_down {
+        Ok(()) // 締め出し中なら挨拶せずに切断する
+    } else {
+        client.greet(&config).await // ウェルカムメッセージ等を送信
+    };
+    drop(setup_permit); // 接続準備が終わったので枠を返す
+    let reason = if cooling_down {
+        DisconnectReason::NameAttempts // 締め出し中
+    } else if greeted.is_err() {
+        DisconnectReason::Closed // クライアントに送信し失敗したら切断
+    } else {
+        loop {
+            // メインループ（切断理由を返して終了）
+            if client.phase == 2 && !client.session.transport.is_machine() {
+                // 接続パスワード待ちなら入力促し
+                let prompt = "SYSTEM> 接続パスワードを入力してください\n"; // 入力促しメッセージ
+                if client.send(prompt).await.is_err() {
+                    // 送信失敗時は切断
+                    break DisconnectReason::Closed;
+                }
+            } else if client.phase == 0
+                && client.handle_name.is_empty()
+                && !client.session.transport.is_machine()
+            {
+                // ハンドルネーム未定義なら入力促し
+                let prompt = "SYSTEM> ハンドルネームを入力してください\n"; // 入力促しメッセージ
+                if client.send(prompt).await.is_err() {
+                    // 送信失敗時は切断
+                    break DisconnectReason::Closed;
+                }
+            }
+            let config = init::CONFIG
+                .read()
+                .unwrap()
+                .for_tenant(&client.session.tenant); // 設定を都度取得
+            client.sample_quality(); // 一定間隔でTCP接続の状態を記録
+            let throttled_until = client
+                .traffic
+                .throttled_until(config.max_bytes_per_second, config.bandwidth_policy); // 帯域超過中か
+            let idle_deadline = client.idle_deadline(&config); // 離席判定時刻
+            let oper_deadline = client.oper_deadline(&config); // オペレーター権限の失効時刻
+            let identify_deadline = client.identify_deadline; // 登録済みのハンドルネームの認証期限
+            let keepalive_deadline = client.keepalive_deadline(&config); // 接続確認の時刻
+            tokio::select! {
+                // クライアントからの入力（queueポリシーで帯域超過中は読み込みを止める）
+                result = client.reader.read(&mut buf), if throttled_until.is_none() => {
+                    let n: usize = result.unwrap_or_default(); // 読み込みエラーは切断扱い（0バイト）
+                    if n == 0 {
+                        break DisconnectReason::Closed; // 接続が閉じられた
+                    }
+                    if let Some(reason) = client.on_input(&buf[..n], &config).await {
+                        break reason; // CTRL-C/CTRL-Dまたはハンドルネームの入力失敗で切断
+                    }
+                }
+                // 帯域超過で読み込みを止めている間は次のウィンドウまで待つ
+                _ = tokio::time::sleep_until(throttled_until.unwrap_or_else(Instant::now)), if throttled_until.is_some() => {}
+                // 一定時間入力がなければ離席中にする
+                _ = tokio::time::sleep_until(idle_deadline.unwrap_or_else(Instant::now)), if idle_deadline.is_some() => {
+                    client.mark_idle(&config);
+                }
+                // 無操作・有効期間切れでオペレーター権限を失効させる
+                _ = tokio::time::sleep_until(oper_deadline.unwrap_or_else(Instant::now)), if oper_deadline.is_some() => {
+                    client.expire_oper().await;
+                }
+                // 登録済みのハンドルネームを認証しないまま猶予が過ぎたら未定義に戻す
+                _ = tokio::time::sleep_until(identify_deadline.unwrap_or_else(Instant::now)), if identify_deadline.is_some() => {
+                    client.expire_identify().await;
+                }
+                // 入力がなければPINGを送り、応答がなければ切断
+                _ = tokio::time::sleep_until(keepalive_deadline.unwrap_or_else(Instant::now)), if keepalive_deadline.is_some() => {
+                    if !client.on_keepalive().await {
+                        break DisconnectReason::PingTimeout;
+                    }
+                }
+                // オペレーター操作などで他のタスクから要求が届いた場合
+                control = session.next_control() => {
+                    match control {
+                        Control::Disconnect(reason) => break reason, // 切断
+                        Control::Rename(new_name) => client.on_forced_rename(new_name, &config).await, // 強制変更
+                    }
+                }
+                // 待受アドレス変更後の猶予が過ぎたら切断
+                _ = tokio::time::sleep_until(rebind_deadline.unwrap_or_else(Instant::now)), if rebind_deadline.is_some() => {
+                    break DisconnectReason::ServerRestart;
+                }
+                // サーバー再起動通知受信時
+                Ok(shutdown) = shutdown_rx.recv() => {
+                    if matches!(&shutdown, ShutdownReason::Maintenance { tenant, .. } if *tenant != client.session.tenant) {
+                        continue; // 他のテナントのメンテナンス予告
+                    }
+                    if let ShutdownReason::AddressChange { new_address: address } = &shutdown {
+                        new_address = Some(address.clone());
+                        if config.rebind_grace_seconds > 0 {
+                            // 古いアドレスで確立済みの接続は猶予の間だけ維持する（既に猶予中なら延長しない）
+                            if rebind_deadline.is_none() {
+                                rebind_deadline = Some(
+                                    Instant::now() + Duration::from_secs(config.rebind_grace_seconds),
+                                );
+                            }
+                            let notice = format!(
+                                "SYSTEM> サーバーの待受アドレスが {} に変わりました（この接続は{}秒後に切断されます）\n",
+                                address, config.rebind_grace_seconds
+                            );
+                            let _ = client.send(&notice).await;
+                            continue;
+                        }
+                    }
+                    if let Some(notice) = shutdown.notice() {
+                        let _ = client.send(&notice).await; // 再読込・メンテナンス予告・アドレス変更を通知
+                    }
+                    if let Some(reason) = shutdown.disconnect_reason() {
+                        break reason; // 終了・再バインド時は切断
+                    }
+                }
+            }
+        }
+    };
+    if !cooling_down
+        && matches!(
+            reason,
+            DisconnectReason::Flood
+                | DisconnectReason::Spam
+                | DisconnectReason::Filtered
+                | DisconnectReason::Repeated
+                | DisconnectReason::NameAttempts
+                | DisconnectReason::PasswordAttempts
+        )
+    {
+        autoban::offend(&client.session.peer_ip(), reason.code()); // 違反による切断を数える
+    }
+    let retry_after = init::CONFIG.read().unwrap().reconnect_after_seconds; // 再接続を勧める秒数
+    let advice = match reason {
+        DisconnectReason::ServerShutdown if retry_after > 0 => Some(ReconnectAdvice {
+            retry_after,
+            address: None, // 同じアドレスで再起動を待つ
+        }),
+        DisconnectReason::ServerRestart => Some(ReconnectAdvice {
+            retry_after,
+            address: new_address, // 新しい待受はすでに動いている
+        }),
+        _ => None,
+    };
+    if reason == DisconnectReason::Closed {
+        client.outbound.abort(); // 既に接続が閉じているので書き出さない
+    } else if tokio::time::timeout(CLOSE_TIMEOUT, client.close(reason, advice))
+        .await
+        .is_err()
+    {
+        // 相手が受信しないなどで書き出しが終わらない場合は諦めて閉じる
+        client.outbound.abort();
+        crate::printdaytimeln!(
+            "切断時の送信タイムアウト: {} {}",
+            client.peer_addr,
+            client.handle_name
+        ); // ログ
+    }
+    crate::printdaytimeln!(
+        "切断: {} {} [{}]",
+        client.peer_addr,
+        client.handle_name,
+        reason.code()
+    ); // 切断ログ（理由コード付き）
+    if client.session.is_oper() {
+        audit::record(&format!(
+            "オペレーター権限失効（切断）: {} {}",
+            client.peer_addr, client.handle_name
+        )); // 監査ログ
+    }
+    session::unregister(client.session.id); // 配送対象から外す
+    let resume_seconds = init::CONFIG.read().unwrap().resume_seconds; // 接続再開の猶予
+    let parked = match &client.resume_token {
+        Some(token)
+            if reason == DisconnectReason::Closed
+                && resume_seconds > 0
+                && !client.handle_name.is_empty() =>
+        {
+            // 通信断による切断では、トークンで戻ってくるまでハンドルネーム・部屋・認証状態を預かる
+            let room = client.session.room();
+            resume::park(
+                token,
+                resume::Parked {
+                    tenant: client.session.tenant.clone(),
+                    handle_name: client.handle_name.clone(),
+                    owner: client.session.peer_ip(),
+                    seen: if client.session.wants_ids() {
+                        client.session.acked.load(Ordering::Relaxed) // 受け取りを確認した所から
+                    } else {
+                        rooms::last_seq(&client.session.tenant, &room)
+                    },
+                    room,
+                    identified: client.session.identified.load(Ordering::Relaxed),
+                    registered_here: client.registered_here,
+                },
+                Duration::from_secs(resume_seconds),
+            );
+            true
+        }
+        _ => false,
+    };
+    if !client.handle_name.is_empty() && init::CONFIG.read().unwrap().announce_connect {
+        client.announce_move(ChatEvent::Disconnect {
+            id: client.session.id,
+            who: client.handle_name.clone(),
+        }); // 他のクライアントに切断を通知
+    }
+    rooms::leave(&client.session, &init::CONFIG.read().unwrap().default_room); // 部屋から出る
+
+    // 切断時にハンドルネームを一覧から削除
+    if !client.handle_name.is_empty() {
+        let mut grace = init::CONFIG.read().unwrap().handle_reserve_seconds; // 予約期間
+        if parked {
+            grace = grace.max(resume_seconds); // 再開するまで他の接続に使わせない
+        }
+        if reason == DisconnectReason::Closed && grace > 0 {
+            // 通信断による切断では、同じ接続元が戻ってくるまでハンドルネームを予約
+            names::release_and_reserve(
+                &client.session.tenant,
+                &client.handle_name,
+                &client.session.queue,
+                &client.session.peer_ip(),
+                Duration::from_secs(grace),
+            );
+        } else {
+            names::release(
+                &client.session.tenant,
+                &client.handle_name,
+                &client.session.queue,
+            ); // 削除
+        }
+    }
+    crate::printdaytimeln!(
+        "通信量: {} {} 受信{}バイト 送信{}バイト 破棄{}バイト",
+        client.peer_addr,
+        client.handle_name,
+        client.session.bytes_in.load(Ordering::Relaxed),
+        client.session.bytes_out.load(Ordering::Relaxed),
+        client.session.bytes_dropped.load(Ordering::Relaxed)
+    ); // 接続全体の送受信量をログ出力
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport;
+    use tokio::io::DuplexStream;
+    use tokio::task::JoinHandle;
+
+    // 期待した文面が届かないまま止まらないよう、読み込みを待つ上限（時刻を止めたテストでは一気に進む）
+    const READ_LIMIT: Duration = Duration::from_secs(600);
+
+    // テスト用のサーバー側（停止通知とメッセージバス、配送タスク付き）
+    struct TestBus {
+        shutdown_tx: broadcast::Sender<ShutdownReason>,
+        msg_tx: broadcast::Sender<RoomMessage>,
+    }
+
+    impl TestBus {
+        fn new() -> Self {
+            let (shutdown_tx, _) = broadcast::channel(16);
+            let (msg_tx, msg_rx) = broadcast::channel(100);
+            tokio::spawn(dispatch::run(msg_rx)); // 各クライアントへの配送
+            TestBus {
+                shutdown_tx,
+                msg_tx,
+            }
+        }
+
+        // インメモリの通信路でhandle_clientにつなぎ、ハンドルネームを確定させる
+        async fn connect(&self, tenant: &str, name: &str) -> TestClient {
+            let mut client = self.open(tenant, &format!("memory-{}", name)).await;
+            client.send(name).await;
+            client.expect(&format!("{}さん、ようこそ", name)).await;
+            client
+        }
+
+        // インメモリの通信路でhandle_clientにつなぎ、ハンドルネームの入力を求められたところで止める
+        async fn open(&self, tenant: &str, peer_addr: &str) -> TestClient {
+            let (server_side, stream) = transport::memory_pair(4096);
+            let (tenant, peer_addr) = (tenant.to_string(), peer_addr.to_string());
+            let (shutdown_tx, msg_tx) = (self.shutdown_tx.clone(), self.msg_tx.clone());
+            let task = tokio::spawn(async move {
+                handle_client(
+                    server_side,
+                    peer_addr,
+                    TransportKind::Tcp,
+                    &tenant,
+                    None,
+                    shutdown_tx,
+                    msg_tx,
+                )
+                .await
+            });
+            let mut client = TestClient {
+                stream,
+                received: Vec::new(),
+                task,
+            };
+            client.expect("ハンドルネームを入力してください").await;
+            client
+        }
+    }
+
+    // テスト用のクライアント（インメモリの通信路の相手側）
+    struct TestClient {
+        stream: DuplexStream,
+        received: Vec<u8>, // 受信して、まだexpectで読み進めていない分
+        task: JoinHandle<()>,
+    }
+
+    impl TestClient {
+        // 1行送る
+        async fn send(&mut self, line: &str) {
+            self.stream
+                .write_all(format!("{}\n", line).as_bytes())
+                .await
+                .unwrap();
+        }
+
+        // 期待する文字列が届くまで読み、それまでに届いた文面を返す
+        async fn expect(&mut self, needle: &str) -> String {
+            let mut buf = [0u8; 1024];
+            let position = loop {
+                if let Some(position) = self
+                    .received
+                    .windows(needle.len())
+                    .position(|window| window == needle.as_bytes())
+                {
+                    break position;
+                }
+                let n = tokio::time::timeout(READ_LIMIT, self.stream.read(&mut buf))
+                    .await
+                    .unwrap_or_else(|_| panic!("{:?} が届かない: {}", needle, self.pending()))
+                    .expect("読み込みに失敗");
+                assert!(
+                    n > 0,
+                    "{:?} が届く前に切断された: {}",
+                    needle,
+                    self.pending()
+                );
+                self.received.extend_from_slice(&buf[..n]);
+            };
+            let read: Vec<u8> = self.received.drain(..position + needle.len()).collect();
+            String::from_utf8(read).unwrap()
+        }
+
+        // 切断して、サーバー側の後始末が終わるのを待つ
+        async fn close(self) {
+            drop(self.stream);
+            self.task.await.unwrap();
+        }
+
+        // まだ読み進めていない受信内容（失敗時の表示用）
+        fn pending(&self) -> String {
+            String::from_utf8_lossy(&self.received).into_owned()
+        }
+    }
+
+    // /resendの欠けの報告: 受け取り済みの次から再送できる最初の行の前までが欠け
+    #[test]
+    fn unsaved_range_reports_gap() {
+        assert_eq!(unsaved_range(0, 1), None); // 最初の行から残っている
+        assert_eq!(unsaved_range(2, 3), None); // 続きがそのまま残っている
+        assert_eq!(unsaved_range(0, 3), Some((1, 2))); // #1〜#2が捨てられた
+        assert_eq!(unsaved_range(4, 6), Some((5, 5)));
+        assert_eq!(unsaved_range(5, 6), None); // 再送する行がない（last + 1）
+        assert_eq!(unsaved_range(u64::MAX, 0), None); // 桁あふれしない
+    }
+
+    // インメモリの通信路でhandle_clientを動かし、連続投稿の制限と接続確認の期限切れを時刻を止めて確かめる
+    #[tokio::test(start_paused = true)]
+    async fn memory_pair_flood_and_ping_timeout() {
+        let _config = init::TestConfig::set(|config| {
+            config.max_messages_per_second = 1; // 1秒に1件
+            config.message_burst = 2; // 連続2件まで
+            config.ping_interval_seconds = 60;
+            config.ping_timeout_seconds = 30;
+        });
+        let bus = TestBus::new();
+        let mut client = bus.connect("", "tester478").await;
+
+        // 一度に3行送ると、バースト分（2行）を超えた3行目で警告される
+        client.stream.write_all(b"one\ntwo\nthree\n").await.unwrap();
+        client.expect("連続投稿が多すぎます").await;
+
+        // バケツが満ちるまで待ってから接続確認を有効にする
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        client.send("/keepalive on").await;
+        let enabled = Instant::now();
+        client.expect("接続確認を有効にしました").await;
+
+        // 入力がないまま60秒でPING、応答しなければさらに30秒で切断
+        client.expect("PING 1\n").await;
+        let pinged = Instant::now();
+        assert_eq!(pinged - enabled, Duration::from_secs(60));
+        client
+            .expect("接続確認（PING）に応答がないため切断します")
+            .await;
+        assert_eq!(Instant::now() - pinged, Duration::from_secs(30));
+        client.task.await.unwrap();
+    }
+
+    // チャット行のIDは部屋ごとに配送の順に1つずつ増え、/ackは最後のIDまでしか進まず戻らない
+    #[tokio::test(start_paused = true)]
+    async fn message_ids_increase_and_ack_clamps() {
+        let _config = init::TestConfig::set(|config| config.history_lines = 2);
+        let bus = TestBus::new();
+        let mut alice = bus.connect("ack-535", "alice535").await;
+        let mut bob = bus.connect("ack-535", "bob535").await;
+        bob.send("/ack on").await;
+        bob.expect("最後のID: 0）").await;
+        for body in ["one", "two", "three"] {
+            alice.send(body).await;
+        }
+        bob.expect("#1 alice535> one").await;
+        bob.expect("#2 alice535> two").await;
+        bob.expect("#3 alice535> three").await;
+
+        // /ack 1 の後は、直近の2行に残っている#2と#3を欠けなく再送する
+        bob.send("/ack 1").await;
+        bob.send("/resend").await;
+        let replay = bob.expect("SYSTEM> ここまで").await;
+        assert!(!replay.contains("保存されていない"), "{}", replay);
+        assert!(
+            replay.contains("再送 (2行)\n#2 alice535> two"),
+            "{}",
+            replay
+        );
+
+        // まだ使われていないIDへの /ack は最後のIDで止まり、小さいIDへの /ack で戻らない
+        bob.send("/ack 99").await;
+        bob.send("/ack 2").await;
+        bob.send("/resend").await;
+        bob.expect("再送 (0行)\nSYSTEM> ここまで").await;
+
+        // 直近の行から捨てられた#1は欠けとして知らせる
+        bob.send("/resend 0").await;
+        let replay = bob.expect("SYSTEM> ここまで").await;
+        assert!(
+            replay.contains("#1〜#1 は保存されていないため再送できません"),
+            "{}",
+            replay
+        );
+        bob.send("/resend 4").await;
+        bob.expect("IDは3まで").await;
+
+        // 続く発言は次のIDになる
+        alice.send("four").await;
+        bob.expect("#4 alice535> four").await;
+        alice.close().await;
+        bob.close().await;
+    }
+
+    // サーバー全体のスローモードは設定したオペレーターのテナントだけに効き、通知も他のテナントには届かない
+    #[tokio::test(start_paused = true)]
+    async fn server_slow_mode_is_per_tenant() {
+        let _config =
+            init::TestConfig::set(|config| config.oper_password = Some("secret550".to_string()));
+        let bus = TestBus::new();
+        let mut op = bus.connect("slow-a", "op550").await;
+        let mut user_a = bus.connect("slow-a", "usera550").await;
+        let mut user_b = bus.connect("slow-b", "userb550").await;
+        let mut observer = bus.connect("slow-b", "watch550").await;
+        op.send("/oper secret550").await;
+        op.expect("オペレーターとして認証されました").await;
+        op.send("/slow server 30").await;
+        user_a
+            .expect("オペレーターがサーバー全体をスローモード（30秒に1回）にしました")
+            .await;
+        user_a.send("first").await;
+        user_a.send("second").await;
+        user_a.expect("スローモード中です").await;
+
+        user_b.send("first").await;
+        user_b.send("second").await;
+        user_b.send("/ack").await; // ここまで届いたことの目印
+        let seen = user_b.expect("ackモード: off").await;
+        assert!(!seen.contains("スローモード"), "{}", seen);
+        let seen = observer.expect("userb550> second").await;
+        assert!(seen.contains("userb550> first"), "{}", seen);
+        assert!(!seen.contains("スローモード"), "{}", seen);
+        for client in [op, user_a, user_b, observer] {
+            client.close().await;
+        }
+    }
+
+    // 設定の再読込（--dry-runを含む）は全テナントに効くので、テナントのオペレーターには使わせない
+    #[tokio::test(start_paused = true)]
+    async fn reload_requires_admin() {
+        let _config =
+            init::TestConfig::set(|config| config.oper_password = Some("secret500".to_string()));
+        let bus = TestBus::new();
+        let mut op = bus.connect("reload-a", "op500").await;
+        op.send("/oper secret500").await;
+        op.expect("オペレーターとして認証されました").await;
+        for command in ["/reload --dry-run", "/reload"] {
+            op.send(command).await;
+            op.expect("SYSTEM> このコマンドは管理者専用です\n").await;
+        }
+        op.close().await;
+    }
+
+    // メンテナンス予告は停止通知の経路で部屋を問わず同じテナントの全員に届き、他のテナントには届かない
+    #[tokio::test(start_paused = true)]
+    async fn maintenance_notice_is_per_tenant() {
+        let _config =
+            init::TestConfig::set(|config| config.oper_password = Some("secret483".to_string()));
+        let bus = TestBus::new();
+        let mut op = bus.connect("maint-a", "op483").await;
+        let mut elsewhere = bus.connect("maint-a", "away483").await;
+        let mut other_tenant = bus.connect("maint-b", "other483").await;
+        elsewhere.send("/join games").await;
+        elsewhere.expect("games").await;
+        op.send("/oper secret483").await;
+        op.expect("オペレーターとして認証されました").await;
+        op.send("/maintenance 15").await;
+        let notice = "SYSTEM> 15分後にメンテナンスのためサーバーを停止します\n";
+        op.expect(notice).await;
+        elsewhere.expect(notice).await;
+        other_tenant.send("/ack").await; // ここまで届いたことの目印
+        let seen = other_tenant.expect("ackモード: off").await;
+        assert!(!seen.contains("メンテナンス"), "{}", seen);
+        for client in [op, elsewhere, other_tenant] {
+            client.close().await; // 予告では切断されない
+        }
+    }
+
+    // テナントのオペレーターの /sessions・/pending・/trace・/whois には他のテナントの接続が一切現れない
+    #[tokio::test(start_paused = true)]
+    async fn operator_commands_stay_in_tenant() {
+        let _config =
+            init::TestConfig::set(|config| config.oper_password = Some("secret521".to_string()));
+        let bus = TestBus::new();
+        let mut op = bus.connect("iso-a", "op521").await;
+        let mate = bus.connect("iso-a", "mate521").await;
+        let other = bus.connect("iso-b", "other521").await;
+        let pending = bus.open("iso-b", "memory-pending521").await;
+        let other_id = session::find_by_handle("iso-b", "other521").unwrap().id;
+        op.send("/oper secret521").await;
+        op.expect("オペレーターとして認証されました").await;
+
+        // 各コマンドの応答を目印（/ack）まで読み、他のテナントの名前・接続元が含まれないことを確かめる
+        let checks = [
+            ("/sessions".to_string(), "セッション一覧 (2件)"),
+            ("/pending".to_string(), "ハンドルネーム未定義の接続 (0件)"),
+            (format!("/trace on {}", other_id), "は存在しません"),
+            ("/whois other521".to_string(), "other521 は接続していません"),
+        ];
+        for (command, expected) in &checks {
+            op.send(command).await;
+            op.send("/ack").await;
+            let reply = op.expect("ackモード: off").await;
+            assert!(reply.contains(expected), "{}: {}", command, reply);
+            for leak in ["iso-b", "memory-other521", "memory-pending521"] {
+                assert!(!reply.contains(leak), "{}: {}", command, reply);
+            }
+            if !command.starts_with("/whois") {
+                assert!(!reply.contains("other521"), "{}: {}", command, reply);
+            }
+        }
+        op.send("/whois mate521").await;
+        op.expect("mate521 : 接続方式").await; // 同じテナントの接続は見える
+        for client in [op, mate, other, pending] {
+            client.close().await;
+        }
+    }
+
+    // 部屋のスローモードでは前の発言から間隔が空くまでの発言を残り秒数を添えて破棄し、オペレーターは対象外
+    #[tokio::test(start_paused = true)]
+    async fn room_slow_mode_spaces_messages() {
+        let _config =
+            init::TestConfig::set(|config| config.oper_password = Some("secret550".to_string()));
+        let bus = TestBus::new();
+        let mut op = bus.connect("slow-room", "op550r").await;
+        let mut user = bus.connect("slow-room", "user550r").await;
+        op.send("/oper secret550").await;
+        op.expect("オペレーターとして認証されました").await;
+        op.send("/slow 10").await;
+        user.expect("op550r がこの部屋をスローモード（10秒に1回）にしました")
+            .await;
+
+        user.send("a").await;
+        op.expect("user550r> a").await;
+        user.send("b").await;
+        user.expect("スローモード中です（10秒に1回まで、あと10秒）")
+            .await;
+        tokio::time::sleep(Duration::from_secs(4)).await;
+        user.send("c").await;
+        user.expect("あと6秒）").await;
+        tokio::time::sleep(Duration::from_secs(6)).await;
+        user.send("d").await;
+        let seen = op.expect("user550r> d").await;
+        assert!(
+            !seen.contains("user550r> b") && !seen.contains("user550r> c"),
+            "{}",
+            seen
+        );
+
+        // オペレーターは間隔を空けずに発言できる
+        op.send("one").await;
+        op.send("two").await;
+        user.expect("op550r> two").await;
+        op.send("/slow off").await;
+        user.expect("op550r がこの部屋のスローモードを解除しました")
+            .await;
+        for client in [op, user] {
+            client.close().await;
+        }
+    }
+
+    // 繰り返しの比較では大文字小文字・空白・記号・数字の違いを無視し、文字のない発言はそのまま比べる
+    #[test]
+    fn repeat_key_ignores_case_and_decoration() {
+        assert_eq!(repeat_key("Buy NOW!!! 123"), repeat_key("buy now"));
+        assert_eq!(repeat_key("  ＢＵＹ　now "), "ｂｕｙnow");
+        assert_ne!(repeat_key("buy now"), repeat_key("buy later"));
+        assert_eq!(repeat_key(" 12345 "), "12345"); // 文字がなければ前後の空白だけ除く
+        assert_ne!(repeat_key("12345"), repeat_key("54321"));
+    }
+
+    // RepeatLimit回目からの同じ発言は期間内なら破棄し（知らせるのは最初の1回）、期間を過ぎれば数え直す
+    #[tokio::test(start_paused = true)]
+    async fn repeated_messages_are_dropped_within_window() {
+        let _config = init::TestConfig::set(|config| {
+            config.repeat_limit = 3;
+            config.repeat_window_seconds = 60;
+        });
+        let bus = TestBus::new();
+        let mut spammer = bus.connect("repeat-549", "spam549").await;
+        let mut observer = bus.connect("repeat-549", "watch549").await;
+        for line in ["Buy now!", "buy  NOW", "BUY NOW!!!", "buy now?"] {
+            spammer.send(line).await;
+        }
+        spammer
+            .expect("同じ内容の発言が続いているため破棄しました（60秒の間に3回目）")
+            .await;
+        spammer.send("/ack").await; // ここまで届いたことの目印
+        let seen = spammer.expect("ackモード: off").await;
+        assert!(!seen.contains("破棄しました"), "{}", seen); // 4回目は知らせない
+        observer.expect("spam549> buy  NOW").await;
+
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        spammer.send("buy now.").await;
+        let seen = observer.expect("spam549> buy now.").await;
+        assert!(
+            !seen.contains("BUY NOW!!!") && !seen.contains("buy now?"),
+            "{}",
+            seen
+        );
+        for client in [spammer, observer] {
+            client.close().await;
+        }
+    }
+
+    // RepeatPolicy disconnect では RepeatLimit回目の同じ発言で切断する
+    #[tokio::test(start_paused = true)]
+    async fn repeated_messages_disconnect_with_policy() {
+        let _config = init::TestConfig::set(|config| {
+            config.repeat_limit = 2;
+            config.repeat_policy = RepeatPolicy::Disconnect;
+        });
+        let bus = TestBus::new();
+        let mut spammer = bus.connect("repeat-549d", "spam549d").await;
+        spammer.send("hello").await;
+        spammer.send("Hello!").await;
+        spammer
+            .expect("SYSTEM> 同じ内容の発言を繰り返したため切断します\n")
+            .await;
+        spammer.task.await.unwrap();
     }
 }
@@ -0,0 +1,90 @@
+// RustTokioChatServer - 色付け表示モジュール
+// MIT License
+//
+// color.rs: /color on（またはColor on）のクライアントに送る行をANSIエスケープシーケンスで色付けする
+// 送信キューに積まれる文面は全員共通のままにして、書き込みタスクが書き出す直前に受信者ごとに色を付ける
+// SYSTEM>で始まる行は全体を、チャット行・私信（末尾に発言時刻のある行）はハンドルネームと発言時刻を色付けし、それ以外の行はそのまま送る
+
+const SYSTEM: &str = "\x1b[33m"; // SYSTEM>の行（黄）
+const HANDLE: &str = "\x1b[1;36m"; // ハンドルネーム（太字の水色）
+const TIMESTAMP: &str = "\x1b[90m"; // 発言時刻（灰）
+const RESET: &str = "\x1b[0m"; // 色を戻す
+
+// 送信する文面（複数行・改行込み）を色付けする
+pub fn colorize(text: &str) -> String {
+    let mut colored = String::with_capacity(text.len() + 32);
+    for line in text.split_inclusive('\n') {
+        let (body, newline) = match line.strip_suffix('\n') {
+            Some(body) => (body, "\n"),
+            None => (line, ""),
+        };
+        colored.push_str(&colorize_line(body));
+        colored.push_str(newline);
+    }
+    colored
+}
+
+// 1行分（改行なし）を色付けする
+fn colorize_line(line: &str) -> String {
+    let (id, rest) = split_id(line); // /ack on のメッセージIDはそのまま
+    if rest.starts_with("SYSTEM>") {
+        return format!("{}{}{}{}", id, SYSTEM, rest, RESET);
+    }
+    let Some((message, timestamp)) = split_timestamp(rest) else {
+        return line.to_string(); // 発言時刻のない行はチャット行ではない
+    };
+    let Some((before, handle, after)) = split_handle(message) else {
+        return line.to_string();
+    };
+    format!(
+        "{}{}{}{}{}{}{}{}{}",
+        id, before, HANDLE, handle, RESET, after, TIMESTAMP, timestamp, RESET
+    )
+}
+
+// 行頭のメッセージID（"#ID "）と残り
+fn split_id(line: &str) -> (&str, &str) {
+    let digits = line.strip_prefix('#').map_or(0, |rest| {
+        rest.bytes().take_while(u8::is_ascii_digit).count()
+    });
+    if digits > 0 && line[1 + digits..].starts_with(' ') {
+        line.split_at(digits + 2)
+    } else {
+        ("", line)
+    }
+}
+
+// 行末の発言時刻（" (YYYY/MM/DD HH:MM)"）の前と発言時刻
+fn split_timestamp(line: &str) -> Option<(&str, &str)> {
+    const PATTERN: &[u8] = b" (0000/00/00 00:00)"; // 0の位置は数字
+    let start = line.len().checked_sub(PATTERN.len())?;
+    let tail = line.as_bytes().get(start..)?;
+    let matches = tail.iter().zip(PATTERN).all(|(byte, expected)| {
+        if *expected == b'0' {
+            byte.is_ascii_digit()
+        } else {
+            byte == expected
+        }
+    });
+    matches.then(|| line.split_at(start))
+}
+
+// チャット行のハンドルネームの前・ハンドルネーム・後ろ
+// "名前> 本文"・"* 名前 動作"・"(私信) 名前> 本文"・"(私信 -> 相手) 名前> 本文"の形
+fn split_handle(message: &str) -> Option<(&str, &str, &str)> {
+    if let Some(rest) = message.strip_prefix("* ") {
+        let end = rest.find(' ').unwrap_or(rest.len());
+        return Some(("* ", &rest[..end], &rest[end..]));
+    }
+    let prefix = if message.starts_with("(私信") {
+        message.find(") ")? + 2
+    } else {
+        0
+    };
+    let rest = &message[prefix..];
+    let end = rest.find("> ")?;
+    if end == 0 || rest[..end].contains(' ') {
+        return None; // ハンドルネームに空白は使えない
+    }
+    Some((&message[..prefix], &rest[..end], &rest[end..]))
+}
@@ -0,0 +1,304 @@
+// RustTokioChatServer - スラッシュコマンド定義モジュール
+// MIT License
+//
+// commands.rs: チャット内コマンドの一覧（名前・別名・引数の書式・説明・使える条件）を一元管理する
+// 受信した1行をコマンドとして解釈し（スラッシュで始まる行と、CTRL-Yなどの制御キー）、/helpの表示にも同じ一覧を使う
+// 各コマンドの処理本体はclient.rsのcmd_*で、ディスパッチャはここで引いた定義の名前で振り分ける
+
+use crate::roles::Role; // 役割
+
+// コマンド1つ分の定義
+#[derive(Debug)]
+pub struct Command {
+    pub name: &'static str,               // コマンド名（スラッシュなし）
+    pub aliases: &'static [&'static str], // 別名
+    pub usage: &'static str,              // 引数の書式（引数がなければ空）
+    pub summary: &'static str,            // /helpに出す説明
+    pub role: Role,                       // 使うのに必要な役割
+    pub while_identifying: bool,          // 登録済みのハンドルネームの認証待ちの間も使えるか
+    pub secret_args: bool,                // 引数にパスワードを含むか（/traceのログでは伏せる）
+}
+
+// 誰でも使えるコマンド
+const fn guest(
+    name: &'static str,
+    aliases: &'static [&'static str],
+    usage: &'static str,
+    summary: &'static str,
+) -> Command {
+    Command {
+        name,
+        aliases,
+        usage,
+        summary,
+        role: Role::Guest,
+        while_identifying: false,
+        secret_args: false,
+    }
+}
+
+// 一般以上の役割が必要なコマンド
+const fn user(
+    name: &'static str,
+    aliases: &'static [&'static str],
+    usage: &'static str,
+    summary: &'static str,
+) -> Command {
+    Command {
+        role: Role::User,
+        ..guest(name, aliases, usage, summary)
+    }
+}
+
+// 認証待ちの間も使えるコマンド
+const fn anytime(
+    name: &'static str,
+    aliases: &'static [&'static str],
+    usage: &'static str,
+    summary: &'static str,
+) -> Command {
+    Command {
+        while_identifying: true,
+        ..guest(name, aliases, usage, summary)
+    }
+}
+
+// オペレーター専用のコマンド
+const fn oper(name: &'static str, usage: &'static str, summary: &'static str) -> Command {
+    Command {
+        role: Role::Operator,
+        ..guest(name, &[], usage, summary)
+    }
+}
+
+// 管理者専用のコマンド
+const fn admin(name: &'static str, usage: &'static str, summary: &'static str) -> Command {
+    Command {
+        role: Role::Admin,
+        ..guest(name, &[], usage, summary)
+    }
+}
+
+// 引数にパスワードを含むコマンド
+const fn secret(command: Command) -> Command {
+    Command {
+        secret_args: true,
+        ..command
+    }
+}
+
+// コマンド一覧（/helpの表示順）
+pub const COMMANDS: &[Command] = &[
+    anytime("help", &[], "[コマンド]", "コマンドの一覧・使い方を表示"),
+    anytime(
+        "reset",
+        &[],
+        "",
+        "ハンドルネームを未定義に戻して入力し直す（CTRL-Yと同じ）",
+    ),
+    guest("nick", &[], "<新しい名前>", "ハンドルネームを変更"),
+    guest(
+        "me",
+        &[],
+        "<動作>",
+        "動作を発言する（* ハンドルネーム 動作 と表示）",
+    ),
+    user(
+        "msg",
+        &[],
+        "<ハンドルネーム> <本文>",
+        "指定したユーザーにだけメッセージを送る",
+    ),
+    user("join", &[], "<部屋名>", "部屋を移動（なければ作成）"),
+    user("part", &[], "", "今いる部屋を出て既定の部屋に戻る"),
+    guest("list", &[], "", "部屋の一覧と参加人数"),
+    guest("who", &[], "", "接続中のユーザーの一覧"),
+    guest(
+        "away",
+        &[],
+        "[理由]",
+        "不在にする（私信の送り主に自動で知らせる）",
+    ),
+    guest("back", &[], "", "不在を解除"),
+    guest(
+        "ignore",
+        &[],
+        "[ハンドルネーム]",
+        "指定したユーザーの発言・私信を受け取らない",
+    ),
+    guest("unignore", &[], "<ハンドルネーム>", "無視を解除"),
+    guest("pins", &[], "", "今いる部屋の固定告知の一覧"),
+    user("topic", &[], "[本文]", "今いる部屋の話題を表示・設定"),
+    user(
+        "mode",
+        &[],
+        "[<+|-><i|m|s> | <+|-><o|v> <ハンドルネーム>]",
+        "今いる部屋のモードを表示・変更（部屋のオペレーター）",
+    ),
+    user(
+        "invite",
+        &[],
+        "<ハンドルネーム>",
+        "今いる部屋に招待（部屋のオペレーター）",
+    ),
+    secret(anytime(
+        "register",
+        &["signup"],
+        "<パスワード>",
+        "今のハンドルネームを登録",
+    )),
+    secret(anytime(
+        "identify",
+        &["login"],
+        "<パスワード>",
+        "登録済みのハンドルネームの本人として認証",
+    )),
+    secret(user(
+        "passwd",
+        &[],
+        "<現在のパスワード> <新しいパスワード>",
+        "登録のパスワードを変更",
+    )),
+    user(
+        "email",
+        &[],
+        "set <メールアドレス>",
+        "登録に連絡先メールアドレスを設定",
+    ),
+    secret(user("drop", &[], "<パスワード>|confirm", "登録を削除")),
+    user("info", &[], "", "自分の登録内容を表示"),
+    guest("limits", &[], "", "自分の接続に今適用されている上限を表示"),
+    guest("keepalive", &[], "<on|off>", "接続確認（PING）を有効にする"),
+    anytime("pong", &[], "<番号>", "PINGへの応答"),
+    guest(
+        "quiet",
+        &[],
+        "[on|off]",
+        "離席・入退室などの通知を受け取らない",
+    ),
+    guest(
+        "color",
+        &[],
+        "[on|off]",
+        "SYSTEM>の行・ハンドルネーム・発言時刻を色付けして受け取る",
+    ),
+    guest(
+        "ack",
+        &[],
+        "[on|off|<ID>]",
+        "メッセージID付きで受け取る・受け取ったIDを知らせる",
+    ),
+    guest("resend", &[], "[ID]", "指定したIDより後のチャット行を再送"),
+    secret(guest("oper", &[], "<パスワード>", "オペレーターとして認証")),
+    guest("whois", &[], "<ハンドルネーム>", "ユーザーの接続情報を表示"),
+    oper("wall", "<本文>", "全クライアントへ強調表示の全体通知を送信"),
+    oper("sessions", "", "同じテナントの全セッションの一覧"),
+    oper("pin", "<本文>", "今いる部屋に告知を固定"),
+    oper("unpin", "<番号>", "今いる部屋の固定告知を外す"),
+    oper(
+        "rename",
+        "<ハンドルネーム> <新しい名前>",
+        "ハンドルネームを強制的に変更",
+    ),
+    oper(
+        "pending",
+        "[expire [秒]]",
+        "ハンドルネーム未定義の接続の一覧・一括切断",
+    ),
+    oper(
+        "trace",
+        "<on|off> <セッションID>",
+        "指定セッションの送受信内容をログに出す",
+    ),
+    oper("stats", "", "サーバー統計を表示"),
+    oper(
+        "maintenance",
+        "<分>",
+        "メンテナンス予告を同じテナントの全クライアントへ通知",
+    ),
+    oper(
+        "slow",
+        "[server] [<秒>|off]",
+        "今いる部屋・サーバー全体のスローモードを表示・切り替え",
+    ),
+    oper(
+        "mute",
+        "<ハンドルネーム> <分>",
+        "指定した時間だけ発言・私信を止める（0で解除）",
+    ),
+    admin("reload", "[--dry-run]", "設定を再読込"),
+    admin(
+        "role",
+        "[ハンドルネーム [役割]]",
+        "役割を表示・付与（guest|user|operator|admin）",
+    ),
+];
+
+// コマンドとして扱う制御キー（押したときに送られる制御文字→コマンド名）
+const CONTROL_KEYS: &[(u8, &str)] = &[(0x19, "reset")]; // CTRL-Y
+
+// 名前・別名からコマンドの定義を引く
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS
+        .iter()
+        .find(|command| command.name == name || command.aliases.contains(&name))
+}
+
+// 引数を/traceのログで伏せるコマンドか（/resumeのトークンも、ハンドルネーム入力中だけのコマンドなので一覧にはない）
+pub fn has_secret_args(name: &str) -> bool {
+    name == "resume" || find(name).is_some_and(|command| command.secret_args)
+}
+
+// 受信した1行をコマンド名と引数に分ける（制御キーを含む行・スラッシュで始まる行、それ以外はNone）
+// lineは受信したままのバイト列、msgはそれをUTF-8に変換して前後の空白を除いたもの
+pub fn parse<'a>(line: &[u8], msg: &'a str) -> Option<(&'a str, &'a str)> {
+    if let Some((_, name)) = CONTROL_KEYS.iter().find(|(key, _)| line.contains(key)) {
+        return Some((name, "")); // 制御キーは引数なし
+    }
+    let command = msg.strip_prefix('/')?;
+    let (name, args) = command.split_once(' ').unwrap_or((command, "")); // コマンド名と引数に分割
+    Some((name, args.trim()))
+}
+
+// 書式付きのコマンド（"/名前 引数"）
+fn synopsis(command: &Command) -> String {
+    if command.usage.is_empty() {
+        format!("/{}", command.name)
+    } else {
+        format!("/{} {}", command.name, command.usage)
+    }
+}
+
+// /helpの一覧（今の役割で使えるコマンドだけ見せる）
+pub fn help(role: Role) -> String {
+    let visible: Vec<&Command> = COMMANDS
+        .iter()
+        .filter(|command| command.role <= role)
+        .collect();
+    let mut text = format!("SYSTEM> コマンド一覧 ({}件)\n", visible.len());
+    for command in visible {
+        text.push_str(&format!(
+            "SYSTEM>   {} : {}\n",
+            synopsis(command),
+            command.summary
+        ));
+    }
+    text.push_str("SYSTEM> /help <コマンド> で使い方を表示します\n");
+    text
+}
+
+// /help <コマンド> の表示
+pub fn help_for(command: &Command) -> String {
+    let mut text = format!("SYSTEM> {} : {}\n", synopsis(command), command.summary);
+    if !command.aliases.is_empty() {
+        let aliases: Vec<String> = command.aliases.iter().map(|a| format!("/{}", a)).collect();
+        text.push_str(&format!("SYSTEM>   別名: {}\n", aliases.join(", ")));
+    }
+    if command.role > Role::Guest {
+        text.push_str(&format!(
+            "SYSTEM>   必要な役割: {}以上\n",
+            command.role.label()
+        ));
+    }
+    text
+}
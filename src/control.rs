@@ -0,0 +1,189 @@
+// RustTokioChatServer - 管理用ソケットモジュール
+// MIT License
+//
+// クレート説明:
+// - tokio: Unixドメインソケットの待受・行単位の読み書き
+// - chrono-tz: JSTタイムゾーン処理（ログ出力用）
+//
+// control.rs: ControlSocketで指定したUnixドメインソケットで管理コマンドを受け付ける（UNIXのみ）
+// 標準入力やシグナルを使わずに、ローカルの管理者が一覧・切断・全体通知・再読込・停止を行える
+// 1行1コマンドで、結果の行の後に成功なら "OK"、失敗なら "ERR 理由" を返す
+// 例: echo list | nc -U /run/rustchat.sock
+use crate::audit; // 監査ログ
+use crate::autoban; // 自動締め出し
+use crate::disconnect::{DisconnectReason, ShutdownReason}; // 切断理由・停止理由
+use crate::dispatch; // 全体通知の配送
+use crate::features::{self, Feature}; // 機能の切り替え
+use crate::init; // テナントの一覧
+use crate::server; // 設定の再読込
+use crate::session; // セッション一覧
+use chrono_tz::Asia::Tokyo; // chrono-tz: JSTタイムゾーン
+use std::sync::atomic::Ordering; // std: トレースフラグの更新
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader}; // Tokio: 行単位の読み書き
+use tokio::net::{UnixListener, UnixStream}; // Tokio: Unixドメインソケット
+use tokio::sync::broadcast; // Tokio: 停止・再読込通知
+
+// 管理用ソケットの待受タスク（ソケットファイルは起動時に作り直し、停止時にremoveで消す）
+pub async fn serve(path: String, shutdown_tx: broadcast::Sender<ShutdownReason>) {
+    let _ = std::fs::remove_file(&path); // 前回の異常終了で残ったソケットファイルを消す
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            crate::printdaytimeln!("管理用ソケットの待受に失敗しました: {} ({})", path, e); // チャットは動かし続ける
+            return;
+        }
+    };
+    crate::printdaytimeln!("管理用ソケット待受開始: {}", path); // ログ
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                crate::printdaytimeln!("管理用ソケット受付エラー: {}", e); // ログ
+                continue;
+            }
+        };
+        tokio::spawn(handle(stream, shutdown_tx.clone()));
+    }
+}
+
+// ソケットファイルを消す（停止時）
+pub fn remove(path: &str) {
+    let _ = std::fs::remove_file(path);
+}
+
+// 管理用接続1本分（切断されるまでコマンドを処理する）
+async fn handle(stream: UnixStream, shutdown_tx: broadcast::Sender<ShutdownReason>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (name, args) = line.split_once(' ').unwrap_or((line, "")); // コマンド名と引数に分割
+        crate::printdaytimeln!("管理コマンド: {}", line); // ログ
+        let reply = match run(name, args.trim(), &shutdown_tx) {
+            Ok(output) => format!("{}OK\n", output),
+            Err(e) => format!("ERR {}\n", e),
+        };
+        if writer.write_all(reply.as_bytes()).await.is_err() {
+            break; // 相手が閉じた
+        }
+    }
+}
+
+// コマンドを実行して結果の行を返す
+fn run(
+    name: &str,
+    args: &str,
+    shutdown_tx: &broadcast::Sender<ShutdownReason>,
+) -> Result<String, String> {
+    match name {
+        "list" => Ok(list()),
+        "kick" => {
+            let (tenant, name) = match args.split_once('/') {
+                Some((tenant, name))
+                    if init::CONFIG
+                        .read()
+                        .unwrap()
+                        .tenants
+                        .iter()
+                        .any(|t| t.name == tenant) =>
+                {
+                    (tenant, name) // テナント名/ハンドルネーム
+                }
+                _ => ("", args), // 既定の待受のハンドルネーム
+            };
+            let target = session::find_by_handle(tenant, name)
+                .ok_or_else(|| format!("{} は接続していません", args))?;
+            target.request_disconnect(DisconnectReason::Kicked); // 本人のタスクで切断
+            Ok(String::new())
+        }
+        "broadcast" if !args.is_empty() => {
+            let now = chrono::Local::now().with_timezone(&Tokyo); // 現在時刻
+            let notice = format!(
+                "!!! WALL> {} (管理者 {}) !!!\n",
+                args,
+                now.format("%Y/%m/%d %H:%M")
+            ); // /wallと同じ強調表示
+            dispatch::broadcast_all(None, &notice); // 全テナントの全セッションへ直接配送
+            Ok(String::new())
+        }
+        "trace" => {
+            let (on, id) = match args.split_once(' ') {
+                Some(("on", id)) => (true, id),
+                Some(("off", id)) => (false, id),
+                _ => return Err("使い方: trace <on|off> <セッションID>".to_string()),
+            };
+            let target = id
+                .trim()
+                .parse::<u64>()
+                .ok()
+                .and_then(session::find_by_id)
+                .ok_or_else(|| format!("セッション #{} は存在しません", id.trim()))?;
+            target.trace.store(on, Ordering::Relaxed); // 次の送受信から反映
+            Ok(String::new())
+        }
+        "bans" => Ok(autoban::report()),
+        "unban" => {
+            if !autoban::lift(args) {
+                return Err(format!("{} は締め出されていません", args));
+            }
+            audit::record(&format!("自動締め出しの解除: {}", args)); // 監査ログ
+            Ok(String::new())
+        }
+        "feature" if args.is_empty() => Ok(features::report()),
+        "feature" => {
+            let usage = || "使い方: feature [<名前> <on|off|default>]".to_string();
+            let (name, state) = args.split_once(' ').ok_or_else(usage)?;
+            let feature = Feature::from_name(name)
+                .ok_or_else(|| format!("不明な機能です: {}（history / spam / schedule）", name))?;
+            let enabled = match state.trim() {
+                "on" => Some(true),
+                "off" => Some(false),
+                "default" => None, // 上書きをやめる
+                _ => return Err(usage()),
+            };
+            let saved = features::set(feature, enabled); // すぐに反映
+            audit::record(&format!("機能の切り替え: {} {}", name, state.trim())); // 監査ログ
+            saved.map_err(|e| format!("切り替えましたが状態を保存できません ({})", e))?;
+            Ok(String::new())
+        }
+        "reload" => {
+            server::reload(shutdown_tx); // SIGHUPと同じ処理
+            Ok(String::new())
+        }
+        "shutdown" => {
+            let _ = shutdown_tx.send(ShutdownReason::Terminate); // 全クライアントとメインループに通知
+            Ok(String::new())
+        }
+        "help" => Ok("list / kick <[テナント名/]ハンドルネーム> / broadcast <本文> / trace <on|off> <セッションID> / bans / unban <アドレス> / feature [<名前> <on|off|default>] / reload / shutdown\n".to_string()),
+        _ => Err(format!("不明なコマンドです: {}（help で一覧）", name)),
+    }
+}
+
+// セッション一覧（1行1セッション、接続順）
+fn list() -> String {
+    let mut sessions = session::snapshot();
+    sessions.sort_by_key(|session| session.id); // 接続順に並べる
+    let mut list = String::new();
+    for target in sessions {
+        let name = target.handle_name();
+        list.push_str(&format!(
+            "#{} {} {} {} [{}] 接続{}秒 品質{} 指紋{}\n",
+            target.id,
+            target.transport.label(),
+            target.peer_addr,
+            if name.is_empty() {
+                "(未定義)"
+            } else {
+                &name
+            },
+            target.qualified_room(),
+            target.connected_at.elapsed().as_secs(),
+            target.quality(),
+            target.fingerprint_id()
+        ));
+    }
+    list
+}
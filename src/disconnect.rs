@@ -0,0 +1,199 @@
+// RustTokioChatServer - 切断理由モジュール
+// MIT License
+//
+// disconnect.rs: サーバー側・クライアント側の切断理由を一元管理
+// クライアントへの通知文とログ用の理由コードはすべてここで定義する
+use crate::scoring::json_string; // JSONの文字列リテラル
+
+// サーバーから全クライアントへ通知する停止・再読込の理由
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShutdownReason {
+    Reload,                                   // 設定再読込（待受アドレスは変わらない）
+    Terminate,                                // サーバー終了
+    Maintenance { tenant: String, eta: u64 }, // メンテナンスによる停止予告（テナントの全員に、etaは停止までの分数）
+    AddressChange { new_address: String },    // 待受アドレス変更による再バインド
+}
+
+impl ShutdownReason {
+    // この通知で接続を切る場合の切断理由（接続を維持する場合はNone）
+    pub fn disconnect_reason(&self) -> Option<DisconnectReason> {
+        match self {
+            ShutdownReason::Reload => None,             // 接続は維持
+            ShutdownReason::Maintenance { .. } => None, // 予告だけなので接続は維持
+            ShutdownReason::Terminate => Some(DisconnectReason::ServerShutdown),
+            ShutdownReason::AddressChange { .. } => Some(DisconnectReason::ServerRestart),
+        }
+    }
+
+    // 接続を維持する場合にクライアントへ送る通知文
+    pub fn notice(&self) -> Option<String> {
+        match self {
+            ShutdownReason::Reload => {
+                Some("SYSTEM> サーバー設定が再読み込みされました\n".to_string())
+            }
+            ShutdownReason::Maintenance { eta, .. } => Some(format!(
+                "SYSTEM> {}分後にメンテナンスのためサーバーを停止します\n",
+                eta
+            )),
+            ShutdownReason::AddressChange { new_address } => Some(format!(
+                "SYSTEM> サーバーの待受アドレスが {} に変わりました\n",
+                new_address
+            )),
+            ShutdownReason::Terminate => None, // 切断理由の通知で足りる
+        }
+    }
+}
+
+// 終了・再バインドで切断するときの再接続の案内（行儀のよいクライアントが一斉に接続し直さないように）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReconnectAdvice {
+    pub retry_after: u64,        // 接続し直すまで待つ秒数
+    pub address: Option<String>, // 新しい待受アドレス（変わった場合のみ）
+}
+
+impl ReconnectAdvice {
+    // 人向けの通知文
+    pub fn notice(&self) -> String {
+        let mut notice = if self.retry_after == 0 {
+            "SYSTEM> すぐに".to_string()
+        } else {
+            format!("SYSTEM> {}秒後に", self.retry_after)
+        };
+        if let Some(address) = &self.address {
+            notice.push_str(&format!(" {} へ", address));
+        }
+        notice.push_str("接続し直してください\n");
+        notice
+    }
+
+    // 機械向けの通信路に送る型付きのイベント（1行のJSON）
+    pub fn json(&self, reason: DisconnectReason) -> String {
+        format!(
+            "{{\"type\":\"reconnect\",\"reason\":\"{}\",\"retry_after\":{},\"address\":{}}}\n",
+            reason.code(),
+            self.retry_after,
+            self.address
+                .as_ref()
+                .map_or("null".to_string(), |address| json_string(address))
+        )
+    }
+}
+
+// 切断理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    Quit,             // クライアントがCTRL-C/CTRL-Dで切断
+    Closed,           // 接続が閉じられた・通信エラー
+    NameAttempts,     // ハンドルネームの入力に続けて失敗した・失敗が続いた接続元の待機中
+    PasswordAttempts, // 接続パスワードを続けて間違えた
+    Banned,           // 接続拒否IPからの接続
+    Denied,           // Allow・Denyで受け付けない接続元からの接続
+    AutoBanned,       // 違反が続いて自動的に締め出した接続元からの接続
+    ServerFull,       // 同時接続数がMaxClientsに達している
+    Expired,          // ハンドルネーム未定義のままオペレーターに期限切れにされた
+    Flood,            // 警告後も連続投稿を続けた
+    Spam,             // スパム判定で繰り返し破棄された
+    Repeated,         // 同じ発言を繰り返した（RepeatPolicy disconnect）
+    Filtered, // 禁止語・禁止規則に一致する発言をした（FilterPolicy disconnect・FilterRegex ban）
+    Lagging,  // 受信が追いつかず送信キューがあふれた（QueueOverflowPolicy disconnect）
+    PingTimeout, // /keepalive on の接続がPINGに応答しなかった
+    Kicked,   // 管理者による切断
+    ServerRestart, // サーバー再起動
+    ServerShutdown, // サーバー終了
+}
+
+impl DisconnectReason {
+    // ログ解析用の理由コード（表示言語に依存しない固定文字列）
+    pub fn code(self) -> &'static str {
+        match self {
+            DisconnectReason::Quit => "quit",
+            DisconnectReason::Closed => "closed",
+            DisconnectReason::NameAttempts => "name-attempts",
+            DisconnectReason::PasswordAttempts => "password-attempts",
+            DisconnectReason::Banned => "banned",
+            DisconnectReason::Denied => "denied",
+            DisconnectReason::AutoBanned => "auto-banned",
+            DisconnectReason::ServerFull => "server-full",
+            DisconnectReason::Expired => "expired",
+            DisconnectReason::Flood => "flood",
+            DisconnectReason::Spam => "spam",
+            DisconnectReason::Repeated => "repeat",
+            DisconnectReason::Filtered => "filtered",
+            DisconnectReason::Lagging => "lagging",
+            DisconnectReason::PingTimeout => "ping-timeout",
+            DisconnectReason::Kicked => "kicked",
+            DisconnectReason::ServerRestart => "server-restart",
+            DisconnectReason::ServerShutdown => "server-shutdown",
+        }
+    }
+
+    // クライアントへ送る通知文（送れない場合はNone）
+    pub fn notice(self) -> Option<&'static str> {
+        match self {
+            DisconnectReason::Quit => Some("SYSTEM> 切断します\n"),
+            DisconnectReason::Closed => None, // 既に接続が閉じているので送らない
+            DisconnectReason::NameAttempts => Some(
+                "SYSTEM> ハンドルネームの入力に続けて失敗したため切断します。しばらくしてから接続し直してください\n",
+            ),
+            DisconnectReason::PasswordAttempts => {
+                Some("SYSTEM> パスワードを続けて間違えたため切断します\n")
+            }
+            DisconnectReason::Banned | DisconnectReason::Denied => {
+                Some("SYSTEM> このアドレスからの接続は拒否されています\n")
+            }
+            DisconnectReason::AutoBanned => Some(
+                "SYSTEM> 違反が続いたため、このアドレスからはしばらく接続できません\n",
+            ),
+            DisconnectReason::ServerFull => Some(
+                "SYSTEM> 申し訳ありません、ただいま満員です。しばらくしてから接続し直してください\n",
+            ),
+            DisconnectReason::Expired => {
+                Some("SYSTEM> ハンドルネームが未定義のまま時間が経過したため切断します\n")
+            }
+            DisconnectReason::PingTimeout => {
+                Some("SYSTEM> 接続確認（PING）に応答がないため切断します\n")
+            }
+            DisconnectReason::Kicked => Some("SYSTEM> 管理者により切断されました\n"),
+            DisconnectReason::Flood => Some("SYSTEM> 連続投稿が多すぎるため切断します\n"),
+            DisconnectReason::Spam => {
+                Some("SYSTEM> スパムと判定された発言が続いたため切断します\n")
+            }
+            DisconnectReason::Repeated => {
+                Some("SYSTEM> 同じ内容の発言を繰り返したため切断します\n")
+            }
+            DisconnectReason::Filtered => {
+                Some("SYSTEM> 禁止されている内容の発言をしたため切断します\n")
+            }
+            DisconnectReason::Lagging => {
+                Some("SYSTEM> 受信が追いつかず送信待ちがあふれたため切断します\n")
+            }
+            DisconnectReason::ServerRestart => Some("SYSTEM> サーバーを再起動するので切断します\n"),
+            DisconnectReason::ServerShutdown => Some("SYSTEM> サーバーを終了するので切断します\n"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 再接続の案内のJSONは、アドレスに引用符・バックスラッシュがあってもエスケープして1行に収める
+    #[test]
+    fn reconnect_json_escapes_address() {
+        let advice = ReconnectAdvice {
+            retry_after: 10,
+            address: Some("host\"x\\y".to_string()),
+        };
+        assert_eq!(
+            advice.json(DisconnectReason::ServerRestart),
+            "{\"type\":\"reconnect\",\"reason\":\"server-restart\",\"retry_after\":10,\"address\":\"host\\\"x\\\\y\"}\n"
+        );
+        let advice = ReconnectAdvice {
+            retry_after: 0,
+            address: None,
+        };
+        assert!(advice
+            .json(DisconnectReason::ServerShutdown)
+            .ends_with("\"address\":null}\n"));
+    }
+}
@@ -0,0 +1,319 @@
+// RustTokioChatServer - メッセージ配送モジュール
+// MIT License
+//
+// クレート説明:
+// - tokio: ブロードキャストチャネル・mpscキュー
+// - chrono: 発言時刻の整形
+//
+// dispatch.rs: メッセージバスから各クライアントの送信キューへの配送を担当（部屋単位）
+// バスには整形前の出来事（ChatEvent）を流し、文面は受信者ごとに配送時に組み立てる
+use crate::session::{self, Session}; // セッション管理モジュール
+use crate::template; // メッセージテンプレート
+use chrono::DateTime; // chrono: 発言時刻
+use chrono_tz::Tz; // chrono-tz: タイムゾーン付き時刻
+use std::sync::atomic::{AtomicBool, Ordering}; // std: 静音時間フラグ
+use tokio::sync::broadcast; // Tokio: ブロードキャスト受信
+
+// 静音時間中か（定時処理で切り替え、重要度の低い通知を全員分止める）
+static QUIET_HOURS: AtomicBool = AtomicBool::new(false);
+
+// 発言の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatKind {
+    Say,    // 通常の発言（"ハンドルネーム> 本文"）
+    Action, // /me の動作（"* ハンドルネーム 本文"）
+}
+
+impl ChatKind {
+    // 履歴に保存する名前
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChatKind::Say => "say",
+            ChatKind::Action => "action",
+        }
+    }
+}
+
+// メッセージバスに流す出来事（整形前）
+#[derive(Debug, Clone)]
+pub enum ChatEvent {
+    Chat {
+        id: u64,          // 部屋ごとのメッセージID（/ack on のクライアントには行の前に付ける）
+        kind: ChatKind,   // 発言の種類
+        from: String,     // 発言者のハンドルネーム
+        body: String,     // 本文（禁止語は伏字済み）
+        ts: DateTime<Tz>, // 発言時刻（JST）
+    }, // 部屋での発言
+    Join {
+        id: u64,     // 入室したセッションのID（本人には送らない）
+        who: String, // 入室したハンドルネーム
+    }, // 部屋に入った
+    Leave {
+        id: u64,     // 退室したセッションのID（本人には送らない）
+        who: String, // 退室したハンドルネーム
+    }, // 部屋から出た
+    Connect {
+        id: u64,     // 接続したセッションのID（本人には送らない）
+        who: String, // ハンドルネーム
+    }, // ハンドルネームを確定した（部屋を問わず同じテナントの全員に送る）
+    Disconnect {
+        id: u64,     // 切断したセッションのID（本人には送らない）
+        who: String, // ハンドルネーム
+    }, // 切断した（部屋を問わず同じテナントの全員に送る）
+    Nick {
+        id: u64,     // 名前を変えたセッションのID（本人には送らない）
+        old: String, // 変更前のハンドルネーム
+        new: String, // 変更後のハンドルネーム
+    }, // /nick でハンドルネームを変えた（部屋を問わず同じテナントの全員に送る）
+    System(String), // システム通知（"SYSTEM> "と改行は配送時に付ける）
+}
+
+impl ChatEvent {
+    // 受信者に送る文面（改行込み）
+    pub fn render(&self) -> String {
+        match self {
+            ChatEvent::Chat {
+                kind: ChatKind::Say,
+                from,
+                body,
+                ts,
+                ..
+            } => format!("{}> {} ({})\n", from, body, ts.format("%Y/%m/%d %H:%M")),
+            ChatEvent::Chat {
+                kind: ChatKind::Action,
+                from,
+                body,
+                ts,
+                ..
+            } => format!("* {} {} ({})\n", from, body, ts.format("%Y/%m/%d %H:%M")),
+            ChatEvent::Join { who, .. } => format!("SYSTEM> {} が入室しました\n", who),
+            ChatEvent::Leave { who, .. } => format!("SYSTEM> {} が退室しました\n", who),
+            ChatEvent::Connect { who, .. } => format!("SYSTEM> {} が接続しました\n", who),
+            ChatEvent::Disconnect { who, .. } => format!("SYSTEM> {} が切断しました\n", who),
+            ChatEvent::Nick { old, new, .. } => {
+                format!("SYSTEM> {} は {} に名前を変更しました\n", old, new)
+            }
+            ChatEvent::System(text) => format!("SYSTEM> {}\n", text),
+        }
+    }
+
+    // 出来事を起こしたハンドルネーム（無視リストとの照合用、名前の変更は無視していても届ける）
+    fn actor(&self) -> Option<&str> {
+        match self {
+            ChatEvent::Chat { from, .. } => Some(from),
+            ChatEvent::Join { who, .. }
+            | ChatEvent::Leave { who, .. }
+            | ChatEvent::Connect { who, .. }
+            | ChatEvent::Disconnect { who, .. } => Some(who),
+            _ => None,
+        }
+    }
+
+    // 入退室・接続・切断の通知か（離席通知と同じく重要度の低い通知として扱う）
+    fn is_ambient(&self) -> bool {
+        matches!(
+            self,
+            ChatEvent::Join { .. }
+                | ChatEvent::Leave { .. }
+                | ChatEvent::Connect { .. }
+                | ChatEvent::Disconnect { .. }
+        )
+    }
+}
+
+// メッセージバスに流すメッセージ（部屋名付き）
+#[derive(Debug, Clone)]
+pub struct RoomMessage {
+    pub tenant: String,   // 配送先のテナント（既定の待受なら空）
+    pub room: String,     // 配送先の部屋
+    pub event: ChatEvent, // 配送する出来事
+}
+
+// 配送タスク本体（メッセージバス1本につき1タスク）
+pub async fn run(mut bus_rx: broadcast::Receiver<RoomMessage>) {
+    loop {
+        let msg = match bus_rx.recv().await {
+            Ok(msg) => msg, // 配送対象メッセージ
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                crate::printdaytimeln!("配送遅延: {}件のメッセージを取りこぼしました", n); // ログ
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break, // バスが閉じたら終了
+        };
+        for session in session::snapshot() {
+            // 接続中のクライアントのうち同じ部屋の参加者へ配送
+            if !should_deliver(&session, &msg) {
+                continue; // 受信者ごとのフィルタで除外
+            }
+            session.deliver(render_for(&session, &msg.event)); // 受信の遅いクライアントがいても他のクライアントを待たせない
+        }
+    }
+}
+
+// メッセージバスを経由せず全セッションへ直接配送する（/wallなどの全体通知用、テナント指定時はそのテナントのみ）
+pub fn broadcast_all(tenant: Option<&str>, msg: &str) {
+    for session in session::snapshot() {
+        if tenant.is_some_and(|tenant| tenant != session.tenant) {
+            continue; // 他のテナントには送らない
+        }
+        session.deliver(msg.to_string()); // あふれた分は書き込みタスクが扱う
+    }
+}
+
+// テンプレートの変数を受信者ごとに展開して全セッションへ配送する（定時のお知らせ用）
+pub fn broadcast_template(template: &str) {
+    for session in session::snapshot() {
+        let (user, room) = (session.handle_name(), session.room());
+        let msg = template::render(
+            template,
+            &template::Context {
+                tenant: &session.tenant,
+                user: &user,
+                room: &room,
+            },
+        );
+        session.deliver(msg); // あふれた分は書き込みタスクが扱う
+    }
+}
+
+// 離席・復帰通知など重要度の低い通知を部屋の参加者へ直接配送する（/quiet on のクライアントには送らない）
+pub fn broadcast_ambient(tenant: &str, room: &str, msg: &str) {
+    if QUIET_HOURS.load(Ordering::Relaxed) {
+        return; // 静音時間中は誰にも送らない
+    }
+    for session in session::snapshot() {
+        if session.is_quiet() || session.tenant != tenant || session.room() != room {
+            continue; // 通信量を抑えたいクライアント・他の部屋には送らない
+        }
+        session.deliver(msg.to_string()); // あふれた分は書き込みタスクが扱う
+    }
+}
+
+// 静音時間の開始・終了
+pub fn set_quiet_hours(on: bool) {
+    QUIET_HOURS.store(on, Ordering::Relaxed);
+}
+
+// 受信者に合わせた文面（/ack on のクライアントにはチャット行の前にメッセージIDを付ける）
+fn render_for(session: &Session, event: &ChatEvent) -> String {
+    match event {
+        ChatEvent::Chat { id, .. } if session.wants_ids() => with_id(*id, &event.render()),
+        _ => event.render(),
+    }
+}
+
+// メッセージIDを付けたチャット行（"#ID "を前に付ける、/resendの再送にも使う）
+pub fn with_id(id: u64, line: &str) -> String {
+    format!("#{} {}", id, line)
+}
+
+// 受信者ごとの配送可否判定（無視リストなどのフィルタはここに集約する）
+fn should_deliver(session: &Session, msg: &RoomMessage) -> bool {
+    match &msg.event {
+        ChatEvent::Join { id, .. }
+        | ChatEvent::Leave { id, .. }
+        | ChatEvent::Connect { id, .. }
+        | ChatEvent::Disconnect { id, .. }
+        | ChatEvent::Nick { id, .. }
+            if *id == session.id =>
+        {
+            return false; // 本人には送らない
+        }
+        _ => {}
+    }
+    if msg
+        .event
+        .actor()
+        .is_some_and(|actor| session.is_ignoring(actor))
+    {
+        return false; // /ignore している相手の発言・入退室は送らない
+    }
+    if msg.event.is_ambient() && (QUIET_HOURS.load(Ordering::Relaxed) || session.is_quiet()) {
+        return false; // 静音時間中・/quiet on のクライアントには入退室・接続・切断を送らない
+    }
+    if matches!(
+        msg.event,
+        ChatEvent::Connect { .. } | ChatEvent::Disconnect { .. } | ChatEvent::Nick { .. }
+    ) {
+        return session.tenant == msg.tenant && !session.is_pending(); // 部屋を問わず同じテナントのハンドルネーム確定済みの全員に
+    }
+    session.tenant == msg.tenant && session.room() == msg.room // 同じテナントの同じ部屋の参加者だけに配送
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::TransportKind;
+    use chrono_tz::Asia::Tokyo;
+    use std::sync::Arc;
+
+    // テスト用のハンドルネーム確定済みのセッション
+    fn named(tenant: &str, name: &str, room: &str) -> Arc<Session> {
+        let (session, _queue_rx) = session::register("test", TransportKind::Tcp, tenant);
+        session.set_handle_name(name);
+        session.set_room(room);
+        session
+    }
+
+    fn message(tenant: &str, room: &str, event: ChatEvent) -> RoomMessage {
+        RoomMessage {
+            tenant: tenant.to_string(),
+            room: room.to_string(),
+            event,
+        }
+    }
+
+    fn chat(from: &str) -> ChatEvent {
+        ChatEvent::Chat {
+            id: 1,
+            kind: ChatKind::Say,
+            from: from.to_string(),
+            body: "hello".to_string(),
+            ts: chrono::Utc::now().with_timezone(&Tokyo),
+        }
+    }
+
+    // 発言は同じテナントの同じ部屋の参加者だけに、無視している相手の発言は届けない
+    #[test]
+    fn chat_is_scoped_to_tenant_room_and_ignore() {
+        let same = named("", "alice", "lobby");
+        let other_room = named("", "bob", "games");
+        let other_tenant = named("dispatch-other", "carol", "lobby");
+        let ignoring = named("", "dave", "lobby");
+        ignoring.ignore("Mallory"); // 大文字小文字は区別しない
+        let msg = message("", "lobby", chat("mallory"));
+        assert!(should_deliver(&same, &msg));
+        assert!(!should_deliver(&other_room, &msg));
+        assert!(!should_deliver(&other_tenant, &msg));
+        assert!(!should_deliver(&ignoring, &msg));
+        assert!(should_deliver(
+            &ignoring,
+            &message("", "lobby", chat("erin"))
+        ));
+        for session in [same, other_room, other_tenant, ignoring] {
+            session::unregister(session.id);
+        }
+    }
+
+    // 名前の変更は部屋を問わず同じテナントの確定済みの全員に、本人には届けない
+    #[test]
+    fn nick_is_scoped_to_tenant() {
+        let renamed = named("dispatch-nick", "frank", "lobby");
+        let other_room = named("dispatch-nick", "grace", "games");
+        let other_tenant = named("", "heidi", "lobby");
+        let (pending, _queue_rx) = session::register("test", TransportKind::Tcp, "dispatch-nick");
+        let event = ChatEvent::Nick {
+            id: renamed.id,
+            old: "frank".to_string(),
+            new: "frank2".to_string(),
+        };
+        let msg = message("dispatch-nick", "lobby", event);
+        assert!(!should_deliver(&renamed, &msg));
+        assert!(should_deliver(&other_room, &msg));
+        assert!(!should_deliver(&other_tenant, &msg));
+        assert!(!should_deliver(&pending, &msg)); // ハンドルネーム入力中
+        for session in [renamed, other_room, other_tenant, pending] {
+            session::unregister(session.id);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+// RustTokioChatServer - 機能切り替えモジュール
+// MIT License
+//
+// クレート説明:
+// - std: 標準ライブラリ（コレクション・同期・ファイル）
+// - lazy_static: グローバル静的変数
+//
+// features.rs: 実行中に管理用ソケットから止めたり戻したりできる機能（履歴の保存・スパム判定・定時処理）の状態
+// 切り替えはすぐに反映され、FeatureStateFileを指定していればそのファイルに保存して次の起動でも引き継ぐ
+// 上書きしていない機能は設定ファイルのとおりに動く（設定の再読込では上書きは消えない）
+use crate::init; // 設定管理モジュール
+use lazy_static::lazy_static; // lazy_static: グローバル静的変数
+use std::collections::HashMap; // std: 上書きした機能の一覧
+use std::sync::Mutex; // std: スレッド安全なミューテックス
+
+// 実行中に切り替えられる機能
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    History,  // チャット履歴の保存（HistoryDatabase）
+    Spam,     // スパム判定（SpamScorer・組み込み時の独自判定）
+    Schedule, // 定時処理（Schedule行）
+}
+
+// 一覧の表示順
+const ALL: [Feature; 3] = [Feature::History, Feature::Spam, Feature::Schedule];
+
+impl Feature {
+    // 管理コマンド・保存ファイルで使う名前
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::History => "history",
+            Feature::Spam => "spam",
+            Feature::Schedule => "schedule",
+        }
+    }
+
+    // 名前から機能を引く
+    pub fn from_name(name: &str) -> Option<Feature> {
+        ALL.into_iter().find(|feature| feature.name() == name)
+    }
+}
+
+lazy_static! {
+    static ref OVERRIDES: Mutex<HashMap<Feature, bool>> = Mutex::new(HashMap::new()); // 管理用ソケットで上書きした状態
+}
+
+// 機能が有効か（上書きしていなければ有効、止めても設定ファイルで無効な機能は動かない）
+pub fn is_enabled(feature: Feature) -> bool {
+    OVERRIDES
+        .lock()
+        .unwrap()
+        .get(&feature)
+        .copied()
+        .unwrap_or(true)
+}
+
+// 上書きを設定する（Noneなら上書きをやめて設定ファイルのとおりに戻す）、保存に失敗しても切り替えは反映する
+pub fn set(feature: Feature, enabled: Option<bool>) -> std::io::Result<()> {
+    let mut overrides = OVERRIDES.lock().unwrap();
+    match enabled {
+        Some(enabled) => overrides.insert(feature, enabled),
+        None => overrides.remove(&feature),
+    };
+    let Some(path) = init::CONFIG.read().unwrap().feature_state_file.clone() else {
+        return Ok(()); // 保存しない設定
+    };
+    let mut text = String::new();
+    for feature in ALL {
+        if let Some(enabled) = overrides.get(&feature) {
+            text.push_str(&format!(
+                "{} {}\n",
+                feature.name(),
+                if *enabled { "on" } else { "off" }
+            ));
+        }
+    }
+    let tmp = format!("{}.tmp", path);
+    std::fs::write(&tmp, text)?;
+    std::fs::rename(&tmp, &path) // 一時ファイルに書いてから置き換える
+}
+
+// 保存した上書きを読み込む（起動時、ファイルがなければ上書きなし）
+pub fn load() {
+    let Some(path) = init::CONFIG.read().unwrap().feature_state_file.clone() else {
+        return;
+    };
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return, // まだ切り替えていない
+        Err(e) => {
+            crate::printdaytimeln!("機能の切り替え状態を読み込めません: {} ({})", path, e); // ログ
+            return;
+        }
+    };
+    let mut overrides = OVERRIDES.lock().unwrap();
+    overrides.clear();
+    for line in text.lines() {
+        let parsed = line.split_once(' ').and_then(|(name, state)| {
+            let enabled = match state.trim() {
+                "on" => true,
+                "off" => false,
+                _ => return None,
+            };
+            Some((Feature::from_name(name)?, enabled))
+        });
+        match parsed {
+            Some((feature, enabled)) => {
+                overrides.insert(feature, enabled);
+            }
+            None if line.trim().is_empty() => {}
+            None => crate::printdaytimeln!("機能の切り替え状態の不明な行です: {}", line), // ログ
+        }
+    }
+    for (feature, enabled) in overrides.iter() {
+        crate::printdaytimeln!(
+            "機能の切り替え状態を引き継ぎ: {} {}",
+            feature.name(),
+            if *enabled { "on" } else { "off" }
+        ); // ログ
+    }
+}
+
+// 各機能の状態（1行1機能、管理用ソケットのfeature用）
+pub fn report() -> String {
+    let overrides = OVERRIDES.lock().unwrap();
+    ALL.into_iter()
+        .map(|feature| match overrides.get(&feature) {
+            Some(true) => format!("{} on (上書き)\n", feature.name()),
+            Some(false) => format!("{} off (上書き)\n", feature.name()),
+            None => format!("{} on\n", feature.name()),
+        })
+        .collect()
+}
@@ -0,0 +1,147 @@
+// RustTokioChatServer - 接続の指紋モジュール
+// MIT License
+//
+// クレート説明:
+// - tokio: 最初の受信までの時間
+// - std: 標準ライブラリ（ハッシュ・コレクション・同期）
+// - lazy_static: グローバル静的変数
+//
+// fingerprint.rs: 接続ごとの簡易な指紋（最初に届いたバイト列の特徴・通信路が申告した機能）を作る
+// IPアドレスを変えて接続拒否を逃れるクライアントを見分ける手がかりとしてセッションに記録し、オペレーターが/whois・/sessionsで見る
+// TLSは未対応のため、ClientHelloが届いた場合は種別だけを記録する（JA3は計算しない）
+use lazy_static::lazy_static; // lazy_static: グローバル静的変数
+use std::collections::hash_map::DefaultHasher; // std: 指紋IDのハッシュ
+use std::collections::HashMap; // std: 申告された機能の受け渡し
+use std::hash::{Hash, Hasher}; // std: ハッシュ計算
+use std::sync::Mutex; // std: スレッド安全なミューテックス
+use tokio::time::Duration; // Tokio: 最初の受信までの時間
+
+// telnetのネゴシエーションとして記録する件数の上限
+const MAX_TELNET_OPTIONS: usize = 8;
+
+// 接続1本分の指紋
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub id: String, // 比較用のID（同じ特徴の接続は同じID、同じサーバーのバージョン内で比較する）
+    pub detail: String, // オペレーター向けの説明
+}
+
+lazy_static! {
+    static ref DECLARED: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new()); // 接続元アドレス→通信路が申告した機能
+}
+
+// 通信路が受け付け時に知った機能（WebSocketのサブプロトコル・User-Agentなど）を預ける
+pub fn declare(peer_addr: &str, capabilities: String) {
+    DECLARED
+        .lock()
+        .unwrap()
+        .insert(peer_addr.to_string(), capabilities);
+}
+
+// 預けられた機能を受け取る（セッション登録時に一度だけ、なければ空）
+pub fn take_declared(peer_addr: &str) -> String {
+    DECLARED
+        .lock()
+        .unwrap()
+        .remove(peer_addr)
+        .unwrap_or_default()
+}
+
+// 最初に届いたバイト列・それまでの時間・申告された機能から指紋を作る
+pub fn compute(first: &[u8], delay: Duration, declared: &str) -> Fingerprint {
+    let mut features = format!("{} {}", kind(first), line_ending(first)); // 接続ごとに変わらない特徴
+    let options = telnet_options(first);
+    if !options.is_empty() {
+        features.push_str(&format!(" telnet[{}]", options.join(",")));
+    }
+    if !declared.is_empty() {
+        features.push_str(&format!(" {}", declared));
+    }
+    let mut hasher = DefaultHasher::new();
+    features.hash(&mut hasher);
+    let id = format!("{:08x}", hasher.finish() as u32); // 一覧で並べて見比べられる長さ
+    let detail = format!(
+        "{} / 初回{}バイト {}",
+        features,
+        first.len(),
+        delay_bucket(delay)
+    ); // 接続ごとに揺れる値は説明にだけ含める
+    Fingerprint { id, detail }
+}
+
+// 最初のバイト列の種類
+fn kind(first: &[u8]) -> &'static str {
+    const HTTP_METHODS: [&[u8]; 6] = [
+        b"GET ",
+        b"POST ",
+        b"HEAD ",
+        b"PUT ",
+        b"OPTIONS ",
+        b"CONNECT ",
+    ];
+    if first.first() == Some(&0xff) {
+        "telnet" // IACで始まるネゴシエーション
+    } else if first.starts_with(&[0x16, 0x03]) {
+        "tls" // TLSのClientHello（平文の待受に来た）
+    } else if HTTP_METHODS.iter().any(|method| first.starts_with(method)) {
+        "http" // HTTPのリクエスト（プロキシ・スキャナーなど）
+    } else if std::str::from_utf8(first).is_ok_and(|text| {
+        text.chars()
+            .all(|c| !c.is_control() || matches!(c, '\r' | '\n' | '\t'))
+    }) {
+        "text" // 普通の文字列
+    } else {
+        "binary" // 制御文字・UTF-8として読めないバイト列
+    }
+}
+
+// 改行の送り方
+fn line_ending(first: &[u8]) -> &'static str {
+    if first.windows(2).any(|pair| pair == b"\r\n") {
+        "crlf"
+    } else if first.contains(&b'\n') {
+        "lf"
+    } else if first.contains(&b'\r') {
+        "cr"
+    } else {
+        "none" // 行の途中で届いた・1文字ずつ送ってくる
+    }
+}
+
+// telnetのネゴシエーション（WILL/WONT/DO/DONTとオプション番号）
+fn telnet_options(first: &[u8]) -> Vec<String> {
+    let mut options = Vec::new();
+    let mut bytes = first.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte != 0xff {
+            continue; // IAC以外は読み飛ばす
+        }
+        let verb = match bytes.next() {
+            Some(251) => "WILL",
+            Some(252) => "WONT",
+            Some(253) => "DO",
+            Some(254) => "DONT",
+            _ => continue, // サブネゴシエーションなどは数えない
+        };
+        if let Some(option) = bytes.next() {
+            options.push(format!("{} {}", verb, option));
+        }
+        if options.len() == MAX_TELNET_OPTIONS {
+            break;
+        }
+    }
+    options
+}
+
+// 接続から最初の受信までの時間（人手とプログラムの見分けに使う程度の粗さ）
+fn delay_bucket(delay: Duration) -> &'static str {
+    if delay < Duration::from_millis(100) {
+        "100ms未満"
+    } else if delay < Duration::from_secs(1) {
+        "1秒未満"
+    } else if delay < Duration::from_secs(10) {
+        "10秒未満"
+    } else {
+        "10秒以上"
+    }
+}
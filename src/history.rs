@@ -0,0 +1,193 @@
+// RustTokioChatServer - チャット履歴保存モジュール
+// MIT License
+//
+// クレート説明:
+// - rusqlite: SQLiteへの書き込み
+// - tokio: 書き込みタスクへのキュー（mpsc）
+// - std: 書き込み専用スレッド・同期
+// - chrono, chrono-tz: 発言時刻（JST）
+// - lazy_static: グローバル静的変数
+//
+// history.rs: HistoryDatabaseが設定されている場合、チャットの各行（発言者・時刻・部屋）をSQLiteに保存する
+// 書き込みは専用スレッドで行い、クライアント処理からはキューに積むだけなので遅いディスクでも待たされない
+// スキーマは起動時に移行（マイグレーション）を順に適用し、適用済みの版数をschema_versionテーブルに記録する
+use crate::features::{self, Feature}; // 機能の切り替え
+use chrono_tz::Asia::Tokyo; // chrono-tz: JSTタイムゾーン
+use lazy_static::lazy_static; // lazy_static: グローバル静的変数
+use rusqlite::Connection; // rusqlite: SQLite接続
+use std::sync::Mutex; // std: スレッド安全なミューテックス
+use tokio::sync::mpsc; // Tokio: 書き込みキュー
+
+// 書き込み待ちの最大件数（超えた分は保存せずに破棄して数える）
+const QUEUE_SIZE: usize = 4096;
+// 1回のトランザクションでまとめて書き込む最大件数
+const BATCH_SIZE: usize = 256;
+
+// スキーマの移行（版数の昇順、適用済みの移行は書き換えず新しい版を追加する）
+const MIGRATIONS: &[(u32, &str)] = &[
+    (
+        1,
+        "CREATE TABLE messages (
+        id INTEGER PRIMARY KEY,
+        room TEXT NOT NULL,
+        sender TEXT NOT NULL,
+        text TEXT NOT NULL,
+        sent_at TEXT NOT NULL
+    );
+    CREATE INDEX messages_room_sent_at ON messages (room, sent_at);",
+    ),
+    (
+        2,
+        "ALTER TABLE messages ADD COLUMN kind TEXT NOT NULL DEFAULT 'say';", // 発言の種類（say・/me のaction）
+    ),
+];
+
+// 保存する1行分
+struct HistoryEntry {
+    room: String,    // 部屋名
+    sender: String,  // 発言者のハンドルネーム
+    text: String,    // 本文（禁止語は伏字にした後）
+    kind: String,    // 発言の種類（say・action）
+    sent_at: String, // 発言時刻（RFC 3339、JST）
+}
+
+// 書き込みスレッドへのキューとスレッドのハンドル
+struct Writer {
+    queue: mpsc::Sender<HistoryEntry>,   // 書き込みキュー
+    thread: std::thread::JoinHandle<()>, // 終了時に書き込み完了を待つ
+}
+
+lazy_static! {
+    static ref WRITER: Mutex<Option<Writer>> = Mutex::new(None); // 書き込みスレッド（保存しない場合はNone）
+}
+
+// データベースを開き、未適用の移行をすべて適用する
+fn open(path: &str) -> std::io::Result<Connection> {
+    let mut conn = Connection::open(path).map_err(std::io::Error::other)?;
+    let current = current_version(&conn).map_err(std::io::Error::other)?; // 適用済みの版数
+    let latest = MIGRATIONS.last().map_or(0, |(version, _)| *version); // このサーバーが知っている最新の版数
+    if current > latest {
+        // 新しいサーバーで作られたデータベースは壊さないよう触らない
+        return Err(std::io::Error::other(format!(
+            "版数v{}はこのサーバーの対応版数v{}より新しいため使用できません",
+            current, latest
+        )));
+    }
+    for (version, sql) in MIGRATIONS.iter().filter(|(version, _)| *version > current) {
+        apply(&mut conn, *version, sql).map_err(std::io::Error::other)?;
+        crate::printdaytimeln!(
+            "履歴データベース移行: {} v{} -> v{}",
+            path,
+            version - 1,
+            version
+        ); // ログ
+    }
+    Ok(conn)
+}
+
+// 適用済みの版数（版数の記録用テーブルがなければ作成して0）
+fn current_version(conn: &Connection) -> rusqlite::Result<u32> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL, applied_at TEXT NOT NULL);",
+    )?;
+    conn.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM schema_version",
+        [],
+        |row| row.get(0),
+    )
+}
+
+// 移行を1つ適用する（全部適用するか何もしないか）
+fn apply(conn: &mut Connection, version: u32, sql: &str) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute_batch(sql)?;
+    tx.execute(
+        "INSERT INTO schema_version (version, applied_at) VALUES (?1, ?2)",
+        (
+            version,
+            chrono::Local::now().with_timezone(&Tokyo).to_rfc3339(),
+        ),
+    )?;
+    tx.commit()
+}
+
+// 移行だけを適用する（--migrate-only用）
+pub fn migrate(path: &str) -> std::io::Result<()> {
+    open(path).map(drop)
+}
+
+// 保存を開始する（移行を適用してから書き込みスレッドを起動）
+pub fn start(path: &str) -> std::io::Result<()> {
+    let conn = open(path)?;
+    let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+    let thread = std::thread::spawn(move || write_loop(conn, rx)); // SQLiteへの書き込みは同期処理なので専用スレッドで行う
+    *WRITER.lock().unwrap() = Some(Writer { queue: tx, thread });
+    crate::printdaytimeln!("履歴保存開始: {}", path); // ログ
+    Ok(())
+}
+
+// 保存を終了する（キューに残った分を書き込み終えるまで待つ）
+pub fn stop() {
+    let Some(writer) = WRITER.lock().unwrap().take() else {
+        return; // 保存していない
+    };
+    drop(writer.queue); // キューを閉じると書き込みスレッドが残りを書いて終わる
+    let _ = writer.thread.join();
+}
+
+// チャットの1行を保存キューに積む（保存しない設定・キューが一杯の場合は何もしない）
+pub fn record(room: &str, sender: &str, text: &str, kind: &str) {
+    if !features::is_enabled(Feature::History) {
+        return; // 管理用ソケットで止めている
+    }
+    let writer = WRITER.lock().unwrap();
+    let Some(writer) = writer.as_ref() else {
+        return; // 保存しない設定
+    };
+    let entry = HistoryEntry {
+        room: room.to_string(),
+        sender: sender.to_string(),
+        text: text.to_string(),
+        kind: kind.to_string(),
+        sent_at: chrono::Local::now().with_timezone(&Tokyo).to_rfc3339(),
+    };
+    if writer.queue.try_send(entry).is_err() {
+        crate::stats::incr(&crate::stats::HISTORY_DROPPED); // 書き込みが追いつかない分は諦める
+    }
+}
+
+// 書き込みスレッド本体（キューから取り出した分をまとめて1トランザクションで書き込む）
+fn write_loop(mut conn: Connection, mut rx: mpsc::Receiver<HistoryEntry>) {
+    while let Some(first) = rx.blocking_recv() {
+        let mut batch = vec![first];
+        while batch.len() < BATCH_SIZE {
+            match rx.try_recv() {
+                Ok(entry) => batch.push(entry), // 溜まっている分もまとめる
+                Err(_) => break,
+            }
+        }
+        if let Err(e) = write_batch(&mut conn, &batch) {
+            crate::printdaytimeln!("履歴保存失敗: {}件 ({})", batch.len(), e); // ログ（サーバーは止めない）
+        }
+    }
+}
+
+// まとめて書き込む
+fn write_batch(conn: &mut Connection, batch: &[HistoryEntry]) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    {
+        let mut insert = tx.prepare_cached(
+            "INSERT INTO messages (room, sender, text, kind, sent_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for entry in batch {
+            insert.execute((
+                &entry.room,
+                &entry.sender,
+                &entry.text,
+                &entry.kind,
+                &entry.sent_at,
+            ))?;
+        }
+    }
+    tx.commit()
+}
@@ -4,61 +4,1352 @@
 // クレート説明:
 // - std: 標準ライブラリ、ファイル入出力・同期
 // - lazy_static: グローバル変数の初期化
+// - serde, toml: TOML形式の設定ファイル
 //
 // init.rs: 初期化処理を分離
+// TOML形式の設定ファイルは各項目を従来の設定行に直してから同じ解釈処理に通す（検査・警告も共通）
+use crate::roles::Role; // 役割
+use crate::scoring; // スパム判定モジュール（設定値の検査）
+use serde::Deserialize; // serde: TOMLの読み込み
+
+// メイン設定ファイルの既定のパス
+const CONFIG_PATH: &str = "RustTokioChatServer.conf";
+// TOML形式の設定ファイルの既定のパス（あればCONFIG_PATHより優先）
+const TOML_CONFIG_PATH: &str = "RustTokioChatServer.toml";
+// Include の入れ子の上限（循環Include対策）
+const MAX_INCLUDE_DEPTH: usize = 8;
+
 #[derive(Debug, Clone)] // Debug出力とCloneを可能にする属性
 pub struct Config {
     // サーバー設定情報を格納する構造体
-    pub address: String,           // 待受アドレス
+    pub addresses: Vec<String>,    // 待受アドレス（Listen行ごと、複数可）
     pub max_handle_name: usize,    // ハンドルネーム最大長
-    pub max_message_length: usize, // メッセージ最大長
+    pub max_message_length: usize, // メッセージ最大バイト数（受信バッファの上限）
+    pub max_bytes_per_second: usize, // クライアントごとの受信帯域上限（0は無制限）
+    pub bandwidth_policy: BandwidthPolicy, // 帯域超過時の扱い
+    pub client_queue_size: usize,  // クライアントごとの送信キュー長
+    pub motd_file: Option<String>, // MOTDファイルのパス
+    pub ban_file: Option<String>,  // 接続拒否IP一覧ファイルのパス
+    pub allow: Vec<crate::access::Cidr>, // 接続を許可する範囲（Allow行ごと、空なら全て許可）
+    pub deny: Vec<crate::access::Cidr>, // 接続を拒否する範囲（Deny行ごと、Allowより優先）
+    pub filter_file: Option<String>, // 禁止語一覧ファイルのパス
+    pub filter_words: Vec<String>, // 禁止語（FilterWords行、複数可、小文字化済み）
+    pub filter_policy: FilterPolicy, // 禁止語を含む発言の扱い
+    pub filter_rules: Vec<FilterRule>, // 正規表現による発言の検査（FilterRegex行、複数可、上から順に照合）
+    pub password: Option<String>, // 接続時にハンドルネームより先に求めるパスワード（未設定なら求めない）
+    pub password_attempts: usize, // 1接続でパスワードを間違えられる回数（0は無制限）
+    pub oper_password: Option<String>, // /operで使うオペレーターパスワード（未設定ならオペレーター無効）
+    pub idle_minutes: u64,             // 離席中とみなすまでの無入力時間（分、0は無効）
+    pub announce_idle: bool,           // 離席・復帰を他のクライアントに通知するか
+    pub announce_connect: bool,        // 接続・切断を他のクライアントに通知するか
+    pub color: bool,                   // 端末から接続したクライアントに色付けして送るか
+    pub handle_reserve_seconds: u64,   // 通信断の後にハンドルネームを予約しておく秒数（0は無効）
+    pub resume_seconds: u64,           // 通信断の後に再接続用トークンで/resumeできる秒数（0は無効）
+    pub name_attempts: usize,          // 1接続でハンドルネームの入力に失敗できる回数（0は無制限）
+    pub name_cooldown_seconds: u64,    // 入力に失敗し続けた接続元からの接続を断る秒数（0は無効）
+    pub auto_ban_strikes: usize,       // この回数の違反で接続元を自動的に締め出す（0は無効）
+    pub auto_ban_seconds: u64,         // 最初の自動締め出しの秒数（締め出すたびに倍）
+    pub max_message_chars: usize,      // メッセージ最大文字数（0は無制限）
+    pub max_burst_lines: usize,        // 1秒間に受け付ける行数の上限（0は無制限）
+    pub max_concurrent_setups: usize,  // 同時に進める接続準備の数（0は無制限）
+    pub max_clients: usize,            // サーバー全体の同時接続数の上限（0は無制限）
+    pub default_room: String,          // 接続時に入る既定の部屋
+    pub schedules: Vec<crate::scheduler::ScheduleEntry>, // 定時処理（Schedule行、複数可）
+    pub websocket_address: Option<String>, // WebSocketの待受アドレス（未設定なら無効）
+    pub unix_sockets: Vec<String>, // クライアント用Unixドメインソケットのパス（ListenUnix行ごと、UNIXのみ）
+    pub rebind_grace_seconds: u64, // 待受アドレス変更後も既存の接続を維持する秒数（0は即座に切断）
+    pub shutdown_timeout_seconds: u64, // 終了時に全クライアントの切断を待つ上限の秒数（0は待たない）
+    pub reconnect_after_seconds: u64, // 終了・再バインドで切断するときに再接続を勧める秒数（0なら終了時は案内しない）
+    pub ping_interval_seconds: u64, // /keepalive on の接続に入力がないときPINGを送る間隔（秒、0は接続確認なし）
+    pub ping_timeout_seconds: u64,  // PINGに応答がなければ切断するまでの秒数
+    pub history_database: Option<String>, // チャット履歴を保存するSQLiteファイル（未設定なら保存しない）
+    pub history_lines: usize,             // 入室時に再送する直近のチャット行数（0は再送しない）
+    pub soft_limit_percent: usize, // 各上限のこの割合に達したら事前に警告する（%、0は警告しない）
+    pub greeting: Option<String>, // ハンドルネーム確定時のあいさつ（テンプレート、未設定なら既定の文）
+    pub max_messages_per_second: usize, // 1秒あたりに補充する発言数（トークンバケット、0は無制限）
+    pub message_burst: usize,     // 連続して送れる発言数（トークンバケットの容量）
+    pub control_socket: Option<String>, // 管理用Unixドメインソケットのパス（未設定なら無効、UNIXのみ）
+    pub feature_state_file: Option<String>, // 管理用ソケットで切り替えた機能の状態を保存するファイル（未設定なら保存しない）
+    pub spam_scorer: Option<String>, // スパム判定の方法（heuristicかhttp://...、未設定なら判定しない）
+    pub spam_threshold: f64,         // この点数以上の発言をスパムとして破棄する（0.0〜1.0）
+    pub repeat_limit: usize, // 同じ（ほぼ同じ）発言をRepeatWindowSeconds以内にこの回数送ったら破棄・切断する（0は無効）
+    pub repeat_window_seconds: u64, // 繰り返しを数える期間（秒）
+    pub repeat_policy: RepeatPolicy, // 繰り返しを検出したときの扱い
+    pub oper_idle_minutes: u64, // オペレーター権限を失効させる無操作時間（分、0なら無効）
+    pub oper_max_minutes: u64, // 認証からオペレーター権限を失効させるまでの時間（分、0なら無効）
+    pub audit_log: Option<String>, // 監査ログを追記するファイル（未設定なら通常のログのみ）
+    pub log_format: LogFormat, // ログの出力形式（整形・JSON）
+    pub account_file: Option<String>, // 登録済みハンドルネームを保存するファイル（未設定なら/registerは無効）
+    pub identify_timeout_seconds: u64, // 登録済みのハンドルネームを/identifyで認証するまでの猶予（秒）
+    pub registered_only: bool, // 登録済みのハンドルネームだけを使わせるか（未登録の名前は猶予の間に/signupが必要）
+    pub roles: Vec<(String, Role)>, // 登録済みのハンドルネームの役割（Role行、複数可、認証した場合のみ）
+    pub default_role: Role,         // 登録済みのハンドルネームを認証していない接続の役割
+    pub room_max_message_lengths: Vec<(String, usize)>, // 部屋ごとのメッセージ最大長（RoomMaxMessageLength行、複数可）
+    pub queue_overflow_policy: OverflowPolicy,          // 送信キューがあふれたときの扱い
+    pub tenants: Vec<Tenant>, // 同じプロセスで受け付ける別のコミュニティ（Tenant行、複数可）
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tenant {
+    // 専用の待受アドレスを持ち、部屋・ハンドルネームが他と混ざらないコミュニティ
+    pub name: String,                      // テナント名（英数字・-・_）
+    pub address: String,                   // 待受アドレス（Listenと同じ書式）
+    pub motd_file: Option<String>,         // このテナントのMOTD（未設定ならMotdFile）
+    pub max_message_length: Option<usize>, // このテナントのメッセージ最大長（未設定ならMaxMessageLength）
+    pub max_handle_name: Option<usize>, // このテナントのハンドルネーム最大長（未設定ならMaxHandleName）
+    pub account_file: Option<String>,   // このテナントの登録ファイル（未設定ならAccountFile）
+}
+
+// テナント名として使えるか（英数字・-・_のみ、32文字まで）
+fn is_valid_tenant_name(name: &str) -> bool {
+    (1..=32).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)] // 比較可能なコピー型
+pub enum BandwidthPolicy {
+    // 帯域上限を超えた受信データの扱い
+    Drop,  // 超過分を破棄する
+    Queue, // 次の計測ウィンドウまで読み込みを止める（TCP側で待たせる）
 }
 
-pub fn load_config() -> Config {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)] // 比較可能なコピー型
+pub enum OverflowPolicy {
+    // 受信の遅いクライアントの送信キューがClientQueueSize行に達したときの扱い
+    DropOldest, // 古い行から捨てて新しい行を積む
+    DropNewest, // 新しい行を積まずに捨てる
+    Disconnect, // 切断する
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)] // 比較可能なコピー型
+pub enum RepeatPolicy {
+    // 同じ発言の繰り返しを検出したときの扱い
+    Throttle,   // 期間内に繰り返した分を破棄する（最初は警告）
+    Disconnect, // 切断する
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)] // 比較可能なコピー型
+pub enum FilterPolicy {
+    // 禁止語（FilterFile・FilterWords）を含む発言の扱い
+    Mask,       // 伏字にして届ける
+    Reject,     // 破棄して本人に警告する
+    Disconnect, // 切断する
+}
+
+#[derive(Debug, Clone)]
+pub struct FilterRule {
+    // FilterRegex行1つ分（設定の読み込み時にコンパイル済み）
+    pub action: FilterAction,  // 一致したときの扱い
+    pub pattern: regex::Regex, // 発言と照合する正規表現
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)] // 比較可能なコピー型
+pub enum FilterAction {
+    // FilterRegexに一致した発言の扱い
+    Drop, // 本人には知らせずに破棄する
+    Mask, // 一致部分を伏字にして届ける
+    Warn, // 破棄して本人に警告する
+    Ban,  // 切断して接続元を締め出す
+}
+
+impl FilterAction {
+    // 設定ファイルでの名前から
+    fn parse(name: &str) -> Option<FilterAction> {
+        match name {
+            "drop" => Some(FilterAction::Drop),
+            "mask" => Some(FilterAction::Mask),
+            "warn" => Some(FilterAction::Warn),
+            "ban" => Some(FilterAction::Ban),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)] // 比較可能なコピー型
+pub enum LogFormat {
+    // ログの出力形式
+    Pretty, // "[日時] 内容"の整形出力（従来どおり）
+    Json,   // 1行1件のJSON（ログ収集基盤向け）
+}
+
+// 行末コメント（行頭または空白直後の#以降）を取り除く
+fn strip_comment(line: &str) -> &str {
+    let mut prev_is_space = true; // 行頭は空白直後とみなす
+    for (i, c) in line.char_indices() {
+        if c == '#' && prev_is_space {
+            return &line[..i]; // コメント開始位置で切る
+        }
+        prev_is_space = c.is_whitespace();
+    }
+    line
+}
+
+// 設定ファイルを読み込み、Include行を展開しながら設定行を集める
+fn read_config_lines(path: &str, depth: usize, lines: &mut Vec<String>) -> std::io::Result<()> {
+    let text = std::fs::read_to_string(path)?; // ファイルを読み込む
+    for line in text.lines() {
+        let line = strip_comment(line).trim(); // コメントと前後の空白を除去
+        if let Some(rest) = line.strip_prefix("Include ") {
+            // Include行を検出
+            let include_path = rest.trim(); // 取り込むファイルのパス
+            if depth >= MAX_INCLUDE_DEPTH {
+                crate::printdaytimeln!("Include無視: {} (入れ子が深すぎます)", include_path);
+            // ログ
+            } else if let Err(e) = read_config_lines(include_path, depth + 1, lines) {
+                crate::printdaytimeln!("Include読み込み失敗: {} ({})", include_path, e);
+                // 読めないファイルは飛ばす
+            }
+        } else if !line.is_empty() {
+            lines.push(line.to_string()); // 通常の設定行
+        }
+    }
+    Ok(())
+}
+
+pub fn load_config() -> std::io::Result<Config> {
     // 設定ファイルからConfigを生成する関数
-    let text =
-        std::fs::read_to_string("RustTokioChatServer.conf").expect("設定ファイル読み込み失敗"); // 設定ファイルを読み込む（失敗時はpanic）
-    let mut address = None; // アドレス初期値（未設定）
+    let (config, warnings) = check_config()?; // 設定ファイルを読み込む
+    for warning in &warnings {
+        crate::printdaytimeln!("設定の警告: {}", warning); // 解釈できなかった行はログに残して無視
+    }
+    Ok(config)
+}
+
+// 設定ファイルを読み込んで解釈する（適用はしない、解釈できなかった行は警告として返す）
+pub fn check_config() -> std::io::Result<(Config, Vec<String>)> {
+    let Some(path) = CONFIG_FILE.read().unwrap().clone() else {
+        // 組み込み時にConfigを直接渡した場合は設定ファイルがない
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "設定ファイルが指定されていません",
+        ));
+    };
+    let path = if path == CONFIG_PATH && std::path::Path::new(TOML_CONFIG_PATH).exists() {
+        TOML_CONFIG_PATH.to_string() // 既定のままならTOML形式を優先
+    } else {
+        path
+    };
+    let mut lines = Vec::new(); // Include展開後の設定行
+    let mut toml_warnings = Vec::new(); // TOMLから設定行に直せなかった項目
+    if path.ends_with(".toml") {
+        read_toml_lines(&path, &mut lines, &mut toml_warnings)?; // TOML形式
+    } else {
+        read_config_lines(&path, 0, &mut lines)?; // 従来の形式
+    }
+    if let Some(listen) = LISTEN_OVERRIDE.read().unwrap().clone() {
+        lines.retain(|line| !line.starts_with("Listen ")); // 設定ファイルのListen行はすべて使わない
+        lines.push(format!("Listen {}", listen)); // コマンドラインの指定だけで待ち受ける
+    }
+    let (config, mut warnings) = parse_config(&lines);
+    toml_warnings.append(&mut warnings);
+    Ok((config, toml_warnings))
+}
+
+// TOML形式の設定ファイル（各セクションの項目は従来の設定行と同じ値を取る）
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct TomlConfig {
+    server: toml::Table,  // 待受・接続・部屋・履歴・オペレーターなど
+    limits: toml::Table,  // 長さ・帯域・発言数・スパム判定の制限
+    logging: toml::Table, // ログの出力形式・監査ログ
+    tls: toml::Table,     // TLS（未対応、書かれていれば警告）
+}
+
+// TOMLの項目名と従来の設定行のキーワードの対応（セクションごと）
+const TOML_KEYS: &[(&str, &[(&str, &str)])] = &[
+    (
+        "server",
+        &[
+            ("listen", "Listen"),
+            ("websocket_listen", "WebSocketListen"),
+            ("listen_unix", "ListenUnix"),
+            ("rebind_grace_seconds", "RebindGraceSeconds"),
+            ("shutdown_timeout_seconds", "ShutdownTimeoutSeconds"),
+            ("reconnect_after_seconds", "ReconnectAfterSeconds"),
+            ("ping_interval_seconds", "PingIntervalSeconds"),
+            ("ping_timeout_seconds", "PingTimeoutSeconds"),
+            ("max_concurrent_setups", "MaxConcurrentSetups"),
+            ("max_clients", "MaxClients"),
+            ("client_queue_size", "ClientQueueSize"),
+            ("queue_overflow_policy", "QueueOverflowPolicy"),
+            ("default_room", "DefaultRoom"),
+            ("greeting", "Greeting"),
+            ("motd_file", "MotdFile"),
+            ("ban_file", "BanFile"),
+            ("allow", "Allow"),
+            ("deny", "Deny"),
+            ("filter_file", "FilterFile"),
+            ("filter_words", "FilterWords"),
+            ("filter_policy", "FilterPolicy"),
+            ("filter_regex", "FilterRegex"),
+            ("idle_minutes", "IdleMinutes"),
+            ("announce_idle", "AnnounceIdle"),
+            ("announce_connect", "AnnounceConnect"),
+            ("color", "Color"),
+            ("handle_reserve_seconds", "HandleReserveSeconds"),
+            ("resume_seconds", "ResumeSeconds"),
+            ("name_attempts", "NameAttempts"),
+            ("name_cooldown_seconds", "NameCooldownSeconds"),
+            ("auto_ban_strikes", "AutoBanStrikes"),
+            ("auto_ban_seconds", "AutoBanSeconds"),
+            ("history_database", "HistoryDatabase"),
+            ("history_lines", "HistoryLines"),
+            ("schedule", "Schedule"),
+            ("oper_password", "OperPassword"),
+            ("password", "Password"),
+            ("password_attempts", "PasswordAttempts"),
+            ("oper_idle_minutes", "OperIdleMinutes"),
+            ("oper_max_minutes", "OperMaxMinutes"),
+            ("control_socket", "ControlSocket"),
+            ("feature_state_file", "FeatureStateFile"),
+            ("account_file", "AccountFile"),
+            ("identify_timeout_seconds", "IdentifyTimeoutSeconds"),
+            ("registered_only", "RegisteredOnly"),
+            ("role", "Role"),
+            ("default_role", "DefaultRole"),
+            ("tenant", "Tenant"),
+            ("tenant_motd_file", "TenantMotdFile"),
+            ("tenant_account_file", "TenantAccountFile"),
+        ],
+    ),
+    (
+        "limits",
+        &[
+            ("max_handle_name", "MaxHandleName"),
+            ("max_message_length", "MaxMessageLength"),
+            ("room_max_message_length", "RoomMaxMessageLength"),
+            ("tenant_max_message_length", "TenantMaxMessageLength"),
+            ("tenant_max_handle_name", "TenantMaxHandleName"),
+            ("max_message_chars", "MaxMessageChars"),
+            ("max_bytes_per_second", "MaxBytesPerSecond"),
+            ("bandwidth_policy", "BandwidthPolicy"),
+            ("max_burst_lines", "MaxBurstLines"),
+            ("max_messages_per_second", "MaxMessagesPerSecond"),
+            ("message_burst", "MessageBurst"),
+            ("soft_limit_percent", "SoftLimitPercent"),
+            ("spam_scorer", "SpamScorer"),
+            ("spam_threshold", "SpamThreshold"),
+            ("repeat_limit", "RepeatLimit"),
+            ("repeat_window_seconds", "RepeatWindowSeconds"),
+            ("repeat_policy", "RepeatPolicy"),
+        ],
+    ),
+    (
+        "logging",
+        &[("log_format", "LogFormat"), ("audit_log", "AuditLog")],
+    ),
+];
+
+// TOML形式の設定ファイルを読み込み、従来の設定行に直して集める（文法エラー・不明なセクションはエラー）
+fn read_toml_lines(
+    path: &str,
+    lines: &mut Vec<String>,
+    warnings: &mut Vec<String>,
+) -> std::io::Result<()> {
+    let text = std::fs::read_to_string(path)?; // ファイルを読み込む
+    let file: TomlConfig = toml::from_str(&text)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?;
+    for key in file.tls.keys() {
+        warnings.push(format!("TLSは未対応のため無視します: [tls] {}", key)); // 将来用
+    }
+    let sections = [
+        ("server", &file.server),
+        ("limits", &file.limits),
+        ("logging", &file.logging),
+    ];
+    for (section, table) in sections {
+        let keys = TOML_KEYS
+            .iter()
+            .find(|(name, _)| *name == section)
+            .map(|(_, keys)| *keys)
+            .unwrap_or_default();
+        for (key, value) in table {
+            let Some((_, keyword)) = keys.iter().find(|(name, _)| name == key) else {
+                warnings.push(format!("不明な設定項目です: [{}] {}", section, key)); // 不明な項目は無視
+                continue;
+            };
+            let values = match value {
+                toml::Value::Array(items) => items.iter().collect(), // 複数行（scheduleなど）
+                value => vec![value],
+            };
+            for value in values {
+                let text = match value {
+                    toml::Value::String(s) if !s.contains('\n') => s.clone(),
+                    toml::Value::Integer(n) => n.to_string(),
+                    toml::Value::Float(f) => f.to_string(),
+                    toml::Value::Boolean(b) => if *b { "on" } else { "off" }.to_string(),
+                    _ => {
+                        warnings.push(format!("値として解釈できません: [{}] {}", section, key)); // 不正な値
+                        continue;
+                    }
+                };
+                lines.push(format!("{} {}", keyword, text)); // 従来の設定行と同じ形にする
+            }
+        }
+    }
+    Ok(())
+}
+
+// 設定行を解釈してConfigを生成する（書かれていない項目は初期値）
+fn parse_config(lines: &[String]) -> (Config, Vec<String>) {
+    let mut warnings = Vec::new(); // 解釈できなかった行
+    let mut addresses: Vec<String> = Vec::new(); // 待受アドレス（未設定）
     let mut max_handle_name = 32; // ハンドルネーム最大長の初期値
     let mut max_message_length = 256; // メッセージ最大長の初期値
-    for line in text.lines() {
+    let mut max_bytes_per_second = 0; // 受信帯域上限の初期値（無制限）
+    let mut bandwidth_policy = BandwidthPolicy::Drop; // 帯域超過時の初期ポリシー
+    let mut client_queue_size = 100; // 送信キュー長の初期値
+    let mut motd_file = None; // MOTDファイル（未設定）
+    let mut ban_file = None; // 接続拒否IP一覧ファイル（未設定）
+    let mut allow = Vec::new(); // 接続を許可する範囲（なければ全て許可）
+    let mut deny = Vec::new(); // 接続を拒否する範囲
+    let mut filter_file = None; // 禁止語一覧ファイル（未設定）
+    let mut filter_words: Vec<String> = Vec::new(); // 禁止語（なし）
+    let mut filter_policy = FilterPolicy::Mask; // 禁止語を含む発言の初期の扱い（伏字）
+    let mut filter_rules = Vec::new(); // 正規表現による検査（なし）
+    let mut oper_password = None; // オペレーターパスワード（未設定）
+    let mut password = None; // 接続パスワード（未設定）
+    let mut password_attempts = 3; // パスワードの失敗回数上限の初期値
+    let mut idle_minutes = 10; // 離席判定時間の初期値（分）
+    let mut announce_idle = false; // 離席通知の初期値（通知しない）
+    let mut announce_connect = true; // 接続・切断通知の初期値（通知する）
+    let mut color = false; // 色付け表示の初期値（色を付けない）
+    let mut handle_reserve_seconds = 0; // ハンドルネーム予約期間の初期値（無効）
+    let mut resume_seconds = 0; // 接続再開の猶予の初期値（無効）
+    let mut name_attempts = 5; // ハンドルネーム入力の失敗回数上限の初期値
+    let mut name_cooldown_seconds = 60; // 締め出し期間の初期値（秒）
+    let mut auto_ban_strikes = 0; // 自動締め出しの違反回数の初期値（無効）
+    let mut auto_ban_seconds = 300; // 最初の自動締め出しの秒数の初期値
+    let mut max_message_chars = 0; // メッセージ最大文字数の初期値（無制限）
+    let mut max_burst_lines = 0; // 1秒間の行数上限の初期値（無制限）
+    let mut max_concurrent_setups = 0; // 同時接続準備数の初期値（無制限）
+    let mut max_clients = 0; // 同時接続数の上限の初期値（無制限）
+    let mut default_room = "lobby".to_string(); // 既定の部屋の初期値
+    let mut schedules = Vec::new(); // 定時処理の初期値（なし）
+    let mut websocket_address = None; // WebSocket待受アドレス（未設定）
+    let mut unix_sockets: Vec<String> = Vec::new(); // クライアント用Unixドメインソケット（なし）
+    let mut rebind_grace_seconds = 0; // 再バインド時の猶予の初期値（即座に切断）
+    let mut shutdown_timeout_seconds = 5; // 終了時の切断待ちの初期値（秒）
+    let mut reconnect_after_seconds = 0; // 再接続の案内の初期値（終了時は案内しない）
+    let mut ping_interval_seconds = 60; // 接続確認の間隔の初期値（秒）
+    let mut ping_timeout_seconds = 30; // 接続確認の応答待ちの初期値（秒）
+    let mut history_database = None; // 履歴データベース（未設定）
+    let mut history_lines = 0; // 再送する行数の初期値（再送しない）
+    let mut soft_limit_percent = 0; // 上限接近の警告の初期値（警告しない）
+    let mut greeting = None; // あいさつ（既定の文）
+    let mut max_messages_per_second = 0; // 発言数の上限の初期値（無制限）
+    let mut message_burst = 5; // 連続発言数の初期値
+    let mut control_socket = None; // 管理用ソケット（未設定）
+    let mut feature_state_file = None; // 機能の切り替え状態の保存先（未設定）
+    let mut spam_scorer = None; // スパム判定（未設定）
+    let mut spam_threshold = 0.8; // スパム判定のしきい値の初期値
+    let mut repeat_limit = 0; // 繰り返しの検出の初期値（無効）
+    let mut repeat_window_seconds = 60; // 繰り返しを数える期間の初期値（秒）
+    let mut repeat_policy = RepeatPolicy::Throttle; // 繰り返しの初期の扱い（破棄）
+    let mut oper_idle_minutes = 0; // オペレーターの無操作失効（無効）
+    let mut oper_max_minutes = 0; // オペレーターの有効期間（無期限）
+    let mut audit_log = None; // 監査ログファイル（未設定）
+    let mut log_format = LogFormat::Pretty; // ログの出力形式の初期値
+    let mut account_file = None; // ハンドルネーム登録ファイル（未設定）
+    let mut identify_timeout_seconds = 60; // 認証の猶予の初期値
+    let mut registered_only = false; // 登録済みのみの初期値（未登録の名前も使える）
+    let mut roles: Vec<(String, Role)> = Vec::new(); // ハンドルネームごとの役割（なし）
+    let mut default_role = Role::User; // 認証していない接続の役割の初期値（一般）
+    let mut room_max_message_lengths: Vec<(String, usize)> = Vec::new(); // 部屋ごとのメッセージ最大長（なし）
+    let mut queue_overflow_policy = OverflowPolicy::DropNewest; // 送信キューがあふれたときの初期ポリシー
+    let mut tenants: Vec<Tenant> = Vec::new(); // テナント（なし）
+    for line in lines {
         // 各行をループ
-        let line = line.trim(); // 前後の空白を除去
         if let Some(rest) = line.strip_prefix("Listen ") {
             // Listen行を検出
             let addr = rest.trim(); // アドレス部分を取得
-            if addr.contains(':') {
+            let address = if addr.contains(':') {
                 // IPアドレス:ポート形式
-                address = Some(addr.to_string()); // 指定アドレスでバインド（IPv4/IPv6どちらでも可）
+                addr.to_string() // 指定アドレスでバインド（IPv4/IPv6どちらでも可）
             } else {
                 // ポート番号のみ指定時はIPv4/IPv6両対応の[::]:ポートでバインド
-                address = Some(format!("[::]:{}", addr));
+                format!("[::]:{}", addr)
+            };
+            if addresses.contains(&address) {
+                warnings.push(format!("待受アドレスが重複しています: {}", line));
+            // 同じアドレスは1回だけ
+            } else {
+                addresses.push(address); // Listen行ごとに待受を追加
             }
         } else if let Some(rest) = line.strip_prefix("MaxHandleName ") {
             // MaxHandleName行を検出
             if let Ok(val) = rest.trim().parse::<usize>() {
                 // 数値変換に成功したら
                 max_handle_name = val; // ハンドルネーム最大長を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
             }
         } else if let Some(rest) = line.strip_prefix("MaxMessageLength ") {
             // MaxMessageLength行を検出
             if let Ok(val) = rest.trim().parse::<usize>() {
                 // 数値変換に成功したら
                 max_message_length = val; // メッセージ最大長を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("MaxBytesPerSecond ") {
+            // MaxBytesPerSecond行を検出
+            if let Ok(val) = rest.trim().parse::<usize>() {
+                // 数値変換に成功したら
+                max_bytes_per_second = val; // 受信帯域上限を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("BandwidthPolicy ") {
+            // BandwidthPolicy行を検出
+            match rest.trim() {
+                "drop" => bandwidth_policy = BandwidthPolicy::Drop, // 超過分を破棄
+                "queue" => bandwidth_policy = BandwidthPolicy::Queue, // 読み込みを遅延
+                _ => warnings.push(format!("不明な値です: {}", line)), // 不明な値は無視
+            }
+        } else if let Some(rest) = line.strip_prefix("ClientQueueSize ") {
+            // ClientQueueSize行を検出
+            if let Ok(val) = rest.trim().parse::<usize>() {
+                // 数値変換に成功したら
+                client_queue_size = val; // 送信キュー長を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("MotdFile ") {
+            motd_file = Some(rest.trim().to_string()); // MOTDファイルを設定
+        } else if let Some(rest) = line.strip_prefix("BanFile ") {
+            ban_file = Some(rest.trim().to_string()); // 接続拒否IP一覧ファイルを設定
+        } else if let Some(rest) = line.strip_prefix("Allow ") {
+            // Allow行を検出
+            match crate::access::Cidr::parse(rest.trim()) {
+                Some(range) => allow.push(range), // 許可する範囲を追加
+                None => warnings.push(format!("アドレス・CIDRとして解釈できません: {}", line)),
+            }
+        } else if let Some(rest) = line.strip_prefix("Deny ") {
+            // Deny行を検出
+            match crate::access::Cidr::parse(rest.trim()) {
+                Some(range) => deny.push(range), // 拒否する範囲を追加
+                None => warnings.push(format!("アドレス・CIDRとして解釈できません: {}", line)),
+            }
+        } else if let Some(rest) = line.strip_prefix("FilterFile ") {
+            filter_file = Some(rest.trim().to_string()); // 禁止語一覧ファイルを設定
+        } else if let Some(rest) = line.strip_prefix("FilterWords ") {
+            // FilterWords行を検出（空白区切り、複数行可）
+            for word in rest.split_whitespace().map(str::to_lowercase) {
+                if !filter_words.contains(&word) {
+                    filter_words.push(word); // 禁止語を追加
+                }
+            }
+        } else if let Some(rest) = line.strip_prefix("FilterPolicy ") {
+            // FilterPolicy行を検出
+            match rest.trim() {
+                "mask" => filter_policy = FilterPolicy::Mask, // 伏字にする
+                "reject" => filter_policy = FilterPolicy::Reject, // 破棄して警告する
+                "disconnect" => filter_policy = FilterPolicy::Disconnect, // 切断する
+                _ => warnings.push(format!("不明な値です: {}", line)), // 不明な値は無視
+            }
+        } else if let Some(rest) = line.strip_prefix("FilterRegex ") {
+            // FilterRegex行を検出（扱い 正規表現、複数行可）
+            let parsed = rest
+                .split_once(' ')
+                .and_then(|(action, pattern)| Some((FilterAction::parse(action)?, pattern.trim())));
+            match parsed {
+                Some((action, pattern)) => match regex::Regex::new(pattern) {
+                    Ok(pattern) => filter_rules.push(FilterRule { action, pattern }), // 読み込み時にコンパイル
+                    Err(e) => {
+                        warnings.push(format!("正規表現として解釈できません: {} ({})", line, e))
+                    }
+                },
+                None => warnings.push(format!("扱いと正規表現として解釈できません: {}", line)), // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("OperPassword ") {
+            oper_password = Some(rest.trim().to_string()); // オペレーターパスワードを設定
+        } else if let Some(rest) = line.strip_prefix("Password ") {
+            password = Some(rest.trim().to_string()); // 接続パスワードを設定
+        } else if let Some(rest) = line.strip_prefix("PasswordAttempts ") {
+            // PasswordAttempts行を検出
+            if let Ok(val) = rest.trim().parse::<usize>() {
+                password_attempts = val; // パスワードの失敗回数上限を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("IdleMinutes ") {
+            // IdleMinutes行を検出
+            if let Ok(val) = rest.trim().parse::<u64>() {
+                // 数値変換に成功したら
+                idle_minutes = val; // 離席判定時間を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("AnnounceIdle ") {
+            announce_idle = rest.trim() == "on"; // on のときだけ離席を通知
+        } else if let Some(rest) = line.strip_prefix("AnnounceConnect ") {
+            // AnnounceConnect行を検出
+            match rest.trim() {
+                "on" => announce_connect = true,   // 接続・切断を通知する
+                "off" => announce_connect = false, // 通知しない（利用者の多いサーバー向け）
+                _ => warnings.push(format!("不明な値です: {}", line)), // 不明な値は無視
+            }
+        } else if let Some(rest) = line.strip_prefix("Color ") {
+            // Color行を検出
+            match rest.trim() {
+                "on" => color = true,   // 接続直後から色付けして送る
+                "off" => color = false, // 色付けしない（各自が/color onで切り替える）
+                _ => warnings.push(format!("不明な値です: {}", line)), // 不明な値は無視
+            }
+        } else if let Some(rest) = line.strip_prefix("HandleReserveSeconds ") {
+            // HandleReserveSeconds行を検出
+            if let Ok(val) = rest.trim().parse::<u64>() {
+                // 数値変換に成功したら
+                handle_reserve_seconds = val; // ハンドルネーム予約期間を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("ResumeSeconds ") {
+            // ResumeSeconds行を検出
+            if let Ok(val) = rest.trim().parse::<u64>() {
+                resume_seconds = val; // 接続再開の猶予を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("NameAttempts ") {
+            // NameAttempts行を検出
+            if let Ok(val) = rest.trim().parse::<usize>() {
+                // 数値変換に成功したら
+                name_attempts = val; // ハンドルネーム入力の失敗回数上限を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("NameCooldownSeconds ") {
+            // NameCooldownSeconds行を検出
+            if let Ok(val) = rest.trim().parse::<u64>() {
+                // 数値変換に成功したら
+                name_cooldown_seconds = val; // 締め出し期間を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("AutoBanStrikes ") {
+            // AutoBanStrikes行を検出
+            if let Ok(val) = rest.trim().parse::<usize>() {
+                auto_ban_strikes = val; // 自動締め出しの違反回数を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("AutoBanSeconds ") {
+            // AutoBanSeconds行を検出
+            if let Ok(val) = rest.trim().parse::<u64>() {
+                auto_ban_seconds = val.max(1); // 最初の自動締め出しの秒数を設定（最低1秒）
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("MaxMessageChars ") {
+            // MaxMessageChars行を検出
+            if let Ok(val) = rest.trim().parse::<usize>() {
+                // 数値変換に成功したら
+                max_message_chars = val; // メッセージ最大文字数を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("MaxBurstLines ") {
+            // MaxBurstLines行を検出
+            if let Ok(val) = rest.trim().parse::<usize>() {
+                // 数値変換に成功したら
+                max_burst_lines = val; // 1秒間の行数上限を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("MaxConcurrentSetups ") {
+            // MaxConcurrentSetups行を検出
+            if let Ok(val) = rest.trim().parse::<usize>() {
+                // 数値変換に成功したら
+                max_concurrent_setups = val; // 同時接続準備数を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("MaxClients ") {
+            // MaxClients行を検出
+            if let Ok(val) = rest.trim().parse::<usize>() {
+                max_clients = val; // 同時接続数の上限を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("DefaultRoom ") {
+            // DefaultRoom行を検出
+            if crate::rooms::is_valid_name(rest.trim()) {
+                default_room = rest.trim().to_string(); // 既定の部屋を設定
+            } else {
+                warnings.push(format!("部屋名として使えません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("Schedule ") {
+            // Schedule行を検出（複数行可）
+            match crate::scheduler::parse_entry(rest) {
+                Some(entry) => schedules.push(entry), // 定時処理を追加
+                None => warnings.push(format!("定時処理として解釈できません: {}", line)), // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("WebSocketListen ") {
+            // WebSocketListen行を検出（Listenと同じ書式）
+            let addr = rest.trim();
+            if addr.contains(':') {
+                websocket_address = Some(addr.to_string()); // 指定アドレスでバインド
+            } else {
+                websocket_address = Some(format!("[::]:{}", addr)); // ポート番号のみ
+            }
+        } else if let Some(rest) = line.strip_prefix("ListenUnix ") {
+            // ListenUnix行を検出（ソケットファイルのパス、複数可）
+            let path = rest.trim().to_string();
+            if unix_sockets.contains(&path) {
+                warnings.push(format!("待受アドレスが重複しています: {}", line));
+            // 同じパスは1回だけ
+            } else {
+                unix_sockets.push(path); // ListenUnix行ごとに待受を追加
+            }
+        } else if let Some(rest) = line.strip_prefix("RebindGraceSeconds ") {
+            // RebindGraceSeconds行を検出
+            if let Ok(val) = rest.trim().parse::<u64>() {
+                rebind_grace_seconds = val; // 再バインド時の猶予を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("ShutdownTimeoutSeconds ") {
+            // ShutdownTimeoutSeconds行を検出
+            if let Ok(val) = rest.trim().parse::<u64>() {
+                shutdown_timeout_seconds = val; // 終了時の切断待ちの上限を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
             }
+        } else if let Some(rest) = line.strip_prefix("ReconnectAfterSeconds ") {
+            // ReconnectAfterSeconds行を検出
+            if let Ok(val) = rest.trim().parse::<u64>() {
+                reconnect_after_seconds = val; // 再接続を勧める秒数を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("PingIntervalSeconds ") {
+            // PingIntervalSeconds行を検出
+            if let Ok(val) = rest.trim().parse::<u64>() {
+                ping_interval_seconds = val; // 接続確認の間隔を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("PingTimeoutSeconds ") {
+            // PingTimeoutSeconds行を検出
+            if let Ok(val) = rest.trim().parse::<u64>() {
+                ping_timeout_seconds = val.max(1); // 接続確認の応答待ちを設定（最低1秒）
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("HistoryDatabase ") {
+            // HistoryDatabase行を検出
+            history_database = Some(rest.trim().to_string()); // 履歴データベースのパスを設定
+        } else if let Some(rest) = line.strip_prefix("HistoryLines ") {
+            // HistoryLines行を検出
+            if let Ok(val) = rest.trim().parse::<usize>() {
+                history_lines = val; // 再送する行数を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("SoftLimitPercent ") {
+            // SoftLimitPercent行を検出
+            if let Ok(val) = rest.trim().parse::<usize>() {
+                soft_limit_percent = val; // 上限接近の警告の割合を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("Greeting ") {
+            // Greeting行を検出
+            greeting = Some(rest.trim().to_string()); // あいさつのテンプレートを設定
+        } else if let Some(rest) = line.strip_prefix("MaxMessagesPerSecond ") {
+            // MaxMessagesPerSecond行を検出
+            if let Ok(val) = rest.trim().parse::<usize>() {
+                max_messages_per_second = val; // 発言数の上限を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("MessageBurst ") {
+            // MessageBurst行を検出
+            if let Ok(val) = rest.trim().parse::<usize>() {
+                message_burst = val.max(1); // 連続発言数を設定（最低1）
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("ControlSocket ") {
+            // ControlSocket行を検出
+            control_socket = Some(rest.trim().to_string()); // 管理用ソケットのパスを設定
+        } else if let Some(rest) = line.strip_prefix("FeatureStateFile ") {
+            // FeatureStateFile行を検出
+            feature_state_file = Some(rest.trim().to_string()); // 機能の切り替え状態の保存先を設定
+        } else if let Some(rest) = line.strip_prefix("SpamScorer ") {
+            // SpamScorer行を検出
+            let spec = rest.trim();
+            if scoring::is_valid_spec(spec) {
+                spam_scorer = Some(spec.to_string()); // スパム判定の方法を設定
+            } else {
+                warnings.push(format!(
+                    "heuristicかhttp://で始まるURLを指定してください: {}",
+                    line
+                )); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("SpamThreshold ") {
+            // SpamThreshold行を検出
+            if let Ok(val) = rest.trim().parse::<f64>() {
+                spam_threshold = val.clamp(0.0, 1.0); // しきい値を設定（0.0〜1.0）
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("RepeatLimit ") {
+            // RepeatLimit行を検出
+            if let Ok(val) = rest.trim().parse::<usize>() {
+                repeat_limit = if val == 0 { 0 } else { val.max(2) }; // 回数を設定（0は無効、最低2回）
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("RepeatWindowSeconds ") {
+            // RepeatWindowSeconds行を検出
+            if let Ok(val) = rest.trim().parse::<u64>() {
+                repeat_window_seconds = val.max(1); // 期間を設定（最低1秒）
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("RepeatPolicy ") {
+            // RepeatPolicy行を検出
+            match rest.trim() {
+                "throttle" => repeat_policy = RepeatPolicy::Throttle, // 繰り返した分を破棄する
+                "disconnect" => repeat_policy = RepeatPolicy::Disconnect, // 切断する
+                _ => warnings.push(format!("不明な値です: {}", line)), // 不明な値は無視
+            }
+        } else if let Some(rest) = line.strip_prefix("OperIdleMinutes ") {
+            // OperIdleMinutes行を検出
+            if let Ok(val) = rest.trim().parse::<u64>() {
+                oper_idle_minutes = val; // 無操作失効時間を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("OperMaxMinutes ") {
+            // OperMaxMinutes行を検出
+            if let Ok(val) = rest.trim().parse::<u64>() {
+                oper_max_minutes = val; // 有効期間を設定
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("AuditLog ") {
+            // AuditLog行を検出
+            audit_log = Some(rest.trim().to_string()); // 監査ログファイルを設定
+        } else if let Some(rest) = line.strip_prefix("LogFormat ") {
+            // LogFormat行を検出
+            match rest.trim() {
+                "pretty" => log_format = LogFormat::Pretty, // 従来どおりの整形出力
+                "json" => log_format = LogFormat::Json,     // 1行1件のJSON
+                _ => warnings.push(format!("不明な値です: {}", line)), // 不明な値は無視
+            }
+        } else if let Some(rest) = line.strip_prefix("AccountFile ") {
+            // AccountFile行を検出
+            account_file = Some(rest.trim().to_string()); // 登録ファイルを設定
+        } else if let Some(rest) = line.strip_prefix("IdentifyTimeoutSeconds ") {
+            // IdentifyTimeoutSeconds行を検出
+            if let Ok(val) = rest.trim().parse::<u64>() {
+                identify_timeout_seconds = val.max(1); // 認証の猶予を設定（最低1秒）
+            } else {
+                warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("RegisteredOnly ") {
+            // RegisteredOnly行を検出
+            match rest.trim() {
+                "on" => registered_only = true, // 登録済みのハンドルネームだけを使わせる
+                "off" => registered_only = false, // 未登録の名前も使える
+                _ => warnings.push(format!("不明な値です: {}", line)), // 不明な値は無視
+            }
+        } else if let Some(rest) = line.strip_prefix("Role ") {
+            // Role行を検出（役割 ハンドルネーム、複数行可）
+            let parsed = rest
+                .split_once(' ')
+                .and_then(|(role, name)| Some((Role::parse(role.trim())?, name.trim())))
+                .filter(|(_, name)| !name.is_empty() && !name.contains(char::is_whitespace));
+            match parsed {
+                Some((role, name)) => {
+                    roles.retain(|(other, _)| other.to_lowercase() != name.to_lowercase()); // 同じ名前は後の行を優先
+                    roles.push((name.to_string(), role));
+                }
+                None => warnings.push(format!(
+                    "役割とハンドルネームとして解釈できません: {}",
+                    line
+                )), // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("DefaultRole ") {
+            // DefaultRole行を検出
+            match Role::parse(rest.trim()) {
+                Some(role @ (Role::Guest | Role::User)) => default_role = role, // ゲスト・一般のみ
+                _ => warnings.push(format!("不明な値です: {}", line)),          // 不明な値は無視
+            }
+        } else if let Some(rest) = line.strip_prefix("RoomMaxMessageLength ") {
+            // RoomMaxMessageLength行を検出（部屋名 バイト数、複数行可）
+            let parsed = rest
+                .split_once(' ')
+                .and_then(|(room, val)| Some((room.trim(), val.trim().parse::<usize>().ok()?)))
+                .filter(|(room, val)| crate::rooms::is_valid_name(room) && *val > 0);
+            match parsed {
+                Some((room, val)) => {
+                    room_max_message_lengths.retain(|(name, _)| name != room); // 同じ部屋は後の行を優先
+                    room_max_message_lengths.push((room.to_string(), val));
+                }
+                None => warnings.push(format!("部屋名とバイト数として解釈できません: {}", line)), // 不正な値
+            }
+        } else if let Some(rest) = line.strip_prefix("QueueOverflowPolicy ") {
+            // QueueOverflowPolicy行を検出
+            match rest.trim() {
+                "drop-oldest" => queue_overflow_policy = OverflowPolicy::DropOldest, // 古いものを捨てる
+                "drop-newest" => queue_overflow_policy = OverflowPolicy::DropNewest, // 新しいものを捨てる
+                "disconnect" => queue_overflow_policy = OverflowPolicy::Disconnect,  // 切断する
+                _ => warnings.push(format!("不明な値です: {}", line)), // 不明な値は無視
+            }
+        } else if let Some(rest) = line.strip_prefix("Tenant ") {
+            // Tenant行を検出（テナント名 待受アドレス、複数行可）
+            match rest.split_once(' ') {
+                Some((name, addr)) if is_valid_tenant_name(name) && !addr.trim().is_empty() => {
+                    let addr = addr.trim();
+                    let address = if addr.contains(':') {
+                        addr.to_string() // 指定アドレスでバインド
+                    } else {
+                        format!("[::]:{}", addr) // ポート番号のみ
+                    };
+                    match tenants.iter_mut().find(|tenant| tenant.name == name) {
+                        Some(tenant) => tenant.address = address, // 同じテナントは後の行を優先
+                        None => tenants.push(Tenant {
+                            name: name.to_string(),
+                            address,
+                            motd_file: None,
+                            max_message_length: None,
+                            max_handle_name: None,
+                            account_file: None,
+                        }),
+                    }
+                }
+                _ => warnings.push(format!(
+                    "テナント名と待受アドレスとして解釈できません: {}",
+                    line
+                )), // 不正な値
+            }
+        } else if let Some((keyword, rest)) = line
+            .split_once(' ')
+            .filter(|(keyword, _)| keyword.starts_with("Tenant"))
+        {
+            // テナントごとの設定行（TenantMotdFileなど、対象のTenant行より後に書く）
+            let Some((name, value)) = rest.split_once(' ').map(|(n, v)| (n, v.trim())) else {
+                warnings.push(format!("テナント名と値として解釈できません: {}", line)); // 不正な値
+                continue;
+            };
+            let Some(tenant) = tenants.iter_mut().find(|tenant| tenant.name == name) else {
+                warnings.push(format!("Tenant行で定義されていないテナントです: {}", line)); // 先にTenant行が必要
+                continue;
+            };
+            match keyword {
+                "TenantMotdFile" => tenant.motd_file = Some(value.to_string()),
+                "TenantAccountFile" => tenant.account_file = Some(value.to_string()),
+                "TenantMaxMessageLength" | "TenantMaxHandleName" => {
+                    let Ok(val) = value.parse::<usize>() else {
+                        warnings.push(format!("数値として解釈できません: {}", line)); // 不正な値
+                        continue;
+                    };
+                    if keyword == "TenantMaxMessageLength" {
+                        tenant.max_message_length = Some(val);
+                    } else {
+                        tenant.max_handle_name = Some(val);
+                    }
+                }
+                _ => warnings.push(format!("不明な設定行です: {}", line)), // 不明な設定行は無視
+            }
+        } else {
+            warnings.push(format!("不明な設定行です: {}", line)); // 不明な設定行は無視
         }
     }
     // Listen行がなければデフォルトで127.0.0.1:8667を使用
-    let address = address.unwrap_or_else(|| "127.0.0.1:8667".to_string()); // デフォルトアドレス
-    Config {
-        address,            // アドレス
-        max_handle_name,    // ハンドルネーム最大長
-        max_message_length, // メッセージ最大長
+    if addresses.is_empty() {
+        addresses.push("127.0.0.1:8667".to_string()); // デフォルトアドレス
     }
+    let config = Config {
+        addresses,                // アドレス
+        max_handle_name,          // ハンドルネーム最大長
+        max_message_length,       // メッセージ最大長
+        max_bytes_per_second,     // 受信帯域上限
+        bandwidth_policy,         // 帯域超過時の扱い
+        client_queue_size,        // 送信キュー長
+        motd_file,                // MOTDファイル
+        ban_file,                 // 接続拒否IP一覧ファイル
+        allow,                    // 接続を許可する範囲
+        deny,                     // 接続を拒否する範囲
+        filter_file,              // 禁止語一覧ファイル
+        filter_words,             // 禁止語
+        filter_policy,            // 禁止語を含む発言の扱い
+        filter_rules,             // 正規表現による検査
+        oper_password,            // オペレーターパスワード
+        password,                 // 接続パスワード
+        password_attempts,        // パスワードの失敗回数上限
+        idle_minutes,             // 離席判定時間
+        announce_idle,            // 離席通知
+        announce_connect,         // 接続・切断通知
+        color,                    // 色付け表示の既定
+        handle_reserve_seconds,   // ハンドルネーム予約期間
+        resume_seconds,           // 接続再開の猶予
+        name_attempts,            // ハンドルネーム入力の失敗回数上限
+        name_cooldown_seconds,    // 締め出し期間
+        auto_ban_strikes,         // 自動締め出しの違反回数
+        auto_ban_seconds,         // 最初の自動締め出しの秒数
+        max_message_chars,        // メッセージ最大文字数
+        max_burst_lines,          // 1秒間の行数上限
+        max_concurrent_setups,    // 同時接続準備数
+        max_clients,              // 同時接続数の上限
+        default_room,             // 既定の部屋
+        schedules,                // 定時処理
+        websocket_address,        // WebSocket待受アドレス
+        unix_sockets,             // クライアント用Unixドメインソケット
+        rebind_grace_seconds,     // 再バインド時の猶予
+        shutdown_timeout_seconds, // 終了時の切断待ちの上限
+        reconnect_after_seconds,  // 再接続の案内
+        ping_interval_seconds,    // 接続確認の間隔
+        ping_timeout_seconds,     // 接続確認の応答待ち
+        history_database,         // 履歴データベース
+        history_lines,            // 再送する行数
+        soft_limit_percent,       // 上限接近の警告の割合
+        greeting,                 // あいさつ
+        max_messages_per_second,  // 発言数の上限
+        message_burst,            // 連続発言数
+        control_socket,           // 管理用ソケット
+        feature_state_file,       // 機能の切り替え状態の保存先
+        spam_scorer,              // スパム判定
+        spam_threshold,           // スパム判定のしきい値
+        repeat_limit,             // 繰り返しの回数
+        repeat_window_seconds,    // 繰り返しを数える期間
+        repeat_policy,            // 繰り返しの扱い
+        oper_idle_minutes,        // オペレーターの無操作失効
+        oper_max_minutes,         // オペレーターの有効期間
+        audit_log,                // 監査ログファイル
+        log_format,               // ログの出力形式
+        account_file,             // ハンドルネーム登録ファイル
+        identify_timeout_seconds, // 認証の猶予
+        registered_only,          // 登録済みのみ
+        roles,                    // ハンドルネームごとの役割
+        default_role,             // 認証していない接続の役割
+        room_max_message_lengths, // 部屋ごとのメッセージ最大長
+        queue_overflow_policy,    // 送信キューがあふれたときの扱い
+        tenants,                  // テナント
+    };
+    (config, warnings)
+}
+
+impl Config {
+    // 上限に対する警告のしきい値（上限が無制限・警告しない設定ならNone）
+    pub fn soft_limit(&self, limit: usize) -> Option<usize> {
+        (limit != 0 && (1..100).contains(&self.soft_limit_percent))
+            .then(|| (limit * self.soft_limit_percent / 100).max(1))
+    }
+
+    // テナントに適用する設定（テナントごとの指定で上書きしたもの、既定の待受なら空文字列でそのまま）
+    pub fn for_tenant(&self, name: &str) -> Config {
+        let mut config = self.clone();
+        if let Some(tenant) = self.tenants.iter().find(|tenant| tenant.name == name) {
+            if tenant.motd_file.is_some() {
+                config.motd_file = tenant.motd_file.clone();
+            }
+            if tenant.account_file.is_some() {
+                config.account_file = tenant.account_file.clone();
+            }
+            config.max_message_length =
+                tenant.max_message_length.unwrap_or(self.max_message_length);
+            config.max_handle_name = tenant.max_handle_name.unwrap_or(self.max_handle_name);
+        }
+        config
+    }
+
+    // 接続の役割（登録済みのハンドルネームを認証していればRole行の役割か一般、していなければDefaultRole）
+    // Role行は既定の待受のAccountFileの登録に対してだけ使う（テナントは登録を別に持つので同じ名前でも別人）
+    pub fn role_for(&self, tenant: &str, handle_name: &str, identified: bool) -> Role {
+        if !identified {
+            return self.default_role;
+        }
+        if !tenant.is_empty() {
+            return Role::User;
+        }
+        self.roles
+            .iter()
+            .find(|(name, _)| name.to_lowercase() == handle_name.to_lowercase())
+            .map_or(Role::User, |(_, role)| *role)
+    }
+
+    // 部屋で許される一行の最大バイト数（部屋ごとの指定がなければMaxMessageLength）
+    pub fn max_message_length_for(&self, room: &str) -> usize {
+        self.room_max_message_lengths
+            .iter()
+            .find(|(name, _)| name == room)
+            .map_or(self.max_message_length, |(_, len)| *len)
+    }
+}
+
+// 設定ファイルに何も書かれていない場合と同じ初期値（組み込み時の出発点）
+impl Default for Config {
+    fn default() -> Self {
+        parse_config(&[]).0
+    }
+}
+
+// 2つの設定の差分を「項目: 変更前 -> 変更後」の形で返す（パスワードは伏せる）
+pub fn config_diff(old: &Config, new: &Config) -> Vec<String> {
+    debug_fields(old)
+        .into_iter()
+        .zip(debug_fields(new))
+        .filter(|(before, after)| before != after)
+        .map(|((name, before), (_, after))| {
+            if name == "oper_password" || name == "password" {
+                format!("{}: (変更あり)", name) // パスワードは表示しない
+            } else {
+                format!("{}: {} -> {}", name, before, after)
+            }
+        })
+        .collect()
+}
+
+// Debug出力（{:#?}）を項目名と値の組に分ける（複数行にわたる値は1行にまとめる）
+fn debug_fields(config: &Config) -> Vec<(String, String)> {
+    let mut fields: Vec<(String, String)> = Vec::new();
+    for line in format!("{:#?}", config).lines() {
+        let Some(rest) = line.strip_prefix("    ") else {
+            continue; // 先頭行（Config {）と末尾行（}）
+        };
+        match rest.split_once(": ") {
+            Some((name, value)) if !rest.starts_with(' ') => {
+                // 字下げが1段の行は新しい項目
+                fields.push((name.to_string(), value.to_string()));
+            }
+            _ => {
+                if let Some((_, value)) = fields.last_mut() {
+                    value.push_str(rest.trim()); // 前の項目の続き（Some(...)や一覧の中身）
+                    if value.ends_with(',') {
+                        value.push(' ');
+                    }
+                }
+            }
+        }
+    }
+    for (_, value) in fields.iter_mut() {
+        // 行末のカンマと閉じ括弧の前の余分なカンマを除く
+        *value = value
+            .trim_end()
+            .trim_end_matches(',')
+            .replace(", )", ")")
+            .replace(", ]", "]");
+    }
+    fields
+}
+
+// 設定ファイルと補助ファイルをすべて再読み込みする（SIGHUP/CTRL-Y用）
+// 待受アドレスが変わった場合は新しいアドレス（複数ならカンマ区切り）を返す
+pub fn reload() -> Option<String> {
+    let new_config = match load_config() {
+        Ok(config) => config, // 設定再読込
+        Err(e) => {
+            crate::printdaytimeln!(
+                "設定ファイルを読み込めないため現在の設定を維持します: {}",
+                e
+            ); // ログ
+            crate::lists::reload_all(); // 補助ファイルだけは再読込する
+            return None;
+        }
+    };
+    let new_addresses = new_config.addresses.clone(); // 新しい待受アドレス
+    crate::logging::apply(new_config.log_format); // ログの出力形式を切り替える
+    let old_addresses = std::mem::replace(&mut *CONFIG.write().unwrap(), new_config).addresses; // 設定を更新
+    crate::lists::reload_all(); // MOTD・接続拒否IP・禁止語を個別に再読込
+    (old_addresses != new_addresses).then(|| new_addresses.join(", "))
 }
 
 use std::sync::RwLock; // RwLockをインポート
 
 lazy_static::lazy_static! { // lazy_staticでグローバルな設定を定義
-    pub static ref CONFIG: RwLock<Config> = RwLock::new(load_config()); // グローバル設定（再読み込み対応）
+    pub static ref CONFIG: RwLock<Config> = RwLock::new(Config::default()); // グローバル設定（起動時に読み込んだ内容で置き換え、再読み込み対応）
+    static ref CONFIG_FILE: RwLock<Option<String>> = RwLock::new(Some(CONFIG_PATH.to_string())); // 読み込む設定ファイル（Noneは設定ファイルなし）
+    static ref LISTEN_OVERRIDE: RwLock<Option<String>> = RwLock::new(None); // 設定ファイルのListenより優先する待受アドレス
+}
+
+// 設定ファイルのListenを上書きする待受アドレスを指定する（再読込しても維持される）
+pub fn set_listen_override(listen: Option<String>) {
+    *LISTEN_OVERRIDE.write().unwrap() = listen;
+}
+
+// 読み込む設定ファイルを変更する（Noneなら再読込しても設定は変わらない）
+pub fn set_config_file(path: Option<String>) {
+    *CONFIG_FILE.write().unwrap() = path;
+}
+
+// テスト中だけグローバル設定を差し替える（ドロップで元に戻す）
+// CONFIGを読むテストは並列に動くと互いの設定を見てしまうので、差し替えている間は他のテストを待たせる
+#[cfg(test)]
+pub struct TestConfig {
+    previous: Option<Config>,                    // 差し替える前の設定
+    _serial: std::sync::MutexGuard<'static, ()>, // CONFIGを使うテストを1つずつ動かす
+}
+
+#[cfg(test)]
+static TEST_CONFIG_SERIAL: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+#[cfg(test)]
+impl TestConfig {
+    // 初期値にchangeを適用した設定に差し替える
+    pub fn set(change: impl FnOnce(&mut Config)) -> Self {
+        let serial = TEST_CONFIG_SERIAL
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()); // 失敗したテストの後も続ける
+        let mut config = Config::default();
+        change(&mut config);
+        let previous = std::mem::replace(&mut *CONFIG.write().unwrap(), config);
+        TestConfig {
+            previous: Some(previous),
+            _serial: serial,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestConfig {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            *CONFIG
+                .write()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()) = previous;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 設定行の一覧を作る
+    fn lines(text: &[&str]) -> Vec<String> {
+        text.iter().map(|line| line.to_string()).collect()
+    }
+
+    // TOMLの項目は従来の設定行に直して同じ解釈処理に通し、不明な項目・解釈できない値は警告して読み飛ばす
+    #[test]
+    fn toml_keys_map_to_config_lines() {
+        let path = std::env::temp_dir().join(format!("rtcs-test-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+[server]
+listen = ["127.0.0.1:9000", "127.0.0.1:9001"]
+announce_idle = true
+role = "admin alice"
+unknown_key = 1
+greeting = { text = "hi" }
+
+[limits]
+max_message_length = 512
+spam_threshold = 0.5
+"#,
+        )
+        .unwrap();
+        let (mut toml_lines, mut warnings) = (Vec::new(), Vec::new());
+        let result = read_toml_lines(path.to_str().unwrap(), &mut toml_lines, &mut warnings);
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap();
+        toml_lines.sort();
+        assert_eq!(
+            toml_lines,
+            [
+                "AnnounceIdle on",
+                "Listen 127.0.0.1:9000",
+                "Listen 127.0.0.1:9001",
+                "MaxMessageLength 512",
+                "Role admin alice",
+                "SpamThreshold 0.5",
+            ]
+        );
+        assert!(warnings.contains(&"不明な設定項目です: [server] unknown_key".to_string()));
+        assert!(warnings.contains(&"値として解釈できません: [server] greeting".to_string()));
+
+        let (config, warnings) = parse_config(&toml_lines);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(config.addresses, ["127.0.0.1:9000", "127.0.0.1:9001"]);
+        assert!(config.announce_idle);
+        assert_eq!(config.max_message_length, 512);
+        assert_eq!(config.spam_threshold, 0.5);
+        assert_eq!(config.roles, [("alice".to_string(), Role::Admin)]);
+    }
+
+    // TOMLの文法エラーは読み込みエラー（現在の設定を維持させる）
+    #[test]
+    fn toml_syntax_error_is_rejected() {
+        let path = std::env::temp_dir().join(format!("rtcs-test-bad-{}.toml", std::process::id()));
+        std::fs::write(&path, "[server\nlisten = 1\n").unwrap();
+        let (mut toml_lines, mut warnings) = (Vec::new(), Vec::new());
+        let result = read_toml_lines(path.to_str().unwrap(), &mut toml_lines, &mut warnings);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    // 設定差分は変わった項目だけを「項目: 変更前 -> 変更後」で示し、パスワードの値は出さない
+    #[test]
+    fn config_diff_lists_changed_fields() {
+        let (old, _) = parse_config(&lines(&["MaxMessageLength 256", "OperPassword old"]));
+        assert!(config_diff(&old, &old).is_empty());
+        let (new, _) = parse_config(&lines(&[
+            "MaxMessageLength 512",
+            "OperPassword new",
+            "Listen 127.0.0.1:9000",
+            "Role admin alice",
+        ]));
+        assert_eq!(
+            config_diff(&old, &new),
+            [
+                "addresses: [\"127.0.0.1:8667\"] -> [\"127.0.0.1:9000\"]",
+                "max_message_length: 256 -> 512",
+                "oper_password: (変更あり)",
+                "roles: [] -> [(\"alice\", Admin)]",
+            ]
+        );
+    }
+
+    // Role行は既定の待受で認証した登録にだけ使い、認証していなければDefaultRole、テナントの接続は一般
+    #[test]
+    fn role_for_uses_roles_only_when_identified() {
+        let (config, _) = parse_config(&lines(&[
+            "Role admin Alice",
+            "Role operator bob",
+            "DefaultRole guest",
+        ]));
+        assert_eq!(config.role_for("", "alice", true), Role::Admin); // 大文字小文字は区別しない
+        assert_eq!(config.role_for("", "bob", true), Role::Operator);
+        assert_eq!(config.role_for("", "carol", true), Role::User); // 指定のない登録済みの名前
+        assert_eq!(config.role_for("", "alice", false), Role::Guest); // 認証していない
+        assert_eq!(config.role_for("games", "alice", true), Role::User); // テナントの同名の別人
+        assert_eq!(config.role_for("games", "alice", false), Role::Guest);
+    }
+
+    // テナントの設定はテナントごとの指定だけを上書きし、既定の待受・未定義のテナントにはそのまま使う
+    #[test]
+    fn for_tenant_overrides_only_tenant_settings() {
+        let (config, warnings) = parse_config(&lines(&[
+            "MaxMessageLength 256",
+            "MaxHandleName 32",
+            "MotdFile motd.txt",
+            "Tenant games 127.0.0.1:8702",
+            "TenantMaxMessageLength games 100",
+            "TenantAccountFile games games-accounts.db",
+            "TenantMaxHandleName nowhere 8",
+        ]));
+        assert_eq!(
+            warnings,
+            ["Tenant行で定義されていないテナントです: TenantMaxHandleName nowhere 8"]
+        );
+        let games = config.for_tenant("games");
+        assert_eq!(games.max_message_length, 100);
+        assert_eq!(games.max_handle_name, 32); // 指定のない項目はそのまま
+        assert_eq!(games.motd_file.as_deref(), Some("motd.txt"));
+        assert_eq!(games.account_file.as_deref(), Some("games-accounts.db"));
+        for name in ["", "unknown"] {
+            let plain = config.for_tenant(name);
+            assert_eq!(plain.max_message_length, 256);
+            assert_eq!(plain.account_file, None);
+        }
+    }
 }
@@ -0,0 +1,59 @@
+// RustTokioChatServer - 非同期チャットサーバー ライブラリ
+// MIT License
+//
+// クレート説明:
+// - tokio: 非同期ランタイム、TCP通信、シグナル処理など
+// - chrono, chrono-tz: 日時・タイムゾーン処理
+// - tracing: ログ出力（printdaytimeln!）
+// - std: 標準ライブラリ、スレッド同期や入出力
+//
+// lib.rs: 他のTokioアプリケーションに組み込めるよう、サーバー本体をライブラリとして公開する
+// 使い方: ChatServer::builder().config(config).build() で生成し、shutdown_handle()で停止用ハンドルを取ってから run().await
+mod access; // 接続元の許可・拒否モジュール
+mod accounts; // ハンドルネーム登録モジュール
+mod audit; // 監査ログモジュール
+mod autoban; // 自動締め出しモジュール
+mod client; // クライアント処理モジュール
+mod color; // 色付け表示モジュール
+mod commands; // スラッシュコマンド定義モジュール
+#[cfg(unix)]
+mod control; // 管理用ソケットモジュール（UNIXのみ）
+mod disconnect; // 切断理由モジュール
+mod dispatch; // メッセージ配送モジュール
+mod features; // 機能切り替えモジュール
+mod fingerprint; // 接続の指紋モジュール
+mod history; // チャット履歴保存モジュール
+mod init; // 設定読み込み用モジュール
+mod listener; // 接続受付モジュール
+mod lists; // MOTD・接続拒否IP・禁止語の補助ファイル管理モジュール
+mod logging; // ログ出力モジュール
+mod names; // ハンドルネーム管理モジュール
+mod outbound; // 送信処理モジュール
+mod resume; // 接続再開モジュール
+mod roles; // 権限モジュール
+mod rooms; // 部屋管理モジュール
+mod scheduler; // 定時処理モジュール
+mod scoring; // スパム判定モジュール
+mod server; // サーバー本体モジュール
+mod session; // セッション管理モジュール
+mod stats; // サーバー統計モジュール
+mod systemd; // systemd連携モジュール
+mod telnet; // telnetネゴシエーションモジュール
+mod template; // メッセージテンプレートモジュール
+mod tenant; // テナント接続受付モジュール
+mod transport; // 通信路抽象化モジュール
+#[cfg(unix)]
+mod unix; // Unixドメインソケット接続受付モジュール（UNIXのみ）
+mod websocket; // WebSocket接続受付モジュール
+
+pub use init::{BandwidthPolicy, Config, LogFormat}; // 設定（Config::default()は設定ファイルが空の場合と同じ）
+pub use scoring::{MessageInfo, ScoreFuture, Scorer}; // 独自のスパム判定を組み込むためのトレイト
+pub use server::{ChatServer, ChatServerBuilder, LifecycleEvent, ShutdownHandle}; // サーバー本体と停止用ハンドル・状態変化の通知
+
+// ログ出力マクロ（クレート全体で利用可能、JSTタイムスタンプと出力形式はlogging.rsで付ける）
+#[macro_export] // クレート全体で利用できるようにエクスポート
+macro_rules! printdaytimeln { // ログ出力用マクロ定義
+    ($($arg:tt)*) => {{ // 可変引数を受け取る
+        ::tracing::info!("{}", format!($($arg)*)); // tracingのイベントとして記録
+    }};
+}
@@ -0,0 +1,215 @@
+// RustTokioChatServer - 接続受付モジュール
+// MIT License
+//
+// クレート説明:
+// - tokio: TCP待受・待機用タイマー・セマフォ・ブロードキャストチャネル
+// - libc: OSのエラー番号（UNIXのみ）
+//
+// listener.rs: バインド済みのリスナー1つ分の受付ループと、accept()の失敗の分類・待機からの再開
+// 受け付けた接続の準備（ウェルカムメッセージ送信など）の同時実行数もここで制限する
+use crate::access; // 接続元の許可・拒否
+use crate::autoban; // 自動締め出し
+use crate::client; // クライアント処理モジュール
+use crate::disconnect::{DisconnectReason, ShutdownReason}; // 切断理由・停止理由
+use crate::dispatch::RoomMessage; // メッセージバスの型
+use crate::lists; // 接続拒否IP
+use crate::stats; // サーバー統計
+use crate::transport::TransportKind; // 通信路の種別
+use std::io; // std: 入出力エラー
+use std::sync::Arc; // std: スレッド安全な参照カウント
+use tokio::io::AsyncWriteExt; // Tokio: 拒否理由の書き込み
+use tokio::net::{TcpListener, TcpStream}; // Tokio: TCP待受
+use tokio::sync::{broadcast, OwnedSemaphorePermit, Semaphore}; // Tokio: 停止通知・メッセージバス・接続準備の同時実行数制限
+use tokio::time::Duration; // Tokio: 待機時間
+
+// リソース枯渇時の待機時間（初回・上限）
+const BACKOFF_INITIAL: Duration = Duration::from_millis(100);
+const BACKOFF_MAX: Duration = Duration::from_secs(5);
+// 受け付けない接続元へ拒否理由を書き込むのを待つ上限（受信しない相手の分はあきらめて閉じる）
+const REJECT_TIMEOUT: Duration = Duration::from_millis(500);
+
+// accept()エラーの分類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AcceptError {
+    Transient, // 相手側の切断など、その接続だけの問題（すぐ再開してよい）
+    Exhausted, // ファイルディスクリプタ・メモリ不足（少し待ってから再開）
+    Other,     // その他
+}
+
+// OSのエラーから分類を判定
+fn classify(e: &io::Error) -> AcceptError {
+    match e.kind() {
+        io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::Interrupted => return AcceptError::Transient,
+        io::ErrorKind::OutOfMemory => return AcceptError::Exhausted,
+        _ => {}
+    }
+    #[cfg(unix)]
+    let exhausted = matches!(
+        e.raw_os_error(),
+        Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM)
+    ); // プロセス/システムのFD上限・バッファ不足
+    #[cfg(windows)]
+    let exhausted = matches!(e.raw_os_error(), Some(10024 | 10055)); // WSAEMFILE / WSAENOBUFS
+    if exhausted {
+        AcceptError::Exhausted
+    } else {
+        AcceptError::Other
+    }
+}
+
+// accept()失敗時の待機状態
+pub struct Backoff {
+    delay: Duration, // 次にリソース枯渇した場合の待機時間
+}
+
+impl Backoff {
+    pub fn new() -> Self {
+        Backoff {
+            delay: BACKOFF_INITIAL,
+        }
+    }
+
+    // 受付に成功したら待機時間を初期値に戻す
+    pub fn reset(&mut self) {
+        self.delay = BACKOFF_INITIAL;
+    }
+}
+
+// accept()の失敗を記録し、リソース枯渇時は待機してから戻る
+pub async fn on_accept_error(e: &io::Error, backoff: &mut Backoff) {
+    match classify(e) {
+        AcceptError::Transient => {
+            stats::incr(&stats::ACCEPT_ERRORS_TRANSIENT);
+            crate::printdaytimeln!("接続受付エラー(一時的): {}", e); // 次の接続はすぐ受け付ける
+        }
+        AcceptError::Exhausted => {
+            stats::incr(&stats::ACCEPT_ERRORS_EXHAUSTED);
+            crate::printdaytimeln!(
+                "接続受付エラー(リソース枯渇): {} / {}ミリ秒待機します",
+                e,
+                backoff.delay.as_millis()
+            ); // ログ
+            tokio::time::sleep(backoff.delay).await; // 既存接続の終了でリソースが空くのを待つ
+            backoff.delay = (backoff.delay * 2).min(BACKOFF_MAX); // 次回は待機時間を倍に
+        }
+        AcceptError::Other => {
+            stats::incr(&stats::ACCEPT_ERRORS_OTHER);
+            crate::printdaytimeln!("接続受付エラー: {}", e); // ログ
+        }
+    }
+}
+
+// 同時に進める接続準備の枠（上限を超えた接続は枠が空くまで待たせる）
+#[derive(Clone)]
+pub struct SetupSlots {
+    semaphore: Option<Arc<Semaphore>>, // Noneは無制限
+    limit: usize,                      // 枠の数
+    warn_at: Option<usize>,            // 使用中の枠がこの数に達したらログで知らせる
+}
+
+impl SetupSlots {
+    pub fn new(limit: usize, warn_at: Option<usize>) -> Self {
+        SetupSlots {
+            semaphore: (limit > 0).then(|| Arc::new(Semaphore::new(limit))),
+            limit,
+            warn_at,
+        }
+    }
+
+    // 枠を1つ確保する（無制限ならNone、準備が終わったら手放す）
+    pub async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        let semaphore = self.semaphore.as_ref()?;
+        let permit = Arc::clone(semaphore).acquire_owned().await.ok()?;
+        let in_use = self.limit - semaphore.available_permits(); // 使用中の枠
+        if Some(in_use) == self.warn_at {
+            // しきい値を超えた時だけ知らせる（上限に達すると接続が待たされ始める）
+            crate::printdaytimeln!("上限接近: 接続準備 {}/{}", in_use, self.limit);
+            // ログ
+        }
+        Some(permit)
+    }
+}
+
+// 受け付けない接続に拒否理由を通知して閉じる
+// 受信しない相手への書き込みで受付ループが止まらないよう、別タスクで上限時間まで書き込む
+fn reject(mut stream: TcpStream, reason: DisconnectReason) {
+    let Some(notice) = reason.notice() else {
+        return; // 通知しない理由はそのまま閉じる
+    };
+    tokio::spawn(async move {
+        let _ = tokio::time::timeout(REJECT_TIMEOUT, stream.write_all(notice.as_bytes())).await;
+    });
+}
+
+// リスナー1つ分の受付ループ（Listen行・テナントの待受ごとに1タスク、止めるときはタスクごと中断する）
+// 受け付けた接続はすべて同じメッセージバスにつなぐ
+pub async fn accept_connections(
+    listener: TcpListener,                          // バインド済みのリスナー
+    tenant: String,                                 // 接続を受け付けるテナント（既定の待受なら空）
+    setup_slots: SetupSlots,                        // 接続準備の同時実行枠
+    shutdown_tx: broadcast::Sender<ShutdownReason>, // 停止・再読込通知用
+    msg_tx: broadcast::Sender<RoomMessage>,         // メッセージバス
+) {
+    let mut backoff = Backoff::new(); // accept()失敗時の待機状態
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                on_accept_error(&e, &mut backoff).await; // 分類してログ・必要なら待機
+                continue;
+            }
+        };
+        backoff.reset(); // 受付に成功したら待機時間を戻す
+        let addr = std::net::SocketAddr::new(addr.ip().to_canonical(), addr.port()); // IPv4射影アドレス(::ffff:a.b.c.d)はIPv4表記に直す
+        if lists::is_banned(&addr.ip()) {
+            // 接続拒否IPからの接続は通知して閉じる
+            let reason = DisconnectReason::Banned; // 切断理由
+            crate::printdaytimeln!("接続拒否: {} [{}]", addr, reason.code()); // ログ
+            reject(stream, reason); // 拒否理由を通知して閉じる
+            continue;
+        }
+        if let Some(left) = autoban::banned_for(&addr.ip().to_string()) {
+            // 違反が続いて締め出し中の接続元は通知して閉じる
+            let reason = DisconnectReason::AutoBanned; // 切断理由
+            crate::printdaytimeln!(
+                "接続拒否: {} [{}] 残り{}秒",
+                addr,
+                reason.code(),
+                left.as_secs()
+            ); // ログ
+            reject(stream, reason); // 拒否理由を通知して閉じる
+            continue;
+        }
+        if let Err(rule) = access::check(&addr.ip()) {
+            // Allow・Denyで受け付けない接続元は通知して閉じる
+            let reason = DisconnectReason::Denied; // 切断理由
+            crate::printdaytimeln!("接続拒否: {} [{}] {}", addr, reason.code(), rule); // ログ（一致した設定）
+            reject(stream, reason); // 拒否理由を通知して閉じる
+            continue;
+        }
+        if tenant.is_empty() {
+            crate::printdaytimeln!("接続: {}", addr); // ログ
+        } else {
+            crate::printdaytimeln!("接続: {} (テナント {})", addr, tenant); // ログ
+        }
+        let tenant = tenant.clone();
+        let setup_slots = setup_slots.clone(); // 接続準備枠を共有
+        let shutdown_tx = shutdown_tx.clone();
+        let msg_tx = msg_tx.clone();
+        tokio::spawn(async move {
+            let setup_permit = setup_slots.acquire().await; // 枠が空くまで待つ
+            client::handle_client(
+                stream,
+                addr.to_string(),
+                TransportKind::Tcp,
+                &tenant,
+                setup_permit,
+                shutdown_tx,
+                msg_tx,
+            )
+            .await;
+        }); // クライアント処理を非同期で開始
+    }
+}
@@ -0,0 +1,244 @@
+// RustTokioChatServer - 補助リスト管理モジュール
+// MIT License
+//
+// クレート説明:
+// - tokio: ファイル更新監視用のタイマー
+// - std: 標準ライブラリ（ファイル入出力・コレクション・同期）
+// - lazy_static: グローバル静的変数
+//
+// lists.rs: MOTD・接続拒否IP・禁止語など、メイン設定とは別ファイルで管理する一覧を扱う
+// 禁止語はメイン設定のFilterWords行の分もここで合わせて照合する
+// 各ファイルは個別に読み込まれ、読み込みに失敗しても直前の内容とメイン設定はそのまま残る
+// テナントごとのMOTD（TenantMotdFile）は起動時・再読込時にだけ読み込む
+use crate::init; // 設定管理モジュール
+use lazy_static::lazy_static; // lazy_static: グローバル静的変数
+use std::collections::{HashMap, HashSet}; // std: 一覧・更新時刻の管理
+use std::net::IpAddr; // std: IPアドレス
+use std::ops::Range; // std: 禁止語に一致した範囲
+use std::sync::{Mutex, RwLock}; // std: スレッド安全なロック
+use std::time::SystemTime; // std: ファイル更新時刻
+use tokio::time::Duration; // Tokio: 監視間隔
+
+// ファイル更新を確認する間隔
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+// 補助ファイルの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ListKind {
+    Motd,   // MOTD（接続時に表示する文章）
+    Bans,   // 接続拒否IP一覧
+    Filter, // 禁止語一覧
+}
+
+const ALL_KINDS: [ListKind; 3] = [ListKind::Motd, ListKind::Bans, ListKind::Filter];
+
+impl ListKind {
+    // ログ表示用の名前
+    fn label(self) -> &'static str {
+        match self {
+            ListKind::Motd => "MOTD",
+            ListKind::Bans => "接続拒否IP",
+            ListKind::Filter => "禁止語",
+        }
+    }
+
+    // 設定から対応するファイルパスを取得
+    fn path(self, config: &init::Config) -> Option<String> {
+        match self {
+            ListKind::Motd => config.motd_file.clone(),
+            ListKind::Bans => config.ban_file.clone(),
+            ListKind::Filter => config.filter_file.clone(),
+        }
+    }
+}
+
+// 読み込み済みの補助リスト
+#[derive(Debug, Default)]
+struct Lists {
+    motd: String,                          // MOTD本文
+    bans: HashSet<IpAddr>,                 // 接続拒否IP
+    filter_words: Vec<String>,             // 禁止語（小文字化済み）
+    config_filter_words: Vec<String>,      // 設定ファイルのFilterWords行の禁止語（小文字化済み）
+    tenant_motds: HashMap<String, String>, // テナント名→MOTD本文（TenantMotdFileを指定したテナントのみ）
+}
+
+lazy_static! {
+    static ref LISTS: RwLock<Lists> = RwLock::new(Lists::default()); // 読み込み済みの一覧
+    static ref MODIFIED: Mutex<HashMap<ListKind, SystemTime>> = Mutex::new(HashMap::new()); // 最後に読み込んだ時点の更新時刻
+}
+
+// 1行1項目のファイルから空行と#コメント行を除いた項目を取り出す
+fn entries(text: &str) -> impl Iterator<Item = &str> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+// 指定の補助ファイルを読み込んで反映する（失敗時は直前の内容を維持）
+fn load(kind: ListKind, path: &str) {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            crate::printdaytimeln!("{}読み込み失敗: {} ({})", kind.label(), path, e); // ログ
+            return;
+        }
+    };
+    if let Ok(modified) = std::fs::metadata(path).and_then(|m| m.modified()) {
+        MODIFIED.lock().unwrap().insert(kind, modified); // 更新時刻を記録
+    }
+    let mut lists = LISTS.write().unwrap(); // 一覧を更新
+    match kind {
+        ListKind::Motd => {
+            lists.motd = text;
+            if !lists.motd.is_empty() && !lists.motd.ends_with('\n') {
+                lists.motd.push('\n'); // 末尾の改行を補う
+            }
+        }
+        ListKind::Bans => {
+            let mut bans = HashSet::new();
+            for entry in entries(&text) {
+                match entry.parse::<IpAddr>() {
+                    Ok(ip) => {
+                        bans.insert(ip.to_canonical()); // 接続拒否IPに追加（IPv4射影アドレスはIPv4として扱う）
+                    }
+                    Err(_) => crate::printdaytimeln!("接続拒否IPの書式誤り: {}", entry), // 読めない行は飛ばす
+                }
+            }
+            lists.bans = bans;
+        }
+        ListKind::Filter => {
+            lists.filter_words = entries(&text).map(str::to_lowercase).collect();
+        }
+    }
+    crate::printdaytimeln!("{}読み込み: {}", kind.label(), path); // ログ
+}
+
+// 指定種類の一覧を空にする（設定からファイル指定が消えた場合）
+fn clear(kind: ListKind) {
+    MODIFIED.lock().unwrap().remove(&kind);
+    let mut lists = LISTS.write().unwrap();
+    match kind {
+        ListKind::Motd => lists.motd.clear(),
+        ListKind::Bans => lists.bans.clear(),
+        ListKind::Filter => lists.filter_words.clear(),
+    }
+}
+
+// すべての補助ファイルを読み込み直す（起動時・SIGHUP時）
+pub fn reload_all() {
+    let config = init::CONFIG.read().unwrap().clone(); // 現在の設定
+    LISTS.write().unwrap().config_filter_words = config.filter_words.clone(); // FilterWordsは再読込時にだけ変わる
+    for kind in ALL_KINDS {
+        match kind.path(&config) {
+            Some(path) => load(kind, &path),
+            None => clear(kind),
+        }
+    }
+    let mut tenant_motds = HashMap::new();
+    for tenant in &config.tenants {
+        let Some(path) = &tenant.motd_file else {
+            continue; // MotdFileを使う
+        };
+        match std::fs::read_to_string(path) {
+            Ok(mut text) => {
+                if !text.is_empty() && !text.ends_with('\n') {
+                    text.push('\n'); // 末尾の改行を補う
+                }
+                tenant_motds.insert(tenant.name.clone(), text);
+            }
+            Err(e) => {
+                crate::printdaytimeln!("MOTD読み込み失敗: {} {} ({})", tenant.name, path, e); // 直前の内容を維持
+                if let Some(text) = LISTS.read().unwrap().tenant_motds.get(&tenant.name) {
+                    tenant_motds.insert(tenant.name.clone(), text.clone());
+                }
+            }
+        }
+    }
+    LISTS.write().unwrap().tenant_motds = tenant_motds;
+}
+
+// 補助ファイルの更新を監視し、変更されたファイルだけを読み込み直すタスク
+pub async fn watch() {
+    loop {
+        tokio::time::sleep(WATCH_INTERVAL).await; // 一定間隔で確認
+        let config = init::CONFIG.read().unwrap().clone(); // 現在の設定
+        for kind in ALL_KINDS {
+            let Some(path) = kind.path(&config) else {
+                continue; // ファイル指定なし
+            };
+            let Ok(modified) = std::fs::metadata(&path).and_then(|m| m.modified()) else {
+                continue; // 読めないファイルは次回に再確認
+            };
+            let changed = MODIFIED.lock().unwrap().get(&kind) != Some(&modified); // 前回読み込み時から変わったか
+            if changed {
+                load(kind, &path); // 変更されたファイルのみ再読込
+            }
+        }
+    }
+}
+
+// 指定ファイルの内容にMOTDを切り替える（定時処理用、MotdFileが更新されるか再読込されるまで有効）
+pub fn rotate_motd(path: &str) {
+    match std::fs::read_to_string(path) {
+        Ok(mut text) => {
+            if !text.is_empty() && !text.ends_with('\n') {
+                text.push('\n'); // 末尾の改行を補う
+            }
+            LISTS.write().unwrap().motd = text;
+            crate::printdaytimeln!("MOTD切替: {}", path); // ログ
+        }
+        Err(e) => crate::printdaytimeln!("MOTD切替失敗: {} ({})", path, e), // 直前の内容を維持
+    }
+}
+
+// テナントのMOTD本文を取得（TenantMotdFileがなければMotdFile、どちらも未設定なら空文字列）
+pub fn motd(tenant: &str) -> String {
+    let lists = LISTS.read().unwrap();
+    lists
+        .tenant_motds
+        .get(tenant)
+        .unwrap_or(&lists.motd)
+        .clone()
+}
+
+// 接続拒否IPに含まれるか
+pub fn is_banned(ip: &IpAddr) -> bool {
+    LISTS.read().unwrap().bans.contains(&ip.to_canonical())
+}
+
+// 禁止語（FilterFileとFilterWords）に一致する範囲（文字単位、大文字小文字は区別しない）
+fn filtered_ranges(chars: &[char]) -> Vec<Range<usize>> {
+    let lists = LISTS.read().unwrap();
+    let lower: Vec<char> = chars
+        .iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .collect(); // 比較用に小文字化
+    let mut ranges = Vec::new();
+    for word in lists.filter_words.iter().chain(&lists.config_filter_words) {
+        let word: Vec<char> = word.chars().collect();
+        if word.is_empty() || word.len() > lower.len() {
+            continue;
+        }
+        for start in 0..=(lower.len() - word.len()) {
+            if lower[start..start + word.len()] == word[..] {
+                ranges.push(start..start + word.len()); // 一致部分
+            }
+        }
+    }
+    ranges
+}
+
+// 禁止語を含むか
+pub fn contains_filtered(msg: &str) -> bool {
+    let chars: Vec<char> = msg.chars().collect();
+    !filtered_ranges(&chars).is_empty()
+}
+
+// 禁止語を伏字（*）に置き換える（大文字小文字は区別しない）
+pub fn mask_filtered(msg: &str) -> String {
+    let mut chars: Vec<char> = msg.chars().collect(); // 文字単位で比較する
+    for range in filtered_ranges(&chars) {
+        chars[range].fill('*'); // 一致部分を伏字に
+    }
+    chars.into_iter().collect()
+}
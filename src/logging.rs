@@ -0,0 +1,101 @@
+// RustTokioChatServer - ログ出力モジュール
+// MIT License
+//
+// クレート説明:
+// - tracing, tracing-subscriber: ログの記録と出力形式
+// - chrono, chrono-tz: JSTタイムスタンプ
+// - lazy_static: グローバル静的変数
+//
+// logging.rs: printdaytimeln!で記録したログ（tracingのイベント）を標準出力に書き出す
+// 出力形式はLogFormatで整形（従来どおり"[日時] 内容"）とJSONを選べ、再読込で切り替わる
+// JSONでは接続ごとのスパン（接続元アドレス・ハンドルネーム）が付くので、ログ収集側で接続ごとに絞り込める
+// 整形出力は従来の見た目のまま（各メッセージに接続元・ハンドルネームが含まれている）
+use crate::init::LogFormat; // ログの出力形式
+use chrono_tz::Asia::Tokyo; // chrono-tz: JSTタイムゾーン
+use lazy_static::lazy_static; // lazy_static: グローバル静的変数
+use std::sync::Mutex; // std: 切り替え用ハンドルの保持
+use tracing::{Event, Subscriber}; // tracing: ログのイベント
+use tracing_subscriber::fmt::format::Writer; // tracing-subscriber: 時刻の書き出し先
+use tracing_subscriber::fmt::time::FormatTime; // tracing-subscriber: 時刻の書式
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields}; // tracing-subscriber: 1行の書式
+use tracing_subscriber::layer::SubscriberExt; // tracing-subscriber: レイヤーの追加
+use tracing_subscriber::registry::LookupSpan; // tracing-subscriber: スパンの参照
+use tracing_subscriber::util::SubscriberInitExt; // tracing-subscriber: グローバル設定
+use tracing_subscriber::{fmt, reload, Layer, Registry}; // tracing-subscriber: 出力レイヤー
+
+// 差し替え可能な出力レイヤー
+type OutputLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+lazy_static! {
+    static ref HANDLE: Mutex<Option<reload::Handle<OutputLayer, Registry>>> = Mutex::new(None); // 出力形式の切り替え用（install前はNone）
+}
+
+// JSTのタイムスタンプ（整形出力では従来どおり角括弧で囲む）
+struct JstTime {
+    bracketed: bool, // 角括弧で囲むか
+}
+
+impl FormatTime for JstTime {
+    fn format_time(&self, w: &mut Writer<'_>) -> std::fmt::Result {
+        let now = chrono::Local::now().with_timezone(&Tokyo); // 現在時刻をJSTで取得
+        if self.bracketed {
+            write!(w, "[{}]", now.format("%Y/%m/%d %H:%M:%S"))
+        } else {
+            write!(w, "{}", now.format("%Y/%m/%d %H:%M:%S"))
+        }
+    }
+}
+
+// 従来どおりの"[日時] 内容"の1行
+struct PrettyFormat;
+
+impl<S, N> FormatEvent<S, N> for PrettyFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        JstTime { bracketed: true }.format_time(&mut writer)?;
+        write!(writer, " ")?;
+        ctx.format_fields(writer.by_ref(), event)?; // 内容
+        writeln!(writer)
+    }
+}
+
+// 出力形式に応じたレイヤーを作る
+fn layer(format: LogFormat) -> OutputLayer {
+    match format {
+        LogFormat::Pretty => fmt::layer().event_format(PrettyFormat).boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_timer(JstTime { bracketed: false })
+            .with_target(false)
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+    }
+}
+
+// プロセス全体のログ出力を設定する（既に他のサブスクライバーが設定されていれば何もしない）
+pub fn install(format: LogFormat) {
+    let (layer, handle) = reload::Layer::new(layer(format));
+    if tracing_subscriber::registry()
+        .with(layer)
+        .try_init()
+        .is_ok()
+    {
+        *HANDLE.lock().unwrap() = Some(handle);
+    }
+}
+
+// 出力形式を切り替える（installしていなければ何もしない）
+pub fn apply(format: LogFormat) {
+    if let Some(handle) = HANDLE.lock().unwrap().as_ref() {
+        let _ = handle.reload(layer(format));
+    }
+}
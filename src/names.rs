@@ -0,0 +1,188 @@
+// RustTokioChatServer - ハンドルネーム管理モジュール
+// MIT License
+//
+// クレート説明:
+// - tokio: 予約・締め出しの期限の時刻・送信キュー
+// - std: 標準ライブラリ（コレクション・同期）
+// - lazy_static: グローバル静的変数
+//
+// names.rs: 使用中のハンドルネーム一覧（各クライアントの送信キューへの登録簿）と、切断直後の予約、入力に失敗し続けた接続元の締め出しを管理
+// ハンドルネームはテナントごとに大文字・小文字を区別せずに一意（表示は登録時の表記のまま）
+// ロックは常にREGISTRY→RESERVEDの順で取る
+use lazy_static::lazy_static; // lazy_static: グローバル静的変数
+use std::collections::HashMap; // std: 登録簿・予約一覧
+use std::sync::Mutex; // std: スレッド安全なミューテックス
+use tokio::sync::mpsc; // Tokio: クライアントごとの送信キュー
+use tokio::time::{Duration, Instant}; // Tokio: 予約期限
+
+// 切断後も一定時間だけハンドルネームを確保しておくための予約
+struct Reservation {
+    owner: String,  // 予約した接続元（IPアドレス）
+    until: Instant, // 予約期限
+}
+
+// ハンドルネームから各クライアントの送信キューを引く登録簿（/msgなど1人宛ての配送用）
+#[derive(Default)]
+pub struct ClientRegistry {
+    clients: HashMap<String, (String, mpsc::UnboundedSender<String>)>, // 比較用の名前→(表示名, 送信キュー)
+}
+
+impl ClientRegistry {
+    // ハンドルネームを登録
+    fn insert(&mut self, tenant: &str, name: &str, queue: mpsc::UnboundedSender<String>) {
+        self.clients
+            .insert(fold(tenant, name), (name.to_string(), queue));
+    }
+
+    // ハンドルネームの登録を外す（同じクライアントの登録の場合のみ）
+    fn remove(&mut self, tenant: &str, name: &str, queue: &mpsc::UnboundedSender<String>) {
+        let key = fold(tenant, name);
+        if self.is_owned_by(&key, queue) {
+            self.clients.remove(&key);
+        }
+    }
+
+    // 比較用の名前が他のクライアントに使われているか
+    fn is_taken(&self, key: &str, queue: &mpsc::UnboundedSender<String>) -> bool {
+        self.clients.contains_key(key) && !self.is_owned_by(key, queue)
+    }
+
+    // 比較用の名前がこのクライアントの登録か
+    fn is_owned_by(&self, key: &str, queue: &mpsc::UnboundedSender<String>) -> bool {
+        self.clients
+            .get(key)
+            .is_some_and(|(_, current)| current.same_channel(queue))
+    }
+}
+
+// 比較用の名前（テナント名を前に付け、大文字・小文字を区別しない）
+// 改行はハンドルネームに含まれないので、別のテナントの名前と重なることはない
+fn fold(tenant: &str, name: &str) -> String {
+    format!("{}\n{}", tenant, name.to_lowercase())
+}
+
+lazy_static! {
+    static ref REGISTRY: Mutex<ClientRegistry> = Mutex::new(ClientRegistry::default()); // 使用中のハンドルネーム
+    static ref RESERVED: Mutex<HashMap<String, Reservation>> = Mutex::new(HashMap::new()); // 切断直後の予約
+    static ref COOLDOWNS: Mutex<HashMap<String, Instant>> = Mutex::new(HashMap::new()); // 接続元→接続を断る期限
+}
+
+// ハンドルネームを使えなかった理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClaimError {
+    Reserved, // 切断した別の接続元のために予約中
+    InUse,    // 他のクライアントが使用中（大文字・小文字の違いのみも含む）
+}
+
+// テナント内の使用中のハンドルネーム一覧
+pub fn list(tenant: &str) -> Vec<String> {
+    let prefix = fold(tenant, "");
+    REGISTRY
+        .lock()
+        .unwrap()
+        .clients
+        .iter()
+        .filter(|(key, _)| key.starts_with(&prefix))
+        .map(|(_, (name, _))| name.clone())
+        .collect()
+}
+
+// テナント内のハンドルネームのクライアントの送信キュー（接続していなければNone）
+pub fn sender(tenant: &str, name: &str) -> Option<mpsc::UnboundedSender<String>> {
+    REGISTRY
+        .lock()
+        .unwrap()
+        .clients
+        .get(&fold(tenant, name))
+        .map(|(_, queue)| queue.clone())
+}
+
+// ハンドルネームの使用を開始する（使用中・他の接続元の予約中なら失敗、確認と登録は1回のロックで行う）
+pub fn claim(
+    tenant: &str,
+    name: &str,
+    owner: &str,
+    queue: mpsc::UnboundedSender<String>,
+) -> Result<(), ClaimError> {
+    let key = fold(tenant, name);
+    let mut registry = REGISTRY.lock().unwrap();
+    if registry.is_taken(&key, &queue) {
+        return Err(ClaimError::InUse); // 他のクライアントが使用中
+    }
+    let mut reserved = RESERVED.lock().unwrap();
+    let now = Instant::now();
+    reserved.retain(|_, r| r.until > now); // 期限切れの予約を削除
+    if let Some(r) = reserved.get(&key) {
+        if r.owner != owner {
+            return Err(ClaimError::Reserved); // 他の接続元の予約中
+        }
+        reserved.remove(&key); // 予約した本人が戻ってきた
+    }
+    registry.insert(tenant, name, queue); // 使用中に追加
+    Ok(())
+}
+
+// 使用中のハンドルネームを別の名前に付け替える（/nick・オペレーターによる強制変更用、1回のロックで行う）
+pub fn rename(
+    tenant: &str,
+    old: &str,
+    new: &str,
+    queue: &mpsc::UnboundedSender<String>,
+) -> Result<(), ClaimError> {
+    let key = fold(tenant, new);
+    let mut registry = REGISTRY.lock().unwrap();
+    if registry.is_taken(&key, queue) {
+        return Err(ClaimError::InUse); // 変更先が使用中（大文字・小文字だけの変更は可）
+    }
+    let now = Instant::now();
+    if RESERVED
+        .lock()
+        .unwrap()
+        .get(&key)
+        .is_some_and(|r| r.until > now)
+    {
+        return Err(ClaimError::Reserved); // 変更先が予約中
+    }
+    registry.remove(tenant, old, queue); // 古い名前を外す
+    registry.insert(tenant, new, queue.clone()); // 新しい名前で登録
+    Ok(())
+}
+
+// ハンドルネームの使用を終了する
+pub fn release(tenant: &str, name: &str, queue: &mpsc::UnboundedSender<String>) {
+    REGISTRY.lock().unwrap().remove(tenant, name, queue);
+}
+
+// ハンドルネームの使用を終了し、同じ接続元のために一定時間予約する
+pub fn release_and_reserve(
+    tenant: &str,
+    name: &str,
+    queue: &mpsc::UnboundedSender<String>,
+    owner: &str,
+    grace: Duration,
+) {
+    release(tenant, name, queue);
+    RESERVED.lock().unwrap().insert(
+        fold(tenant, name),
+        Reservation {
+            owner: owner.to_string(),
+            until: Instant::now() + grace,
+        },
+    );
+}
+
+// ハンドルネームの入力に失敗し続けた接続元を一定時間締め出す
+pub fn start_cooldown(owner: &str, duration: Duration) {
+    COOLDOWNS
+        .lock()
+        .unwrap()
+        .insert(owner.to_string(), Instant::now() + duration);
+}
+
+// 接続元が締め出し中か
+pub fn is_cooling_down(owner: &str) -> bool {
+    let mut cooldowns = COOLDOWNS.lock().unwrap();
+    let now = Instant::now();
+    cooldowns.retain(|_, until| *until > now); // 期限切れを削除
+    cooldowns.contains_key(owner)
+}
@@ -0,0 +1,250 @@
+// RustTokioChatServer - 送信処理モジュール
+// MIT License
+//
+// クレート説明:
+// - tokio: 非同期I/O・送信キュー（mpsc）・終了の合図（oneshot）
+// - std: 標準ライブラリ（送信待ちの行・同期）
+//
+// outbound.rs: クライアントごとの送信キューを専用の書き込みタスクで書き出す
+// 書き込みが詰まっても読み込み側のループや配送タスクは待たされず、送信待ちがClientQueueSize行を超えた分は
+// QueueOverflowPolicyに従って古い行・新しい行を捨てるか、クライアントを切断する（どちらも再読込で接続中のクライアントにも反映）
+// telnetのネゴシエーションの応答など行でないバイト列は別の受け渡し口から受け取り、上限に関わらず行の合間に書き出す
+// /color on のクライアントには、書き出す直前に行を色付けする（送信キューに積まれる文面は全員共通）
+use crate::color; // 色付け表示
+use crate::disconnect::DisconnectReason; // 切断理由
+use crate::init::{self, OverflowPolicy}; // 送信キューの上限・あふれたときの扱い
+use crate::session::Session; // セッション管理モジュール
+use crate::stats; // サーバー統計
+use std::collections::VecDeque; // std: 送信待ちの行
+use std::sync::atomic::Ordering; // std: アトミック操作の順序指定
+use std::sync::Arc; // std: スレッド安全な参照カウント
+use tokio::io::{AsyncWrite, AsyncWriteExt}; // Tokio: 非同期書き込み
+use tokio::sync::{mpsc, oneshot}; // Tokio: 送信キュー・終了の合図
+use tokio::task::JoinHandle; // Tokio: 書き込みタスク
+use tokio::time::{Duration, Instant}; // Tokio: 書き込み停滞の判定
+
+// 1行の書き込みにこれ以上かかったら書き込み停滞として数える
+const WRITE_STALL: Duration = Duration::from_millis(500);
+
+// 書き込みタスクへの参照（接続1本につき1つ）
+pub struct Outbound {
+    finish: Option<oneshot::Sender<()>>, // 残りを書き出して終わる合図
+    raw: mpsc::UnboundedSender<Vec<u8>>, // 行でないバイト列の受け渡し
+    task: JoinHandle<()>,                // 書き込みタスク
+}
+
+impl Outbound {
+    // 送信キューの残りを書き出し、送信側を閉じるまで待つ
+    pub async fn finish(&mut self) {
+        if let Some(finish) = self.finish.take() {
+            let _ = finish.send(());
+        }
+        let _ = (&mut self.task).await;
+    }
+
+    // 行でないバイト列（telnetのネゴシエーションの応答など）を書き出す
+    pub fn send_raw(&self, bytes: Vec<u8>) {
+        if !bytes.is_empty() {
+            let _ = self.raw.send(bytes); // 書き込みタスクが終了済みなら捨てる
+        }
+    }
+
+    // 書き出しを打ち切る（接続が既に閉じている・書き出しが終わらない場合）
+    pub fn abort(&self) {
+        self.task.abort();
+    }
+}
+
+// 書き込みタスクの状態
+struct Writer<W> {
+    writer: W,                                   // 通信路の書き込み側
+    session: Arc<Session>,                       // 統計・切断要求の送り先
+    family: Option<&'static stats::FamilyStats>, // アドレスファミリー別統計（IP以外の通信路はNone）
+    pending: VecDeque<String>,                   // 送信待ちの行
+    raw: VecDeque<Vec<u8>>,                      // 送信待ちの行でないバイト列（行より先に書く）
+    overflowed: bool,                            // 切断を要求済みか（disconnectポリシー）
+}
+
+// 通信路の書き込み側と送信キューの受信側を受け取り、書き込みタスクを起動する
+pub fn spawn<W: AsyncWrite + Unpin + Send + 'static>(
+    writer: W,
+    queue_rx: mpsc::UnboundedReceiver<String>,
+    session: Arc<Session>,
+    family: Option<&'static stats::FamilyStats>,
+) -> Outbound {
+    let (finish, finish_rx) = oneshot::channel();
+    let (raw, raw_rx) = mpsc::unbounded_channel();
+    let writer = Writer {
+        writer,
+        session,
+        family,
+        pending: VecDeque::new(),
+        raw: VecDeque::new(),
+        overflowed: false,
+    };
+    let task = tokio::spawn(writer.run(queue_rx, raw_rx, finish_rx));
+    Outbound {
+        finish: Some(finish),
+        raw,
+        task,
+    }
+}
+
+impl<W: AsyncWrite + Unpin> Writer<W> {
+    // 送信キューを受け取りながら1行ずつ書き出す（書き込み中も受け取り続け、あふれた分はポリシーで扱う）
+    async fn run(
+        mut self,
+        mut queue_rx: mpsc::UnboundedReceiver<String>,
+        mut raw_rx: mpsc::UnboundedReceiver<Vec<u8>>,
+        mut finish_rx: oneshot::Receiver<()>,
+    ) {
+        let mut current: Option<(Vec<u8>, usize, Instant)> = None; // 書き込み中の行・書き込み済みバイト数・開始時刻
+        let mut stalled = false; // 書き込み中の行を停滞として数えたか
+        loop {
+            if current.is_none() {
+                current = self
+                    .raw
+                    .pop_front()
+                    .or_else(|| {
+                        self.pending.pop_front().map(|text| {
+                            self.trace(&text);
+                            self.render(text).into_bytes()
+                        })
+                    })
+                    .map(|bytes| (bytes, 0, Instant::now()));
+                stalled = false;
+            }
+            let stall_at = current
+                .as_ref()
+                .filter(|_| !stalled)
+                .map(|(_, _, started)| *started + WRITE_STALL); // 停滞とみなす時刻
+            let unwritten = current
+                .as_ref()
+                .map_or(&[][..], |(bytes, pos, _)| &bytes[*pos..]); // 書き込み中の行の残り
+            tokio::select! {
+                // 書き込み中の行の続きを書く（Pendingのまま他の分岐が選ばれても書き込み済みの分は失われない）
+                result = self.writer.write(unwritten), if !unwritten.is_empty() => {
+                    let Ok(n @ 1..) = result else {
+                        self.session.request_disconnect(DisconnectReason::Closed); // 書けなければ切断扱い
+                        return;
+                    };
+                    let (bytes, pos, _) = current.as_mut().unwrap();
+                    *pos += n;
+                    if *pos == bytes.len() {
+                        self.count_sent(bytes.len());
+                        current = None; // 1行書き終えた
+                    }
+                }
+                // 相手の受信が遅い・回線が詰まっている（書き終わらないままでも数える）
+                _ = tokio::time::sleep_until(stall_at.unwrap_or_else(Instant::now)), if stall_at.is_some() => {
+                    self.session.write_stalls.fetch_add(1, Ordering::Relaxed);
+                    stalled = true;
+                }
+                // 配送タスク・クライアント処理から届いた行
+                received = queue_rx.recv() => {
+                    match received {
+                        Some(text) => self.push(text),
+                        None => break, // 送信キューが閉じた
+                    }
+                }
+                // クライアント処理から届いた行でないバイト列（閉じたら待たない）
+                Some(bytes) = raw_rx.recv() => self.raw.push_back(bytes),
+                // 切断処理から残りを書き出して終わるよう合図された
+                _ = &mut finish_rx => break,
+            }
+        }
+        while let Ok(text) = queue_rx.try_recv() {
+            self.pending.push_back(text); // 切断理由の通知などの最後の行はあふれても捨てない
+        }
+        if let Some((bytes, pos, _)) = current {
+            if self.writer.write_all(&bytes[pos..]).await.is_err() {
+                return;
+            }
+            self.count_sent(bytes.len());
+        }
+        while let Some(text) = self.pending.pop_front() {
+            self.trace(&text);
+            let text = self.render(text);
+            if self.writer.write_all(text.as_bytes()).await.is_err() {
+                return;
+            }
+            self.count_sent(text.len());
+        }
+        let _ = self.writer.flush().await; // 書き込みの完了を待つ
+        let _ = self.writer.shutdown().await; // 送信側を閉じる
+    }
+
+    // 送信待ちに積む（上限に達していればポリシーに従う）
+    fn push(&mut self, text: String) {
+        if text.is_empty() {
+            return; // 書くものがない
+        }
+        let (capacity, policy) = {
+            let config = init::CONFIG.read().unwrap();
+            (
+                config.client_queue_size.max(1),
+                config.queue_overflow_policy,
+            )
+        }; // 再読込後の値を使う
+        if self.pending.len() < capacity {
+            self.pending.push_back(text);
+            return;
+        }
+        self.session.lag_drops.fetch_add(1, Ordering::Relaxed); // 受信が追いつかないクライアント
+        match policy {
+            OverflowPolicy::DropOldest => {
+                crate::printdaytimeln!(
+                    "配送破棄: {} (送信キュー満杯、古い行)",
+                    self.session.peer_addr
+                ); // ログ
+                self.pending.pop_front(); // 最も古い行を捨てる
+                self.pending.push_back(text);
+            }
+            OverflowPolicy::DropNewest => {
+                crate::printdaytimeln!("配送破棄: {} (送信キュー満杯)", self.session.peer_addr);
+                // ログ
+                // 届いた行を捨てる
+            }
+            OverflowPolicy::Disconnect => {
+                if !std::mem::replace(&mut self.overflowed, true) {
+                    crate::printdaytimeln!(
+                        "送信キューあふれ: {} {}",
+                        self.session.peer_addr,
+                        self.session.handle_name()
+                    ); // ログ
+                    self.pending.clear(); // 切断理由の通知を待たせない
+                    self.session.request_disconnect(DisconnectReason::Lagging);
+                }
+            }
+        }
+    }
+
+    // 受信者の設定に合わせた文面（/color on なら色付け）
+    fn render(&self, text: String) -> String {
+        if self.session.wants_color() {
+            color::colorize(&text)
+        } else {
+            text
+        }
+    }
+
+    // 送信内容をログに出す（/trace on のセッションのみ）
+    fn trace(&self, text: &str) {
+        if self.session.is_tracing() {
+            for line in text.lines() {
+                crate::printdaytimeln!("TRACE #{} > {:?}", self.session.id, line);
+                // 送信内容
+            }
+        }
+    }
+
+    // 書き終えたバイト数を記録する
+    fn count_sent(&self, len: usize) {
+        self.session
+            .bytes_out
+            .fetch_add(len as u64, Ordering::Relaxed); // 送信累計を加算
+        if let Some(family) = self.family {
+            stats::add(&family.bytes_out, len as u64); // ファミリー別の送信量
+        }
+    }
+}
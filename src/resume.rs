@@ -0,0 +1,63 @@
+// RustTokioChatServer - 接続再開モジュール
+// MIT License
+//
+// クレート説明:
+// - argon2: トークン生成用の乱数（OsRng）
+// - tokio: 再開できる期限の時刻
+// - std: 標準ライブラリ（コレクション・同期）
+// - lazy_static: グローバル静的変数
+//
+// resume.rs: ResumeSecondsが設定されている場合、接続ごとに再接続用のトークンを発行する
+// 通信断で切断された接続はトークンで預かり、期限内に別の接続から /resume <トークン> されたら
+// ハンドルネーム・部屋・認証状態を引き継ぎ、切断中の発言を部屋の直近のチャット行から再送する
+// 預かりはメモリ上だけで、再起動すると消える
+use argon2::password_hash::rand_core::{OsRng, RngCore}; // argon2: トークン生成用の乱数
+use lazy_static::lazy_static; // lazy_static: グローバル静的変数
+use std::collections::HashMap; // std: トークン→預かった接続
+use std::sync::Mutex; // std: スレッド安全なミューテックス
+use tokio::time::{Duration, Instant}; // Tokio: 再開できる期限
+
+// トークンのバイト数（16進数で表示するので文字数はこの2倍）
+const TOKEN_BYTES: usize = 16;
+
+// 通信断で切断された接続から引き継ぐ状態
+#[derive(Debug, Clone)]
+pub struct Parked {
+    pub tenant: String,        // 接続を受け付けたテナント
+    pub handle_name: String,   // ハンドルネーム
+    pub owner: String,         // 切断した接続元（ハンドルネームの予約の持ち主）
+    pub room: String,          // 切断時にいた部屋
+    pub seen: u64,             // 切断時に部屋の最後だったチャット行の通し番号
+    pub identified: bool,      // 登録済みのハンドルネームの認証を済ませていたか
+    pub registered_here: bool, // 切断した接続で新しく登録した名前か（Role行の役割を付けない）
+}
+
+lazy_static! {
+    static ref PARKED: Mutex<HashMap<String, (Parked, Instant)>> = Mutex::new(HashMap::new()); // トークン→預かった接続
+}
+
+// 新しいトークンを発行する（推測されないようOSの乱数から作る）
+pub fn issue() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// 切断した接続の状態をトークンで預かる（grace の間だけ再開できる）
+pub fn park(token: &str, parked: Parked, grace: Duration) {
+    PARKED
+        .lock()
+        .unwrap()
+        .insert(token.to_string(), (parked, Instant::now() + grace));
+}
+
+// トークンで預かった状態を取り出す（期限切れ・別のテナントのトークンならNone、取り出したトークンは使えなくなる）
+pub fn take(token: &str, tenant: &str) -> Option<Parked> {
+    let mut parked = PARKED.lock().unwrap();
+    let now = Instant::now();
+    parked.retain(|_, (_, until)| *until > now); // 期限切れを削除
+    if parked.get(token)?.0.tenant != tenant {
+        return None; // 他のテナントの接続は引き継がない
+    }
+    parked.remove(token).map(|(p, _)| p)
+}
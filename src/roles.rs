@@ -0,0 +1,70 @@
+// RustTokioChatServer - 権限モジュール
+// MIT License
+//
+// roles.rs: 接続ごとの役割（ゲスト・一般・オペレーター・管理者）と、コマンドを使える役割の判定
+// 役割は設定ファイルのRole行（登録済みのハンドルネームを認証した場合のみ）とDefaultRole、
+// /operによる一時的なオペレーター権限、管理者の/roleによる付与で決まる
+// 各コマンドに必要な役割はcommands.rsの一覧に書き、ディスパッチャが一か所で判定する
+
+// 役割（後のものほど強く、上位の役割は下位の役割のコマンドをすべて使える）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Guest,    // ゲスト（チャットはできるが、部屋の作成・私信などは使えない）
+    User,     // 一般
+    Operator, // オペレーター（全体通知・固定告知・強制変更など）
+    Admin,    // 管理者（役割の付与）
+}
+
+impl Role {
+    // 設定ファイル・/roleで使う名前から
+    pub fn parse(name: &str) -> Option<Role> {
+        match name.to_lowercase().as_str() {
+            "guest" => Some(Role::Guest),
+            "user" => Some(Role::User),
+            "operator" | "oper" => Some(Role::Operator),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+
+    // 設定ファイル・/roleで使う名前
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Guest => "guest",
+            Role::User => "user",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        }
+    }
+
+    // 表示用の名前
+    pub fn label(self) -> &'static str {
+        match self {
+            Role::Guest => "ゲスト",
+            Role::User => "一般",
+            Role::Operator => "オペレーター",
+            Role::Admin => "管理者",
+        }
+    }
+
+    // セッションに保存する値から（範囲外は一般）
+    pub fn from_u8(value: u8) -> Role {
+        match value {
+            0 => Role::Guest,
+            2 => Role::Operator,
+            3 => Role::Admin,
+            _ => Role::User,
+        }
+    }
+}
+
+// 必要な役割に足りないときの案内
+pub fn denied(required: Role) -> String {
+    match required {
+        Role::Guest | Role::User => {
+            "SYSTEM> ゲストはこのコマンドを使えません（ハンドルネームを登録・認証してください）\n"
+                .to_string()
+        }
+        _ => format!("SYSTEM> このコマンドは{}専用です\n", required.label()),
+    }
+}
@@ -0,0 +1,495 @@
+// RustTokioChatServer - 部屋管理モジュール
+// MIT License
+//
+// クレート説明:
+// - tokio: 部屋の作成時刻
+// - std: 標準ライブラリ（コレクション・同期）
+// - lazy_static: グローバル静的変数
+//
+// rooms.rs: 部屋（チャンネル）の一覧と参加者を管理
+// 各クライアントは同時に1つの部屋に入り、チャットはその部屋の参加者にだけ配送される
+// 部屋はテナントごとに別（同じ名前でも別のテナントの部屋とは混ざらない）
+// 既定の部屋以外は作成した人が部屋のオペレーターになり、モード（招待制・発言制限・非公開）を切り替えられる
+// スローモード（同じ人の発言の最小間隔）は部屋ごとと、テナントごとのサーバー全体の2つがあり、長い方を使う
+use crate::session::Session; // セッション情報
+use lazy_static::lazy_static; // lazy_static: グローバル静的変数
+use std::collections::{HashMap, HashSet, VecDeque}; // std: 部屋一覧・参加者・直近の発言用コレクション
+use std::sync::atomic::Ordering; // std: 受け取り確認の更新
+use std::sync::Mutex; // std: スレッド安全なミューテックス
+use tokio::time::Instant; // Tokio: 作成時刻
+
+// 部屋名の最大長（文字数）
+const MAX_ROOM_NAME: usize = 32;
+// 部屋ごとの固定告知の最大件数（超えたら古いものから外す）
+pub const MAX_PINS: usize = 5;
+// 固定告知1件の最大長（文字数）
+pub const MAX_PIN_CHARS: usize = 200;
+// 話題の最大長（文字数）
+pub const MAX_TOPIC_CHARS: usize = 200;
+
+// 部屋1つ分の情報
+#[derive(Debug)]
+pub struct Room {
+    pub name: String,         // 部屋名
+    pub created_at: Instant,  // 作成時刻
+    members: HashSet<u64>,    // 参加中のセッションID
+    pins: Vec<String>,        // 固定告知（古い順、入室時に表示）
+    topic: Option<String>,    // 話題（入室時に表示、設定されていなければNone）
+    modes: Modes,             // モード（/mode で切り替え）
+    slow_seconds: u64,        // スローモードの間隔（秒、0は無効、/slow で切り替え）
+    ops: HashSet<u64>,        // 部屋のオペレーターのセッションID（部屋を出たら外れる）
+    voiced: HashSet<u64>, // 発言権のあるセッションID（+m の部屋で発言できる、部屋を出たら外れる）
+    invited: HashSet<String>, // 招待されたハンドルネーム（小文字、入室したら消える）
+    recent: VecDeque<Line>, // 直近のチャット行（古い順、入室時・再開時・/resendで再送）
+    last_seq: u64,        // 最後に採番したメッセージID（部屋ごとに1から増える）
+}
+
+// 部屋のモード
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modes {
+    pub invite_only: bool, // +i 招待された人（とサーバーのオペレーター）だけが入れる
+    pub moderated: bool,   // +m 部屋のオペレーターと発言権のある人だけが発言できる
+    pub secret: bool,      // +s /list に出さない
+}
+
+impl Modes {
+    // "+ims" の形（何も設定されていなければ空）
+    pub fn flags(&self) -> String {
+        let flags: String = [
+            (self.invite_only, 'i'),
+            (self.moderated, 'm'),
+            (self.secret, 's'),
+        ]
+        .iter()
+        .filter(|(on, _)| *on)
+        .map(|(_, flag)| *flag)
+        .collect();
+        if flags.is_empty() {
+            flags
+        } else {
+            format!("+{}", flags)
+        }
+    }
+}
+
+// 部屋に入れなかった理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    InviteOnly, // 招待制の部屋に招待されていない
+}
+
+// 直近のチャット行1行分
+#[derive(Debug, Clone)]
+pub struct Line {
+    pub id: u64,      // メッセージID
+    pub from: String, // 発言者のハンドルネーム（再送時に無視リストと照合する）
+    pub text: String, // 配送した文面（改行付き）
+}
+
+lazy_static! {
+    static ref ROOMS: Mutex<HashMap<(String, String), Room>> = Mutex::new(HashMap::new()); // (テナント名, 部屋名)で引く
+    static ref SERVER_SLOW_SECONDS: Mutex<HashMap<String, u64>> = Mutex::new(HashMap::new()); // テナント名→サーバー全体のスローモードの間隔（秒）
+}
+
+// 部屋一覧のキー
+fn key(tenant: &str, name: &str) -> (String, String) {
+    (tenant.to_string(), name.to_string())
+}
+
+// 部屋名として使えるか（空白・制御文字を含まず、長すぎないこと）
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.chars().count() <= MAX_ROOM_NAME
+        && name.chars().all(|c| !c.is_control() && !c.is_whitespace())
+}
+
+// 部屋に入る（今いる部屋からは出る、部屋がなければ作成して部屋のオペレーターになる）
+// 招待制の部屋に招待なしで入ろうとした場合は今いる部屋に留まる（既定の部屋はいつでも入れる）
+pub fn join(session: &Session, name: &str, default_room: &str) -> Result<(), JoinError> {
+    let mut rooms = ROOMS.lock().unwrap();
+    let old = session.room(); // 今いる部屋
+    if old == name {
+        return Ok(()); // 既に入っている
+    }
+    if let Some(room) = rooms.get_mut(&key(&session.tenant, name)) {
+        if room.modes.invite_only
+            && name != default_room
+            && !session.is_oper()
+            && !room.invited.remove(&session.handle_name().to_lowercase())
+        {
+            return Err(JoinError::InviteOnly);
+        }
+    }
+    leave_locked(&mut rooms, session, &old, default_room); // 今いる部屋から出る
+    let room = rooms
+        .entry(key(&session.tenant, name))
+        .or_insert_with(|| Room {
+            name: name.to_string(),
+            created_at: Instant::now(),
+            members: HashSet::new(),
+            pins: Vec::new(),
+            topic: None,
+            modes: Modes::default(),
+            slow_seconds: 0,
+            ops: if name == default_room {
+                HashSet::new() // 既定の部屋はサーバーのオペレーターだけが管理する
+            } else {
+                HashSet::from([session.id]) // 作成した人
+            },
+            voiced: HashSet::new(),
+            invited: HashSet::new(),
+            recent: VecDeque::new(),
+            last_seq: 0,
+        });
+    room.members.insert(session.id); // 参加者に追加
+    session.acked.store(room.last_seq, Ordering::Relaxed); // 入室前の発言は受け取り確認の対象外
+    session.set_room(name); // 配送判定用にセッションにも記録
+    Ok(())
+}
+
+// 今いる部屋から出る（切断時）
+pub fn leave(session: &Session, default_room: &str) {
+    let mut rooms = ROOMS.lock().unwrap();
+    leave_locked(&mut rooms, session, &session.room(), default_room);
+    session.set_room(""); // どの部屋にもいない
+}
+
+// 部屋から参加者を外し、空になった部屋は削除する（既定の部屋は残す）
+fn leave_locked(
+    rooms: &mut HashMap<(String, String), Room>,
+    session: &Session,
+    name: &str,
+    default_room: &str,
+) {
+    let key = key(&session.tenant, name);
+    let Some(room) = rooms.get_mut(&key) else {
+        return; // どの部屋にもいない
+    };
+    room.members.remove(&session.id);
+    room.ops.remove(&session.id); // 部屋の権限は出たら外れる
+    room.voiced.remove(&session.id);
+    if room.members.is_empty() && name != default_room {
+        rooms.remove(&key); // 空の部屋を片付ける
+    }
+}
+
+// テナントの部屋一覧（部屋名・参加人数・作成からの秒数・モード、名前順）
+pub fn list(tenant: &str) -> Vec<(String, usize, u64, Modes)> {
+    let rooms = ROOMS.lock().unwrap();
+    let mut list: Vec<_> = rooms
+        .iter()
+        .filter(|((room_tenant, _), _)| room_tenant == tenant)
+        .map(|(_, room)| {
+            (
+                room.name.clone(),
+                room.members.len(),
+                room.created_at.elapsed().as_secs(),
+                room.modes,
+            )
+        })
+        .collect();
+    list.sort_by(|a, b| a.0.cmp(&b.0)); // 名前順に並べる
+    list
+}
+
+// 部屋のモード（部屋がなければNone）
+pub fn modes(tenant: &str, name: &str) -> Option<Modes> {
+    let rooms = ROOMS.lock().unwrap();
+    Some(rooms.get(&key(tenant, name))?.modes)
+}
+
+// 部屋のモードを変更する（部屋がなければfalse）
+pub fn set_modes(tenant: &str, name: &str, change: impl FnOnce(&mut Modes)) -> bool {
+    let mut rooms = ROOMS.lock().unwrap();
+    let Some(room) = rooms.get_mut(&key(tenant, name)) else {
+        return false;
+    };
+    change(&mut room.modes);
+    true
+}
+
+// 部屋のスローモードの間隔（秒、0は無効、部屋がなければNone）
+pub fn slow_seconds(tenant: &str, name: &str) -> Option<u64> {
+    let rooms = ROOMS.lock().unwrap();
+    Some(rooms.get(&key(tenant, name))?.slow_seconds)
+}
+
+// 部屋のスローモードの間隔を設定する（部屋がなければfalse）
+pub fn set_slow_seconds(tenant: &str, name: &str, seconds: u64) -> bool {
+    let mut rooms = ROOMS.lock().unwrap();
+    let Some(room) = rooms.get_mut(&key(tenant, name)) else {
+        return false;
+    };
+    room.slow_seconds = seconds;
+    true
+}
+
+// テナントのサーバー全体のスローモードの間隔（秒、0は無効）
+pub fn server_slow_seconds(tenant: &str) -> u64 {
+    let slow = SERVER_SLOW_SECONDS.lock().unwrap();
+    slow.get(tenant).copied().unwrap_or(0)
+}
+
+// テナントのサーバー全体のスローモードの間隔を設定する（他のテナントには影響しない）
+pub fn set_server_slow_seconds(tenant: &str, seconds: u64) {
+    let mut slow = SERVER_SLOW_SECONDS.lock().unwrap();
+    if seconds == 0 {
+        slow.remove(tenant); // 解除
+    } else {
+        slow.insert(tenant.to_string(), seconds);
+    }
+}
+
+// 部屋で適用するスローモードの間隔（部屋とサーバー全体の長い方、0は無効）
+pub fn slow_interval(tenant: &str, name: &str) -> u64 {
+    slow_seconds(tenant, name)
+        .unwrap_or(0)
+        .max(server_slow_seconds(tenant))
+}
+
+// 部屋のオペレーターか（サーバーのオペレーターはどの部屋でも部屋のオペレーター扱い）
+pub fn is_op(session: &Session, name: &str) -> bool {
+    if session.is_oper() {
+        return true;
+    }
+    let rooms = ROOMS.lock().unwrap();
+    rooms
+        .get(&key(&session.tenant, name))
+        .is_some_and(|room| room.ops.contains(&session.id))
+}
+
+// 部屋で発言できるか（+m の部屋では部屋のオペレーターと発言権のある人だけ）
+pub fn may_speak(session: &Session, name: &str) -> bool {
+    if session.is_oper() {
+        return true;
+    }
+    let rooms = ROOMS.lock().unwrap();
+    rooms.get(&key(&session.tenant, name)).is_none_or(|room| {
+        !room.modes.moderated || room.ops.contains(&session.id) || room.voiced.contains(&session.id)
+    })
+}
+
+// 参加者を部屋のオペレーター（op）・発言権のある人（!op）にする・外す（参加者でなければfalse）
+pub fn set_member(tenant: &str, name: &str, id: u64, op: bool, on: bool) -> bool {
+    let mut rooms = ROOMS.lock().unwrap();
+    let Some(room) = rooms.get_mut(&key(tenant, name)) else {
+        return false;
+    };
+    if !room.members.contains(&id) {
+        return false;
+    }
+    let set = if op { &mut room.ops } else { &mut room.voiced };
+    if on {
+        set.insert(id);
+    } else {
+        set.remove(&id);
+    }
+    true
+}
+
+// 招待制の部屋にハンドルネームを招待する（次に入室するまで有効、部屋がなければfalse）
+pub fn invite(tenant: &str, name: &str, handle_name: &str) -> bool {
+    let mut rooms = ROOMS.lock().unwrap();
+    let Some(room) = rooms.get_mut(&key(tenant, name)) else {
+        return false;
+    };
+    room.invited.insert(handle_name.to_lowercase());
+    true
+}
+
+// 部屋の話題（なければNone）
+pub fn topic(tenant: &str, name: &str) -> Option<String> {
+    let rooms = ROOMS.lock().unwrap();
+    rooms.get(&key(tenant, name))?.topic.clone()
+}
+
+// 部屋の話題を設定する（部屋がなければfalse）
+pub fn set_topic(tenant: &str, name: &str, text: &str) -> bool {
+    let mut rooms = ROOMS.lock().unwrap();
+    let Some(room) = rooms.get_mut(&key(tenant, name)) else {
+        return false;
+    };
+    room.topic = Some(text.to_string());
+    true
+}
+
+// 部屋に告知を固定する（件数超過時は最も古いものを外す、部屋がなければfalse）
+pub fn pin(tenant: &str, name: &str, text: &str) -> bool {
+    let mut rooms = ROOMS.lock().unwrap();
+    let Some(room) = rooms.get_mut(&key(tenant, name)) else {
+        return false;
+    };
+    if room.pins.len() >= MAX_PINS {
+        room.pins.remove(0); // 古いものから外す
+    }
+    room.pins.push(text.to_string());
+    true
+}
+
+// 固定告知を外す（番号は1始まり、外した告知を返す）
+pub fn unpin(tenant: &str, name: &str, index: usize) -> Option<String> {
+    let mut rooms = ROOMS.lock().unwrap();
+    let room = rooms.get_mut(&key(tenant, name))?;
+    if index == 0 || index > room.pins.len() {
+        return None; // 範囲外
+    }
+    Some(room.pins.remove(index - 1))
+}
+
+// 部屋の固定告知一覧（古い順）
+pub fn pins(tenant: &str, name: &str) -> Vec<String> {
+    let rooms = ROOMS.lock().unwrap();
+    rooms
+        .get(&key(tenant, name))
+        .map(|room| room.pins.clone())
+        .unwrap_or_default()
+}
+
+// 部屋のチャット行に次のメッセージIDを採番して直近の行に追加する（limit行を超えたら古いものから捨てる）
+// postにはIDを渡して同じロックの中で配送させ、返された文面を保存する（同じ部屋の発言がIDの順に配送されるように）
+pub fn remember(
+    tenant: &str,
+    name: &str,
+    from: &str,
+    limit: usize,
+    post: impl FnOnce(u64) -> String,
+) {
+    let mut rooms = ROOMS.lock().unwrap();
+    let Some(room) = rooms.get_mut(&key(tenant, name)) else {
+        post(0); // 部屋がなければ採番しない
+        return;
+    };
+    room.last_seq += 1;
+    let text = post(room.last_seq);
+    room.recent.push_back(Line {
+        id: room.last_seq,
+        from: from.to_string(),
+        text,
+    });
+    while room.recent.len() > limit {
+        room.recent.pop_front(); // 古いものから捨てる
+    }
+}
+
+// 部屋の直近のチャット行（古い順、最大n行）
+pub fn recent(tenant: &str, name: &str, n: usize) -> Vec<Line> {
+    let rooms = ROOMS.lock().unwrap();
+    let Some(room) = rooms.get(&key(tenant, name)) else {
+        return Vec::new();
+    };
+    let skip = room.recent.len().saturating_sub(n);
+    room.recent.iter().skip(skip).cloned().collect()
+}
+
+// 部屋の最後のメッセージID（部屋がなければ0）
+pub fn last_seq(tenant: &str, name: &str) -> u64 {
+    let rooms = ROOMS.lock().unwrap();
+    rooms
+        .get(&key(tenant, name))
+        .map_or(0, |room| room.last_seq)
+}
+
+// 部屋の直近のチャット行のうち、メッセージIDがseqより後のもの（古い順、捨てられた行は含まない）
+pub fn recent_since(tenant: &str, name: &str, seq: u64) -> Vec<Line> {
+    let rooms = ROOMS.lock().unwrap();
+    let Some(room) = rooms.get(&key(tenant, name)) else {
+        return Vec::new();
+    };
+    room.recent
+        .iter()
+        .filter(|line| line.id > seq)
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session;
+    use crate::transport::TransportKind;
+
+    // 直近の行から捨てられた後も、メッセージIDは採番を続け、残っている行だけを返す
+    #[test]
+    fn recent_since_skips_dropped_lines() {
+        let tenant = "rooms-recent-since"; // 他のテストと部屋が重ならないテナント
+        let (session, _queue_rx) = session::register("test", TransportKind::Tcp, tenant);
+        join(&session, "lobby", "lobby").unwrap();
+        for n in 1..=5 {
+            remember(tenant, "lobby", "alice", 3, |id| format!("{} {}\n", id, n));
+        }
+        assert_eq!(last_seq(tenant, "lobby"), 5);
+        let ids = |seq| {
+            recent_since(tenant, "lobby", seq)
+                .iter()
+                .map(|line| line.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(0), [3, 4, 5]); // 1と2は捨てられている
+        assert_eq!(ids(3), [4, 5]);
+        assert!(ids(5).is_empty()); // 最後のIDより後はない
+        assert_eq!(recent_since(tenant, "lobby", 4)[0].text, "5 5\n");
+        assert!(recent_since(tenant, "nowhere", 0).is_empty()); // 部屋がなければ空
+        assert_eq!(last_seq(tenant, "nowhere"), 0);
+        leave(&session, "lobby");
+        session::unregister(session.id);
+    }
+
+    // 招待制（+i）は招待された名前だけを1回入れ、発言制限（+m）は部屋のオペレーターと発言権のある人だけに発言させる
+    #[test]
+    fn modes_gate_join_and_speech() {
+        let tenant = "rooms-modes"; // 他のテストと部屋が重ならないテナント
+        let (owner, _owner_rx) = session::register("test", TransportKind::Tcp, tenant);
+        let (guest, _guest_rx) = session::register("test", TransportKind::Tcp, tenant);
+        owner.set_handle_name("owner");
+        guest.set_handle_name("Guest");
+        join(&owner, "lobby", "lobby").unwrap();
+        join(&guest, "lobby", "lobby").unwrap();
+        join(&owner, "club", "lobby").unwrap();
+        assert!(is_op(&owner, "club")); // 作成した人は部屋のオペレーター
+        assert!(!is_op(&guest, "lobby")); // 既定の部屋はサーバーのオペレーターだけが管理する
+
+        assert!(set_modes(tenant, "club", |modes| {
+            modes.invite_only = true;
+            modes.moderated = true;
+        }));
+        assert_eq!(modes(tenant, "club").unwrap().flags(), "+im");
+        assert_eq!(join(&guest, "club", "lobby"), Err(JoinError::InviteOnly));
+        assert_eq!(guest.room(), "lobby"); // 入れなければ今の部屋に留まる
+        assert!(invite(tenant, "club", "guest"));
+        join(&guest, "club", "lobby").unwrap(); // 大文字小文字を区別しない
+        assert!(!may_speak(&guest, "club"));
+        assert!(may_speak(&owner, "club"));
+        assert!(set_member(tenant, "club", guest.id, false, true)); // +v
+        assert!(may_speak(&guest, "club"));
+
+        join(&guest, "lobby", "lobby").unwrap();
+        assert!(!set_member(tenant, "club", guest.id, false, true)); // 参加者でなければ付けられない
+        assert_eq!(join(&guest, "club", "lobby"), Err(JoinError::InviteOnly)); // 招待は1回限り
+        for session in [owner, guest] {
+            leave(&session, "lobby");
+            session::unregister(session.id);
+        }
+        assert!(modes(tenant, "club").is_none()); // 全員が出た部屋は消える
+    }
+
+    // スローモードは部屋の指定と同じテナントのサーバー全体の指定の長い方で、他のテナントには効かない
+    #[test]
+    fn slow_interval_takes_longer_of_room_and_tenant() {
+        let tenant = "rooms-slow"; // 他のテストと部屋が重ならないテナント
+        let (session, _queue_rx) = session::register("test", TransportKind::Tcp, tenant);
+        join(&session, "lobby", "lobby").unwrap();
+        assert_eq!(slow_interval(tenant, "lobby"), 0);
+        assert!(set_slow_seconds(tenant, "lobby", 10));
+        assert!(!set_slow_seconds(tenant, "nowhere", 10)); // 部屋がなければ設定できない
+        assert_eq!(slow_interval(tenant, "lobby"), 10);
+        set_server_slow_seconds(tenant, 30);
+        assert_eq!(slow_interval(tenant, "lobby"), 30);
+        assert_eq!(slow_interval(tenant, "nowhere"), 30); // 部屋の指定がなくてもサーバー全体の指定は効く
+        assert_eq!(slow_interval("rooms-slow-other", "lobby"), 0);
+        set_server_slow_seconds(tenant, 0);
+        assert_eq!(server_slow_seconds(tenant), 0);
+        assert_eq!(slow_interval(tenant, "lobby"), 10);
+        leave(&session, "lobby");
+        session::unregister(session.id);
+    }
+}
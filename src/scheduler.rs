@@ -0,0 +1,145 @@
+// RustTokioChatServer - 定時処理モジュール
+// MIT License
+//
+// クレート説明:
+// - tokio: 次の分までの待機
+// - chrono, chrono-tz: JSTでの現在時刻
+//
+// scheduler.rs: 設定のSchedule行（cron形式）に従って、定期お知らせ・MOTD切替・静音時間を実行する
+// 設定は毎分読み直すので、SIGHUP等で再読込した内容が次の分から反映される
+use crate::dispatch; // メッセージ配送モジュール
+use crate::features::{self, Feature}; // 機能の切り替え
+use crate::init; // 設定管理モジュール
+use crate::lists; // MOTDの切替
+use chrono::{Datelike, Timelike}; // chrono: 日時の各要素
+use chrono_tz::Asia::Tokyo; // chrono-tz: JSTタイムゾーン
+use tokio::time::Duration; // Tokio: 待機時間
+
+// 定時に実行する処理
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleAction {
+    Announce(String), // 全クライアントへのお知らせ
+    Motd(String),     // 指定ファイルの内容にMOTDを切り替える
+    QuietHours(bool), // 静音時間（離席・復帰などの通知を全員分止める）の開始・終了
+}
+
+// Schedule行1つ分（分 時 日 月 曜日 の各項目は許可する値のビット集合）
+#[derive(Clone, PartialEq, Eq)]
+pub struct ScheduleEntry {
+    text: String,           // 設定に書かれた内容（表示用）
+    minutes: u64,           // 0-59
+    hours: u64,             // 0-23
+    days: u64,              // 1-31
+    months: u64,            // 1-12
+    weekdays: u64,          // 0-6（0が日曜、7も日曜として扱う）
+    action: ScheduleAction, // 実行する処理
+}
+
+// cron形式の1項目を解釈する（*、数値、a-b、カンマ区切り、/nの間隔指定に対応）
+fn parse_field(field: &str, min: u32, max: u32) -> Option<u64> {
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|&n| n > 0)?),
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            (a.parse().ok()?, b.parse().ok()?)
+        } else {
+            let n = range.parse().ok()?;
+            (n, if step > 1 { max } else { n }) // 5/10 は5から10刻み
+        };
+        if start < min || end > max || start > end {
+            return None; // 範囲外
+        }
+        for n in (start..=end).step_by(step as usize) {
+            bits |= 1 << n;
+        }
+    }
+    Some(bits)
+}
+
+// Schedule行（"分 時 日 月 曜日 処理 引数"）を解釈する
+pub fn parse_entry(text: &str) -> Option<ScheduleEntry> {
+    let mut fields = text.split_whitespace();
+    let minutes = parse_field(fields.next()?, 0, 59)?;
+    let hours = parse_field(fields.next()?, 0, 23)?;
+    let days = parse_field(fields.next()?, 1, 31)?;
+    let months = parse_field(fields.next()?, 1, 12)?;
+    let mut weekdays = parse_field(fields.next()?, 0, 7)?;
+    if weekdays & (1 << 7) != 0 {
+        weekdays |= 1; // 7は日曜
+    }
+    let action = fields.next()?;
+    let args = fields.collect::<Vec<_>>().join(" ");
+    let action = match (action, args.as_str()) {
+        ("announce", text) if !text.is_empty() => ScheduleAction::Announce(text.to_string()),
+        ("motd", path) if !path.is_empty() => ScheduleAction::Motd(path.to_string()),
+        ("quiet", "on") => ScheduleAction::QuietHours(true),
+        ("quiet", "off") => ScheduleAction::QuietHours(false),
+        _ => return None, // 不明な処理
+    };
+    Some(ScheduleEntry {
+        text: text.trim().to_string(),
+        minutes,
+        hours,
+        days,
+        months,
+        weekdays,
+        action,
+    })
+}
+
+// Debug出力（/reload --dry-run の差分表示など）は設定に書かれた内容で表す
+impl std::fmt::Debug for ScheduleEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.text)
+    }
+}
+
+impl ScheduleEntry {
+    // 指定時刻に実行すべきか（日と曜日は両方一致したときのみ）
+    fn matches<T: Datelike + Timelike>(&self, now: &T) -> bool {
+        self.minutes & (1 << now.minute()) != 0
+            && self.hours & (1 << now.hour()) != 0
+            && self.days & (1 << now.day()) != 0
+            && self.months & (1 << now.month()) != 0
+            && self.weekdays & (1 << now.weekday().num_days_from_sunday()) != 0
+    }
+
+    // 処理を実行する
+    fn run(&self) {
+        match &self.action {
+            ScheduleAction::Announce(text) => {
+                crate::printdaytimeln!("定時お知らせ: {}", text); // ログ
+                dispatch::broadcast_template(&format!("SYSTEM> お知らせ: {}\n", text));
+                // 変数は受信者ごとに展開
+            }
+            ScheduleAction::Motd(path) => lists::rotate_motd(path),
+            ScheduleAction::QuietHours(on) => {
+                crate::printdaytimeln!("静音時間: {}", if *on { "開始" } else { "終了" }); // ログ
+                dispatch::set_quiet_hours(*on);
+            }
+        }
+    }
+}
+
+// 定時処理タスク（毎分0秒に設定を読み直して該当する行を実行）
+pub async fn run() {
+    loop {
+        let now = chrono::Local::now().with_timezone(&Tokyo); // 現在時刻
+        let elapsed = u64::from(now.second()) * 1000 + u64::from(now.nanosecond() / 1_000_000); // 分の頭からの経過ミリ秒
+        let wait = 60_000u64.saturating_sub(elapsed) + 100; // 次の分の0秒過ぎまで（前の分で起きないよう少し余裕を持たせる）
+        tokio::time::sleep(Duration::from_millis(wait)).await;
+        let now = chrono::Local::now().with_timezone(&Tokyo); // 実行時刻
+        if !features::is_enabled(Feature::Schedule) {
+            continue; // 管理用ソケットで止めている
+        }
+        let schedules = init::CONFIG.read().unwrap().schedules.clone(); // 現在の設定
+        for entry in schedules.iter().filter(|entry| entry.matches(&now)) {
+            entry.run();
+        }
+    }
+}
@@ -0,0 +1,213 @@
+// RustTokioChatServer - スパム判定モジュール
+// MIT License
+//
+// クレート説明:
+// - tokio: 外部判定サーバーへのHTTP接続
+// - std: 標準ライブラリ（同期・Future）
+// - lazy_static: グローバル静的変数
+//
+// scoring.rs: チャットの各発言にスパムらしさの点数（0.0〜1.0）を付ける
+// 判定方法はScorerトレイトで差し替えられる（組み込み時はChatServerBuilder::scorerで独自実装を渡せる）
+// 設定のSpamScorerでは、同梱の簡易判定（heuristic）か外部のHTTP判定サーバー（http://...）を選ぶ
+use lazy_static::lazy_static; // lazy_static: グローバル静的変数
+use std::future::Future; // std: 判定結果のFuture
+use std::pin::Pin; // std: Futureの固定
+use std::sync::{Arc, Mutex, RwLock}; // std: 判定方法の共有
+use tokio::io::{AsyncReadExt, AsyncWriteExt}; // Tokio: HTTPの送受信
+use tokio::net::TcpStream; // Tokio: 外部判定サーバーへの接続
+
+// 判定に使う発言の情報
+#[derive(Debug, Clone)]
+pub struct MessageInfo {
+    pub content: String,          // 本文
+    pub handle_name: String,      // 発言者のハンドルネーム
+    pub room: String,             // 発言した部屋
+    pub lines_in_window: usize,   // 直近1秒間に送った行数
+    pub recent_violations: usize, // 直近の受信制限の違反回数
+    pub connected_secs: u64,      // 接続してからの秒数（アカウントがないので接続時間で代用）
+}
+
+// 判定結果のFuture（0.0はスパムでない、1.0は確実にスパム）
+pub type ScoreFuture<'a> = Pin<Box<dyn Future<Output = f64> + Send + 'a>>;
+
+// スパム判定の方法
+pub trait Scorer: Send + Sync {
+    fn score(&self, message: MessageInfo) -> ScoreFuture<'_>;
+}
+
+// 共有される判定方法
+type SharedScorer = Arc<dyn Scorer>;
+
+lazy_static! {
+    static ref CUSTOM: RwLock<Option<SharedScorer>> = RwLock::new(None); // 組み込み時に渡された判定方法（設定より優先）
+    static ref CONFIGURED: Mutex<(Option<String>, Option<SharedScorer>)> = Mutex::new((None, None)); // 設定から作った判定方法（設定値と組）
+}
+
+// 組み込み時の独自の判定方法を設定する
+pub fn set_custom(scorer: Option<Arc<dyn Scorer>>) {
+    *CUSTOM.write().unwrap() = scorer;
+}
+
+// 現在の判定方法（設定値が変わっていれば作り直す、判定しない設定ならNone）
+pub fn current(spec: Option<&str>) -> Option<Arc<dyn Scorer>> {
+    if let Some(custom) = CUSTOM.read().unwrap().clone() {
+        return Some(custom);
+    }
+    let mut configured = CONFIGURED.lock().unwrap();
+    if configured.0.as_deref() != spec {
+        *configured = (spec.map(str::to_string), spec.and_then(from_spec)); // 再読込で変わった
+    }
+    configured.1.clone()
+}
+
+// 設定値として正しいか（設定読み込み時の検査用）
+pub fn is_valid_spec(spec: &str) -> bool {
+    from_spec(spec).is_some()
+}
+
+// 設定値から判定方法を作る
+fn from_spec(spec: &str) -> Option<Arc<dyn Scorer>> {
+    if spec == "heuristic" {
+        Some(Arc::new(Heuristic))
+    } else {
+        HttpScorer::parse(spec).map(|scorer| Arc::new(scorer) as Arc<dyn Scorer>)
+    }
+}
+
+// 同梱の簡易判定（大文字・同じ文字の連続・URLの多さ・連投・違反歴・接続直後を加点）
+pub struct Heuristic;
+
+impl Scorer for Heuristic {
+    fn score(&self, message: MessageInfo) -> ScoreFuture<'_> {
+        Box::pin(async move { heuristic_score(&message) })
+    }
+}
+
+fn heuristic_score(message: &MessageInfo) -> f64 {
+    let content = &message.content;
+    let mut score: f64 = 0.0;
+    let letters = content.chars().filter(|c| c.is_ascii_alphabetic()).count();
+    let upper = content.chars().filter(|c| c.is_ascii_uppercase()).count();
+    if letters >= 10 && upper * 10 >= letters * 8 {
+        score += 0.3; // ほぼ大文字
+    }
+    let mut run = 1; // 同じ文字の連続数
+    let mut longest = 1;
+    let chars: Vec<char> = content.chars().collect();
+    for pair in chars.windows(2) {
+        run = if pair[0] == pair[1] { run + 1 } else { 1 };
+        longest = longest.max(run);
+    }
+    if longest >= 10 {
+        score += 0.3; // 同じ文字の連続
+    }
+    let urls = content.matches("http://").count() + content.matches("https://").count();
+    if urls >= 3 {
+        score += 0.3; // URLが多い
+    }
+    if urls > 0 && message.connected_secs < 60 {
+        score += 0.3; // 接続直後のURL
+    }
+    if message.lines_in_window >= 5 {
+        score += 0.2; // 連投
+    }
+    score += 0.1 * message.recent_violations.min(3) as f64; // 違反歴
+    score.min(1.0)
+}
+
+// 外部のHTTP判定サーバー（発言の情報をJSONでPOSTし、本文の数値を点数とする）
+pub struct HttpScorer {
+    addr: String, // 接続先（ホスト:ポート）
+    host: String, // Hostヘッダー
+    path: String, // パス
+}
+
+impl HttpScorer {
+    // "http://ホスト[:ポート]/パス" を解釈する（httpsは非対応）
+    fn parse(url: &str) -> Option<Self> {
+        let rest = url.strip_prefix("http://")?;
+        let (host, path) = match rest.find('/') {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, "/"),
+        };
+        if host.is_empty() {
+            return None;
+        }
+        let addr = if host
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+        {
+            host.to_string()
+        } else {
+            format!("{}:80", host) // ポート省略時
+        };
+        Some(HttpScorer {
+            addr,
+            host: host.to_string(),
+            path: path.to_string(),
+        })
+    }
+
+    // 1回分の問い合わせ
+    async fn request(&self, message: &MessageInfo) -> std::io::Result<f64> {
+        let body = format!(
+            "{{\"content\":{},\"handle_name\":{},\"room\":{},\"lines_in_window\":{},\"recent_violations\":{},\"connected_secs\":{}}}",
+            json_string(&message.content),
+            json_string(&message.handle_name),
+            json_string(&message.room),
+            message.lines_in_window,
+            message.recent_violations,
+            message.connected_secs
+        );
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.host,
+            body.len(),
+            body
+        );
+        let mut stream = TcpStream::connect(&self.addr).await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        let response = String::from_utf8_lossy(&response);
+        let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+        if !head.starts_with("HTTP/1.1 200") && !head.starts_with("HTTP/1.0 200") {
+            return Err(std::io::Error::other(
+                head.lines().next().unwrap_or("").to_string(),
+            ));
+        }
+        body.trim()
+            .parse::<f64>()
+            .map_err(|_| std::io::Error::other("点数として解釈できない応答"))
+    }
+}
+
+impl Scorer for HttpScorer {
+    fn score(&self, message: MessageInfo) -> ScoreFuture<'_> {
+        Box::pin(async move {
+            match self.request(&message).await {
+                Ok(score) => score,
+                Err(e) => {
+                    crate::printdaytimeln!("外部スパム判定失敗: {} ({})", self.addr, e); // 判定できない発言は通す
+                    0.0
+                }
+            }
+        })
+    }
+}
+
+// JSONの文字列リテラルにする（引用符・バックスラッシュ・制御文字をエスケープ、再接続の案内のJSONでも使う）
+pub fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
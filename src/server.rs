@@ -0,0 +1,471 @@
+// RustTokioChatServer - サーバー本体モジュール
+// MIT License
+//
+// クレート説明:
+// - tokio: TCP待受、ブロードキャストチャネル、シグナル処理
+//
+// server.rs: 待受・補助タスクの起動と停止をまとめ、他のTokioアプリケーションに組み込めるようにする
+// 設定やセッション一覧はプロセス全体で共有するため、同時に動かせるサーバーは1プロセスにつき1つ
+#[cfg(unix)]
+use crate::control; // 管理用ソケットモジュール
+use crate::disconnect::ShutdownReason; // 停止理由
+use crate::dispatch::{self, RoomMessage}; // メッセージ配送モジュール
+use crate::features; // 機能切り替えモジュール
+use crate::history; // チャット履歴保存モジュール
+use crate::init::{self, Config, LogFormat}; // 設定管理モジュール
+use crate::listener; // 接続受付モジュール
+use crate::lists; // 補助ファイル管理モジュール
+use crate::logging; // ログ出力モジュール
+use crate::scheduler; // 定時処理モジュール
+use crate::scoring::{self, Scorer}; // スパム判定モジュール
+use crate::session; // 終了時の切断待ち
+use crate::systemd; // systemd連携モジュール
+use crate::tenant; // テナント接続受付モジュール
+#[cfg(unix)]
+use crate::unix; // Unixドメインソケット接続受付モジュール
+use crate::websocket; // WebSocket接続受付モジュール
+use std::sync::Arc; // std: 判定方法の共有
+#[cfg(windows)]
+use tokio::io::AsyncReadExt; // Tokio: 非同期read（Windowsのみ）
+use tokio::net::TcpListener; // Tokio: TCPリスナー
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind}; // Tokio: Unixシグナル受信（UNIXのみ）
+use tokio::sync::broadcast; // Tokio: ブロードキャストチャネル
+use tokio::task::JoinHandle; // Tokio: 補助タスクの停止用
+
+// チャットサーバー（ChatServer::builder()で生成し、run()で停止まで動かす）
+pub struct ChatServer {
+    config: Option<Config>, // 直接渡された設定（Noneなら設定ファイルから読む）
+    handle_signals: bool,   // SIGHUP/SIGTERM（WindowsはCTRL-Y/CTRL-C）を扱うか
+    init_logging: bool,     // ログ出力を設定するか
+    shutdown_tx: broadcast::Sender<ShutdownReason>, // 停止・再読込通知用
+    events_tx: broadcast::Sender<LifecycleEvent>, // 起動・停止などの状態変化の通知用
+}
+
+// サーバーの状態変化（組み込み先のアプリケーションが自分の処理と足並みをそろえるための通知）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LifecycleEvent {
+    Starting,                    // run()が呼ばれ、設定の読み込みを始めた
+    Bound(std::net::SocketAddr), // 待受を開始した（再バインド時も通知、ポート0なら実際のポート）
+    Reloaded,                    // 設定と補助ファイルを再読込した
+    Draining,                    // 停止要求を受け、各クライアントに通知を書き出している
+    Stopped,                     // 補助タスクも止まり、run()が戻る直前（起動失敗時も通知）
+}
+
+// ChatServerの組み立て
+pub struct ChatServerBuilder {
+    config: Option<Config>,          // 直接渡された設定
+    config_file: Option<String>,     // 設定ファイルのパス
+    listen: Option<String>,          // 設定ファイルのListenより優先する待受アドレス
+    handle_signals: bool,            // シグナルを扱うか
+    init_logging: bool,              // ログ出力を設定するか
+    scorer: Option<Arc<dyn Scorer>>, // 独自のスパム判定
+}
+
+// 実行中のサーバーを外から停止・再読込するためのハンドル（複製可）
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    shutdown_tx: broadcast::Sender<ShutdownReason>, // 停止・再読込通知用
+    events_tx: broadcast::Sender<LifecycleEvent>,   // 状態変化の通知用
+}
+
+impl ChatServer {
+    // 組み立てを始める（既定は実行ディレクトリのRustTokioChatServer.confを読み、シグナルは扱わない）
+    pub fn builder() -> ChatServerBuilder {
+        ChatServerBuilder {
+            config: None,
+            config_file: None,
+            listen: None,
+            handle_signals: false,
+            init_logging: false,
+            scorer: None,
+        }
+    }
+
+    // 停止・再読込用のハンドルを取得する
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            shutdown_tx: self.shutdown_tx.clone(),
+            events_tx: self.events_tx.clone(),
+        }
+    }
+
+    // 設定を読み込んでグローバル設定に反映する
+    fn install_config(&mut self) -> std::io::Result<()> {
+        if self.init_logging {
+            logging::install(LogFormat::Pretty); // 設定の警告も出せるよう先に整形出力で始める
+        }
+        let config = match self.config.take() {
+            Some(config) => config, // 直接渡された設定
+            None => init::load_config().map_err(|e| {
+                std::io::Error::new(e.kind(), format!("設定ファイル読み込み失敗: {}", e))
+            })?, // 設定ファイルを読み込む
+        };
+        logging::apply(config.log_format); // 設定の出力形式に切り替える
+        *init::CONFIG.write().unwrap() = config; // グローバル設定を置き換える
+        Ok(())
+    }
+
+    // 設定ファイルを検査する（適用はしない、解釈できなかった行を警告として返す）
+    pub fn check_config(&self) -> std::io::Result<Vec<String>> {
+        init::check_config().map(|(_, warnings)| warnings)
+    }
+
+    // 履歴データベースの移行だけを適用して終了する（--migrate-only）
+    pub async fn migrate_only(mut self) -> std::io::Result<()> {
+        self.install_config()?;
+        let Some(path) = init::CONFIG.read().unwrap().history_database.clone() else {
+            crate::printdaytimeln!("HistoryDatabaseが設定されていないため移行するものはありません"); // ログ
+            return Ok(());
+        };
+        history::migrate(&path)?;
+        crate::printdaytimeln!("履歴データベース移行完了: {}", path); // ログ
+        Ok(())
+    }
+
+    // サーバーを起動し、停止されるまで待つ（設定ファイルを読めない・待受できない場合はエラー）
+    pub async fn run(mut self) -> std::io::Result<()> {
+        let _ = self.events_tx.send(LifecycleEvent::Starting);
+        let result = self.serve().await;
+        let _ = self.events_tx.send(LifecycleEvent::Stopped);
+        result
+    }
+
+    // 起動から停止までの本体
+    async fn serve(&mut self) -> std::io::Result<()> {
+        self.install_config()?;
+        lists::reload_all(); // 補助ファイルを読み込む
+        features::load(); // 前回切り替えた機能の状態を引き継ぐ
+        let history_database = init::CONFIG.read().unwrap().history_database.clone(); // 変更は再起動で反映
+        if let Some(path) = history_database {
+            history::start(&path).map_err(|e| {
+                std::io::Error::new(
+                    e.kind(),
+                    format!("履歴データベースを開けません: {} ({})", path, e),
+                )
+            })?; // 移行を適用して保存を開始
+        }
+
+        // メッセージ用ブロードキャストチャネルを作成
+        let (msg_tx, msg_rx) = broadcast::channel::<RoomMessage>(100); // 部屋ごとのチャット配送用
+        let mut tasks = vec![
+            tokio::spawn(lists::watch()),        // 補助ファイルの更新監視タスク
+            tokio::spawn(scheduler::run()),      // 定時処理タスク
+            tokio::spawn(dispatch::run(msg_rx)), // 各クライアントの送信キューへ配送するタスク
+        ];
+
+        let mut listeners = Vec::new(); // TCP以外の待受タスク（終了時は切断を待つ前に止める）
+
+        // WebSocketの待受を開始（設定されている場合のみ、待受アドレスの変更は再起動で反映）
+        if let Some(address) = init::CONFIG.read().unwrap().websocket_address.clone() {
+            listeners.push(tokio::spawn(websocket::serve(
+                address,
+                self.shutdown_tx.clone(),
+                msg_tx.clone(),
+            )));
+        }
+        // テナントごとの待受を開始（テナントの追加・削除・待受アドレスの変更は再起動で反映）
+        for tenant in init::CONFIG.read().unwrap().tenants.clone() {
+            listeners.push(tokio::spawn(tenant::serve(
+                tenant.name,
+                tenant.address,
+                self.shutdown_tx.clone(),
+                msg_tx.clone(),
+            )));
+        }
+        // クライアント用Unixドメインソケットの待受を開始（UNIXのみ、パスの追加・削除は再起動で反映）
+        #[cfg(unix)]
+        let unix_sockets = init::CONFIG.read().unwrap().unix_sockets.clone();
+        #[cfg(unix)]
+        for path in unix_sockets.clone() {
+            listeners.push(tokio::spawn(unix::serve(
+                path,
+                self.shutdown_tx.clone(),
+                msg_tx.clone(),
+            )));
+        }
+        // 管理用ソケットの待受を開始（UNIXのみ、設定されている場合のみ、パスの変更は再起動で反映）
+        #[cfg(unix)]
+        let control_socket = init::CONFIG.read().unwrap().control_socket.clone();
+        #[cfg(unix)]
+        if let Some(path) = control_socket.clone() {
+            tasks.push(tokio::spawn(control::serve(path, self.shutdown_tx.clone())));
+        }
+        if self.handle_signals {
+            tasks.push(spawn_signal_handler(self.shutdown_tx.clone())); // シグナルで再読込・終了
+        }
+
+        let result = accept_loop(&self.shutdown_tx, &self.events_tx, &msg_tx).await; // 停止されるまで接続を受け付ける
+        for listener in listeners {
+            listener.abort(); // 新しい接続を受け付けない
+        }
+        if result.is_ok() {
+            drain().await; // 各クライアントが終了通知と送信キューの残りを書き出して閉じるのを待つ
+        }
+        for task in tasks {
+            task.abort(); // 補助タスクを止める
+        }
+        history::stop(); // 残りの履歴を書き込んで保存を終える
+        #[cfg(unix)]
+        if let Some(path) = control_socket {
+            control::remove(&path); // ソケットファイルを片付ける
+        }
+        #[cfg(unix)]
+        for path in &unix_sockets {
+            unix::remove(path); // クライアント用のソケットファイルも片付ける
+        }
+        result
+    }
+}
+
+impl ChatServerBuilder {
+    // 設定を直接渡す（設定ファイルは読まず、再読込しても変わらない）
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    // 読み込む設定ファイルのパスを指定する
+    pub fn config_file(mut self, path: &str) -> Self {
+        self.config_file = Some(path.to_string());
+        self
+    }
+
+    // 設定ファイルのListenの代わりに使う待受アドレスを指定する（Listen行と同じ書式、再読込しても維持）
+    pub fn listen(mut self, address: &str) -> Self {
+        self.listen = Some(address.to_string());
+        self
+    }
+
+    // SIGHUP/SIGTERM（WindowsはCTRL-Y/CTRL-C）で再読込・終了するか
+    pub fn handle_signals(mut self, enabled: bool) -> Self {
+        self.handle_signals = enabled;
+        self
+    }
+
+    // 標準出力へのログ出力を設定するか（組み込み時はアプリケーション側でtracingのサブスクライバーを設定してもよい）
+    pub fn init_logging(mut self, enabled: bool) -> Self {
+        self.init_logging = enabled;
+        self
+    }
+
+    // 独自のスパム判定を使う（設定のSpamScorerより優先、点数の扱いはSpamThresholdに従う）
+    pub fn scorer(mut self, scorer: Arc<dyn Scorer>) -> Self {
+        self.scorer = Some(scorer);
+        self
+    }
+
+    // サーバーを生成する（設定の読み込みと待受はrun()で行う）
+    pub fn build(self) -> ChatServer {
+        if self.config.is_some() {
+            init::set_config_file(None); // 直接渡した設定を再読込で上書きしない
+        } else if let Some(path) = self.config_file {
+            init::set_config_file(Some(path));
+        }
+        init::set_listen_override(self.listen);
+        scoring::set_custom(self.scorer);
+        let (shutdown_tx, _) = broadcast::channel::<ShutdownReason>(100); // 接続済クライアントへの通知用
+        let (events_tx, _) = broadcast::channel::<LifecycleEvent>(16); // 組み込み先への状態変化の通知用
+        ChatServer {
+            config: self.config,
+            handle_signals: self.handle_signals,
+            init_logging: self.init_logging,
+            shutdown_tx,
+            events_tx,
+        }
+    }
+}
+
+impl ShutdownHandle {
+    // 全クライアントに終了を通知してサーバーを停止する（run()が戻る）
+    pub fn shutdown(&self) {
+        let _ = self.shutdown_tx.send(ShutdownReason::Terminate);
+    }
+
+    // 設定と補助ファイルを再読込する（SIGHUPと同じ）
+    pub fn reload(&self) {
+        reload(&self.shutdown_tx);
+    }
+
+    // サーバーの状態変化を受け取る（購読した後の変化だけが届く）
+    pub fn events(&self) -> broadcast::Receiver<LifecycleEvent> {
+        self.events_tx.subscribe()
+    }
+}
+
+// 設定を再読込し、待受アドレスが変わったかどうかに応じた通知を全クライアントへ送る
+pub(crate) fn reload(shutdown_tx: &broadcast::Sender<ShutdownReason>) {
+    systemd::notify_reloading(); // 再読込の間はsystemdに知らせる
+    let reason = match init::reload() {
+        Some(new_address) => ShutdownReason::AddressChange { new_address }, // 再バインドが必要（完了は待受ループが通知）
+        None => {
+            systemd::notify("READY=1"); // 再読込完了
+            ShutdownReason::Reload // 制限値などの変更のみ（接続は維持）
+        }
+    };
+    let _ = shutdown_tx.send(reason); // 全クライアントとメインループに通知
+}
+
+// TCPの待受ループ（Listen行ごとにリスナーを立て、待受アドレスが変わったら全部を再バインド、終了通知で戻る）
+async fn accept_loop(
+    shutdown_tx: &broadcast::Sender<ShutdownReason>,
+    events_tx: &broadcast::Sender<LifecycleEvent>,
+    msg_tx: &broadcast::Sender<RoomMessage>,
+) -> std::io::Result<()> {
+    let mut shutdown_rx = shutdown_tx.subscribe(); // 待受前の通知も取りこぼさないよう先に作成
+    let inherited = systemd::listen_fds(); // ソケットアクティベーションで渡された待受（あればListen行の代わりに使う）
+    loop {
+        // 現在の設定を読み取る
+        let current_config = init::CONFIG.read().unwrap().clone(); // 設定を取得
+        crate::printdaytimeln!("設定読込: {}", current_config.addresses.join(", ")); // ログ出力
+
+        // TCP待受開始（1つでもバインドできなければ起動失敗）
+        let mut listeners = Vec::new(); // Listen行ごとのリスナー
+        for inherited in &inherited {
+            // 再バインドでも閉じないよう複製して使う
+            let listener = inherited.try_clone().and_then(TcpListener::from_std)?;
+            if let Ok(bound) = listener.local_addr() {
+                crate::printdaytimeln!("待受開始: {} (systemdから受け取り)", bound); // ログ出力
+                let _ = events_tx.send(LifecycleEvent::Bound(bound)); // 実際の待受アドレスを通知
+            }
+            listeners.push(listener);
+        }
+        let addresses = if inherited.is_empty() {
+            current_config.addresses.as_slice()
+        } else {
+            &[] // 渡された待受があれば自分ではバインドしない
+        };
+        for address in addresses {
+            match TcpListener::bind(address).await {
+                Ok(listener) => {
+                    crate::printdaytimeln!("待受開始: {}", address); // バインド成功時に再度ログ
+                    if let Ok(bound) = listener.local_addr() {
+                        let _ = events_tx.send(LifecycleEvent::Bound(bound)); // 実際の待受アドレスを通知
+                    }
+                    listeners.push(listener);
+                }
+                Err(e) => {
+                    return Err(std::io::Error::new(
+                        e.kind(),
+                        format!(
+                            "ポートバインドに失敗しました: {}\n既に他のプロセスが {} を使用中かもしれません。",
+                            e, address
+                        ),
+                    )); // 呼び出し元に返す
+                }
+            }
+        }
+
+        // リスナーごとに受付タスクを起動（接続準備の枠と同じメッセージバスを共有）
+        let setup_slots = listener::SetupSlots::new(
+            current_config.max_concurrent_setups,
+            current_config.soft_limit(current_config.max_concurrent_setups),
+        ); // 接続準備の同時実行枠
+        let accepts: Vec<JoinHandle<()>> = listeners
+            .into_iter()
+            .map(|listener| {
+                tokio::spawn(listener::accept_connections(
+                    listener,
+                    String::new(),
+                    setup_slots.clone(),
+                    shutdown_tx.clone(),
+                    msg_tx.clone(),
+                ))
+            })
+            .collect();
+        systemd::notify("READY=1"); // 起動・再バインド完了
+
+        // 停止・再読込の通知を待つ
+        let rebind = loop {
+            let Ok(reason) = shutdown_rx.recv().await else {
+                continue; // 取りこぼした通知は無視
+            };
+            match reason {
+                ShutdownReason::AddressChange { new_address } => {
+                    crate::printdaytimeln!(
+                        "待受アドレス変更のためリスナー再バインド: {}",
+                        new_address
+                    ); // ログ出力
+                    let _ = events_tx.send(LifecycleEvent::Reloaded);
+                    break true; // 受付を止めて再バインド
+                }
+                ShutdownReason::Reload => {
+                    let _ = events_tx.send(LifecycleEvent::Reloaded);
+                }
+                ShutdownReason::Maintenance { .. } => {} // 予告だけなので受付は続ける
+                ShutdownReason::Terminate => {
+                    crate::printdaytimeln!("サーバーを停止します"); // ログ出力
+                    systemd::notify("STOPPING=1");
+                    let _ = events_tx.send(LifecycleEvent::Draining);
+                    break false; // 受付を止めて切断待ちへ
+                }
+            }
+        };
+        for accept in accepts {
+            accept.abort(); // 受付を止める
+            let _ = accept.await; // リスナーを閉じてから再バインドする
+        }
+        if !rebind {
+            return Ok(());
+        }
+    }
+}
+
+// 終了時に全クライアントの切断を待つ（ShutdownTimeoutSecondsを過ぎたら残りを待たずに戻る）
+async fn drain() {
+    let timeout =
+        tokio::time::Duration::from_secs(init::CONFIG.read().unwrap().shutdown_timeout_seconds);
+    if tokio::time::timeout(timeout, session::wait_until_empty())
+        .await
+        .is_err()
+    {
+        crate::printdaytimeln!(
+            "終了時の切断待ちタイムアウト: 残り{}件",
+            session::snapshot().len()
+        ); // ログ
+    }
+}
+
+// SIGHUP/SIGTERMで再読込・終了するタスク（UNIXのみ）
+#[cfg(unix)]
+fn spawn_signal_handler(shutdown_tx: broadcast::Sender<ShutdownReason>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut hup = signal(SignalKind::hangup()).expect("SIGHUP登録失敗"); // SIGHUPシグナル受信設定
+        let mut term = signal(SignalKind::terminate()).expect("SIGTERM登録失敗"); // SIGTERMシグナル受信設定
+        loop {
+            tokio::select! {
+                Some(()) = hup.recv() => {
+                    crate::printdaytimeln!("SIGHUP受信：設定ファイルを再読み込み"); // ログ出力
+                    reload(&shutdown_tx); // 設定と補助ファイルを再読込して通知
+                }
+                Some(()) = term.recv() => {
+                    crate::printdaytimeln!("SIGTERM受信：サーバーを安全に終了します"); // ログ出力
+                    let _ = shutdown_tx.send(ShutdownReason::Terminate); // 全クライアントとメインループに通知
+                }
+            }
+        }
+    })
+}
+
+// Windows用：CTRL-Y/CTRL-Cで再読込・終了するタスク
+#[cfg(windows)]
+fn spawn_signal_handler(shutdown_tx: broadcast::Sender<ShutdownReason>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut stdin = tokio::io::stdin(); // 標準入力ハンドルを取得
+        let mut buf = [0u8; 1]; // 1バイトバッファ
+        loop {
+            if let Ok(n) = stdin.read(&mut buf).await {
+                // 標準入力から1バイト読む
+                if n == 1 && buf[0] == 0x19 {
+                    // 0x19はCTRL-Y
+                    crate::printdaytimeln!("CTRL-Y受信：設定ファイルを再読み込み"); // ログ出力
+                    reload(&shutdown_tx); // 設定と補助ファイルを再読込して通知
+                } else if n == 1 && buf[0] == 0x03 {
+                    // 0x03はCTRL-C
+                    crate::printdaytimeln!("CTRL-C受信：サーバーを終了します"); // ログ出力
+                    let _ = shutdown_tx.send(ShutdownReason::Terminate); // 全クライアントとメインループに通知
+                }
+            }
+        }
+    })
+}
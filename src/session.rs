@@ -0,0 +1,484 @@
+// RustTokioChatServer - セッション管理モジュール
+// MIT License
+//
+// クレート説明:
+// - tokio: クライアントごとの送信キュー（mpsc）
+// - std: 標準ライブラリ（コレクション・同期・アトミック）
+// - lazy_static: グローバル静的変数
+//
+// session.rs: 接続中クライアントの一覧と送信キューを管理
+// 他のタスク（/whois・管理用一覧など）から参照される状態はここに置く
+use crate::disconnect::DisconnectReason; // 切断理由
+use crate::fingerprint::Fingerprint; // 接続の指紋
+use crate::roles::Role; // 役割
+use crate::transport::{TcpQuality, TransportKind}; // 接続元の通信路種別・TCP接続の状態
+use lazy_static::lazy_static; // lazy_static: グローバル静的変数
+use std::collections::{HashMap, HashSet, VecDeque}; // std: セッション一覧・無視リスト・違反履歴用コレクション
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering}; // std: セッションID採番・状態フラグ用
+use std::sync::{Arc, Mutex}; // std: スレッド安全な参照カウント・ミューテックス
+use tokio::sync::{mpsc, Notify}; // Tokio: クライアントごとの送信キュー・切断要求の通知
+use tokio::time::Instant; // Tokio: 接続時刻
+
+// セッションID採番用カウンタ
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+// 違反履歴として保持する件数
+const VIOLATION_HISTORY: usize = 10;
+// 接続品質を「不良」とする書き込み停滞の回数
+const STALLS_POOR: u64 = 3;
+// 接続品質を「不安定」とするTCPの再送数
+const RETRANSMITS_UNSTABLE: u64 = 10;
+
+// 受信制限の違反の種類（/whoisの違反履歴用）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    Bandwidth,    // 受信帯域の超過
+    BurstLines,   // 短時間の行数の超過
+    MessageChars, // 一行の文字数の超過
+    MessageBytes, // 一行のバイト数の超過
+    Flood,        // 発言数の超過（トークンバケット）
+    Spam,         // スパム判定で破棄
+    Filtered,     // 禁止語を含む発言を破棄
+    Repeat,       // 同じ発言の繰り返しを破棄
+}
+
+impl Violation {
+    // 表示名
+    pub fn label(self) -> &'static str {
+        match self {
+            Violation::Bandwidth => "帯域超過",
+            Violation::BurstLines => "行数超過",
+            Violation::MessageChars => "文字数超過",
+            Violation::MessageBytes => "バイト数超過",
+            Violation::Flood => "連続投稿",
+            Violation::Spam => "スパム判定",
+            Violation::Filtered => "禁止語",
+            Violation::Repeat => "繰り返し",
+        }
+    }
+}
+
+// 他のタスク（オペレーター操作など）からクライアントタスクへの要求
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Control {
+    Disconnect(DisconnectReason), // 切断
+    Rename(String),               // ハンドルネームの強制変更（登録簿とセッションは変更済み）
+}
+
+// 現在の受信計測ウィンドウの状態（/whois表示用にクライアントタスクが更新）
+#[derive(Debug, Clone, Copy)]
+pub struct RateWindow {
+    pub start: Instant, // ウィンドウ開始時刻
+    pub bytes: usize,   // ウィンドウ内で受理した受信バイト数
+    pub lines: usize,   // ウィンドウ内で受理した行数
+}
+
+// 接続中クライアント1件分の情報
+#[derive(Debug)]
+pub struct Session {
+    pub id: u64,                                       // セッションID
+    pub peer_addr: String,                             // クライアントアドレス
+    pub transport: TransportKind,                      // 接続元の通信路種別
+    pub tenant: String,        // 接続を受け付けたテナント（既定の待受なら空）
+    pub connected_at: Instant, // 接続時刻
+    pub queue: mpsc::UnboundedSender<String>, // このクライアント宛ての送信キュー（上限は書き込みタスクが管理）
+    pub oper: AtomicBool,                     // /operでオペレーター認証済みか
+    role: AtomicU8,                           // 設定・/roleで決まった役割（/operの権限は含まない）
+    pub identified: AtomicBool, // 登録済みのハンドルネームを/identify・/registerで認証したか
+    pub bytes_in: AtomicU64,    // 受信累計バイト数
+    pub bytes_out: AtomicU64,   // 送信累計バイト数
+    pub bytes_dropped: AtomicU64, // 帯域超過で破棄した受信バイト数
+    pub write_stalls: AtomicU64, // 書き込みに時間がかかった回数
+    pub lag_drops: AtomicU64,   // 送信キュー満杯で配送できなかった件数
+    tcp_quality: Mutex<Option<TcpQuality>>, // 直近に取得したTCP接続の状態
+    fingerprint: Mutex<Option<Fingerprint>>, // 接続の指紋（最初の受信までNone）
+    window: Mutex<Option<(u16, u16)>>, // telnetのNAWSで知らされた端末の大きさ（幅, 高さ）
+    pub idle: AtomicBool,       // 離席中（一定時間入力なし）か
+    pub quiet: AtomicBool,      // 重要度の低い通知を受け取らない（/quiet on）か
+    pub trace: AtomicBool,      // 生の送受信内容をログに出す（/trace on）か
+    pub acks: AtomicBool,       // チャット行をメッセージID付きで受け取る（/ack on）か
+    pub color: AtomicBool,      // 送信する行をANSIエスケープで色付けする（/color on）か
+    pub acked: AtomicU64, // 今いる部屋で受け取りを確認した最後のメッセージID（入室時は入室時点の最新）
+    handle_name: Mutex<String>, // ハンドルネーム（未定義なら空）
+    room: Mutex<String>,  // 参加中の部屋（どこにもいなければ空）
+    away: Mutex<Option<String>>, // /away の理由（不在でなければNone、理由なしなら空）
+    ignored: Mutex<HashSet<String>>, // /ignore したハンドルネーム（小文字）
+    muted_until: Mutex<Option<Instant>>, // /mute で発言を止められている期限（ミュートされていなければNone）
+    last_active: Mutex<Instant>,         // 最後に入力があった時刻
+    pub warnings: AtomicU64,             // 制限超過で警告を送った回数
+    rate: Mutex<RateWindow>,             // 現在の受信計測ウィンドウ
+    violations: Mutex<VecDeque<(Instant, Violation)>>, // 直近の違反履歴
+    controls: Mutex<VecDeque<Control>>,  // 他タスクからの未処理の要求
+    control_notify: Notify,              // 要求が届いたことの通知
+}
+
+impl Session {
+    // オペレーター以上の役割か（/operで認証した場合を含む）
+    pub fn is_oper(&self) -> bool {
+        self.role() >= Role::Operator
+    }
+
+    // 現在の役割（/operで認証していればオペレーター以上）
+    pub fn role(&self) -> Role {
+        let role = Role::from_u8(self.role.load(Ordering::Relaxed));
+        if self.oper.load(Ordering::Relaxed) {
+            role.max(Role::Operator)
+        } else {
+            role
+        }
+    }
+
+    // 役割を設定する（/operの権限はそのまま）
+    pub fn set_role(&self, role: Role) {
+        self.role.store(role as u8, Ordering::Relaxed);
+    }
+
+    // 現在のハンドルネームを取得
+    pub fn handle_name(&self) -> String {
+        self.handle_name.lock().unwrap().clone()
+    }
+
+    // ハンドルネームを更新（未定義に戻す場合は空文字列）
+    pub fn set_handle_name(&self, name: &str) {
+        *self.handle_name.lock().unwrap() = name.to_string();
+    }
+
+    // 接続の指紋（まだ何も受信していなければNone）
+    pub fn fingerprint(&self) -> Option<Fingerprint> {
+        self.fingerprint.lock().unwrap().clone()
+    }
+
+    // 接続の指紋のID（一覧表示用、未取得なら"-"）
+    pub fn fingerprint_id(&self) -> String {
+        self.fingerprint()
+            .map_or_else(|| "-".to_string(), |fingerprint| fingerprint.id)
+    }
+
+    // 最初の受信から作った指紋を記録
+    pub fn set_fingerprint(&self, fingerprint: Fingerprint) {
+        *self.fingerprint.lock().unwrap() = Some(fingerprint);
+    }
+
+    // telnetのNAWSで知らされた端末の大きさ（幅, 高さ、知らされていなければNone）
+    pub fn window(&self) -> Option<(u16, u16)> {
+        *self.window.lock().unwrap()
+    }
+
+    // 端末の大きさを記録
+    pub fn set_window(&self, window: (u16, u16)) {
+        *self.window.lock().unwrap() = Some(window);
+    }
+
+    // 参加中の部屋（どこにもいなければ空文字列）
+    pub fn room(&self) -> String {
+        self.room.lock().unwrap().clone()
+    }
+
+    // テナント名を付けた部屋名（管理用一覧・履歴用、既定の待受なら部屋名のみ）
+    pub fn qualified_room(&self) -> String {
+        let room = self.room();
+        if self.tenant.is_empty() {
+            room
+        } else {
+            format!("{}/{}", self.tenant, room)
+        }
+    }
+
+    // 参加中の部屋を更新（部屋の出入りはrooms::join/leaveから行う）
+    pub fn set_room(&self, name: &str) {
+        *self.room.lock().unwrap() = name.to_string();
+    }
+
+    // 接続元のIPアドレス部分（ポート番号を除く、解釈できなければアドレス全体）
+    pub fn peer_ip(&self) -> String {
+        match self.peer_addr.parse::<std::net::SocketAddr>() {
+            Ok(addr) => addr.ip().to_string(),
+            Err(_) => self.peer_addr.clone(),
+        }
+    }
+
+    // 入力があったことを記録し、離席中から戻った場合はtrueを返す
+    pub fn touch(&self) -> bool {
+        *self.last_active.lock().unwrap() = Instant::now(); // 最終入力時刻を更新
+        self.idle.swap(false, Ordering::Relaxed) // 離席状態を解除
+    }
+
+    // 最後に入力があった時刻
+    pub fn last_active(&self) -> Instant {
+        *self.last_active.lock().unwrap()
+    }
+
+    // 離席中か
+    pub fn is_idle(&self) -> bool {
+        self.idle.load(Ordering::Relaxed)
+    }
+
+    // /away で不在にしているなら理由（理由なしなら空）
+    pub fn away(&self) -> Option<String> {
+        self.away.lock().unwrap().clone()
+    }
+
+    // 不在にする・不在を解除する（None）、前の状態を返す
+    pub fn set_away(&self, reason: Option<String>) -> Option<String> {
+        std::mem::replace(&mut *self.away.lock().unwrap(), reason)
+    }
+
+    // ミュート中なら解除される時刻
+    pub fn muted_until(&self) -> Option<Instant> {
+        self.muted_until
+            .lock()
+            .unwrap()
+            .filter(|until| *until > Instant::now())
+    }
+
+    // ミュートする・ミュートを解除する（None）
+    pub fn set_muted_until(&self, until: Option<Instant>) {
+        *self.muted_until.lock().unwrap() = until;
+    }
+
+    // 期限が来たミュートを解除する（その後に掛け直された・解除されていればfalse）
+    pub fn expire_mute(&self, until: Instant) -> bool {
+        let mut muted_until = self.muted_until.lock().unwrap();
+        if *muted_until != Some(until) {
+            return false;
+        }
+        *muted_until = None;
+        true
+    }
+
+    // 無視リストに追加（既に入っていればfalse）
+    pub fn ignore(&self, name: &str) -> bool {
+        self.ignored.lock().unwrap().insert(name.to_lowercase())
+    }
+
+    // 無視リストから外す（入っていなければfalse）
+    pub fn unignore(&self, name: &str) -> bool {
+        self.ignored.lock().unwrap().remove(&name.to_lowercase())
+    }
+
+    // 無視しているハンドルネームか（大文字・小文字を区別しない）
+    pub fn is_ignoring(&self, name: &str) -> bool {
+        let ignored = self.ignored.lock().unwrap();
+        !ignored.is_empty() && ignored.contains(&name.to_lowercase())
+    }
+
+    // 無視リスト（名前順）
+    pub fn ignored(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.ignored.lock().unwrap().iter().cloned().collect();
+        names.sort();
+        names
+    }
+
+    // 無視している相手が名前を変えたら無視リストも付け替える
+    fn rename_ignored(&self, old: &str, new: &str) {
+        let mut ignored = self.ignored.lock().unwrap();
+        if ignored.remove(&old.to_lowercase()) {
+            ignored.insert(new.to_lowercase());
+        }
+    }
+
+    // 重要度の低い通知を受け取らない設定か
+    pub fn is_quiet(&self) -> bool {
+        self.quiet.load(Ordering::Relaxed)
+    }
+
+    // チャット行をメッセージID付きで受け取る設定か
+    pub fn wants_ids(&self) -> bool {
+        self.acks.load(Ordering::Relaxed)
+    }
+
+    // 送信する行を色付けする設定か
+    pub fn wants_color(&self) -> bool {
+        self.color.load(Ordering::Relaxed)
+    }
+
+    // 送受信内容のトレース中か
+    pub fn is_tracing(&self) -> bool {
+        self.trace.load(Ordering::Relaxed)
+    }
+
+    // ハンドルネーム未定義（接続直後・CTRL-Yで再定義中）か
+    pub fn is_pending(&self) -> bool {
+        self.handle_name.lock().unwrap().is_empty()
+    }
+
+    // 現在の受信計測ウィンドウを記録
+    pub fn set_rate(&self, rate: RateWindow) {
+        *self.rate.lock().unwrap() = rate;
+    }
+
+    // 現在の受信計測ウィンドウ
+    pub fn rate(&self) -> RateWindow {
+        *self.rate.lock().unwrap()
+    }
+
+    // 送信キューに積む（あふれた分は書き込みタスクがQueueOverflowPolicyに従って扱う）
+    pub fn deliver(&self, text: String) {
+        let _ = self.queue.send(text); // 切断処理中なら捨てる
+    }
+
+    // 直近に取得したTCP接続の状態を記録
+    pub fn set_tcp_quality(&self, quality: TcpQuality) {
+        *self.tcp_quality.lock().unwrap() = Some(quality);
+    }
+
+    // 接続品質の要約（オペレーター向け一覧用、他のセッションと比べてクライアント側の問題か切り分ける）
+    pub fn quality(&self) -> String {
+        let stalls = self.write_stalls.load(Ordering::Relaxed);
+        let drops = self.lag_drops.load(Ordering::Relaxed);
+        let tcp = *self.tcp_quality.lock().unwrap();
+        let retransmits = tcp.map_or(0, |tcp| tcp.retransmits);
+        let label = if drops > 0 || stalls >= STALLS_POOR {
+            "不良"
+        } else if stalls > 0 || retransmits >= RETRANSMITS_UNSTABLE {
+            "不安定"
+        } else {
+            "良好"
+        };
+        let mut summary = format!("{}(停滞{} 取りこぼし{}", label, stalls, drops);
+        if let Some(tcp) = tcp {
+            summary.push_str(&format!(" 再送{} RTT{}ms", tcp.retransmits, tcp.rtt_ms));
+        }
+        summary.push(')');
+        summary
+    }
+
+    // 制限超過で警告したことを履歴に残す
+    pub fn record_violation(&self, kind: Violation) {
+        self.warnings.fetch_add(1, Ordering::Relaxed); // 警告回数を加算
+        let mut violations = self.violations.lock().unwrap();
+        if violations.len() >= VIOLATION_HISTORY {
+            violations.pop_front(); // 古いものから捨てる
+        }
+        violations.push_back((Instant::now(), kind));
+    }
+
+    // 直近の違反履歴（古い順）
+    pub fn violations(&self) -> Vec<(Instant, Violation)> {
+        self.violations.lock().unwrap().iter().copied().collect()
+    }
+
+    // 他のタスクからこのセッションのクライアントタスクへ要求を送る
+    pub fn request(&self, control: Control) {
+        self.controls.lock().unwrap().push_back(control); // 要求を積む
+        self.control_notify.notify_one(); // クライアントタスクに通知（待機中でなくても次回に届く）
+    }
+
+    // 他のタスクからこのセッションの切断を要求する
+    pub fn request_disconnect(&self, reason: DisconnectReason) {
+        self.request(Control::Disconnect(reason));
+    }
+
+    // 他のタスクからの要求が届くまで待ち、古い順に1件返す
+    pub async fn next_control(&self) -> Control {
+        loop {
+            if let Some(control) = self.controls.lock().unwrap().pop_front() {
+                return control;
+            }
+            self.control_notify.notified().await;
+        }
+    }
+}
+
+// グローバルなセッション一覧
+lazy_static! {
+    static ref SESSIONS: Mutex<HashMap<u64, Arc<Session>>> = Mutex::new(HashMap::new()); // セッションIDで引く
+    static ref UNREGISTERED: Notify = Notify::new(); // セッションが外れたことの通知（終了時の切断待ち用）
+}
+
+// 新しいセッションを登録し、送信キューの受信側を返す
+// 受信側は書き込みタスクが常に受け取り続け、ClientQueueSize行を超えた分はそこで扱う
+pub fn register(
+    peer_addr: &str,
+    transport: TransportKind,
+    tenant: &str,
+) -> (Arc<Session>, mpsc::UnboundedReceiver<String>) {
+    let (queue, queue_rx) = mpsc::unbounded_channel(); // 書き込みタスクへの受け渡し
+    let session = Arc::new(Session {
+        id: NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed), // IDを採番
+        peer_addr: peer_addr.to_string(),
+        transport,
+        tenant: tenant.to_string(),
+        connected_at: Instant::now(),
+        queue,
+        oper: AtomicBool::new(false),
+        role: AtomicU8::new(Role::User as u8),
+        identified: AtomicBool::new(false),
+        bytes_in: AtomicU64::new(0),
+        bytes_out: AtomicU64::new(0),
+        bytes_dropped: AtomicU64::new(0),
+        write_stalls: AtomicU64::new(0),
+        lag_drops: AtomicU64::new(0),
+        tcp_quality: Mutex::new(None),
+        fingerprint: Mutex::new(None),
+        window: Mutex::new(None),
+        idle: AtomicBool::new(false),
+        quiet: AtomicBool::new(false),
+        trace: AtomicBool::new(false),
+        acks: AtomicBool::new(false),
+        color: AtomicBool::new(false),
+        acked: AtomicU64::new(0),
+        handle_name: Mutex::new(String::new()),
+        room: Mutex::new(String::new()),
+        away: Mutex::new(None),
+        ignored: Mutex::new(HashSet::new()),
+        muted_until: Mutex::new(None),
+        last_active: Mutex::new(Instant::now()),
+        warnings: AtomicU64::new(0),
+        rate: Mutex::new(RateWindow {
+            start: Instant::now(),
+            bytes: 0,
+            lines: 0,
+        }),
+        violations: Mutex::new(VecDeque::new()),
+        controls: Mutex::new(VecDeque::new()),
+        control_notify: Notify::new(),
+    });
+    SESSIONS
+        .lock()
+        .unwrap()
+        .insert(session.id, Arc::clone(&session)); // 一覧に追加
+    (session, queue_rx)
+}
+
+// セッションを一覧から削除
+pub fn unregister(id: u64) {
+    SESSIONS.lock().unwrap().remove(&id); // 削除
+    UNREGISTERED.notify_waiters(); // 終了時の切断待ちに知らせる
+}
+
+// 全セッションが外れるまで待つ（終了時、各クライアントが送信キューを書き出して閉じ終えるのを待つ）
+pub async fn wait_until_empty() {
+    loop {
+        let unregistered = UNREGISTERED.notified(); // 確認との間の通知も取りこぼさないよう先に作る
+        if SESSIONS.lock().unwrap().is_empty() {
+            return;
+        }
+        unregistered.await;
+    }
+}
+
+// 現在のセッション一覧のスナップショットを取得（ロックは即座に解放）
+pub fn snapshot() -> Vec<Arc<Session>> {
+    SESSIONS.lock().unwrap().values().cloned().collect() // Arcを複製して返す
+}
+
+// セッションIDからセッションを検索
+pub fn find_by_id(id: u64) -> Option<Arc<Session>> {
+    SESSIONS.lock().unwrap().get(&id).cloned()
+}
+
+// テナント内で名前が変わったことを全員の無視リストに反映する（他のテナントの同じ名前は別人）
+pub fn rename_ignored(tenant: &str, old: &str, new: &str) {
+    for session in snapshot() {
+        if session.tenant == tenant {
+            session.rename_ignored(old, new); // 名前を変えても無視され続ける
+        }
+    }
+}
+
+// テナント内でハンドルネームからセッションを検索
+pub fn find_by_handle(tenant: &str, name: &str) -> Option<Arc<Session>> {
+    snapshot().into_iter().find(|session| {
+        session.tenant == tenant && session.handle_name().to_lowercase() == name.to_lowercase()
+    }) // ハンドルネームと同じく大文字・小文字を区別しない
+}
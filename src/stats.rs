@@ -0,0 +1,94 @@
+// RustTokioChatServer - サーバー統計モジュール
+// MIT License
+//
+// クレート説明:
+// - std: 標準ライブラリ（アトミックカウンタ）
+//
+// stats.rs: サーバー全体の統計カウンタ（/statsで表示）
+use std::net::IpAddr; // std: IPアドレス
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering}; // std: スレッド安全なカウンタ
+
+// 接続受付エラーの累計（種類別）
+pub static ACCEPT_ERRORS_TRANSIENT: AtomicU64 = AtomicU64::new(0); // 一時的なエラー（接続中断など）
+pub static ACCEPT_ERRORS_EXHAUSTED: AtomicU64 = AtomicU64::new(0); // リソース枯渇（ファイルディスクリプタ不足など）
+pub static ACCEPT_ERRORS_OTHER: AtomicU64 = AtomicU64::new(0); // その他のエラー
+
+// 接続中のクライアント数（クライアント処理の開始・終了で増減、MaxClientsの判定に使う）
+pub static ACTIVE_CLIENTS: AtomicUsize = AtomicUsize::new(0);
+
+// MaxClientsを超えて断った接続数の累計
+pub static CLIENTS_REJECTED_FULL: AtomicU64 = AtomicU64::new(0);
+
+// 履歴保存キューが一杯で保存できなかった行数
+pub static HISTORY_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+// アドレスファミリーごとの接続数・通信量の累計
+pub struct FamilyStats {
+    pub connections: AtomicU64, // 接続数
+    pub bytes_in: AtomicU64,    // 受信バイト数
+    pub bytes_out: AtomicU64,   // 送信バイト数
+}
+
+impl FamilyStats {
+    const fn new() -> Self {
+        FamilyStats {
+            connections: AtomicU64::new(0),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+        }
+    }
+
+    // 表示用の1行
+    fn report(&self, label: &str) -> String {
+        format!(
+            "SYSTEM> {}: 接続 {} / 受信 {}バイト / 送信 {}バイト\n",
+            label,
+            self.connections.load(Ordering::Relaxed),
+            self.bytes_in.load(Ordering::Relaxed),
+            self.bytes_out.load(Ordering::Relaxed)
+        )
+    }
+}
+
+pub static IPV4: FamilyStats = FamilyStats::new(); // IPv4（IPv4射影アドレスを含む）
+pub static IPV6: FamilyStats = FamilyStats::new(); // IPv6
+
+// IPアドレスのファミリーに対応する統計
+pub fn family(ip: &IpAddr) -> &'static FamilyStats {
+    match ip.to_canonical() {
+        IpAddr::V4(_) => &IPV4,
+        IpAddr::V6(_) => &IPV6,
+    }
+}
+
+// カウンタを1増やす
+pub fn incr(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+// カウンタにnを加える
+pub fn add(counter: &AtomicU64, n: u64) {
+    counter.fetch_add(n, Ordering::Relaxed);
+}
+
+// 統計の表示用テキストを生成
+pub fn report() -> String {
+    let mut text = format!(
+        "SYSTEM> 接続受付エラー: 一時的 {} / リソース枯渇 {} / その他 {}\n",
+        ACCEPT_ERRORS_TRANSIENT.load(Ordering::Relaxed),
+        ACCEPT_ERRORS_EXHAUSTED.load(Ordering::Relaxed),
+        ACCEPT_ERRORS_OTHER.load(Ordering::Relaxed)
+    );
+    text.push_str(&format!(
+        "SYSTEM> 接続中: {} / 満員で拒否: {}\n",
+        ACTIVE_CLIENTS.load(Ordering::Relaxed),
+        CLIENTS_REJECTED_FULL.load(Ordering::Relaxed)
+    ));
+    text.push_str(&IPV4.report("IPv4")); // アドレスファミリー別
+    text.push_str(&IPV6.report("IPv6"));
+    text.push_str(&format!(
+        "SYSTEM> 履歴保存の取りこぼし: {}行\n",
+        HISTORY_DROPPED.load(Ordering::Relaxed)
+    ));
+    text
+}
@@ -0,0 +1,120 @@
+// RustTokioChatServer - systemd連携モジュール
+// MIT License
+//
+// クレート説明:
+// - std: 標準ライブラリ（Unixドメインソケット・環境変数）
+// - libc: 単調時計の取得（UNIXのみ）
+//
+// systemd.rs: systemdのソケットアクティベーション（LISTEN_FDS）で渡された待受を受け取り、
+// Type=notifyのユニット向けに起動・再読込・停止の状態（READY=1・RELOADING=1・STOPPING=1）をNOTIFY_SOCKETへ送る
+// systemdの下で動いていない場合（環境変数がない・UNIX以外）は何もしない
+#[cfg(unix)]
+use std::os::unix::io::FromRawFd; // std: 受け取ったファイルディスクリプタからリスナーを作る
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram; // std: 通知用のデータグラムソケット
+#[cfg(unix)]
+use std::sync::atomic::{AtomicBool, Ordering}; // std: 受け取り済みの印
+
+// systemdが渡すファイルディスクリプタの先頭番号（SD_LISTEN_FDS_START）
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+// 渡された待受を受け取り済みか（同じプロセスでサーバーを動かし直した場合は自分でバインドする）
+#[cfg(unix)]
+static LISTEN_FDS_TAKEN: AtomicBool = AtomicBool::new(false);
+
+// systemdに状態を通知する（NOTIFY_SOCKETが設定されていなければ何もしない）
+#[cfg(unix)]
+pub fn notify(state: &str) {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return; // systemdの下で動いていない・Type=notifyでない
+    };
+    let Ok(socket) = UnixDatagram::unbound() else {
+        return;
+    };
+    let path = path.to_string_lossy();
+    let sent = match path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            // 抽象名前空間のソケット（先頭の@はNULバイトの代わり）
+            use std::os::linux::net::SocketAddrExt; // std: 抽象名前空間のアドレス
+            std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes())
+                .and_then(|addr| socket.send_to_addr(state.as_bytes(), &addr))
+        }
+        _ => socket.send_to(state.as_bytes(), path.as_ref()),
+    };
+    if let Err(e) = sent {
+        crate::printdaytimeln!(
+            "systemdへの通知に失敗しました: {} ({})",
+            state.replace('\n', " "),
+            e
+        ); // ログ
+    }
+}
+
+// UNIX以外ではsystemdに通知しない
+#[cfg(not(unix))]
+pub fn notify(_state: &str) {}
+
+// 設定の再読込を始めたことを通知する（Type=notify-reloadでは単調時計の時刻も必要）
+pub fn notify_reloading() {
+    notify(&format!("RELOADING=1\nMONOTONIC_USEC={}", monotonic_usec()));
+}
+
+// 単調時計の現在時刻（マイクロ秒）
+#[cfg(unix)]
+fn monotonic_usec() -> u64 {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // 安全性: nowは有効な領域を指す
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    now.tv_sec as u64 * 1_000_000 + now.tv_nsec as u64 / 1_000
+}
+
+// UNIX以外では使わない
+#[cfg(not(unix))]
+fn monotonic_usec() -> u64 {
+    0
+}
+
+// ソケットアクティベーションで渡された待受を受け取る（このプロセス宛てでなければ空、受け取りは起動時に一度だけ）
+#[cfg(unix)]
+pub fn listen_fds() -> Vec<std::net::TcpListener> {
+    let for_me = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        == Some(std::process::id()); // 子プロセスに引き継がれた変数は無視する
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|fds| fds.parse::<i32>().ok())
+        .unwrap_or(0);
+    if !for_me || LISTEN_FDS_TAKEN.swap(true, Ordering::Relaxed) {
+        // 環境変数は他のスレッドと競合しうるので消さず、受け取り済みの印で二度目を防ぐ
+        return Vec::new();
+    }
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .filter_map(|fd| {
+            // 安全性: systemdがこのプロセスのために開いたファイルディスクリプタで、受け取りは一度だけなので他では使っていない
+            let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+            match listener.set_nonblocking(true) {
+                Ok(()) => Some(listener),
+                Err(e) => {
+                    crate::printdaytimeln!(
+                        "systemdから受け取った待受を使えません: fd {} ({})",
+                        fd,
+                        e
+                    ); // ログ
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+// UNIX以外ではソケットアクティベーションはない
+#[cfg(not(unix))]
+pub fn listen_fds() -> Vec<std::net::TcpListener> {
+    Vec::new()
+}
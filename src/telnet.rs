@@ -0,0 +1,221 @@
+// RustTokioChatServer - telnetネゴシエーションモジュール
+// MIT License
+//
+// telnet.rs: telnetクライアントが送るIACシーケンス（オプションのネゴシエーション・サブネゴシエーション・コマンド）を
+// 受信データから取り除き、SGA・NAWSのネゴシエーションに応じる（ECHOなどそれ以外のオプションは断り、入力の表示はクライアントに任せる）
+// IAC(0xff)はUTF-8の文字には現れないため、通信路に関わらずすべての受信データをここに通す
+// 応答は行ではないバイト列なので、送信キューではなく書き込みタスクに直接渡す（Outbound::send_raw）
+
+// telnetのコマンド（RFC 854）
+const SE: u8 = 240; // サブネゴシエーションの終わり
+const NUL: u8 = 0; // CR NULの改行で送られる
+const EOF: u8 = 236; // ファイルの終わり（CTRL-Dと同じ扱い）
+const IP: u8 = 244; // 割り込み（CTRL-Cと同じ扱い）
+const SB: u8 = 250; // サブネゴシエーションの始まり
+const WILL: u8 = 251;
+const WONT: u8 = 252;
+const DO: u8 = 253;
+const DONT: u8 = 254;
+const IAC: u8 = 255;
+
+// オプション番号
+const SGA: u8 = 3; // Suppress Go Ahead（RFC 858）
+const NAWS: u8 = 31; // 端末の大きさの通知（RFC 1073）
+
+// サブネゴシエーションとして受け取る長さの上限（NAWSは4バイト、それ以外は捨てるので長くても困らない）
+const MAX_SUBNEGOTIATION: usize = 64;
+
+// 受信データのどこを読んでいるか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Data,     // 普通のデータ
+    Cr,       // CRの直後（CR NULのNULを捨てる）
+    Iac,      // IACの直後
+    Verb(u8), // WILL/WONT/DO/DONTの直後（オプション番号待ち）
+    Sub,      // サブネゴシエーションの中
+    SubIac,   // サブネゴシエーションの中のIACの直後
+}
+
+// 接続1本分のtelnetの状態（受信データが途中で切れても続きから読む）
+#[derive(Debug)]
+pub struct Telnet {
+    state: State,
+    sub: Vec<u8>,               // 受け取り中のサブネゴシエーション（オプション番号から）
+    remote_sga: bool,           // クライアントがSGAを有効にしているか
+    remote_naws: bool,          // クライアントがNAWSを有効にしているか
+    local_sga: bool,            // サーバーがSGAを有効にしているか
+    window: Option<(u16, u16)>, // NAWSで知らされた端末の大きさ（幅, 高さ）
+}
+
+impl Telnet {
+    pub fn new() -> Self {
+        Telnet {
+            state: State::Data,
+            sub: Vec::new(),
+            remote_sga: false,
+            remote_naws: false,
+            local_sga: false,
+            window: None,
+        }
+    }
+
+    // NAWSで知らされた端末の大きさ（幅, 高さ）
+    pub fn window(&self) -> Option<(u16, u16)> {
+        self.window
+    }
+
+    // 受信データからIACシーケンスを取り除き、（残りのデータ, クライアントへの応答）を返す
+    pub fn filter(&mut self, data: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut text = Vec::with_capacity(data.len());
+        let mut reply = Vec::new();
+        for &byte in data {
+            self.state = match (self.state, byte) {
+                (State::Data | State::Cr, IAC) => State::Iac,
+                (State::Cr, NUL) => State::Data, // CR NULはCRだけ残す
+                (State::Data | State::Cr, _) => {
+                    text.push(byte);
+                    if byte == b'\r' {
+                        State::Cr
+                    } else {
+                        State::Data
+                    }
+                }
+                (State::Iac, IAC) => {
+                    text.push(IAC); // IAC IACは0xffそのもの
+                    State::Data
+                }
+                (State::Iac, WILL | WONT | DO | DONT) => State::Verb(byte),
+                (State::Iac, SB) => {
+                    self.sub.clear();
+                    State::Sub
+                }
+                (State::Iac, IP) => {
+                    text.push(0x03); // CTRL-Cとして扱う
+                    State::Data
+                }
+                (State::Iac, EOF) => {
+                    text.push(0x04); // CTRL-Dとして扱う
+                    State::Data
+                }
+                (State::Iac, _) => State::Data, // NOP・GAなどの2バイトのコマンドは読み捨てる
+                (State::Verb(verb), option) => {
+                    self.negotiate(verb, option, &mut reply);
+                    State::Data
+                }
+                (State::Sub, IAC) => State::SubIac,
+                (State::Sub, _) => {
+                    if self.sub.len() < MAX_SUBNEGOTIATION {
+                        self.sub.push(byte);
+                    }
+                    State::Sub
+                }
+                (State::SubIac, IAC) => {
+                    if self.sub.len() < MAX_SUBNEGOTIATION {
+                        self.sub.push(IAC); // サブネゴシエーション内のIAC IAC
+                    }
+                    State::Sub
+                }
+                (State::SubIac, SE) => {
+                    self.subnegotiate();
+                    State::Data
+                }
+                (State::SubIac, _) => State::Data, // SEのない壊れたサブネゴシエーションは捨てる
+            };
+        }
+        (text, reply)
+    }
+
+    // WILL/WONT/DO/DONTに応答する（状態が変わるときと断るときだけ返し、応答の応答でループしない）
+    fn negotiate(&mut self, verb: u8, option: u8, reply: &mut Vec<u8>) {
+        let (enabled, accept, refuse) = if matches!(verb, WILL | WONT) {
+            let enabled = match option {
+                SGA => Some(&mut self.remote_sga),
+                NAWS => Some(&mut self.remote_naws),
+                _ => None,
+            }; // クライアント側のオプション
+            (enabled, DO, DONT)
+        } else {
+            let enabled = match option {
+                SGA => Some(&mut self.local_sga),
+                _ => None, // ECHOも断る（入力の表示はクライアントに任せる）
+            }; // サーバー側のオプション
+            (enabled, WILL, WONT)
+        };
+        let wanted = matches!(verb, WILL | DO); // 有効にしたい（してほしい）か
+        match enabled {
+            Some(enabled) if *enabled != wanted => {
+                *enabled = wanted;
+                reply.extend_from_slice(&[IAC, if wanted { accept } else { refuse }, option]);
+            }
+            None if wanted => reply.extend_from_slice(&[IAC, refuse, option]), // 対応していないオプションは断る
+            _ => {} // 既にその状態なので応答不要
+        }
+    }
+
+    // 受け取り終えたサブネゴシエーションを処理する（NAWSのみ）
+    fn subnegotiate(&mut self) {
+        if let [NAWS, w1, w2, h1, h2] = self.sub[..] {
+            self.window = Some((u16::from_be_bytes([w1, w2]), u16::from_be_bytes([h1, h2])));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // IAC IACは0xffとして残り、CR NULはCRになる
+    #[test]
+    fn filter_keeps_escaped_iac_and_cr() {
+        let mut telnet = Telnet::new();
+        let (text, reply) = telnet.filter(&[b'a', IAC, IAC, b'b', b'\r', NUL, b'c']);
+        assert_eq!(text, [b'a', 0xff, b'b', b'\r', b'c']);
+        assert!(reply.is_empty());
+    }
+
+    // 受信データの途中で切れたIACシーケンスは次の受信データから続けて読む
+    #[test]
+    fn filter_resumes_split_sequences() {
+        let mut telnet = Telnet::new();
+        let (text, reply) = telnet.filter(&[b'x', IAC]);
+        assert_eq!(text, b"x");
+        assert!(reply.is_empty());
+        let (text, reply) = telnet.filter(&[WILL]);
+        assert!(text.is_empty());
+        assert!(reply.is_empty());
+        let (text, reply) = telnet.filter(&[SGA, b'y']);
+        assert_eq!(text, b"y");
+        assert_eq!(reply, [IAC, DO, SGA]);
+        let (text, _) = telnet.filter(&[IAC]);
+        assert!(text.is_empty());
+        let (text, _) = telnet.filter(&[IAC, b'z']);
+        assert_eq!(text, [0xff, b'z']); // 分かれて届いたIAC IAC
+    }
+
+    // 対応していないオプションは断り、同じ要求には二度応答しない
+    #[test]
+    fn filter_negotiates_options() {
+        let mut telnet = Telnet::new();
+        let (_, reply) = telnet.filter(&[IAC, DO, 1]); // DO ECHO
+        assert_eq!(reply, [IAC, WONT, 1]);
+        let (_, reply) = telnet.filter(&[IAC, WILL, NAWS, IAC, WILL, NAWS]);
+        assert_eq!(reply, [IAC, DO, NAWS]);
+        let (_, reply) = telnet.filter(&[IAC, DONT, 1]); // 既に無効なので応答不要
+        assert!(reply.is_empty());
+    }
+
+    // NAWSのサブネゴシエーション（分かれて届いても、値に0xffを含んでも）で端末の大きさを受け取る
+    #[test]
+    fn filter_reads_naws_window() {
+        let mut telnet = Telnet::new();
+        assert_eq!(telnet.window(), None);
+        let (text, _) = telnet.filter(&[IAC, SB, NAWS, 0, 80, 0]);
+        assert!(text.is_empty());
+        assert_eq!(telnet.window(), None); // まだ途中
+        let (text, _) = telnet.filter(&[24, IAC, SE, b'o', b'k']);
+        assert_eq!(text, b"ok");
+        assert_eq!(telnet.window(), Some((80, 24)));
+        telnet.filter(&[IAC, SB, NAWS, 1, IAC, IAC, 0, 50, IAC, SE]);
+        assert_eq!(telnet.window(), Some((0x1ff, 50)));
+    }
+}
@@ -0,0 +1,31 @@
+// RustTokioChatServer - メッセージテンプレートモジュール
+// MIT License
+//
+// クレート説明:
+// - chrono, chrono-tz: {time}の現在時刻（JST）
+//
+// template.rs: 設定に書いたシステムメッセージ（Greeting・Schedule announce）の変数を受信者ごとに展開する
+// 使える変数: {user} 受信者のハンドルネーム / {room} 受信者のいる部屋 / {count} 接続中の人数（受信者のテナント内） / {time} 現在時刻
+// 知らない変数はそのまま残す
+use crate::names; // 接続中の人数
+use chrono_tz::Asia::Tokyo; // chrono-tz: JSTタイムゾーン
+
+// 受信者ごとの変数の値
+pub struct Context<'a> {
+    pub tenant: &'a str, // 受信者のテナント（既定の待受なら空）
+    pub user: &'a str,   // 受信者のハンドルネーム
+    pub room: &'a str,   // 受信者のいる部屋
+}
+
+// テンプレートの変数を展開する
+pub fn render(template: &str, context: &Context) -> String {
+    if !template.contains('{') {
+        return template.to_string(); // 変数がなければそのまま
+    }
+    let now = chrono::Local::now().with_timezone(&Tokyo); // 現在時刻
+    template
+        .replace("{user}", context.user)
+        .replace("{room}", context.room)
+        .replace("{count}", &names::list(context.tenant).len().to_string())
+        .replace("{time}", &now.format("%Y/%m/%d %H:%M").to_string())
+}
@@ -0,0 +1,40 @@
+// RustTokioChatServer - テナント接続受付モジュール
+// MIT License
+//
+// クレート説明:
+// - tokio: TCP待受・ブロードキャストチャネル
+//
+// tenant.rs: Tenant行で指定したテナントごとの待受アドレスで接続を受け付ける
+// 受け付けた接続はテナント名付きでhandle_clientに渡し、部屋・ハンドルネーム・MOTD・制限値を他のテナントと分ける
+// テナントの追加・削除・待受アドレスの変更は再起動で反映される
+use crate::disconnect::ShutdownReason; // 停止理由
+use crate::dispatch::RoomMessage; // メッセージバスの型
+use crate::init; // 設定管理モジュール
+use crate::listener; // 接続受付ループ・接続準備枠
+use tokio::net::TcpListener; // Tokio: TCP待受
+use tokio::sync::broadcast; // Tokio: ブロードキャストチャネル
+
+// テナント1つ分の待受タスク（待受に失敗しても他のテナント・既定の待受は動かし続ける）
+pub async fn serve(
+    tenant: String,                                 // テナント名
+    address: String,                                // 待受アドレス
+    shutdown_tx: broadcast::Sender<ShutdownReason>, // 停止・再読込通知用
+    msg_tx: broadcast::Sender<RoomMessage>,         // メッセージバス
+) {
+    let listener = match TcpListener::bind(&address).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            crate::printdaytimeln!("テナント待受に失敗しました: {} {} ({})", tenant, address, e); // ログ
+            return;
+        }
+    };
+    crate::printdaytimeln!("テナント待受開始: {} {}", tenant, address); // ログ
+    let setup_slots = {
+        let config = init::CONFIG.read().unwrap();
+        listener::SetupSlots::new(
+            config.max_concurrent_setups,
+            config.soft_limit(config.max_concurrent_setups),
+        )
+    }; // 接続準備の同時実行枠（テナントごと）
+    listener::accept_connections(listener, tenant, setup_slots, shutdown_tx, msg_tx).await;
+}
@@ -0,0 +1,104 @@
+// RustTokioChatServer - 通信路抽象化モジュール
+// MIT License
+//
+// クレート説明:
+// - tokio: 非同期I/Oトレイト・インメモリ双方向ストリーム
+// - libc: TCP_INFOの取得（Linuxのみ）
+//
+// transport.rs: handle_clientが扱う通信路（TCP・UNIXドメインソケット・インメモリ等）を抽象化
+// TLSなど別の通信路を足す場合もTransportを実装すればhandle_clientのセッション処理をそのまま使える
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream}; // Tokio: 非同期I/Oトレイトとインメモリストリーム
+use tokio::net::TcpStream; // Tokio: TCP接続
+#[cfg(unix)]
+use tokio::net::UnixStream; // Tokio: UNIXドメインソケット
+
+// 接続元の通信路種別（/whoisや管理用一覧での表示用）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportKind {
+    Tcp,              // 平文TCP
+    WebSocket,        // WebSocket（ブラウザ）
+    WebSocketMachine, // WebSocket（機械向けサブプロトコルで接続したプログラム）
+    Unix,             // UNIXドメインソケット（ListenUnix）
+}
+
+impl TransportKind {
+    // 表示用の名前
+    pub fn label(self) -> &'static str {
+        match self {
+            TransportKind::Tcp => "tcp",
+            TransportKind::WebSocket => "websocket",
+            TransportKind::WebSocketMachine => "websocket-machine",
+            TransportKind::Unix => "unix",
+        }
+    }
+
+    // 端末から接続する通信路か（ANSIエスケープで色付けできる、WebSocketはブラウザ・プログラムなので除く）
+    pub fn is_terminal(self) -> bool {
+        matches!(self, TransportKind::Tcp | TransportKind::Unix)
+    }
+
+    // 人向けのバナー・入力促しの代わりに構造化されたhelloを送る通信路か
+    pub fn is_machine(self) -> bool {
+        matches!(self, TransportKind::WebSocketMachine)
+    }
+}
+
+// OSから取得したTCP接続の状態（接続品質の表示用）
+#[derive(Debug, Clone, Copy)]
+pub struct TcpQuality {
+    pub retransmits: u64, // 再送した累計セグメント数
+    pub rtt_ms: u64,      // 平滑化した往復時間（ミリ秒）
+}
+
+// TCP接続の状態を取得する関数（通信路を読み込み側と書き込み側に分けた後も使えるよう、通信路とは別に持つ）
+pub type QualityProbe = Box<dyn Fn() -> Option<TcpQuality> + Send>;
+
+// クライアントとの通信路として使える型（読み書き可能でタスク間を移動できるもの）
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send + 'static {
+    // TCP接続の状態の取得方法（取得できない通信路・OSではNone）
+    fn quality_probe(&self) -> Option<QualityProbe> {
+        None
+    }
+}
+
+impl Transport for TcpStream {
+    #[cfg(target_os = "linux")]
+    fn quality_probe(&self) -> Option<QualityProbe> {
+        use std::os::unix::io::AsRawFd; // std: ソケットのファイルディスクリプタ
+        let fd = self.as_raw_fd();
+        Some(Box::new(move || tcp_info(fd)))
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixStream {} // UNIXドメインソケット（TCP_INFOはない）
+
+impl Transport for DuplexStream {} // インメモリ（WebSocketの中継・テスト）
+
+// TCP_INFOから再送数と往復時間を取得する（Linuxのみ）
+// 呼び出し側は読み込み側・書き込み側のどちらかを保持している間だけ使う（閉じた後は別のソケットを指しうる）
+#[cfg(target_os = "linux")]
+fn tcp_info(fd: std::os::unix::io::RawFd) -> Option<TcpQuality> {
+    let mut info: libc::tcp_info = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+    // 安全性: infoとlenは有効な領域を指す（fdが無効ならgetsockoptがエラーを返すだけ）
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut info as *mut libc::tcp_info as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    (result == 0).then_some(TcpQuality {
+        retransmits: info.tcpi_total_retrans as u64,
+        rtt_ms: info.tcpi_rtt as u64 / 1000, // マイクロ秒からミリ秒へ
+    })
+}
+
+// ソケットを使わないインメモリの通信路を作成する（サーバー側, クライアント側）
+// WebSocketなど行単位のストリームでない接続や、テスト・組み込み用途でTcpStreamの代わりにhandle_clientへ渡せる
+pub fn memory_pair(max_buf_size: usize) -> (DuplexStream, DuplexStream) {
+    tokio::io::duplex(max_buf_size) // 双方向のインメモリストリーム
+}
@@ -0,0 +1,80 @@
+// RustTokioChatServer - Unixドメインソケット接続受付モジュール
+// MIT License
+//
+// クレート説明:
+// - tokio: Unixドメインソケットの待受・ブロードキャストチャネル
+//
+// unix.rs: ListenUnixで指定したUnixドメインソケットでチャットクライアントを受け付ける（UNIXのみ）
+// ローカルのツールやリバースプロキシ向けで、受け付けた接続はTCPと同じhandle_clientに渡し、同じメッセージバスを共有する
+// ソケットファイルは起動時に作り直し、停止時にremoveで消す（パスの追加・削除は再起動で反映）
+use crate::client; // クライアント処理モジュール
+use crate::disconnect::ShutdownReason; // 停止理由
+use crate::dispatch::RoomMessage; // メッセージバスの型
+use crate::init; // 設定管理モジュール
+use crate::listener; // 接続受付エラーの処理・接続準備枠
+use crate::transport::TransportKind; // 通信路の種別
+use tokio::net::UnixListener; // Tokio: Unixドメインソケットの待受
+use tokio::sync::broadcast; // Tokio: ブロードキャストチャネル
+
+// ソケットファイル1つ分の待受タスク（待受に失敗してもTCPの待受は動かし続ける）
+pub async fn serve(
+    path: String,                                   // ソケットファイルのパス
+    shutdown_tx: broadcast::Sender<ShutdownReason>, // 停止・再読込通知用
+    msg_tx: broadcast::Sender<RoomMessage>,         // メッセージバス
+) {
+    let _ = std::fs::remove_file(&path); // 前回の異常終了で残ったソケットファイルを消す
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            crate::printdaytimeln!("Unixソケットの待受に失敗しました: {} ({})", path, e); // ログ
+            return;
+        }
+    };
+    crate::printdaytimeln!("Unixソケット待受開始: {}", path); // ログ
+    let mut backoff = listener::Backoff::new(); // accept()失敗時の待機状態
+    let setup_slots = {
+        let config = init::CONFIG.read().unwrap();
+        listener::SetupSlots::new(
+            config.max_concurrent_setups,
+            config.soft_limit(config.max_concurrent_setups),
+        )
+    }; // 接続準備の同時実行枠
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                listener::on_accept_error(&e, &mut backoff).await; // 分類してログ・必要なら待機
+                continue;
+            }
+        };
+        backoff.reset(); // 受付に成功したら待機時間を戻す
+
+        // 接続元はIPアドレスの代わりにソケットのパスと相手のユーザーIDで表す（予約・締め出しはユーザーごと）
+        let peer_addr = match stream.peer_cred() {
+            Ok(cred) => format!("unix:{}@{}", path, cred.uid()),
+            Err(_) => format!("unix:{}", path),
+        };
+        crate::printdaytimeln!("接続: {}", peer_addr); // ログ
+        let setup_slots = setup_slots.clone();
+        let shutdown_tx = shutdown_tx.clone();
+        let msg_tx = msg_tx.clone();
+        tokio::spawn(async move {
+            let setup_permit = setup_slots.acquire().await; // 枠が空くまで待つ
+            client::handle_client(
+                stream,
+                peer_addr,
+                TransportKind::Unix,
+                "", // Unixソケットは既定の待受と同じテナント
+                setup_permit,
+                shutdown_tx,
+                msg_tx,
+            )
+            .await;
+        });
+    }
+}
+
+// ソケットファイルを消す（停止時）
+pub fn remove(path: &str) {
+    let _ = std::fs::remove_file(path);
+}
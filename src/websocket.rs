@@ -0,0 +1,207 @@
+// RustTokioChatServer - WebSocket接続受付モジュール
+// MIT License
+//
+// クレート説明:
+// - tokio: TCP待受・インメモリ通信路
+// - tokio-tungstenite: WebSocketのハンドシェイクとメッセージ処理
+// - futures-util: WebSocketの送受信（StreamExt/SinkExt）
+//
+// websocket.rs: ブラウザからのWebSocket接続を受け付け、1メッセージを1行としてhandle_clientに渡す
+// WebSocketとhandle_clientの間はインメモリの通信路でつなぎ、TCPクライアントと同じメッセージバスを共有する
+// サブプロトコルにMACHINE_PROTOCOLを求めたクライアントには、バナーの代わりにJSONのhelloを送る
+use crate::access; // 接続元の許可・拒否
+use crate::autoban; // 自動締め出し
+use crate::client; // クライアント処理モジュール
+use crate::disconnect::{DisconnectReason, ShutdownReason}; // 切断理由・停止理由
+use crate::dispatch::RoomMessage; // メッセージバスの型
+use crate::fingerprint; // 接続の指紋
+use crate::init; // 設定管理モジュール
+use crate::listener; // 接続受付エラーの処理・接続準備枠
+use crate::lists; // 接続拒否IP
+use crate::transport::{self, TransportKind}; // 通信路の抽象化
+use futures_util::{SinkExt, StreamExt}; // futures-util: WebSocketの送受信
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream}; // Tokio: インメモリ通信路の読み書き
+use tokio::net::{TcpListener, TcpStream}; // Tokio: TCP待受
+use tokio::sync::broadcast; // Tokio: ブロードキャストチャネル
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response}; // ハンドシェイクの要求・応答
+use tokio_tungstenite::tungstenite::http::HeaderValue; // 応答ヘッダ
+use tokio_tungstenite::tungstenite::Message; // WebSocketメッセージ
+use tokio_tungstenite::WebSocketStream; // WebSocket接続
+
+// WebSocketとhandle_clientの間の通信路のバッファサイズ
+const PIPE_BUFFER: usize = 16 * 1024;
+
+// 指紋に含めるUser-Agentの最大文字数
+const USER_AGENT_CHARS: usize = 64;
+
+// 機械向けのサブプロトコル名（Sec-WebSocket-Protocolで指定する）
+const MACHINE_PROTOCOL: &str = "rtcs.machine.v1";
+
+// WebSocketの待受タスク（起動時にWebSocketListenが設定されている場合のみ）
+pub async fn serve(
+    address: String,                                // 待受アドレス
+    shutdown_tx: broadcast::Sender<ShutdownReason>, // 停止・再読込通知用
+    msg_tx: broadcast::Sender<RoomMessage>,         // メッセージバス
+) {
+    let listener = match TcpListener::bind(&address).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            crate::printdaytimeln!("WebSocket待受に失敗しました: {} ({})", address, e); // TCP側は動かし続ける
+            return;
+        }
+    };
+    crate::printdaytimeln!("WebSocket待受開始: {}", address); // ログ
+    let mut backoff = listener::Backoff::new(); // accept()失敗時の待機状態
+    let setup_slots = {
+        let config = init::CONFIG.read().unwrap();
+        listener::SetupSlots::new(
+            config.max_concurrent_setups,
+            config.soft_limit(config.max_concurrent_setups),
+        )
+    }; // ハンドシェイクも接続準備に含める
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                listener::on_accept_error(&e, &mut backoff).await; // 分類してログ・必要なら待機
+                continue;
+            }
+        };
+        backoff.reset(); // 受付に成功したら待機時間を戻す
+        let addr = std::net::SocketAddr::new(addr.ip().to_canonical(), addr.port()); // IPv4射影アドレスはIPv4表記に直す
+        let setup_slots = setup_slots.clone();
+        let shutdown_tx = shutdown_tx.clone();
+        let msg_tx = msg_tx.clone();
+        tokio::spawn(async move {
+            let setup_permit = setup_slots.acquire().await; // 枠が空くまで待つ
+            let mut machine = false; // 機械向けサブプロトコルが選ばれたか
+            let mut declared = String::new(); // 指紋に含める申告（サブプロトコル・User-Agent）
+            #[allow(clippy::result_large_err)] // 戻り値の型はtungsteniteのCallbackで決まっている
+            let negotiate = |request: &Request, mut response: Response| {
+                declared = declared_capabilities(request);
+                let offered = request
+                    .headers()
+                    .get_all("Sec-WebSocket-Protocol")
+                    .iter()
+                    .filter_map(|value| value.to_str().ok())
+                    .flat_map(|value| value.split(','))
+                    .any(|protocol| protocol.trim() == MACHINE_PROTOCOL);
+                if offered {
+                    response.headers_mut().insert(
+                        "Sec-WebSocket-Protocol",
+                        HeaderValue::from_static(MACHINE_PROTOCOL),
+                    ); // 選んだサブプロトコルを返す
+                    machine = true;
+                }
+                Ok(response)
+            };
+            let mut ws = match tokio_tungstenite::accept_hdr_async(stream, negotiate).await {
+                Ok(ws) => ws,
+                Err(e) => {
+                    crate::printdaytimeln!("WebSocketハンドシェイク失敗: {} ({})", addr, e); // ログ
+                    return;
+                }
+            };
+            let denied = if lists::is_banned(&addr.ip()) {
+                Some((DisconnectReason::Banned, String::new())) // 接続拒否IP
+            } else if let Some(left) = autoban::banned_for(&addr.ip().to_string()) {
+                Some((
+                    DisconnectReason::AutoBanned,
+                    format!(" 残り{}秒", left.as_secs()),
+                )) // 自動締め出し
+            } else {
+                access::check(&addr.ip())
+                    .err()
+                    .map(|rule| (DisconnectReason::Denied, format!(" {}", rule)))
+                // Allow・Deny
+            };
+            if let Some((reason, rule)) = denied {
+                // 受け付けない接続元からの接続は通知して閉じる
+                crate::printdaytimeln!("接続拒否: {} [{}]{}", addr, reason.code(), rule); // ログ
+                if let Some(notice) = reason.notice() {
+                    let _ = ws.send(Message::Text(notice.trim_end().to_string())).await;
+                    // 拒否理由を通知
+                }
+                let _ = ws.close(None).await;
+                return;
+            }
+            let kind = if machine {
+                TransportKind::WebSocketMachine
+            } else {
+                TransportKind::WebSocket
+            };
+            crate::printdaytimeln!("接続: {} ({})", addr, kind.label()); // ログ
+            fingerprint::declare(&addr.to_string(), declared); // セッション登録時に受け取らせる
+            let (server_side, client_side) = transport::memory_pair(PIPE_BUFFER); // handle_clientとの通信路
+            tokio::spawn(relay(ws, client_side)); // WebSocketと通信路の中継
+            client::handle_client(
+                server_side,
+                addr.to_string(),
+                kind,
+                "", // WebSocketは既定の待受と同じテナント
+                setup_permit,
+                shutdown_tx,
+                msg_tx,
+            )
+            .await;
+        });
+    }
+}
+
+// ハンドシェイクで申告された機能（指紋用、User-Agentは長すぎない範囲で）
+fn declared_capabilities(request: &Request) -> String {
+    let header = |name: &str| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("-")
+            .chars()
+            .take(USER_AGENT_CHARS)
+            .collect::<String>()
+    };
+    format!(
+        "protocols={} ua={}",
+        header("Sec-WebSocket-Protocol").replace(' ', ""),
+        header("User-Agent")
+    )
+}
+
+// WebSocketのメッセージと通信路の行を相互に中継する（どちらかが閉じたら終了）
+async fn relay(ws: WebSocketStream<TcpStream>, pipe: DuplexStream) {
+    let (mut ws_tx, mut ws_rx) = ws.split();
+    let (pipe_rx, mut pipe_tx) = tokio::io::split(pipe);
+    let mut pipe_rx = BufReader::new(pipe_rx);
+    let mut line = Vec::new(); // サーバーからの出力（1行分）
+    loop {
+        tokio::select! {
+            // ブラウザから届いたメッセージは1行として渡す
+            msg = ws_rx.next() => {
+                let mut data = match msg {
+                    Some(Ok(Message::Text(text))) => text.into_bytes(),
+                    Some(Ok(Message::Binary(data))) => data,
+                    Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_))) => continue, // Pingへの応答はライブラリが行う
+                    _ => break, // 切断・エラー
+                };
+                if !data.ends_with(b"\n") {
+                    data.push(b'\n'); // 改行を補う
+                }
+                if pipe_tx.write_all(&data).await.is_err() {
+                    break; // handle_clientが終了した
+                }
+            }
+            // サーバーからの出力は1行ずつテキストメッセージとして送る
+            result = pipe_rx.read_until(b'\n', &mut line) => {
+                if !matches!(result, Ok(n) if n > 0) {
+                    break; // handle_clientが終了した
+                }
+                let text = String::from_utf8_lossy(&line).trim_end().to_string(); // 末尾の改行を除く
+                line.clear();
+                if ws_tx.send(Message::Text(text)).await.is_err() {
+                    break; // ブラウザ側が閉じた
+                }
+            }
+        }
+    }
+    let _ = ws_tx.close().await; // WebSocketを閉じる
+}